@@ -1,5 +1,8 @@
-use buru::prelude::*;
-use clap::{Parser, Subcommand};
+use buru::{
+    parser::{ParseErrorDetail, parse_query},
+    prelude::*,
+};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use sqlx::Pool;
 use std::path::PathBuf;
 
@@ -22,9 +25,164 @@ pub enum Commands {
 
         #[arg(short, long, help = "Image source URL")]
         source: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "BITS",
+            help = "Warn about archived images whose perceptual hash differs in at most this many bits"
+        )]
+        duplicate_check: Option<u32>,
+    },
+    Import {
+        #[arg(help = "Directory to import")]
+        dir: std::path::PathBuf,
+
+        #[arg(
+            short,
+            long,
+            action = ArgAction::Set,
+            num_args = 0..=1,
+            default_value_t = true,
+            default_missing_value = "true",
+            help = "Import subdirectories as well"
+        )]
+        recursive: bool,
+
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Only import files with these extensions (comma separated)"
+        )]
+        ext: Vec<String>,
+
+        #[arg(short, long, default_value_t = 4, help = "Files archived at once")]
+        jobs: usize,
+
+        #[arg(short, long, help = "Tags attached to every file (space separated)")]
+        tags: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            help = "Copy undecodable files into this directory",
+            conflicts_with = "abort_on_error"
+        )]
+        quarantine: Option<std::path::PathBuf>,
+
+        #[arg(long, help = "Stop at the first undecodable file")]
+        abort_on_error: bool,
+    },
+    Query {
+        #[arg(help = "Search query, e.g. \"cat AND NOT dog AND width >= 1920\"")]
+        query: String,
+
+        #[arg(short, long, help = "Maximum number of results")]
+        limit: Option<u32>,
+
+        #[arg(short, long, help = "Number of results to skip")]
+        offset: Option<u32>,
+
+        #[arg(long, value_enum, help = "Order of the results")]
+        order: Option<Order>,
+
+        #[arg(long, help = "Print the results as JSON")]
+        json: bool,
+    },
+    Verify {
+        #[arg(
+            long,
+            help = "Record files missing from the database and remove rows without a file"
+        )]
+        fix: bool,
+    },
+    Tag {
+        #[command(subcommand)]
+        command: TagCommands,
+    },
+    DbCompact {
+        #[arg(
+            long,
+            help = "Rebuild the whole file (takes an exclusive lock and temporary disk space)"
+        )]
+        full: bool,
+
+        #[arg(
+            long,
+            default_value_t = 1000,
+            help = "Free pages reclaimed per incremental run"
+        )]
+        pages: u32,
     },
 }
 
+#[derive(Subcommand)]
+pub enum TagCommands {
+    Rename {
+        #[arg(help = "Tag to rename")]
+        old: String,
+
+        #[arg(help = "New name, merged into the tag if it exists already")]
+        new: String,
+    },
+    Merge {
+        #[arg(help = "Tag to merge away")]
+        from: String,
+
+        #[arg(help = "Existing tag that keeps the images of both")]
+        to: String,
+    },
+    Alias {
+        #[arg(help = "Tag that becomes an alias, retagging its images")]
+        alias: String,
+
+        #[arg(help = "Tag the alias stands for")]
+        canonical: String,
+    },
+    Aliases,
+    Imply {
+        #[arg(help = "Tag that implies the other one")]
+        antecedent: String,
+
+        #[arg(help = "Tag added to images tagged with the antecedent")]
+        consequent: String,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Order {
+    Newest,
+    Oldest,
+    Largest,
+    Smallest,
+    Featured,
+    Random,
+}
+
+impl From<Order> for OrderBy {
+    fn from(value: Order) -> Self {
+        match value {
+            Order::Newest => OrderBy::CreatedAtDesc,
+            Order::Oldest => OrderBy::CreatedAtAsc,
+            Order::Largest => OrderBy::FileSizeDesc,
+            Order::Smallest => OrderBy::FileSizeAsc,
+            Order::Featured => OrderBy::FeaturedFirst,
+            Order::Random => OrderBy::Random,
+        }
+    }
+}
+
+/// Prints where a query failed to parse, pointing at the offending input.
+fn print_parse_error(query: &str, error: &ParseErrorDetail) {
+    eprintln!("❌ Invalid query ({:?}):", error.kind);
+    eprintln!("  {}", query);
+    // The location is the unparsed rest of the input, unless the input ended early.
+    if let Some(column) = query.len().checked_sub(error.location.len())
+        && query.get(column..) == Some(error.location.as_str())
+    {
+        eprintln!("  {}^", " ".repeat(query[..column].chars().count()));
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), AppError> {
     let cli = Cli::parse();
@@ -35,25 +193,258 @@ async fn main() -> Result<(), AppError> {
     let storage = Storage::new(PathBuf::from("./images"));
 
     match cli.command {
-        Commands::Archive { path, tags, source } => {
-            let bytes = tokio::fs::read(&path)
+        Commands::Archive {
+            path,
+            tags,
+            source,
+            duplicate_check,
+        } => {
+            let file = tokio::fs::File::open(&path)
                 .await
-                .expect("failed to read image bytes");
+                .expect("failed to open image file");
 
             let cmd = ArchiveImageCommand {
-                bytes,
                 tags: tags
                     .unwrap_or_default()
                     .split_whitespace()
                     .map(String::from)
                     .collect::<Vec<_>>(),
                 source,
+                source_policy: SourcePolicy::default(),
+                rating: None,
+                duplicate_check,
+                ..ArchiveImageCommand::from_reader(file)
             };
 
-            let image = cmd.execute(&storage, &db).await?;
+            let outcome = cmd.execute_with_warnings(&storage, &db).await?;
 
             println!("✅ Archived image:");
-            println!("{:?}", image);
+            println!("{:?}", outcome.media);
+            for warning in &outcome.warnings {
+                println!("⚠️ {}", warning);
+            }
+        }
+        Commands::Import {
+            dir,
+            recursive,
+            ext,
+            jobs,
+            tags,
+            quarantine,
+            abort_on_error,
+        } => {
+            let policy = match (quarantine, abort_on_error) {
+                (Some(dir), _) => FailedFilePolicy::QuarantineFiles(dir),
+                (None, true) => FailedFilePolicy::Abort,
+                (None, false) => FailedFilePolicy::Skip,
+            };
+
+            let report = ImportDirectoryCommand::new(dir)
+                .with_tags(
+                    tags.unwrap_or_default()
+                        .split_whitespace()
+                        .map(String::from),
+                )
+                .with_policy(policy)
+                .with_extensions(ext)
+                .with_recursive(recursive)
+                .with_concurrency(jobs)
+                .execute(&storage, &db)
+                .await?;
+
+            println!(
+                "✅ Imported {} files, skipped {} already archived, {} failed",
+                report.imported.len(),
+                report.skipped.len(),
+                report.failed.len()
+            );
+            for failed in &report.failed {
+                println!("❌ {}: {}", failed.path.display(), failed.reason);
+            }
+            if !report.quarantined.is_empty() {
+                println!("⚠️ Quarantined {} files:", report.quarantined.len());
+                for file in &report.quarantined {
+                    println!("  {}", file.destination.display());
+                }
+            }
+        }
+        Commands::Query {
+            query,
+            limit,
+            offset,
+            order,
+            json,
+        } => {
+            let expr = match parse_query(&query) {
+                Ok(expr) => expr,
+                Err(e) => {
+                    print_parse_error(&query, &e);
+                    std::process::exit(2);
+                }
+            };
+
+            let mut image_query = ImageQuery::filter(expr);
+            if let Some(limit) = limit {
+                image_query = image_query.with_limit(limit);
+            }
+            if let Some(offset) = offset {
+                image_query = image_query.with_offset(offset);
+            }
+            if let Some(order) = order {
+                image_query = image_query.with_order(order.into());
+            }
+
+            let images = query_image(&db, &storage, image_query).await?;
+
+            if json {
+                let images = images
+                    .iter()
+                    .map(|image| {
+                        serde_json::json!({
+                            "hash": image.hash.to_string(),
+                            "tags": image.tags,
+                            "width": image.metadata.width,
+                            "height": image.metadata.height,
+                            "file_size": image.metadata.file_size,
+                            "path": image.path.content_path(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                println!("{}", serde_json::to_string_pretty(&images).unwrap());
+            } else {
+                println!(
+                    "{:<16}  {:>11}  {:>10}  {:<40}  TAGS",
+                    "HASH", "SIZE", "BYTES", "PATH"
+                );
+                for image in &images {
+                    println!(
+                        "{:<16}  {:>11}  {:>10}  {:<40}  {}",
+                        image.hash,
+                        format!("{}x{}", image.metadata.width, image.metadata.height),
+                        image.metadata.file_size,
+                        image.path.content_path().display(),
+                        image.tags.join(" ")
+                    );
+                }
+                println!("✅ Found {} images", images.len());
+            }
+        }
+        Commands::Verify { fix } => {
+            let report = verify_integrity(&storage, &db).await?;
+
+            let sections: [(&str, Vec<String>); 5] = [
+                (
+                    "Missing from database",
+                    report
+                        .missing_from_db
+                        .iter()
+                        .map(|h| h.to_string())
+                        .collect(),
+                ),
+                (
+                    "Hash mismatch",
+                    report
+                        .hash_mismatch
+                        .iter()
+                        .map(|(stored, actual)| format!("{stored} (content hashes to {actual})"))
+                        .collect(),
+                ),
+                (
+                    "Orphaned database entries",
+                    report
+                        .orphaned_db_entries
+                        .iter()
+                        .map(|h| h.to_string())
+                        .collect(),
+                ),
+                (
+                    "Unreadable",
+                    report.unreadable.iter().map(|h| h.to_string()).collect(),
+                ),
+                (
+                    "Unverified (lossy format)",
+                    report.unverified.iter().map(|h| h.to_string()).collect(),
+                ),
+            ];
+            for (title, hashes) in &sections {
+                if !hashes.is_empty() {
+                    println!("⚠️ {} ({}):", title, hashes.len());
+                    for hash in hashes {
+                        println!("  {}", hash);
+                    }
+                }
+            }
+
+            let removed = storage.remove_empty_dirs()?;
+            if removed > 0 {
+                println!("🧹 Removed {} empty directories", removed);
+            }
+
+            if report.is_clean() {
+                println!("✅ Storage and database are consistent");
+            } else if fix {
+                fix_integrity(&storage, &db, &report).await?;
+                println!(
+                    "✅ Recorded {} files and removed {} orphaned entries",
+                    report.missing_from_db.len(),
+                    report.orphaned_db_entries.len()
+                );
+            }
+        }
+        Commands::Tag {
+            command: TagCommands::Rename { old, new },
+        } => {
+            rename_tag(&db, &old, &new).await?;
+            println!("✅ Renamed tag {} to {}", old, new);
+        }
+        Commands::Tag {
+            command: TagCommands::Merge { from, to },
+        } => {
+            merge_tags(&db, &from, &to).await?;
+            println!("✅ Merged tag {} into {}", from, to);
+        }
+        Commands::Tag {
+            command: TagCommands::Alias { alias, canonical },
+        } => {
+            create_tag_alias(&db, &alias, &canonical).await?;
+            println!("✅ {} is now an alias of {}", alias, canonical);
+        }
+        Commands::Tag {
+            command: TagCommands::Aliases,
+        } => {
+            for (alias, canonical) in
+                query_tag_aliases(&db, TagQuery::new(TagQueryKind::All)).await?
+            {
+                println!("{} -> {}", alias, canonical);
+            }
+        }
+        Commands::Tag {
+            command:
+                TagCommands::Imply {
+                    antecedent,
+                    consequent,
+                },
+        } => {
+            add_tag_implication(&db, &antecedent, &consequent).await?;
+            println!("✅ {} now implies {}", antecedent, consequent);
+        }
+        Commands::DbCompact { full, pages } => {
+            let mode = if full {
+                CompactMode::Full
+            } else {
+                CompactMode::Incremental(pages)
+            };
+
+            match db.compact(mode).await.map_err(AppError::from)? {
+                CompactOutcome::Compacted { before, after } => {
+                    println!("✅ Compacted database:");
+                    println!("before: {:?}", before);
+                    println!("after:  {:?}", after);
+                }
+                CompactOutcome::Skipped { reason } => {
+                    println!("ℹ️ Skipped compaction: {}", reason);
+                }
+            }
         }
     }
 
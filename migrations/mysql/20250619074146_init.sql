@@ -0,0 +1,47 @@
+-- Add migration script here
+
+-- Keys are VARCHAR because MySQL cannot index TEXT without a prefix length, and
+-- utf8mb4_bin keeps tags case-sensitive like the other backends.
+
+CREATE TABLE images (
+    hash VARCHAR(64) PRIMARY KEY,
+    source TEXT
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
+
+CREATE TABLE image_metadatas (
+    image_hash VARCHAR(64) PRIMARY KEY,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    format TEXT NOT NULL,
+    color_type TEXT NOT NULL,
+    file_size BIGINT NOT NULL,
+    created_at VARCHAR(64) NOT NULL,
+    duration DOUBLE,
+    FOREIGN KEY (image_hash) REFERENCES images(hash) ON DELETE CASCADE
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
+
+CREATE INDEX idx_image_metadatas_created_at_desc
+ON image_metadatas (created_at DESC);
+
+CREATE TABLE tags (
+    name VARCHAR(255) PRIMARY KEY
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
+
+CREATE TABLE image_tags (
+    image_hash VARCHAR(64),
+    tag_name VARCHAR(255),
+    PRIMARY KEY (image_hash, tag_name),
+    FOREIGN KEY (image_hash) REFERENCES images(hash) ON DELETE CASCADE,
+    FOREIGN KEY (tag_name) REFERENCES tags(name) ON DELETE CASCADE
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
+
+CREATE TABLE tag_counts (
+    tag_name VARCHAR(255) PRIMARY KEY,
+    count BIGINT NOT NULL,
+    FOREIGN KEY (tag_name) REFERENCES tags(name) ON DELETE CASCADE
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
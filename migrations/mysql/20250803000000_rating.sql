@@ -0,0 +1,11 @@
+-- Rates images as general, sensitive, questionable or explicit, or leaves them unrated.
+
+ALTER TABLE images ADD COLUMN rating VARCHAR(1);
+
+-- The view expands `*` when it is created, so it must be rebuilt to expose the column.
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
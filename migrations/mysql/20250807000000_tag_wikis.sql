@@ -0,0 +1,8 @@
+-- Describes what a tag means, one entry per tag.
+
+CREATE TABLE tag_wikis (
+    tag_name VARCHAR(255) PRIMARY KEY,
+    body TEXT NOT NULL,
+    updated_at VARCHAR(64) NOT NULL,
+    FOREIGN KEY (tag_name) REFERENCES tags(name) ON DELETE CASCADE
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
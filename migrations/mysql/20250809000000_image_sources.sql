@@ -0,0 +1,22 @@
+-- Records any number of sources per image, in the order they were added.
+
+CREATE TABLE image_sources (
+    image_hash VARCHAR(64) NOT NULL,
+    position INT NOT NULL,
+    url TEXT NOT NULL,
+    PRIMARY KEY (image_hash, position),
+    FOREIGN KEY (image_hash) REFERENCES images(hash) ON DELETE CASCADE
+) DEFAULT CHARSET = utf8mb4 COLLATE = utf8mb4_bin;
+
+INSERT INTO image_sources (image_hash, position, url)
+SELECT hash, 0, source FROM images WHERE source IS NOT NULL AND source <> '';
+
+-- The view expands `*` when it is created, so it must be rebuilt to drop the column.
+DROP VIEW image_with_metadata;
+
+ALTER TABLE images DROP COLUMN source;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -0,0 +1,5 @@
+-- Records when a tag was attached to an image, so tags can be listed in the order
+-- they were added. Tags attached before are dated to the migration.
+
+ALTER TABLE image_tags
+ADD COLUMN created_at DATETIME(6) NOT NULL DEFAULT CURRENT_TIMESTAMP(6);
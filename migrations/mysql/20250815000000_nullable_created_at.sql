@@ -0,0 +1,4 @@
+-- Leaves `created_at` NULL for files whose creation time is unknown, as ingested with
+-- `CreatedAtFallback::None`, instead of recording the ingest time.
+
+ALTER TABLE image_metadatas MODIFY COLUMN created_at VARCHAR(64) NULL;
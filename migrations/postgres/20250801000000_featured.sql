@@ -0,0 +1,11 @@
+-- Marks curated images that are surfaced first.
+
+ALTER TABLE images ADD COLUMN is_featured BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The view expands `*` when it is created, so it must be rebuilt to expose the column.
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
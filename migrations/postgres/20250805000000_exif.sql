@@ -0,0 +1,16 @@
+-- Stores camera details read from the EXIF data of images, all unknown for other files.
+
+ALTER TABLE image_metadatas ADD COLUMN camera_make TEXT;
+ALTER TABLE image_metadatas ADD COLUMN camera_model TEXT;
+ALTER TABLE image_metadatas ADD COLUMN gps_lat DOUBLE PRECISION;
+ALTER TABLE image_metadatas ADD COLUMN gps_lon DOUBLE PRECISION;
+ALTER TABLE image_metadatas ADD COLUMN taken_at TEXT;
+ALTER TABLE image_metadatas ADD COLUMN exposure DOUBLE PRECISION;
+
+-- The view expands `*` when it is created, so it must be rebuilt to expose the columns.
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -0,0 +1,12 @@
+-- Maps alternative names of tags to the tag they stand for.
+--
+-- Aliases are kept flat: a canonical tag is never an alias itself, so an alias
+-- resolves in a single lookup.
+
+CREATE TABLE tag_aliases (
+    alias TEXT PRIMARY KEY,
+    canonical TEXT NOT NULL
+);
+
+CREATE INDEX idx_tag_aliases_canonical
+ON tag_aliases (canonical);
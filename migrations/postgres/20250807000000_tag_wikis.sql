@@ -0,0 +1,8 @@
+-- Describes what a tag means, one entry per tag.
+
+CREATE TABLE tag_wikis (
+    tag_name TEXT PRIMARY KEY,
+    body TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    FOREIGN KEY (tag_name) REFERENCES tags(name) ON DELETE CASCADE
+);
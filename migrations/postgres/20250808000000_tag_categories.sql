@@ -0,0 +1,3 @@
+-- Categorizes tags as general, artist, character, copyright or meta tags.
+
+ALTER TABLE tags ADD COLUMN category TEXT NOT NULL DEFAULT 'general';
@@ -0,0 +1,8 @@
+-- Records when a tag was attached to an image, so tags can be listed in the order
+-- they were added. Tags attached before are dated to the migration.
+--
+-- `clock_timestamp` rather than `CURRENT_TIMESTAMP`, which is frozen for the whole
+-- transaction.
+
+ALTER TABLE image_tags
+ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT clock_timestamp();
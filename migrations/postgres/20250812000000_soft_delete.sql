@@ -0,0 +1,12 @@
+-- Marks images as deleted without removing them, so that they can be restored.
+-- Soft-deleted images are left out of queries. NULL for images that are not deleted.
+
+ALTER TABLE images ADD COLUMN deleted_at TEXT;
+
+-- The view expands `*` when it is created, so it must be rebuilt to expose the column.
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
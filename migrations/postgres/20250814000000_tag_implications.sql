@@ -0,0 +1,10 @@
+-- Maps tags to the tags they imply, e.g. `cat` to `animal`.
+--
+-- Implications are followed transitively when tags are attached, so `kitten`
+-- implying `cat` also implies `animal`. They never form a cycle.
+
+CREATE TABLE tag_implications (
+    antecedent TEXT NOT NULL,
+    consequent TEXT NOT NULL,
+    PRIMARY KEY (antecedent, consequent)
+);
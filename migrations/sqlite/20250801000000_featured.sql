@@ -0,0 +1,10 @@
+-- Marks curated images that are surfaced first.
+
+ALTER TABLE images ADD COLUMN is_featured BOOLEAN NOT NULL DEFAULT 0;
+
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -0,0 +1,10 @@
+-- Hides private images from queries unless they are explicitly included.
+
+ALTER TABLE images ADD COLUMN is_public BOOLEAN NOT NULL DEFAULT 1;
+
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -0,0 +1,10 @@
+-- Rates images as general, sensitive, questionable or explicit, or leaves them unrated.
+
+ALTER TABLE images ADD COLUMN rating TEXT;
+
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -0,0 +1,10 @@
+-- Stores the 64-bit perceptual hash (dHash) of each image, bit for bit as a signed integer.
+
+ALTER TABLE images ADD COLUMN phash INTEGER;
+
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -0,0 +1,15 @@
+-- Stores camera details read from the EXIF data of images, all unknown for other files.
+
+ALTER TABLE image_metadatas ADD COLUMN camera_make TEXT;
+ALTER TABLE image_metadatas ADD COLUMN camera_model TEXT;
+ALTER TABLE image_metadatas ADD COLUMN gps_lat REAL;
+ALTER TABLE image_metadatas ADD COLUMN gps_lon REAL;
+ALTER TABLE image_metadatas ADD COLUMN taken_at TEXT;
+ALTER TABLE image_metadatas ADD COLUMN exposure REAL;
+
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
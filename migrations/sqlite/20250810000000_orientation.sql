@@ -0,0 +1,12 @@
+-- Stores the EXIF orientation of images, from 1 to 8, to be applied when displaying
+-- them. Stored pixels are never rotated. Unknown for files without EXIF data.
+
+ALTER TABLE image_metadatas ADD COLUMN orientation INTEGER;
+
+-- The view expands `*` when it is created, so it must be rebuilt to expose the column.
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -0,0 +1,20 @@
+-- Records when a tag was attached to an image, so tags can be listed in the order
+-- they were added. Tags attached before are dated to the migration.
+--
+-- A column with a non-constant default cannot be added, so the table is rebuilt.
+
+CREATE TABLE image_tags_new (
+    image_hash TEXT,
+    tag_name TEXT,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+    PRIMARY KEY (image_hash, tag_name),
+    FOREIGN KEY (image_hash) REFERENCES images(hash) ON DELETE CASCADE,
+    FOREIGN KEY (tag_name) REFERENCES tags(name) ON DELETE CASCADE
+);
+
+INSERT INTO image_tags_new (image_hash, tag_name)
+SELECT image_hash, tag_name FROM image_tags;
+
+DROP TABLE image_tags;
+
+ALTER TABLE image_tags_new RENAME TO image_tags;
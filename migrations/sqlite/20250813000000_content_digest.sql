@@ -0,0 +1,13 @@
+-- Stores a 128-bit digest of the content the pixel hash is computed from, as hex, so
+-- that distinct images sharing a pixel hash are told apart from duplicates. Unknown
+-- for images archived before.
+
+ALTER TABLE image_metadatas ADD COLUMN digest TEXT;
+
+-- The view expands `*` when it is created, so it must be rebuilt to expose the column.
+DROP VIEW image_with_metadata;
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -0,0 +1,44 @@
+-- Leaves `created_at` NULL for files whose creation time is unknown, as ingested with
+-- `CreatedAtFallback::None`, instead of recording the ingest time.
+
+-- SQLite cannot drop a NOT NULL constraint, so the table is rebuilt, and the view
+-- reading it along with it.
+DROP VIEW image_with_metadata;
+
+CREATE TABLE image_metadatas_new (
+    image_hash TEXT,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    format TEXT NOT NULL,
+    color_type TEXT NOT NULL,
+    file_size INTEGER NOT NULL,
+    created_at TEXT,
+    duration REAL,
+    camera_make TEXT,
+    camera_model TEXT,
+    gps_lat REAL,
+    gps_lon REAL,
+    taken_at TEXT,
+    exposure REAL,
+    orientation INTEGER,
+    digest TEXT,
+    PRIMARY KEY (image_hash),
+    FOREIGN KEY (image_hash) REFERENCES images(hash) ON DELETE CASCADE
+);
+
+INSERT INTO image_metadatas_new
+SELECT image_hash, width, height, format, color_type, file_size, created_at, duration,
+    camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation, digest
+FROM image_metadatas;
+
+DROP TABLE image_metadatas;
+
+ALTER TABLE image_metadatas_new RENAME TO image_metadatas;
+
+CREATE INDEX idx_image_metadatas_created_at_desc
+ON image_metadatas (created_at DESC);
+
+CREATE VIEW image_with_metadata AS
+SELECT *
+FROM images
+LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash;
@@ -9,7 +9,8 @@
 //!
 //! - **ArchiveImageCommand**: Central to creating and executing image archival requests,
 //!   this struct facilitates the inclusion of tags and source information while managing
-//!   the invocation of storage and database procedures.
+//!   the invocation of storage and database procedures. `execute_with_warnings` also
+//!   returns `ArchiveWarning`s about images that look like mis-uploads.
 //! - **Image**: Represents a comprehensive image model that bundles file path, hash,
 //!   metadata, tags, and optional source information, capturing all details needed
 //!   for managing and retrieving images.
@@ -21,14 +22,30 @@
 //! - **attach_tags**: Synchronizes and updates tag associations for a given image hash,
 //!   efficiently calculating differences and applying updates in parallel.
 //! - **attach_source**: Updates source information for an image in the database,
-//!   ensuring accurate attribution of origin points for stored images.
+//!   ensuring accurate attribution of origin points for stored images. Sources can be
+//!   validated with a `SourcePolicy`.
 //! - **remove_image**: Completely deletes an image from both storage and database,
 //!   handling cleanup of records and metadata to maintain consistency.
+//! - **soft_delete_image** and **restore_image**: Hide an image from queries without
+//!   removing anything, and bring it back.
 //! - **find_image_by_hash**: Retrieves a full image model by its hash, consolidating
 //!   metadata, tags, and file path, encapsulating all necessary image information.
 //!
 //! - **query_image** and **count_image**: Execute filtered queries on images, efficiently
 //!   retrieving or counting matches based on conditions defined by `ImageQuery` objects.
+//!   **query_image_page** pages through them with a cursor instead of an offset,
+//!   and **query_image_with_total** returns an offset page with the total of matches.
+//! - **get_images_by_hashes**: Retrieves images for a list of hashes in the exact order
+//!   given, with a `MissingPolicy` deciding how absent hashes are represented.
+//! - **capabilities**: Describes the features, search syntax, and limits of this deployment.
+//! - **ImportDirectoryCommand**: Archives every file below a directory, handling undecodable
+//!   files according to a `FailedFilePolicy`.
+//! - **export_jsonl** and **restore**: Write a full backup of the archive as JSON Lines,
+//!   and archive every image of such a backup again.
+//! - **view_context**: Loads an image with its neighbors, position, and total within a
+//!   query for a gallery viewer.
+//! - **pregenerate_variants**: Generates every configured variant size of every image
+//!   ahead of time for deployments that serve variants without a CDN.
 //!
 //! ## Error Handling
 //!
@@ -36,19 +53,61 @@
 //! and other custom errors to promote clear and manageable error management
 //! throughout image operations.
 
+#[cfg(feature = "webp")]
+use crate::storage::TranscodeConfig;
 use crate::{
-    database::{Database, DatabaseError},
-    query::{ImageQuery, TagQuery},
-    storage::{ImageMetadata, MediaPath, PixelHash, Storage, StorageError},
+    capabilities::{self, Capabilities, DatabaseInfo, Features, Limits, SearchSyntax},
+    database::{Database, DatabaseError, Rating, TagCategory, TagWiki, canonical_tags},
+    parser,
+    query::{Cursor, ImageQuery, OrderBy, TagQuery, TagQueryExpr, TagQueryKind},
+    storage::{
+        CreateReport, ImageMetadata, MediaPath, NearDuplicate, PixelHash, Priority, Storage,
+        StorageError, VariantSpec,
+    },
 };
+use chrono::{DateTime, Utc};
+#[cfg(feature = "webp")]
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
-use tokio::task::JoinSet;
+use tokio::io::AsyncRead;
+
+mod batch;
+mod export;
+mod import;
+mod integrity;
+mod rehash;
+mod repair;
+mod restore;
+mod similar;
+mod source;
+mod variants;
+mod view;
+mod warnings;
+
+pub use batch::ArchiveImagesCommand;
+pub use export::{ArchiveRecord, FileReference, RecordFile, export_jsonl, export_tags_csv};
+pub use import::{
+    FailedFile, FailedFilePolicy, ImportDirectoryCommand, ImportReport, QuarantinedFile,
+    SkippedFile,
+};
+pub use integrity::{IntegrityReport, fix_integrity, import_from_storage, verify_integrity};
+pub use rehash::{RehashReport, RehashedFile, rehash_archive};
+pub use repair::{IncompleteRecord, find_incomplete};
+pub use restore::{
+    ExistingRecordPolicy, FailedRecord, RestoreOptions, RestoreReport, restore,
+    restore_with_options,
+};
+pub use similar::find_similar_images;
+pub use source::SourcePolicy;
+pub use variants::{FailedVariant, VariantReport, pregenerate_variants};
+pub use view::{ViewContext, view_context};
+pub use warnings::{ArchiveOutcome, ArchiveWarning, WarningThresholds};
 
 /// Represents a command for archiving an image into the system.
 ///
-/// This structure holds the raw image bytes, optional source URL, and associated tags.
-/// Use builder-style methods (`with_tags`, `with_source`) to set additional information
-/// before calling `execute()` to perform the archival process.
+/// This structure holds the raw image bytes or a stream of them, optional source URLs,
+/// and associated tags. Use builder-style methods (`with_tags`, `with_sources`) to set
+/// additional information before calling `execute()` to perform the archival process.
 pub struct ArchiveImageCommand {
     /// Raw image bytes.
     pub bytes: Vec<u8>,
@@ -56,6 +115,26 @@ pub struct ArchiveImageCommand {
     pub tags: Vec<String>,
     /// An optional source URL indicating the origin of the image.
     pub source: Option<String>,
+    /// Further source URLs of the image, recorded after `source`.
+    pub sources: Vec<String>,
+    /// The policy the sources are validated against, permissive by default.
+    pub source_policy: SourcePolicy,
+    /// An optional content rating of the image.
+    pub rating: Option<Rating>,
+    /// A stream the image is read from instead of `bytes`, see `from_reader`.
+    pub reader: Option<Box<dyn AsyncRead + Send + Unpin>>,
+    /// Whether an already archived image is updated instead of rejected, see `with_upsert`.
+    pub upsert: bool,
+    /// The variants generated once the image is archived, see `with_variants`.
+    pub variants: Vec<VariantSpec>,
+    /// The limits beyond which warnings are raised, see `execute_with_warnings`.
+    pub warning_thresholds: WarningThresholds,
+    /// The maximum Hamming distance of archived images reported as near-duplicates, see
+    /// `with_duplicate_check`.
+    pub duplicate_check: Option<u32>,
+    /// The format the image is stored in instead of its original one, see `with_transcode`.
+    #[cfg(feature = "webp")]
+    pub transcode: Option<TranscodeConfig>,
 }
 
 impl ArchiveImageCommand {
@@ -73,6 +152,36 @@ impl ArchiveImageCommand {
             bytes: bytes.to_vec(),
             tags: vec![],
             source: None,
+            sources: vec![],
+            source_policy: SourcePolicy::default(),
+            rating: None,
+            reader: None,
+            upsert: false,
+            variants: vec![],
+            warning_thresholds: WarningThresholds::default(),
+            duplicate_check: None,
+            #[cfg(feature = "webp")]
+            transcode: None,
+        }
+    }
+
+    /// Creates a new `ArchiveImageCommand` reading the image from a stream.
+    ///
+    /// The stream is spooled to a temporary file by `Storage::create_file_from_async_reader`
+    /// when the command is executed, so large videos are archived without being held
+    /// in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream of the image's raw bytes.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `ArchiveImageCommand` instance.
+    pub fn from_reader(reader: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        ArchiveImageCommand {
+            reader: Some(Box::new(reader)),
+            ..ArchiveImageCommand::new(&[])
         }
     }
 
@@ -104,10 +213,149 @@ impl ArchiveImageCommand {
         self
     }
 
+    /// Sets further source URLs for the image, e.g. mirrors of the original post.
+    ///
+    /// The sources are recorded in the given order, after the one of `with_source`.
+    ///
+    /// # Arguments
+    ///
+    /// * `sources` - The source URLs of the image.
+    ///
+    /// # Returns
+    ///
+    /// Returns the modified `ArchiveImageCommand` with the sources set.
+    pub fn with_sources(mut self, sources: Vec<String>) -> Self {
+        self.sources = sources;
+        self
+    }
+
+    /// Sets the content rating of the image.
+    ///
+    /// # Arguments
+    ///
+    /// * `rating` - The `Rating` of the image.
+    ///
+    /// # Returns
+    ///
+    /// Returns the modified `ArchiveImageCommand` with the rating set.
+    pub fn with_rating(mut self, rating: Rating) -> Self {
+        self.rating = Some(rating);
+        self
+    }
+
+    /// Sets the policy the source is validated against.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The `SourcePolicy` deciding which sources are accepted.
+    ///
+    /// # Returns
+    ///
+    /// Returns the modified `ArchiveImageCommand` with the policy set.
+    pub fn with_source_policy(mut self, policy: SourcePolicy) -> Self {
+        self.source_policy = policy;
+        self
+    }
+
+    /// Stores the image in another format, e.g. WebP, instead of its original one.
+    ///
+    /// The pixel hash is computed from the original pixels, so the image is still
+    /// recognized as a duplicate of the same picture in any format. Videos and
+    /// animated GIFs are stored as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The `TranscodeConfig` naming the format and quality.
+    ///
+    /// # Returns
+    ///
+    /// Returns the modified `ArchiveImageCommand` with transcoding set.
+    #[cfg(feature = "webp")]
+    pub fn with_transcode(mut self, config: TranscodeConfig) -> Self {
+        self.transcode = Some(config);
+        self
+    }
+
+    /// Updates an image already archived with the same pixel hash instead of failing.
+    ///
+    /// By default, archiving an image whose visual content is archived already fails
+    /// with `StorageError::HashCollision`. With this set, the stored file is kept, and
+    /// the tags, source and rating of this command are applied to the archived image
+    /// as they would be to a new one: given tags replace its tags, and unset values
+    /// keep the archived ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `upsert` - Whether an archived image is updated.
+    ///
+    /// # Returns
+    ///
+    /// Returns the modified `ArchiveImageCommand` with the flag set.
+    pub fn with_upsert(mut self, upsert: bool) -> Self {
+        self.upsert = upsert;
+        self
+    }
+
+    /// Generates downscaled variants of the image once it is archived, e.g. previews,
+    /// so that they need not be generated on first request.
+    ///
+    /// Variants that exist already are kept. Generating them is best effort: the image
+    /// is archived even if a variant fails, as `Storage::get_variant` generates
+    /// missing variants on demand.
+    ///
+    /// # Arguments
+    ///
+    /// * `variants` - The specs of the variants to generate.
+    ///
+    /// # Returns
+    ///
+    /// Returns the modified `ArchiveImageCommand` with the variants set.
+    pub fn with_variants(mut self, variants: Vec<VariantSpec>) -> Self {
+        self.variants = variants;
+        self
+    }
+
+    /// Sets the limits beyond which `execute_with_warnings` raises warnings.
+    ///
+    /// # Arguments
+    ///
+    /// * `thresholds` - The `WarningThresholds` to judge the image against.
+    ///
+    /// # Returns
+    ///
+    /// Returns the modified `ArchiveImageCommand` with the thresholds set.
+    pub fn with_warning_thresholds(mut self, thresholds: WarningThresholds) -> Self {
+        self.warning_thresholds = thresholds;
+        self
+    }
+
+    /// Warns about archived images that look like this one, e.g. a re-encoded copy,
+    /// which the pixel hash does not catch.
+    ///
+    /// The perceptual hash of the image is compared with those recorded in the
+    /// database, see `find_similar_images`, and each archived image within the
+    /// distance raises `ArchiveWarning::NearDuplicate`. The image is archived anyway.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_distance` - The maximum number of differing bits (0-64), e.g. 8.
+    ///
+    /// # Returns
+    ///
+    /// Returns the modified `ArchiveImageCommand` with the check set.
+    pub fn with_duplicate_check(mut self, max_distance: u32) -> Self {
+        self.duplicate_check = Some(max_distance);
+        self
+    }
+
     /// Executes the archival process for the image.
     ///
     /// This involves storing the image, extracting metadata, inserting a database record,
-    /// and attaching tags and an optional source URL if provided.
+    /// and attaching tags and source URLs if provided.
+    ///
+    /// The content digest of the image is recorded with its metadata. An image whose
+    /// pixel hash collides with an archived image of another digest fails with
+    /// `StorageError::TrueCollision`, even with `with_upsert`.
     ///
     /// # Arguments
     ///
@@ -118,48 +366,175 @@ impl ArchiveImageCommand {
     ///
     /// Returns a `Result` containing the full `Image` model upon success or an `AppError` on failure.
     pub async fn execute(self, storage: &Storage, db: &Database) -> Result<Media, AppError> {
-        let hash = match storage.create_file(&self.bytes) {
-            Ok(hash) => Ok(hash),
-            Err(e) => match &e {
-                // allows creating the image if registration is incomplete.
-                StorageError::HashCollision { hash, .. } => {
-                    if !db.image_exists(hash).await? {
-                        Ok(hash.clone())
-                    } else if db.get_metadata(hash).await?.is_none() {
-                        Ok(hash.clone())
-                    } else {
-                        Err(e)
-                    }
-                }
-                _ => Err(e),
-            },
-        }?;
+        Ok(self.execute_with_warnings(storage, db).await?.media)
+    }
+
+    /// Executes the archival process like `execute`, and also returns the warnings
+    /// raised by the archived image.
+    ///
+    /// Warnings never fail the archival. They are judged against the
+    /// `WarningThresholds` set with `with_warning_thresholds`, and near-duplicates are
+    /// looked up if `with_duplicate_check` is set.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - Reference to the storage system where the image will be stored.
+    /// * `db` - Reference to the database where metadata and other information will be recorded.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `ArchiveOutcome` upon success or an `AppError` on failure.
+    pub async fn execute_with_warnings(
+        mut self,
+        storage: &Storage,
+        db: &Database,
+    ) -> Result<ArchiveOutcome, AppError> {
+        if db.is_read_only() {
+            return Err(DatabaseError::ReadOnly.into());
+        }
+        let sources: Vec<String> = self
+            .source
+            .take()
+            .into_iter()
+            .chain(std::mem::take(&mut self.sources))
+            .collect();
+        for src in &sources {
+            self.source_policy
+                .check(src)
+                .map_err(|reason| SourcePolicy::invalid(src, reason))?;
+        }
+        #[cfg(feature = "webp")]
+        let storage = &match self.transcode {
+            Some(config) => Cow::Owned(storage.clone().with_transcode(config)),
+            None => Cow::Borrowed(storage),
+        };
+
+        let (report, created) = match self.reader.take() {
+            Some(reader) => {
+                storage
+                    .create_or_get_from_async_reader(reader, None, Priority::Interactive)
+                    .await?
+            }
+            None => {
+                storage
+                    .create_or_get_async(std::mem::take(&mut self.bytes), Priority::Interactive)
+                    .await?
+            }
+        };
+        let CreateReport {
+            hash,
+            phash,
+            path,
+            digest,
+            ..
+        } = report;
+
+        let recorded = if created {
+            None
+        } else {
+            db.get_metadata(&hash).await?
+        };
+        // Only the content digests tell a different image with a colliding pixel hash
+        // from a duplicate. Images recorded without a digest are taken as duplicates.
+        if let Some(recorded) = recorded.as_ref().and_then(|m| m.digest.as_ref())
+            && digest.as_ref().is_some_and(|digest| digest != recorded)
+        {
+            return Err(StorageError::TrueCollision {
+                existing_path: storage.root().join(path.content_path()),
+                hash,
+            }
+            .into());
+        }
+
+        // A stored file whose registration is incomplete is archived as if it were new.
+        let registered = recorded.is_some() && db.image_exists(&hash).await?;
+        if registered && !self.upsert {
+            return Err(StorageError::HashCollision {
+                existing_path: storage.root().join(path.content_path()),
+                hash,
+                digest,
+            }
+            .into());
+        }
 
         let result = {
-            let metadata = storage.get_metadata(&hash)?;
+            storage.push_to_backend(&hash).await?;
+            let metadata = ImageMetadata {
+                digest,
+                ..storage.get_metadata(&hash)?
+            };
+
+            let near_duplicates = match (self.duplicate_check, phash) {
+                (Some(max_distance), Some(phash)) => {
+                    similar::near_duplicates(db, &hash, phash, max_distance).await?
+                }
+                _ => vec![],
+            };
 
             db.ensure_image(&hash).await?;
-            db.ensure_image_has_metadata(&hash, &metadata).await?;
+            if let Some(phash) = phash {
+                db.ensure_image_has_phash(&hash, phash).await?;
+            }
+            let metadata = db
+                .ensure_image_has_metadata_returning(&hash, &metadata)
+                .await?;
 
-            if !self.tags.is_empty() {
-                attach_tags(
+            // The file was just stored, so the tags and source are set without
+            // checking its presence again.
+            let tags = if !self.tags.is_empty() {
+                sync_tags(
                     db,
-                    storage,
                     &hash,
                     &self.tags.iter().map(|s| s.as_str()).collect::<Vec<&str>>(),
                 )
-                .await?;
-            }
+                .await?
+            } else {
+                db.get_tags(&hash).await?
+            };
 
-            if let Some(src) = self.source {
-                attach_source(db, storage, &hash, &src).await?;
+            if !sources.is_empty() {
+                db.set_sources(&hash, &sources).await?;
             }
+            let sources = db.get_sources(&hash).await?;
+
+            let rating = match self.rating {
+                Some(rating) => {
+                    db.ensure_image_has_rating(&hash, rating).await?;
+                    Some(rating)
+                }
+                None => db.get_rating(&hash).await?,
+            };
 
-            find_image_by_hash(db, storage, &hash).await
+            let is_public = db.is_public(&hash).await?;
+
+            Ok((
+                Media::new(path, hash.clone(), metadata, tags, None)
+                    .with_sources(sources)
+                    .with_visibility(is_public)
+                    .with_rating(rating),
+                near_duplicates,
+            ))
         };
 
         match result {
-            Ok(ok) => Ok(ok),
+            Ok((ok, near_duplicates)) => {
+                if !self.variants.is_empty() {
+                    let _ = storage.create_variants(&hash, &self.variants);
+                }
+                let mut warnings = self.warning_thresholds.check(&ok, &self.tags);
+                warnings.extend(near_duplicates.into_iter().map(
+                    |NearDuplicate { hash, distance }| ArchiveWarning::NearDuplicate {
+                        hash,
+                        distance,
+                    },
+                ));
+                Ok(ArchiveOutcome {
+                    media: ok,
+                    warnings,
+                })
+            }
+            // An image archived before stays, whatever failed while updating it.
+            Err(e) if registered => Err(e),
             Err(e) => {
                 remove_image(storage, db, hash).await?;
                 Err(e)
@@ -171,7 +546,9 @@ impl ArchiveImageCommand {
 /// Synchronizes the tag state of a given image hash with the provided desired tag list.
 ///
 /// This function computes the difference between current tags in the database and desired tags,
-/// adding or removing tags accordingly using parallel execution.
+/// adding or removing tags accordingly using parallel execution. Aliases among the desired
+/// tags are replaced by the tags they stand for, see `Database::create_tag_alias`, and the
+/// tags they imply are added, see `Database::add_implication`.
 ///
 /// # Arguments
 ///
@@ -182,18 +559,30 @@ impl ArchiveImageCommand {
 ///
 /// # Returns
 ///
-/// Returns a `Result` indicating success or an `AppError` if an error occurred.
+/// Returns a `Result` containing the image's tags after the update in canonical order,
+/// matching `Media::tags` of a subsequent `find_image_by_hash`, or an `AppError` if an
+/// error occurred.
 pub async fn attach_tags(
     db: &Database,
     storage: &Storage,
     hash: &PixelHash,
     tags: &[&str],
-) -> Result<(), AppError> {
+) -> Result<Vec<String>, AppError> {
     if storage.index_file(hash).is_none() {
         return Err(AppError::StorageNotFound { hash: hash.clone() });
     }
 
-    let desired: HashSet<&str> = tags.iter().copied().collect();
+    sync_tags(db, hash, tags).await
+}
+
+/// Synchronizes the tags of an image known to be stored, see `attach_tags`.
+async fn sync_tags(
+    db: &Database,
+    hash: &PixelHash,
+    tags: &[&str],
+) -> Result<Vec<String>, AppError> {
+    let resolved = resolve_tags(db, tags).await?;
+    let desired: HashSet<&str> = resolved.iter().map(String::as_str).collect();
     let current = db.get_tags(hash).await?;
     let current: HashSet<&str> = current.iter().map(|f| f.as_str()).collect();
 
@@ -203,7 +592,18 @@ pub async fn attach_tags(
     db.ensure_image_has_tags(hash, to_add.as_slice()).await?;
     db.ensure_tags_removed(hash, to_remove.as_slice()).await?;
 
-    Ok(())
+    Ok(canonical_tags(desired.into_iter().map(String::from)))
+}
+
+/// Resolves each of the given tags, see `Database::resolve_alias`, and adds the tags
+/// they imply, see `Database::implied_tags`.
+async fn resolve_tags(db: &Database, tags: &[&str]) -> Result<Vec<String>, AppError> {
+    let mut resolved = Vec::with_capacity(tags.len());
+    for tag in tags {
+        resolved.push(db.resolve_alias(tag).await?);
+    }
+    let resolved: Vec<&str> = resolved.iter().map(String::as_str).collect();
+    Ok(db.implied_tags(&resolved).await?)
 }
 
 /// Updates the source information for a specific image in the database.
@@ -234,6 +634,123 @@ pub async fn attach_source(
     Ok(())
 }
 
+/// Updates the source information for a specific image after validating it.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the source update will be applied.
+/// * `storage` - Reference to the storage for ensuring the image file presence.
+/// * `hash` - The hash of the image to be updated.
+/// * `src` - The new source string to associate with the image.
+/// * `policy` - The `SourcePolicy` the source must satisfy.
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success, `AppError::InvalidSource` if the policy
+/// rejects the source, or another `AppError` if the update fails.
+pub async fn attach_source_with_policy(
+    db: &Database,
+    storage: &Storage,
+    hash: &PixelHash,
+    src: &str,
+    policy: &SourcePolicy,
+) -> Result<(), AppError> {
+    policy
+        .check(src)
+        .map_err(|reason| SourcePolicy::invalid(src, reason))?;
+
+    attach_source(db, storage, hash, src).await
+}
+
+/// Updates the source information for many images at once.
+///
+/// The sources are applied in a single transaction, so either all are stored or,
+/// on error, none are.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the source updates will be applied.
+/// * `storage` - Reference to the storage for ensuring the image files' presence.
+/// * `sources` - Pairs of an image hash and the source to associate with it.
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success, or an `AppError` if an image is not in
+/// storage or the update fails.
+pub async fn attach_sources(
+    db: &Database,
+    storage: &Storage,
+    sources: &[(PixelHash, String)],
+) -> Result<(), AppError> {
+    if let Some((hash, _)) = sources
+        .iter()
+        .find(|(h, _)| storage.index_file(h).is_none())
+    {
+        return Err(AppError::StorageNotFound { hash: hash.clone() });
+    }
+
+    db.set_sources_bulk(sources).await?;
+
+    Ok(())
+}
+
+/// Marks or unmarks an image as featured.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the flag will be stored.
+/// * `storage` - Reference to the storage for ensuring the image file presence.
+/// * `hash` - The hash of the image to be updated.
+/// * `featured` - Whether the image is featured.
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success or an `AppError` if an error occurs.
+pub async fn set_featured(
+    db: &Database,
+    storage: &Storage,
+    hash: &PixelHash,
+    featured: bool,
+) -> Result<(), AppError> {
+    if storage.index_file(hash).is_none() {
+        return Err(AppError::StorageNotFound { hash: hash.clone() });
+    }
+
+    db.set_featured(hash, featured).await?;
+
+    Ok(())
+}
+
+/// Makes an image public or private.
+///
+/// Private images are hidden from `query_image` and `count_image` unless the
+/// query includes them.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the flag will be stored.
+/// * `storage` - Reference to the storage for ensuring the image file presence.
+/// * `hash` - The hash of the image to be updated.
+/// * `is_public` - Whether the image is public.
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success or an `AppError` if an error occurs.
+pub async fn set_visibility(
+    db: &Database,
+    storage: &Storage,
+    hash: &PixelHash,
+    is_public: bool,
+) -> Result<(), AppError> {
+    if storage.index_file(hash).is_none() {
+        return Err(AppError::StorageNotFound { hash: hash.clone() });
+    }
+
+    db.set_visibility(hash, is_public).await?;
+
+    Ok(())
+}
+
 /// Completely removes an image from both storage and the database.
 ///
 /// # Arguments
@@ -250,12 +767,61 @@ pub async fn remove_image(
     db: &Database,
     hash: PixelHash,
 ) -> Result<(), AppError> {
-    storage.ensure_deleted(&hash)?;
+    if db.is_read_only() {
+        return Err(DatabaseError::ReadOnly.into());
+    }
+
+    storage.delete(&hash).await?;
     db.ensure_image_removed(&hash).await?;
 
     Ok(())
 }
 
+/// Marks an image as deleted while keeping its file and records, so that it can be
+/// brought back with `restore_image`.
+///
+/// Soft-deleted images are left out of queries unless they are included with
+/// `ImageQuery::including_deleted`, and `find_image_by_hash` fails with
+/// `AppError::ImageDeleted` for them. Deleting an image that is deleted already
+/// succeeds. Use `remove_image` to remove an image for good.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the image is marked.
+/// * `hash` - The hash of the image to delete.
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success, or an `AppError` if the image is not
+/// recorded or the update fails.
+pub async fn soft_delete_image(db: &Database, hash: &PixelHash) -> Result<(), AppError> {
+    if !db.soft_delete_image(hash).await? && !db.image_exists(hash).await? {
+        return Err(AppError::StorageNotFound { hash: hash.clone() });
+    }
+
+    Ok(())
+}
+
+/// Restores an image deleted with `soft_delete_image`. Restoring an image that is
+/// not deleted succeeds.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the image is unmarked.
+/// * `hash` - The hash of the image to restore.
+///
+/// # Returns
+///
+/// Returns a `Result` indicating success, or an `AppError` if the image is not
+/// recorded or the update fails.
+pub async fn restore_image(db: &Database, hash: &PixelHash) -> Result<(), AppError> {
+    if !db.restore_image(hash).await? && !db.image_exists(hash).await? {
+        return Err(AppError::StorageNotFound { hash: hash.clone() });
+    }
+
+    Ok(())
+}
+
 /// Retrieves a full image model by its hash.
 ///
 /// This function loads the file path from storage, retrieves metadata and tags
@@ -269,7 +835,8 @@ pub async fn remove_image(
 ///
 /// # Returns
 ///
-/// Returns a `Result` containing the complete `Image` structure or an `AppError` if retrieval fails.
+/// Returns a `Result` containing the complete `Image` structure or an `AppError` if retrieval
+/// fails. Soft-deleted images fail with `AppError::ImageDeleted`.
 pub async fn find_image_by_hash(
     db: &Database,
     storage: &Storage,
@@ -279,24 +846,38 @@ pub async fn find_image_by_hash(
         .index_file(hash)
         .ok_or_else(|| AppError::StorageNotFound { hash: hash.clone() })?;
 
-    let tags = db.get_tags(hash).await?;
+    if let Some(deleted_at) = db.deleted_at(hash).await? {
+        return Err(AppError::ImageDeleted {
+            hash: hash.clone(),
+            deleted_at,
+        });
+    }
+
+    let categorized_tags = db.get_tags_with_categories(hash).await?;
+    let tags = categorized_tags
+        .iter()
+        .map(|(tag, _)| tag.clone())
+        .collect();
 
     let metadata = db.get_metadata(hash).await?.unwrap_or_default();
 
-    let source = db.get_source(hash).await?;
+    let sources = db.get_sources(hash).await?;
 
-    Ok(Media {
-        path,
-        hash: hash.clone(),
-        tags,
-        metadata,
-        source,
-    })
+    let rating = db.get_rating(hash).await?;
+
+    let is_public = db.is_public(hash).await?;
+
+    Ok(Media::new(path, hash.clone(), metadata, tags, None)
+        .with_sources(sources)
+        .with_visibility(is_public)
+        .with_rating(rating)
+        .with_categorized_tags(categorized_tags))
 }
 
 /// Queries images using a filter and retrieves full `Image` structs for each match.
 ///
-/// Metadata, tags, and source information are loaded in parallel to improve efficiency.
+/// Metadata, tags, and source information are loaded in bulk, with a fixed number of
+/// queries per page rather than several queries per image.
 ///
 /// # Arguments
 ///
@@ -314,32 +895,247 @@ pub async fn query_image(
 ) -> Result<Vec<Media>, AppError> {
     let hashes = db.query_image(query).await?;
 
-    let mut set = JoinSet::new();
-    for hash in hashes.clone() {
-        let db = db.clone();
-        let storage = storage.clone();
-        set.spawn(async move {
-            let image = find_image_by_hash(&db, &storage, &hash).await?;
-            Ok::<(PixelHash, Media), AppError>((hash, image))
-        });
-    }
+    let mut map = hydrate_images(db, storage, hashes.iter().cloned()).await?;
 
-    let mut map = HashMap::new();
-    while let Some(result) = set.join_next().await {
-        match result {
-            Ok(Ok((hash, image))) => {
-                map.insert(hash, image);
-            }
-            Ok(Err(e)) => return Err(e),
-            Err(join_err) => panic!("task panicked in image retrieval: {join_err}"),
+    let images = hashes.into_iter().filter_map(|h| map.remove(&h)).collect();
+
+    Ok(images)
+}
+
+/// A page of results of keyset pagination, see `query_image_page`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PagedResult<T> {
+    /// The results of the page.
+    pub items: Vec<T>,
+    /// The cursor of the following page in the same direction, or `None` if this
+    /// page is the last one. It is formatted into an opaque string for clients.
+    pub next_cursor: Option<Cursor>,
+}
+
+/// Queries a page of images using a cursor instead of an offset.
+///
+/// Pages follow the order of the query, by ascending hash if none is set, with ties
+/// broken by hash. A page before a cursor is in the reverse order. Images archived
+/// or removed between two fetches therefore never shift the following pages. Pass
+/// `next_cursor` to `ImageQuery::after`, or to `ImageQuery::before` when paging
+/// backwards, keeping the order of the query.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the query will be executed.
+/// * `storage` - Reference to the storage system for image file access.
+/// * `query` - An `ImageQuery` with a limit and optionally a cursor.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `PagedResult`, or an `AppError` if the query
+/// fails. Without a limit, the page holds every result and has no next cursor.
+///
+/// # Errors
+/// `AppError::InvalidCursor` if the query is ordered randomly, or if its cursor is
+/// of results in another order.
+pub async fn query_image_page(
+    db: &Database,
+    storage: &Storage,
+    query: ImageQuery,
+) -> Result<PagedResult<Media>, AppError> {
+    let order = query.cursor_order();
+    if order.keys().is_none() {
+        return Err(AppError::InvalidCursor {
+            reason: format!("results ordered by {order:?} cannot be paged with a cursor"),
+        });
+    }
+    query
+        .check_cursor()
+        .map_err(|reason| AppError::InvalidCursor { reason })?;
+
+    let limit = query.limit;
+    let query = query.with_order(order.clone());
+    let hashes = db.query_image(query).await?;
+
+    let next_cursor = match (limit, hashes.last()) {
+        (Some(limit), Some(last)) if hashes.len() >= limit as usize => {
+            db.cursor_of(last, &order).await?
         }
+        _ => None,
+    };
+    let mut map = hydrate_images(db, storage, hashes.iter().cloned()).await?;
+    let items = hashes.into_iter().filter_map(|h| map.remove(&h)).collect();
+
+    Ok(PagedResult { items, next_cursor })
+}
+
+/// A page of results of offset pagination, see `query_image_with_total`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    /// The results of the page.
+    pub items: Vec<T>,
+    /// The number of results matching the query across every page.
+    pub total: u64,
+    /// The maximum number of results of a page, or `None` if the page holds them all.
+    pub limit: Option<u32>,
+    /// The number of results before this page.
+    pub offset: u32,
+    /// Whether results follow this page.
+    pub has_next: bool,
+}
+
+/// Queries a page of images along with the number of images matching the query.
+///
+/// The count runs against the same filter as the page, without its order, limit,
+/// and offset, so the total is the same for every page, whatever the order. The
+/// cursor of keyset pagination is ignored, see `query_image_page` for that.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the query will be executed.
+/// * `storage` - Reference to the storage system for image file access.
+/// * `query` - An `ImageQuery` with optionally a limit and an offset.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `Page`, or an `AppError` if either query fails.
+pub async fn query_image_with_total(
+    db: &Database,
+    storage: &Storage,
+    query: ImageQuery,
+) -> Result<Page<Media>, AppError> {
+    let count_query = ImageQuery {
+        limit: None,
+        offset: None,
+        order: None,
+        cursor: None,
+        ..query.clone()
+    };
+    let (limit, offset) = (query.limit, query.offset.unwrap_or_default());
+    let query = ImageQuery {
+        cursor: None,
+        ..query
+    };
+
+    let (total, hashes) = tokio::try_join!(db.count_image(count_query), db.query_image(query))?;
+
+    let has_next = u64::from(offset) + (hashes.len() as u64) < total;
+    let mut map = hydrate_images(db, storage, hashes.iter().cloned()).await?;
+    let items = hashes.into_iter().filter_map(|h| map.remove(&h)).collect();
+
+    Ok(Page {
+        items,
+        total,
+        limit,
+        offset,
+        has_next,
+    })
+}
+
+/// Controls how `get_images_by_hashes` represents hashes that are not archived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingPolicy {
+    /// Leaves missing hashes out of the result.
+    Skip,
+    /// Keeps a `MediaOrMissing::Missing` entry at the position of each missing hash.
+    Placeholder,
+    /// Fails with `AppError::StorageNotFound` on the first missing hash.
+    Error,
+}
+
+/// An entry in the result of `get_images_by_hashes`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaOrMissing {
+    /// The image was found.
+    Media(Box<Media>),
+    /// No image is archived under this hash, or it is soft-deleted.
+    Missing(PixelHash),
+}
+
+/// Retrieves full `Media` structs for a list of hashes, preserving the input order.
+///
+/// Existence is checked with a single bulk lookup, and every distinct hash is
+/// loaded only once. Duplicate hashes in the input are repeated in the output,
+/// so each input position yields at most one entry. Soft-deleted images are
+/// treated as missing, see `soft_delete_image`.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the images are recorded.
+/// * `storage` - Reference to the storage system for image file access.
+/// * `hashes` - The hashes to retrieve, in the desired output order.
+/// * `missing` - How hashes that are not archived should be represented.
+///
+/// # Returns
+///
+/// Returns a `Result` containing one entry per input hash (fewer with `MissingPolicy::Skip`),
+/// or an `AppError` if retrieval fails or a hash is missing under `MissingPolicy::Error`.
+pub async fn get_images_by_hashes(
+    db: &Database,
+    storage: &Storage,
+    hashes: &[PixelHash],
+    missing: MissingPolicy,
+) -> Result<Vec<MediaOrMissing>, AppError> {
+    let existing = db.filter_existing(hashes).await?;
+
+    if missing == MissingPolicy::Error
+        && let Some(hash) = hashes.iter().find(|h| !existing.contains(h))
+    {
+        return Err(AppError::StorageNotFound { hash: hash.clone() });
     }
 
-    let images = hashes.into_iter().filter_map(|h| map.remove(&h)).collect();
+    let map = hydrate_images(db, storage, existing).await?;
+
+    let images = hashes
+        .iter()
+        .filter_map(|hash| match map.get(hash) {
+            Some(media) => Some(MediaOrMissing::Media(Box::new(media.clone()))),
+            None if missing == MissingPolicy::Placeholder => {
+                Some(MediaOrMissing::Missing(hash.clone()))
+            }
+            None => None,
+        })
+        .collect();
 
     Ok(images)
 }
 
+/// Loads full `Media` structs for the given hashes, keyed by hash.
+///
+/// The records of all hashes are loaded with `Database::get_images_bulk`, so the
+/// number of queries does not grow with the number of hashes.
+async fn hydrate_images(
+    db: &Database,
+    storage: &Storage,
+    hashes: impl IntoIterator<Item = PixelHash>,
+) -> Result<HashMap<PixelHash, Media>, AppError> {
+    let hashes: Vec<PixelHash> = hashes
+        .into_iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let mut records = db.get_images_bulk(&hashes).await?;
+
+    let mut map = HashMap::new();
+    for hash in hashes {
+        let path = storage
+            .index_file(&hash)
+            .ok_or_else(|| AppError::StorageNotFound { hash: hash.clone() })?;
+        let record = records.remove(&hash).unwrap_or_default();
+        let tags = record.tags.iter().map(|(tag, _)| tag.clone()).collect();
+        let media = Media::new(
+            path,
+            hash.clone(),
+            record.metadata.unwrap_or_default(),
+            tags,
+            None,
+        )
+        .with_sources(record.sources)
+        .with_visibility(record.is_public)
+        .with_rating(record.rating)
+        .with_categorized_tags(record.tags);
+        map.insert(hash, media);
+    }
+
+    Ok(map)
+}
+
 /// Counts the number of images matching a given query.
 ///
 /// # Arguments
@@ -406,6 +1202,271 @@ pub async fn query_tags(db: &Database, query: TagQuery) -> Result<Vec<String>, A
     db.query_tags(query).await.map_err(AppError::from)
 }
 
+/// Renames a tag on every image it is associated with.
+///
+/// Renaming into an existing tag merges both tags. Renaming a tag to itself does
+/// nothing. See `Database::rename_tag`.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the tag is renamed.
+/// * `old` - The tag to rename.
+/// * `new` - The name the tag is renamed to.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the tag was renamed, or an `AppError` if the new name is not a
+/// valid tag, the old tag does not exist, or the transaction fails.
+pub async fn rename_tag(db: &Database, old: &str, new: &str) -> Result<(), AppError> {
+    if !is_valid_tag(new) {
+        return Err(AppError::InvalidTag {
+            tag: new.to_string(),
+            reason: INVALID_TAG_REASON.to_string(),
+        });
+    }
+    if old == new {
+        return Ok(());
+    }
+
+    if db.rename_tag(old, new).await? {
+        Ok(())
+    } else {
+        Err(AppError::TagNotFound {
+            tag: old.to_string(),
+        })
+    }
+}
+
+/// Merges a tag into another existing one, see `Database::merge_tags`.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the tags are merged.
+/// * `from` - The tag merged away.
+/// * `to` - The tag that keeps the images of both.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the tags were merged, or an `AppError` if either tag does not
+/// exist or the transaction fails.
+pub async fn merge_tags(db: &Database, from: &str, to: &str) -> Result<(), AppError> {
+    if db.merge_tags(from, to).await? {
+        return Ok(());
+    }
+
+    let query = TagQuery::new(TagQueryKind::Where(TagQueryExpr::Exact(from.to_string())));
+    let tag = if db.query_tags(query).await?.is_empty() {
+        from
+    } else {
+        to
+    };
+    Err(AppError::TagNotFound {
+        tag: tag.to_string(),
+    })
+}
+
+/// Makes a tag an alias of another one, see `Database::create_tag_alias`.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the alias is created.
+/// * `alias` - The tag that becomes an alias.
+/// * `canonical` - The tag the alias stands for.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the alias was created, or an `AppError` if either tag is not
+/// valid, the alias would form a cycle, or the transaction fails.
+pub async fn create_tag_alias(db: &Database, alias: &str, canonical: &str) -> Result<(), AppError> {
+    for tag in [alias, canonical] {
+        if !is_valid_tag(tag) {
+            return Err(AppError::InvalidTag {
+                tag: tag.to_string(),
+                reason: INVALID_TAG_REASON.to_string(),
+            });
+        }
+    }
+
+    if db.create_tag_alias(alias, canonical).await? {
+        Ok(())
+    } else {
+        Err(AppError::InvalidTag {
+            tag: alias.to_string(),
+            reason: format!("{canonical} resolves to {alias}, which would form a cycle"),
+        })
+    }
+}
+
+/// Makes a tag imply another one, see `Database::add_implication`.
+///
+/// Aliases are resolved first, so an implication of an alias holds for the tag it
+/// stands for.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the implication is added.
+/// * `antecedent` - The tag that implies the other one.
+/// * `consequent` - The tag implied by `antecedent`.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if the implication holds, or an `AppError` if either tag is not
+/// valid, the implication would form a cycle, or the transaction fails.
+pub async fn add_tag_implication(
+    db: &Database,
+    antecedent: &str,
+    consequent: &str,
+) -> Result<(), AppError> {
+    for tag in [antecedent, consequent] {
+        if !is_valid_tag(tag) {
+            return Err(AppError::InvalidTag {
+                tag: tag.to_string(),
+                reason: INVALID_TAG_REASON.to_string(),
+            });
+        }
+    }
+
+    let antecedent = db.resolve_alias(antecedent).await?;
+    let consequent = db.resolve_alias(consequent).await?;
+    if db.add_implication(&antecedent, &consequent).await? {
+        Ok(())
+    } else {
+        Err(AppError::InvalidTag {
+            tag: antecedent.clone(),
+            reason: format!("{consequent} implies {antecedent}, which would form a cycle"),
+        })
+    }
+}
+
+/// Executes a tag query against the aliases, see `Database::query_tag_aliases`.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database to execute the query.
+/// * `query` - A `TagQuery` matched against the aliases.
+///
+/// # Returns
+///
+/// Returns a `Result` containing pairs of an alias and the tag it stands for, or an
+/// `AppError`.
+pub async fn query_tag_aliases(
+    db: &Database,
+    query: TagQuery,
+) -> Result<Vec<(String, String)>, AppError> {
+    Ok(db.query_tag_aliases(query).await?)
+}
+
+/// Sets the wiki entry of a tag, see `Database::set_tag_wiki`.
+///
+/// An entry set for an alias describes the tag the alias stands for.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the entry is stored.
+/// * `tag` - The tag the entry describes, created if it does not exist.
+/// * `body` - The description of the tag.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the stored `TagWiki`, or an `AppError` if the tag
+/// is not valid or the update fails.
+pub async fn set_tag_wiki(db: &Database, tag: &str, body: &str) -> Result<TagWiki, AppError> {
+    if !is_valid_tag(tag) {
+        return Err(AppError::InvalidTag {
+            tag: tag.to_string(),
+            reason: INVALID_TAG_REASON.to_string(),
+        });
+    }
+
+    let tag = db.resolve_alias(tag).await?;
+    Ok(db.set_tag_wiki(&tag, body).await?)
+}
+
+/// Retrieves the wiki entry of a tag, or of the tag it is an alias of.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the entry is stored.
+/// * `tag` - The tag whose entry is retrieved.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `TagWiki`, or `None` if the tag has no entry.
+pub async fn get_tag_wiki(db: &Database, tag: &str) -> Result<Option<TagWiki>, AppError> {
+    let tag = db.resolve_alias(tag).await?;
+    Ok(db.get_tag_wiki(&tag).await?)
+}
+
+/// Sets the category of a tag, see `Database::set_tag_category`.
+///
+/// A category set for an alias applies to the tag the alias stands for.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the category is stored.
+/// * `tag` - The tag to categorize, created if it does not exist.
+/// * `category` - The category of the tag.
+///
+/// # Returns
+///
+/// Returns `Ok(())` on success, or an `AppError` if the tag is not valid or the
+/// update fails.
+pub async fn set_tag_category(
+    db: &Database,
+    tag: &str,
+    category: TagCategory,
+) -> Result<(), AppError> {
+    if !is_valid_tag(tag) {
+        return Err(AppError::InvalidTag {
+            tag: tag.to_string(),
+            reason: INVALID_TAG_REASON.to_string(),
+        });
+    }
+
+    let tag = db.resolve_alias(tag).await?;
+    Ok(db.set_tag_category(&tag, category).await?)
+}
+
+/// Why `is_valid_tag` rejects a tag.
+const INVALID_TAG_REASON: &str = "tags must be non-empty and contain no whitespace";
+
+/// Returns whether a tag can be entered, since tags are separated by whitespace
+/// everywhere they are entered.
+fn is_valid_tag(tag: &str) -> bool {
+    !tag.is_empty() && !tag.contains(char::is_whitespace)
+}
+
+/// Describes the features, search syntax, and limits of this deployment.
+///
+/// The database is probed for its server version; a failed probe is reported as
+/// an unknown version rather than an error, so this always succeeds.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database to probe.
+/// * `limits` - The limits enforced by the caller's active configuration.
+///
+/// # Returns
+///
+/// Returns the `Capabilities` of this deployment.
+pub async fn capabilities(db: &Database, limits: &Limits) -> Capabilities {
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        api_flavor: "danbooru".to_string(),
+        api_version: capabilities::API_VERSION,
+        database: DatabaseInfo {
+            backend: db.backend_name().to_string(),
+            server_version: db.server_version().await.ok(),
+        },
+        features: Features::compiled(),
+        search: SearchSyntax {
+            keywords: parser::KEYWORDS.iter().map(|k| k.to_string()).collect(),
+            meta_tokens: parser::meta_tokens(),
+        },
+        limits: limits.clone(),
+    }
+}
+
 /// Represents a complete image with associated metadata, tags, and optional source information.
 ///
 /// This structure holds the file path, hash, metadata, and other attributes required to fully
@@ -418,10 +1479,95 @@ pub struct Media {
     pub hash: PixelHash,
     /// Metadata associated with the image.
     pub metadata: ImageMetadata,
-    /// Tags associated with the image.
+    /// Tags associated with the image, in the canonical order defined by `canonical_tags`:
+    /// ascending by Unicode code point, without duplicates.
     pub tags: Vec<String>,
-    /// An optional source URL indicating where the image came from.
+    /// The first source URL of the image, indicating where it came from.
     pub source: Option<String>,
+    /// Every source URL of the image, in the order they were added.
+    pub sources: Vec<String>,
+    /// Whether the image is public. Private images are hidden from queries by default.
+    pub is_public: bool,
+    /// The content rating of the image, `None` if it is unrated.
+    pub rating: Option<Rating>,
+    /// The tags along with their categories, in the same order as `tags`. `None` if
+    /// the categories were not loaded, e.g. right after archiving.
+    pub categorized_tags: Option<Vec<(String, TagCategory)>>,
+}
+
+impl Media {
+    /// Creates a new public, unrated `Media`, putting the tags into canonical order.
+    pub fn new(
+        path: MediaPath,
+        hash: PixelHash,
+        metadata: ImageMetadata,
+        tags: Vec<String>,
+        source: Option<String>,
+    ) -> Self {
+        Media {
+            path,
+            hash,
+            metadata,
+            tags: canonical_tags(tags),
+            sources: source.iter().cloned().collect(),
+            source,
+            is_public: true,
+            rating: None,
+            categorized_tags: None,
+        }
+    }
+
+    /// Sets the sources of the image, the first of which becomes `source`.
+    pub fn with_sources(mut self, sources: Vec<String>) -> Self {
+        self.source = sources.first().cloned();
+        self.sources = sources;
+        self
+    }
+
+    /// Sets whether the image is public.
+    pub fn with_visibility(mut self, is_public: bool) -> Self {
+        self.is_public = is_public;
+        self
+    }
+
+    /// Sets the content rating of the image.
+    pub fn with_rating(mut self, rating: Option<Rating>) -> Self {
+        self.rating = rating;
+        self
+    }
+
+    /// Sets the tags along with their categories, putting them into canonical order.
+    pub fn with_categorized_tags(mut self, mut tags: Vec<(String, TagCategory)>) -> Self {
+        tags.sort_by(|a, b| a.0.cmp(&b.0));
+        tags.dedup_by(|a, b| a.0 == b.0);
+        self.categorized_tags = Some(tags);
+        self
+    }
+
+    /// Returns the tags of the given category in canonical order.
+    ///
+    /// If the categories were not loaded, every tag is a general tag.
+    pub fn tags_of_category(&self, category: TagCategory) -> Vec<&str> {
+        match &self.categorized_tags {
+            Some(tags) => tags
+                .iter()
+                .filter(|(_, c)| *c == category)
+                .map(|(tag, _)| tag.as_str())
+                .collect(),
+            None if category == TagCategory::General => {
+                self.tags.iter().map(String::as_str).collect()
+            }
+            None => vec![],
+        }
+    }
+
+    /// Returns the tags joined by single spaces in canonical order.
+    ///
+    /// Two reads of the same tag state always yield the same string, so it is
+    /// suitable for ETags and checksums.
+    pub fn canonical_tag_string(&self) -> String {
+        self.tags.join(" ")
+    }
 }
 
 /// Error types within the application, encapsulating storage, database, and other custom errors.
@@ -435,16 +1581,56 @@ pub enum AppError {
 
     #[error("image not found: {hash}")]
     StorageNotFound { hash: PixelHash },
+
+    #[error("image {hash} was deleted at {deleted_at}")]
+    ImageDeleted {
+        hash: PixelHash,
+        deleted_at: DateTime<Utc>,
+    },
+
+    #[error("task for {key} failed: {reason}")]
+    TaskFailed { key: String, reason: String },
+
+    #[error("invalid source {src:?}: {reason}")]
+    InvalidSource { src: String, reason: String },
+
+    #[error("tag not found: {tag}")]
+    TagNotFound { tag: String },
+
+    #[error("invalid tag {tag:?}: {reason}")]
+    InvalidTag { tag: String, reason: String },
+
+    #[error("invalid cursor: {reason}")]
+    InvalidCursor { reason: String },
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        app::{ArchiveImageCommand, attach_tags, find_image_by_hash, query_image, remove_image},
-        database::{Database, MIGRATOR, Pool},
-        query::{ImageQuery, ImageQueryExpr, ImageQueryKind},
-        storage::Storage,
+        app::{
+            AppError, ArchiveImageCommand, ArchiveWarning, Media, MediaOrMissing, MissingPolicy,
+            Page, PagedResult, SourcePolicy, WarningThresholds, add_tag_implication,
+            attach_source_with_policy, attach_sources, attach_tags, capabilities, count_image,
+            create_tag_alias, find_image_by_hash, get_images_by_hashes, get_tag_wiki, merge_tags,
+            query_image, query_image_page, query_image_with_total, remove_image, rename_tag,
+            restore_image, set_tag_category, set_tag_wiki, soft_delete_image,
+        },
+        capabilities::Limits,
+        database::{
+            Database, DatabaseError, ImageRecord, MIGRATOR, Pool, Rating, TagCategory,
+            canonical_tags,
+        },
+        parser,
+        query::{Cursor, ImageQuery, ImageQueryExpr, ImageQueryKind, OrderBy},
+        storage::{
+            MediaPath, MemoryBackend, PixelHash, Storage, StorageError, VariantSize, VariantSpec,
+        },
     };
+    use image::{ImageBuffer, ImageFormat, Rgb};
+    use std::{io, sync::Arc};
     use tempfile::TempDir;
 
     fn get_storage() -> Storage {
@@ -452,6 +1638,15 @@ mod tests {
         Storage::new(tmp_dir.path().to_path_buf())
     }
 
+    /// Encodes a small single-color PNG, so each seed yields a distinct pixel hash.
+    pub(super) fn png_bytes(seed: u8) -> Vec<u8> {
+        let mut bytes = vec![];
+        ImageBuffer::from_pixel(4, 4, Rgb([seed, 0, 0]))
+            .write_to(&mut io::Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
     #[sqlx::test(migrator = "MIGRATOR")]
     async fn test_query(pool: Pool) {
         let db = Database::new(pool);
@@ -472,47 +1667,1201 @@ mod tests {
         dbg!(res);
     }
 
+    /// Loading a page of images takes the same statements, however many images the
+    /// page has.
     #[sqlx::test(migrator = "MIGRATOR")]
-    async fn test_remove_image(pool: Pool) {
+    async fn test_query_image_statements(pool: Pool) {
         let db = Database::new(pool);
         let storage = get_storage();
-        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        for seed in 0..20 {
+            ArchiveImageCommand::new(&png_bytes(seed))
+                .with_tags(["cat".to_string(), format!("seed_{seed}")])
+                .with_source(&format!("https://example.com/{seed}"))
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+        }
+        db.take_attempts();
 
-        let image = ArchiveImageCommand::new(file_bytes)
-            .with_tags(["cat".to_string()])
-            .with_source("https://example.com")
-            .execute(&storage, &db)
-            .await
-            .unwrap();
+        let images = query_image(&db, &storage, ImageQuery::all()).await.unwrap();
+        assert_eq!(20, images.len());
+        assert!(images.iter().all(|image| image.tags.len() == 2));
 
-        remove_image(&storage, &db, image.hash).await.unwrap();
+        // One query for the hashes, and one each for metadata, tags and sources.
+        assert_eq!(
+            vec![
+                "query_image",
+                "get_images_bulk",
+                "get_images_bulk",
+                "get_images_bulk"
+            ],
+            db.take_attempts()
+        );
     }
 
     #[sqlx::test(migrator = "MIGRATOR")]
-    async fn test_attach_tags(pool: Pool) {
+    async fn test_query_image_page(pool: Pool) {
         let db = Database::new(pool);
         let storage = get_storage();
-        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let mut hashes = vec![];
+        for seed in 0..5 {
+            let media = ArchiveImageCommand::new(&png_bytes(seed))
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+            hashes.push(media.hash);
+        }
+        hashes.sort();
 
-        let image = ArchiveImageCommand::new(file_bytes)
-            .with_tags(["cat".to_string(), "scary".to_string()])
-            .with_source("https://example.com")
-            .execute(&storage, &db)
+        let first = query_image_page(&db, &storage, ImageQuery::all().with_limit(2))
             .await
             .unwrap();
+        let page_hashes = |page: &PagedResult<Media>| {
+            page.items
+                .iter()
+                .map(|m| m.hash.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hashes[..2], page_hashes(&first));
+        assert_eq!(Some(Cursor::from(hashes[1].clone())), first.next_cursor);
 
-        let desired = &["cat", "cute"];
-
-        attach_tags(&db, &storage, &image.hash, desired)
+        // An image archived in between does not shift the following pages.
+        ArchiveImageCommand::new(&png_bytes(5))
+            .execute(&storage, &db)
             .await
             .unwrap();
+        let query = ImageQuery::all().with_limit(2).after(hashes[1].clone());
+        let second = query_image_page(&db, &storage, query).await.unwrap();
+        assert!(page_hashes(&second).iter().all(|h| h > &hashes[1]));
+        assert!(page_hashes(&second).windows(2).all(|w| w[0] < w[1]));
 
+        let query = ImageQuery::all().with_limit(3).before(hashes[3].clone());
+        let backwards = query_image_page(&db, &storage, query).await.unwrap();
         assert_eq!(
-            desired.to_vec(),
-            find_image_by_hash(&db, &storage, &image.hash)
-                .await
+            vec![hashes[2].clone(), hashes[1].clone(), hashes[0].clone()],
+            page_hashes(&backwards)
+        );
+        assert_eq!(Some(Cursor::from(hashes[0].clone())), backwards.next_cursor);
+
+        let query = ImageQuery::all().with_limit(10).after(hashes[4].clone());
+        let last = query_image_page(&db, &storage, query).await.unwrap();
+        assert_eq!(None, last.next_cursor);
+
+        // Pages in another order resume from the order keys of their cursor, passed
+        // around as a string.
+        let newest = ImageQuery::all().with_order(OrderBy::CreatedAtDesc);
+        let all = query_image(&db, &storage, newest.clone()).await.unwrap();
+        let mut paged = vec![];
+        let mut cursor: Option<String> = None;
+        loop {
+            let query = match &cursor {
+                Some(cursor) => newest.clone().after(cursor.parse::<Cursor>().unwrap()),
+                None => newest.clone(),
+            };
+            let page = query_image_page(&db, &storage, query.with_limit(4))
+                .await
+                .unwrap();
+            paged.extend(page_hashes(&page));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next.to_string()),
+                None => break,
+            }
+        }
+        assert_eq!(all.into_iter().map(|m| m.hash).collect::<Vec<_>>(), paged);
+
+        let random = ImageQuery::all().with_order(OrderBy::Random).with_limit(2);
+        assert!(matches!(
+            query_image_page(&db, &storage, random).await,
+            Err(AppError::InvalidCursor { .. })
+        ));
+        let mismatched = newest.with_limit(2).after(hashes[0].clone());
+        assert!(matches!(
+            query_image_page(&db, &storage, mismatched).await,
+            Err(AppError::InvalidCursor { .. })
+        ));
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_image_page_without_metadata(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        for seed in 0..3 {
+            ArchiveImageCommand::new(&png_bytes(seed))
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+        }
+        // An image recorded without metadata has no creation date and no file size.
+        let (bare, _) = storage.create_file(&png_bytes(3)).unwrap();
+        db.ensure_image(&bare).await.unwrap();
+
+        for order in [
+            OrderBy::CreatedAtAsc,
+            OrderBy::CreatedAtDesc,
+            OrderBy::FileSizeAsc,
+            OrderBy::FileSizeDesc,
+            OrderBy::FeaturedFirst,
+        ] {
+            let query = ImageQuery::all().with_order(order.clone());
+            let all = query_image(&db, &storage, query.clone())
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|m| m.hash)
+                .collect::<Vec<_>>();
+            assert!(all.contains(&bare));
+
+            let mut paged = vec![];
+            let mut cursor = None;
+            loop {
+                let query = match cursor.take() {
+                    Some(cursor) => query.clone().after(cursor),
+                    None => query.clone(),
+                };
+                let page = query_image_page(&db, &storage, query.with_limit(1))
+                    .await
+                    .unwrap();
+                paged.extend(page.items.into_iter().map(|m| m.hash));
+                match page.next_cursor {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+            assert_eq!(all, paged, "{order:?}");
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_image_with_total(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        for seed in 0..5 {
+            ArchiveImageCommand::new(&png_bytes(seed))
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+        }
+
+        for order in [OrderBy::HashAsc, OrderBy::Random] {
+            let mut seen = vec![];
+            for (offset, has_next) in [(0, true), (2, true), (4, false)] {
+                let query = ImageQuery::all()
+                    .with_order(order.clone())
+                    .with_limit(2)
+                    .with_offset(offset);
+                let page: Page<Media> = query_image_with_total(&db, &storage, query).await.unwrap();
+                assert_eq!(5, page.total);
+                assert_eq!((Some(2), offset), (page.limit, page.offset));
+                assert_eq!(has_next, page.has_next);
+                seen.extend(page.items.into_iter().map(|m| m.hash));
+            }
+            if order == OrderBy::HashAsc {
+                seen.sort();
+                seen.dedup();
+                assert_eq!(5, seen.len());
+            }
+        }
+
+        let page = query_image_with_total(&db, &storage, ImageQuery::all())
+            .await
+            .unwrap();
+        assert_eq!((5, 5, false), (page.items.len(), page.total, page.has_next));
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_with_rating(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        let rated = ArchiveImageCommand::new(&png_bytes(1))
+            .with_rating(Rating::Sensitive)
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(Some(Rating::Sensitive), rated.rating);
+
+        let unrated = ArchiveImageCommand::new(&png_bytes(2))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(None, unrated.rating);
+
+        let found = find_image_by_hash(&db, &storage, &rated.hash)
+            .await
+            .unwrap();
+        assert_eq!(Some(Rating::Sensitive), found.rating);
+        let found = find_image_by_hash(&db, &storage, &unrated.hash)
+            .await
+            .unwrap();
+        assert_eq!(None, found.rating);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_warnings(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        let encode = |size: u32| {
+            let mut bytes = vec![];
+            ImageBuffer::from_fn(size, size, |x, y| Rgb([x as u8, y as u8, 0]))
+                .write_to(&mut io::Cursor::new(&mut bytes), ImageFormat::Png)
+                .unwrap();
+            bytes
+        };
+
+        let tiny = ArchiveImageCommand::new(&encode(16))
+            .with_tags(["cat".to_string(), "a".repeat(100)])
+            .execute_with_warnings(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                ArchiveWarning::TinyImage {
+                    width: 16,
+                    height: 16
+                },
+                ArchiveWarning::LongTag {
+                    tag: "a".repeat(100),
+                    length: 100
+                },
+            ],
+            tiny.warnings
+        );
+
+        let normal = ArchiveImageCommand::new(&encode(256))
+            .with_tags(["cat".to_string()])
+            .execute_with_warnings(&storage, &db)
+            .await
+            .unwrap();
+        assert!(normal.warnings.is_empty());
+        assert_eq!(256, normal.media.metadata.width);
+
+        let lenient = ArchiveImageCommand::new(&encode(17))
+            .with_warning_thresholds(WarningThresholds {
+                min_dimension: 8,
+                ..WarningThresholds::default()
+            })
+            .execute_with_warnings(&storage, &db)
+            .await
+            .unwrap();
+        assert!(lenient.warnings.is_empty());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_soft_delete_image(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        let hash = ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await
+            .unwrap()
+            .hash;
+
+        soft_delete_image(&db, &hash).await.unwrap();
+        soft_delete_image(&db, &hash).await.unwrap();
+
+        assert!(
+            query_image(&db, &storage, ImageQuery::all())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(0, count_image(&db, ImageQuery::all()).await.unwrap());
+        let deleted = query_image(&db, &storage, ImageQuery::all().including_deleted())
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![hash.clone()],
+            deleted.into_iter().map(|m| m.hash).collect::<Vec<_>>()
+        );
+        assert!(matches!(
+            find_image_by_hash(&db, &storage, &hash).await,
+            Err(AppError::ImageDeleted { hash: h, .. }) if h == hash
+        ));
+        // The file is kept.
+        assert!(storage.index_file(&hash).is_some());
+
+        restore_image(&db, &hash).await.unwrap();
+        restore_image(&db, &hash).await.unwrap();
+        assert_eq!(
+            hash,
+            find_image_by_hash(&db, &storage, &hash).await.unwrap().hash
+        );
+        assert_eq!(1, count_image(&db, ImageQuery::all()).await.unwrap());
+
+        let unknown = PixelHash::try_from("0000000000000001").unwrap();
+        assert!(matches!(
+            soft_delete_image(&db, &unknown).await,
+            Err(AppError::StorageNotFound { .. })
+        ));
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_from_reader(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        let streamed = ArchiveImageCommand::from_reader(io::Cursor::new(png_bytes(1)))
+            .with_tags(["cat".into()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(vec!["cat"], streamed.tags);
+
+        let buffered = ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await;
+        assert!(matches!(
+            buffered,
+            Err(AppError::Storage(StorageError::HashCollision { hash, .. })) if hash == streamed.hash
+        ));
+
+        let empty = ArchiveImageCommand::from_reader(tokio::io::empty())
+            .execute(&storage, &db)
+            .await;
+        assert!(matches!(
+            empty,
+            Err(AppError::Storage(StorageError::EmptyInput))
+        ));
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_upsert(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let archived = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["cat".into()])
+            .with_source("https://example.com/1")
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        let rejected = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["dog".into()])
+            .execute(&storage, &db)
+            .await;
+        assert!(matches!(
+            rejected,
+            Err(AppError::Storage(StorageError::HashCollision { hash, .. })) if hash == archived.hash
+        ));
+
+        let updated = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["dog".into()])
+            .with_rating(Rating::Sensitive)
+            .with_upsert(true)
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(archived.hash, updated.hash);
+        assert_eq!(vec!["dog"], updated.tags);
+        assert_eq!(archived.source, updated.source);
+        assert_eq!(Some(Rating::Sensitive), updated.rating);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_true_collision(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let archived = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["cat".into()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        let digest = archived.metadata.digest.clone();
+        assert!(digest.is_some());
+
+        let duplicate = ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await;
+        assert!(matches!(
+            duplicate,
+            Err(AppError::Storage(StorageError::HashCollision { digest: d, .. })) if d == digest
+        ));
+
+        // Record another digest, as if the stored image were a different one whose
+        // pixel hash collides with the upload.
+        let sql = format!(
+            "UPDATE image_metadatas SET digest = '{}' WHERE image_hash = '{}'",
+            "0".repeat(32),
+            archived.hash
+        );
+        sqlx::raw_sql(&sql).execute(&db.pool).await.unwrap();
+
+        for upsert in [false, true] {
+            let result = ArchiveImageCommand::new(&png_bytes(1))
+                .with_tags(["dog".into()])
+                .with_upsert(upsert)
+                .execute(&storage, &db)
+                .await;
+            assert!(matches!(
+                result,
+                Err(AppError::Storage(StorageError::TrueCollision { hash, .. })) if hash == archived.hash
+            ));
+        }
+
+        // The archived image is left as it was.
+        let stored = find_image_by_hash(&db, &storage, &archived.hash)
+            .await
+            .unwrap();
+        assert_eq!(vec!["cat"], stored.tags);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_source_policy(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        let media = ArchiveImageCommand::new(&png_bytes(1))
+            .with_source("https://example.com/posts/1")
+            .with_source_policy(SourcePolicy::web())
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("https://example.com/posts/1".to_string()),
+            media.source
+        );
+
+        // A rejected source stores nothing.
+        let result = ArchiveImageCommand::new(&png_bytes(2))
+            .with_source("javascript:alert(1)")
+            .with_source_policy(SourcePolicy::web())
+            .execute(&storage, &db)
+            .await;
+        assert!(matches!(result, Err(AppError::InvalidSource { .. })));
+        assert_eq!(1, storage.list_hashes().unwrap().len());
+
+        let result = attach_source_with_policy(
+            &db,
+            &storage,
+            &media.hash,
+            "ftp://example.com/a.png",
+            &SourcePolicy::web(),
+        )
+        .await;
+        assert!(matches!(result, Err(AppError::InvalidSource { .. })));
+        assert_eq!(
+            Some("https://example.com/posts/1".to_string()),
+            db.get_source(&media.hash).await.unwrap()
+        );
+    }
+
+    #[cfg(feature = "webp")]
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_with_transcode(pool: Pool) {
+        use crate::storage::{TranscodeConfig, TranscodeFormat};
+
+        let db = Database::new(pool);
+        let storage = get_storage();
+        let config = TranscodeConfig {
+            format: TranscodeFormat::WebP,
+            quality: 80,
+        };
+
+        let media = ArchiveImageCommand::new(&png_bytes(1))
+            .with_transcode(config)
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!("webp", media.metadata.format);
+
+        // The original of a transcoded image is its duplicate.
+        let (hash, created) = storage.create_or_get(&png_bytes(1)).unwrap();
+        assert_eq!((media.hash, false), (hash, created));
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_with_sources(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let media = ArchiveImageCommand::new(&png_bytes(1))
+            .with_source("https://pixiv.net/1")
+            .with_sources(vec![
+                "https://x.com/1".to_string(),
+                "https://pixiv.net/1".to_string(),
+            ])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(Some("https://pixiv.net/1".to_string()), media.source);
+        assert_eq!(
+            vec!["https://pixiv.net/1", "https://x.com/1"],
+            media.sources
+        );
+
+        let found = find_image_by_hash(&db, &storage, &media.hash)
+            .await
+            .unwrap();
+        assert_eq!(media.sources, found.sources);
+
+        // Every source is validated before anything is stored.
+        let result = ArchiveImageCommand::new(&png_bytes(2))
+            .with_sources(vec![
+                "https://x.com/2".to_string(),
+                "javascript:alert(1)".to_string(),
+            ])
+            .with_source_policy(SourcePolicy::web())
+            .execute(&storage, &db)
+            .await;
+        assert!(matches!(result, Err(AppError::InvalidSource { .. })));
+        assert_eq!(1, storage.list_hashes().unwrap().len());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_with_variants(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        let variants = vec![
+            VariantSpec::Box(VariantSize::new(180, 180)),
+            VariantSpec::Scale(50),
+        ];
+        let bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+
+        let media = ArchiveImageCommand::new(bytes)
+            .with_variants(variants.clone())
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(variants, storage.list_variants(&media.hash).unwrap());
+
+        // Archiving again keeps the variants that exist.
+        let preview = storage
+            .root()
+            .join(storage.variant_path(&media.hash, variants[0]).unwrap());
+        std::fs::write(&preview, b"cached").unwrap();
+        ArchiveImageCommand::new(bytes)
+            .with_upsert(true)
+            .with_variants(variants.clone())
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(b"cached".to_vec(), std::fs::read(&preview).unwrap());
+        assert_eq!(variants, storage.list_variants(&media.hash).unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_attach_sources(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let mut sources = vec![];
+        for seed in 1..=3 {
+            let media = ArchiveImageCommand::new(&png_bytes(seed))
+                .with_source("https://example.com")
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+            sources.push((media.hash, format!("https://example.com/{seed}")));
+        }
+
+        attach_sources(&db, &storage, &sources).await.unwrap();
+
+        for (hash, source) in &sources {
+            let media = find_image_by_hash(&db, &storage, hash).await.unwrap();
+            assert_eq!(Some(source.clone()), media.source);
+        }
+
+        let missing = PixelHash::try_from("0000000000000000").unwrap();
+        let result = attach_sources(
+            &db,
+            &storage,
+            &[
+                (sources[0].0.clone(), "https://example.org".to_string()),
+                (missing, "https://example.org".to_string()),
+            ],
+        )
+        .await;
+
+        assert!(matches!(result, Err(AppError::StorageNotFound { .. })));
+        assert_eq!(
+            Some(sources[0].1.clone()),
+            db.get_source(&sources[0].0).await.unwrap()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_remove_image(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+
+        let image = ArchiveImageCommand::new(file_bytes)
+            .with_tags(["cat".to_string()])
+            .with_source("https://example.com")
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        remove_image(&storage, &db, image.hash).await.unwrap();
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_memory_backend_round_trip(pool: Pool) {
+        let db = Database::new(pool);
+        let backend = Arc::new(MemoryBackend::new());
+        let storage = get_storage().with_backend(backend.clone());
+
+        let image = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["cat".to_string()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        let MediaPath::Image(path) = &image.path else {
+            panic!("expected an image");
+        };
+        let key = path.to_str().unwrap().replace('\\', "/");
+        assert_eq!(1, backend.len());
+
+        let query = ImageQuery::new(ImageQueryKind::Where(ImageQueryExpr::tag("cat")));
+        let found = query_image(&db, &storage, query).await.unwrap();
+        assert_eq!(
+            vec![image.hash.clone()],
+            found.iter().map(|m| m.hash.clone()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            std::fs::read(storage.root().join(path)).unwrap(),
+            storage.backend().read(&key).await.unwrap()
+        );
+
+        remove_image(&storage, &db, image.hash.clone())
+            .await
+            .unwrap();
+        assert!(backend.is_empty());
+        assert!(storage.index_file(&image.hash).is_none());
+        let query = ImageQuery::new(ImageQueryKind::Where(ImageQueryExpr::tag("cat")));
+        assert!(query_image(&db, &storage, query).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_read_only(pool: Pool) {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let db = Database::new(pool);
+        let archived = ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        let db = db.with_read_only(true);
+
+        let result = ArchiveImageCommand::new(&png_bytes(2))
+            .execute(&storage, &db)
+            .await;
+        assert!(matches!(
+            result,
+            Err(AppError::Database(DatabaseError::ReadOnly))
+        ));
+        assert_eq!(
+            1,
+            glob::glob(&tmp_dir.path().join("**/*.*").to_string_lossy())
+                .unwrap()
+                .count()
+        );
+
+        let result = remove_image(&storage, &db, archived.hash.clone()).await;
+        assert!(matches!(
+            result,
+            Err(AppError::Database(DatabaseError::ReadOnly))
+        ));
+        assert!(storage.index_file(&archived.hash).is_some());
+        assert!(
+            find_image_by_hash(&db, &storage, &archived.hash)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_attach_tags(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+
+        let image = ArchiveImageCommand::new(file_bytes)
+            .with_tags(["cat".to_string(), "scary".to_string()])
+            .with_source("https://example.com")
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        let desired = &["cat", "cute"];
+
+        attach_tags(&db, &storage, &image.hash, desired)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            desired.to_vec(),
+            find_image_by_hash(&db, &storage, &image.hash)
+                .await
+                .unwrap()
+                .tags
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_rename_tag(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let image = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["cat".to_string(), "scary".to_string()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        for invalid in ["", "black cat"] {
+            assert!(matches!(
+                rename_tag(&db, "cat", invalid).await,
+                Err(AppError::InvalidTag { .. })
+            ));
+        }
+        assert!(matches!(
+            rename_tag(&db, "dog", "puppy").await,
+            Err(AppError::TagNotFound { tag }) if tag == "dog"
+        ));
+
+        rename_tag(&db, "cat", "cat").await.unwrap();
+        rename_tag(&db, "cat", "kitten").await.unwrap();
+        assert_eq!(
+            vec!["kitten", "scary"],
+            find_image_by_hash(&db, &storage, &image.hash)
+                .await
+                .unwrap()
+                .tags
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_merge_tags(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let image = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["cat".to_string(), "kitten".to_string()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            merge_tags(&db, "dog", "cat").await,
+            Err(AppError::TagNotFound { tag }) if tag == "dog"
+        ));
+        assert!(matches!(
+            merge_tags(&db, "cat", "puppy").await,
+            Err(AppError::TagNotFound { tag }) if tag == "puppy"
+        ));
+
+        merge_tags(&db, "cat", "cat").await.unwrap();
+        assert_eq!(
+            vec!["cat", "kitten"],
+            find_image_by_hash(&db, &storage, &image.hash)
+                .await
                 .unwrap()
                 .tags
         );
+
+        merge_tags(&db, "cat", "kitten").await.unwrap();
+        assert_eq!(
+            vec!["kitten"],
+            find_image_by_hash(&db, &storage, &image.hash)
+                .await
+                .unwrap()
+                .tags
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_create_tag_alias(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        create_tag_alias(&db, "kitty", "cat").await.unwrap();
+        assert!(matches!(
+            create_tag_alias(&db, "cat", "kitty").await,
+            Err(AppError::InvalidTag { tag, .. }) if tag == "cat"
+        ));
+        assert!(matches!(
+            create_tag_alias(&db, "big cat", "cat").await,
+            Err(AppError::InvalidTag { .. })
+        ));
+
+        let image = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["kitty".to_string(), "cat".to_string()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(vec!["cat"], image.tags);
+
+        let tags = attach_tags(&db, &storage, &image.hash, &["kitty", "scary"])
+            .await
+            .unwrap();
+        assert_eq!(vec!["cat", "scary"], tags);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_add_tag_implication(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        create_tag_alias(&db, "kitty", "cat").await.unwrap();
+        add_tag_implication(&db, "kitty", "animal").await.unwrap();
+        add_tag_implication(&db, "animal", "creature")
+            .await
+            .unwrap();
+        assert!(matches!(
+            add_tag_implication(&db, "creature", "cat").await,
+            Err(AppError::InvalidTag { tag, .. }) if tag == "creature"
+        ));
+
+        let image = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["kitty".to_string()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(vec!["animal", "cat", "creature"], image.tags);
+
+        let tags = attach_tags(&db, &storage, &image.hash, &["cat", "scary"])
+            .await
+            .unwrap();
+        assert_eq!(vec!["animal", "cat", "creature", "scary"], tags);
+        assert_eq!(tags, db.get_tags(&image.hash).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_tag_wiki(pool: Pool) {
+        let db = Database::new(pool);
+
+        assert!(matches!(
+            set_tag_wiki(&db, "", "Nothing").await,
+            Err(AppError::InvalidTag { .. })
+        ));
+
+        create_tag_alias(&db, "kitty", "cat").await.unwrap();
+        let wiki = set_tag_wiki(&db, "kitty", "A small feline.").await.unwrap();
+        assert_eq!("cat", wiki.tag);
+        assert_eq!(Some(wiki), get_tag_wiki(&db, "cat").await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_tag_category(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        assert!(matches!(
+            set_tag_category(&db, "", TagCategory::Artist).await,
+            Err(AppError::InvalidTag { .. })
+        ));
+
+        let archived = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["alice".into(), "cat".into()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        // Categories are not loaded when archiving, so every tag is a general tag.
+        assert_eq!(None, archived.categorized_tags);
+        assert_eq!(
+            vec!["alice", "cat"],
+            archived.tags_of_category(TagCategory::General)
+        );
+
+        create_tag_alias(&db, "by_alice", "alice").await.unwrap();
+        set_tag_category(&db, "by_alice", TagCategory::Artist)
+            .await
+            .unwrap();
+
+        let found = find_image_by_hash(&db, &storage, &archived.hash)
+            .await
+            .unwrap();
+        assert_eq!(vec!["alice", "cat"], found.tags);
+        assert_eq!(vec!["alice"], found.tags_of_category(TagCategory::Artist));
+        assert_eq!(vec!["cat"], found.tags_of_category(TagCategory::General));
+        assert!(found.tags_of_category(TagCategory::Meta).is_empty());
+    }
+
+    /// Shuffles `items` with a small linear congruential generator, so permutations
+    /// are random but reproducible.
+    fn shuffle<T>(items: &mut [T], seed: u64) {
+        let mut state = seed;
+        for i in (1..items.len()).rev() {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            items.swap(i, (state >> 33) as usize % (i + 1));
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_canonical_tag_order(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        let tags = [
+            "zebra", "Apple", "apple", "éclair", "日本", "_meta", "10", "9",
+        ];
+        let expected = canonical_tags(tags.iter().map(|t| t.to_string()));
+        assert_eq!(
+            vec![
+                "10", "9", "Apple", "_meta", "apple", "zebra", "éclair", "日本"
+            ],
+            expected
+        );
+
+        let mut hashes = vec![];
+        for seed in 0..8 {
+            let image = ArchiveImageCommand::new(&png_bytes(seed))
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+
+            let mut permuted = tags.to_vec();
+            shuffle(&mut permuted, seed as u64);
+            for i in 1..=permuted.len() {
+                attach_tags(&db, &storage, &image.hash, &permuted[..i])
+                    .await
+                    .unwrap();
+            }
+            hashes.push(image.hash);
+        }
+
+        let first = find_image_by_hash(&db, &storage, &hashes[0]).await.unwrap();
+        for hash in &hashes {
+            let media = find_image_by_hash(&db, &storage, hash).await.unwrap();
+            assert_eq!(expected, media.tags);
+            assert_eq!(first.canonical_tag_string(), media.canonical_tag_string());
+
+            let reread = find_image_by_hash(&db, &storage, hash).await.unwrap();
+            assert_eq!(media.canonical_tag_string(), reread.canonical_tag_string());
+        }
+
+        for tags in db.tags_for_images(&hashes).await.unwrap().into_values() {
+            assert_eq!(expected, tags);
+        }
+        assert_eq!(expected, db.common_tags(&hashes).await.unwrap());
+        assert_eq!(expected, db.all_tags(&hashes).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_attach_tags_matches_find(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let image = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["b".to_string(), "a".to_string()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(vec!["a", "b"], image.tags);
+
+        for desired in [
+            &["c", "a", "c", "B"][..],
+            &["a"][..],
+            &["日本", "Z", "a", "z"][..],
+            &[][..],
+        ] {
+            let attached = attach_tags(&db, &storage, &image.hash, desired)
+                .await
+                .unwrap();
+            let found = find_image_by_hash(&db, &storage, &image.hash)
+                .await
+                .unwrap();
+
+            assert_eq!(attached, found.tags);
+            assert_eq!(attached.join(" "), found.canonical_tag_string());
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_get_images_by_hashes(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let mut archived = vec![];
+        for seed in 0..3 {
+            let image = ArchiveImageCommand::new(&png_bytes(seed))
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+            archived.push(image.hash);
+        }
+        let absent = PixelHash::try_from("0000000000000000").unwrap();
+
+        let hashes = vec![
+            archived[2].clone(),
+            absent.clone(),
+            archived[0].clone(),
+            archived[2].clone(),
+            archived[1].clone(),
+        ];
+
+        let mut individual = vec![];
+        for hash in hashes.iter() {
+            individual.push(match find_image_by_hash(&db, &storage, hash).await {
+                Ok(media) => MediaOrMissing::Media(Box::new(media)),
+                Err(_) => MediaOrMissing::Missing(hash.clone()),
+            });
+        }
+
+        let placeholder = get_images_by_hashes(&db, &storage, &hashes, MissingPolicy::Placeholder)
+            .await
+            .unwrap();
+        assert_eq!(individual, placeholder);
+
+        let skipped = get_images_by_hashes(&db, &storage, &hashes, MissingPolicy::Skip)
+            .await
+            .unwrap();
+        individual.remove(1);
+        assert_eq!(individual, skipped);
+
+        let result = get_images_by_hashes(&db, &storage, &hashes, MissingPolicy::Error).await;
+        let Err(AppError::StorageNotFound { hash }) = result else {
+            panic!("Expected StorageNotFound error, but got {:?}", result);
+        };
+        assert_eq!(absent, hash);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_get_images_by_hashes_deleted(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+        let kept = ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        let deleted = ArchiveImageCommand::new(&png_bytes(2))
+            .execute(&storage, &db)
+            .await
+            .unwrap()
+            .hash;
+        soft_delete_image(&db, &deleted).await.unwrap();
+
+        let hashes = vec![deleted.clone(), kept.hash.clone()];
+        assert_eq!(
+            vec![
+                MediaOrMissing::Missing(deleted.clone()),
+                MediaOrMissing::Media(Box::new(
+                    find_image_by_hash(&db, &storage, &kept.hash).await.unwrap()
+                )),
+            ],
+            get_images_by_hashes(&db, &storage, &hashes, MissingPolicy::Placeholder)
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            get_images_by_hashes(&db, &storage, &hashes, MissingPolicy::Error).await,
+            Err(AppError::StorageNotFound { hash }) if hash == deleted
+        ));
+
+        restore_image(&db, &deleted).await.unwrap();
+        assert_eq!(
+            2,
+            get_images_by_hashes(&db, &storage, &hashes, MissingPolicy::Skip)
+                .await
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_image_matches_find_image_by_hash(pool: Pool) {
+        let db = Database::new(pool);
+        let storage = get_storage();
+
+        let mut hashes = vec![];
+        for seed in 0..4 {
+            let image = ArchiveImageCommand::new(&png_bytes(seed))
+                .with_tags(vec!["cat".to_string(), format!("tag{seed}")])
+                .with_sources(
+                    (0..seed)
+                        .map(|i| format!("https://example.com/{seed}/{i}"))
+                        .collect(),
+                )
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+            hashes.push(image.hash);
+        }
+        db.set_tag_category("tag1", TagCategory::Artist)
+            .await
+            .unwrap();
+        db.ensure_image_has_rating(&hashes[2], Rating::Explicit)
+            .await
+            .unwrap();
+        db.set_visibility(&hashes[3], false).await.unwrap();
+
+        let mut query = ImageQuery::new(ImageQueryKind::Where(ImageQueryExpr::tag("cat")));
+        query.include_private = true;
+        let bulk = query_image(&db, &storage, query).await.unwrap();
+
+        let mut individual = vec![];
+        for media in &bulk {
+            individual.push(
+                find_image_by_hash(&db, &storage, &media.hash)
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(4, bulk.len());
+        assert_eq!(individual, bulk);
+
+        // Hashes that are not recorded get the defaults of a new image.
+        let absent = PixelHash::try_from("0000000000000000").unwrap();
+        let records = db
+            .get_images_bulk(std::slice::from_ref(&absent))
+            .await
+            .unwrap();
+        assert_eq!(Some(&ImageRecord::default()), records.get(&absent));
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_capabilities_limits(pool: Pool) {
+        let db = Database::new(pool);
+        let limits = Limits {
+            max_upload_bytes: Some(1024),
+            max_page_size: Some(50),
+            max_image_decodes: Some(3),
+            max_video_thumbnails: Some(1),
+            decode_queue_depth: None,
+        };
+
+        let caps = capabilities(&db, &limits).await;
+
+        assert_eq!(limits, caps.limits);
+        assert_eq!(parser::meta_tokens(), caps.search.meta_tokens);
+        assert!(caps.database.server_version.is_some());
+    }
+
+    #[cfg(all(feature = "sqlite", not(any(feature = "postgres", feature = "mysql"))))]
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_capabilities_snapshot(pool: Pool) {
+        let db = Database::new(pool);
+
+        let mut caps = capabilities(&db, &Limits::default()).await;
+        caps.database.server_version = Some("3".to_string());
+
+        assert_eq!(
+            format!(
+                concat!(
+                    r#"{{"version":"{}","api_flavor":"danbooru","api_version":1,"#,
+                    r#""database":{{"backend":"sqlite","server_version":"3"}},"#,
+                    r#""features":{{"video":true,"full_text_search":false,"regex_tags":false,"signed_urls":false}},"#,
+                    r#""search":{{"keywords":["AND","OR","XOR","NOT"],"#,
+                    r#""meta_tokens":["date >=","date <=","age:<","age:>","is:animated","is:photo","is:lossless","is:featured","is:private","#,
+                    r#""rating:general","rating:sensitive","rating:questionable","rating:explicit","#,
+                    r#""width:>=","width:<=","width:>","width:<","width:=","#,
+                    r#""height:>=","height:<=","height:>","height:<","height:=","#,
+                    r#""filesize:>=","filesize:<=","filesize:>","filesize:<","filesize:=","#,
+                    r#""sources:>=","sources:<=","sources:>","sources:<","sources:=","ratio:"]}},"#,
+                    r#""limits":{{"max_upload_bytes":null,"max_page_size":null,"max_image_decodes":null,"#,
+                    r#""max_video_thumbnails":null,"decode_queue_depth":null}}}}"#,
+                ),
+                env!("CARGO_PKG_VERSION")
+            ),
+            serde_json::to_string(&caps).unwrap()
+        );
     }
 }
@@ -0,0 +1,211 @@
+//! Archival of many files in one call.
+//!
+//! Archiving files one `ArchiveImageCommand` at a time ensures and associates the
+//! tags of every file in transactions of their own, although most of them are
+//! usually shared. `ArchiveImagesCommand` ensures the union of the tags once, and
+//! associates the tags of all archived files in a single transaction.
+
+use super::{AppError, ArchiveImageCommand, Media, attach_tags, remove_image, resolve_tags};
+use crate::{
+    database::{Database, canonical_tags},
+    storage::{PixelHash, Storage},
+};
+use std::collections::{BTreeSet, HashSet};
+
+/// Represents a command for archiving many images at once.
+///
+/// Each image is described by an `ArchiveImageCommand`, so tags, sources and ratings
+/// are set per image as usual.
+pub struct ArchiveImagesCommand {
+    /// The images to archive, in order.
+    pub commands: Vec<ArchiveImageCommand>,
+}
+
+impl ArchiveImagesCommand {
+    /// Creates a new `ArchiveImagesCommand` for the given images.
+    ///
+    /// # Arguments
+    ///
+    /// * `commands` - An iterator over the commands describing each image.
+    pub fn new<T: IntoIterator<Item = ArchiveImageCommand>>(commands: T) -> Self {
+        ArchiveImagesCommand {
+            commands: commands.into_iter().collect(),
+        }
+    }
+
+    /// Executes the archival process for every image.
+    ///
+    /// The images are stored and recorded one after another, as `ArchiveImageCommand`
+    /// would, but without their tags. Then the union of the tags is ensured once, and
+    /// the tags of every archived image are associated in a single transaction. If
+    /// that fails, the tags are attached image by image instead.
+    ///
+    /// An image failing at any step is removed again, and does not affect the others:
+    /// images archived successfully stay archived, whatever happens to later ones.
+    /// Images of commands with `upsert` set are not removed, since they may have been
+    /// archived before.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - Reference to the storage system where the images will be stored.
+    /// * `db` - Reference to the database where metadata will be recorded.
+    ///
+    /// # Returns
+    ///
+    /// Returns the result of each image, in the order of the commands.
+    pub async fn execute(self, storage: &Storage, db: &Database) -> Vec<Result<Media, AppError>> {
+        let mut results = Vec::with_capacity(self.commands.len());
+        let mut pending = vec![];
+
+        for mut command in self.commands {
+            let tags = std::mem::take(&mut command.tags);
+            let tags = match resolve_tags(db, &tags.iter().map(String::as_str).collect::<Vec<_>>())
+                .await
+            {
+                Ok(tags) => canonical_tags(tags),
+                Err(e) => {
+                    results.push(Err(e));
+                    continue;
+                }
+            };
+            let upsert = command.upsert;
+            let result = command.execute(storage, db).await;
+            if result.is_ok() && !tags.is_empty() {
+                pending.push((results.len(), tags, upsert));
+            }
+            results.push(result);
+        }
+
+        // Images recovered from an incomplete registration may carry tags already,
+        // which are replaced as `ArchiveImageCommand` does.
+        let mut entries = Vec::with_capacity(pending.len());
+        for (index, tags, upsert) in pending {
+            let Ok(media) = &results[index] else {
+                continue;
+            };
+            let hash = media.hash.clone();
+            let desired: HashSet<&str> = tags.iter().map(String::as_str).collect();
+            let stale: Vec<&str> = media
+                .tags
+                .iter()
+                .map(String::as_str)
+                .filter(|tag| !desired.contains(tag))
+                .collect();
+
+            if !stale.is_empty()
+                && let Err(e) = db.ensure_tags_removed(&hash, &stale).await
+            {
+                results[index] = Err(discard(storage, db, hash, upsert, e.into()).await);
+                continue;
+            }
+            entries.push((index, hash, tags, upsert));
+        }
+        if entries.is_empty() {
+            return results;
+        }
+
+        let union: BTreeSet<&str> = entries
+            .iter()
+            .flat_map(|(_, _, tags, _)| tags.iter().map(String::as_str))
+            .collect();
+        let bulk: Vec<(PixelHash, Vec<String>)> = entries
+            .iter()
+            .map(|(_, hash, tags, _)| (hash.clone(), tags.clone()))
+            .collect();
+        let batched = match db.ensure_tags(&union.into_iter().collect::<Vec<_>>()).await {
+            Ok(()) => db.add_tags_bulk(&bulk).await,
+            Err(e) => Err(e),
+        };
+
+        for (index, hash, tags, upsert) in entries {
+            let attached = if batched.is_ok() {
+                Ok(tags)
+            } else {
+                let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+                attach_tags(db, storage, &hash, &tags).await
+            };
+
+            match (attached, &mut results[index]) {
+                (Ok(tags), Ok(media)) => media.tags = tags,
+                (Err(e), result) => *result = Err(discard(storage, db, hash, upsert, e).await),
+                (Ok(_), Err(_)) => {}
+            }
+        }
+
+        results
+    }
+}
+
+/// Removes an image that failed to archive, returning the error to report for it.
+///
+/// Images of upserting commands are kept, see `ArchiveImagesCommand::execute`.
+async fn discard(
+    storage: &Storage,
+    db: &Database,
+    hash: PixelHash,
+    upsert: bool,
+    e: AppError,
+) -> AppError {
+    if upsert {
+        return e;
+    }
+
+    match remove_image(storage, db, hash).await {
+        Ok(()) => e,
+        Err(removal) => removal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArchiveImagesCommand;
+    use crate::{
+        app::{AppError, ArchiveImageCommand, find_image_by_hash, tests::png_bytes},
+        database::{Database, MIGRATOR, Pool},
+        storage::{Storage, StorageError},
+    };
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_images(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        let results = ArchiveImagesCommand::new([
+            ArchiveImageCommand::new(&png_bytes(1)).with_tags(["cat".into(), "red".into()]),
+            ArchiveImageCommand::new(b"not an image"),
+            ArchiveImageCommand::new(&png_bytes(2))
+                .with_tags(["cat".into(), "blue".into()])
+                .with_source("https://example.com/2"),
+            ArchiveImageCommand::new(&png_bytes(1)).with_tags(["dog".into()]),
+        ])
+        .execute(&storage, &db)
+        .await;
+
+        assert_eq!(4, results.len());
+        let first = results[0].as_ref().unwrap();
+        assert_eq!(vec!["cat", "red"], first.tags);
+        assert!(matches!(
+            &results[1],
+            Err(AppError::Storage(StorageError::UnsupportedFile { .. }))
+        ));
+        let second = results[2].as_ref().unwrap();
+        assert_eq!(vec!["blue", "cat"], second.tags);
+        assert_eq!(Some("https://example.com/2"), second.source.as_deref());
+        assert!(matches!(
+            &results[3],
+            Err(AppError::Storage(StorageError::HashCollision { .. }))
+        ));
+
+        // Images archived before a failure stay archived, with their tags intact.
+        let stored = find_image_by_hash(&db, &storage, &first.hash)
+            .await
+            .unwrap();
+        assert_eq!(first.tags, stored.tags);
+        let stored = find_image_by_hash(&db, &storage, &second.hash)
+            .await
+            .unwrap();
+        assert_eq!(second.tags, stored.tags);
+    }
+}
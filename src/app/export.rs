@@ -0,0 +1,249 @@
+//! Export of archive metadata in interchange formats.
+//!
+//! `export_tags_csv` lists the tags of an archive. `export_jsonl` writes a full
+//! backup, one `ArchiveRecord` per line, which `restore` reads back.
+
+use super::AppError;
+use crate::{
+    database::Database,
+    query::{ImageQuery, ImageQueryExpr},
+    storage::Storage,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    io::{self, Write},
+    ops::ControlFlow,
+    path::PathBuf,
+};
+
+/// Writes every tag with its image count to `writer` as CSV, ordered by name.
+///
+/// The output starts with a `name,count` header. Counts are those of the last
+/// `Database::refresh_image_count`. Tags are streamed from the database and
+/// written one row at a time, so large archives are not held in memory.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database to read the tags from.
+/// * `writer` - The destination of the CSV, e.g. a file or stdout.
+///
+/// # Returns
+///
+/// Returns `Ok(())` once every tag is written, or an `AppError` if the query or a
+/// write fails.
+pub async fn export_tags_csv<W: Write>(db: &Database, mut writer: W) -> Result<(), AppError> {
+    writeln!(writer, "name,count")?;
+    let written = db
+        .for_each_tag_count(
+            |name, count| match writeln!(writer, "{},{}", csv_field(name), count) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(e) => ControlFlow::Break(e),
+            },
+        )
+        .await?;
+    if let ControlFlow::Break(e) = written {
+        return Err(e.into());
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// How `export_jsonl` references the file of each image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileReference {
+    /// The absolute path of the stored file, which must still exist when restoring.
+    #[default]
+    Path,
+    /// The bytes of the stored file, base64 encoded, for self-contained backups.
+    Inline,
+}
+
+/// The file of an `ArchiveRecord`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordFile {
+    /// The path of the file, relative paths being resolved by `restore`.
+    Path(PathBuf),
+    /// The bytes of the file, base64 encoded.
+    Data(String),
+}
+
+/// One image of a JSONL export, as written by `export_jsonl` and read by `restore`.
+///
+/// Tag categories, aliases and wikis belong to tags rather than images, and are not
+/// part of the records.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveRecord {
+    /// The pixel hash the image is stored under.
+    pub hash: String,
+    /// The stored file of the image.
+    pub file: RecordFile,
+    /// The tags of the image, in canonical order.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The sources of the image, in the order they were added.
+    #[serde(default)]
+    pub sources: Vec<String>,
+    /// The content rating of the image, as returned by `Rating::as_str`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<String>,
+    /// Whether the image is public.
+    #[serde(default = "public")]
+    pub is_public: bool,
+    /// Whether the image is featured.
+    #[serde(default)]
+    pub is_featured: bool,
+}
+
+fn public() -> bool {
+    true
+}
+
+/// Writes every archived image to `writer` as JSON Lines, one `ArchiveRecord` per
+/// line, in ascending hash order.
+///
+/// Private images are included. Rows without a stored file are left out, since
+/// they cannot be restored, see `verify_integrity`. Records are written one at a
+/// time, so only a single file is held in memory with `FileReference::Inline`.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database to read the images from.
+/// * `storage` - Reference to the storage holding the files.
+/// * `writer` - The destination of the records, e.g. a file or stdout.
+/// * `reference` - Whether files are referenced by path or embedded.
+///
+/// # Returns
+///
+/// Returns the number of records written, or an `AppError` if a query, a read or a
+/// write fails.
+pub async fn export_jsonl<W: Write>(
+    db: &Database,
+    storage: &Storage,
+    mut writer: W,
+    reference: FileReference,
+) -> Result<usize, AppError> {
+    let featured: HashSet<_> = db
+        .query_image(ImageQuery::filter(ImageQueryExpr::featured()).with_private(true))
+        .await?
+        .into_iter()
+        .collect();
+    let mut hashes = db.list_images().await?;
+    hashes.sort();
+
+    let mut written = 0;
+    for hash in hashes {
+        let Some(path) = storage.index_file(&hash) else {
+            continue;
+        };
+        let file = match reference {
+            FileReference::Path => RecordFile::Path(storage.root().join(path.content_path())),
+            FileReference::Inline => RecordFile::Data(STANDARD.encode(storage.read_file(&hash)?)),
+        };
+        let record = ArchiveRecord {
+            hash: hash.to_string(),
+            file,
+            tags: db.get_tags(&hash).await?,
+            sources: db.get_sources(&hash).await?,
+            rating: db.get_rating(&hash).await?.map(|r| r.as_str().to_string()),
+            is_public: db.is_public(&hash).await?,
+            is_featured: featured.contains(&hash),
+        };
+
+        serde_json::to_writer(&mut writer, &record).map_err(io::Error::from)?;
+        writeln!(writer)?;
+        written += 1;
+    }
+    writer.flush()?;
+
+    Ok(written)
+}
+
+/// Quotes a field containing separators, quotes or line breaks, doubling its quotes.
+fn csv_field(value: &str) -> Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArchiveRecord, FileReference, RecordFile, export_jsonl, export_tags_csv};
+    use crate::{
+        app::{ArchiveImageCommand, tests::png_bytes},
+        database::{Database, MIGRATOR, Pool},
+        storage::Storage,
+    };
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_export_tags_csv(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(vec!["cat".into(), "red, white".into()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        ArchiveImageCommand::new(&png_bytes(2))
+            .with_tags(vec!["cat".into(), "say \"hi\"".into()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        db.refresh_image_count().await.unwrap();
+
+        let mut csv = vec![];
+        export_tags_csv(&db, &mut csv).await.unwrap();
+        assert_eq!(
+            "name,count\ncat,2\n\"red, white\",1\n\"say \"\"hi\"\"\",1\n",
+            String::from_utf8(csv).unwrap()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_export_jsonl(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        let media = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(vec!["cat".into()])
+            .with_source("https://example.com/1")
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        db.set_visibility(&media.hash, false).await.unwrap();
+
+        let mut jsonl = vec![];
+        let written = export_jsonl(&db, &storage, &mut jsonl, FileReference::Path)
+            .await
+            .unwrap();
+        assert_eq!(1, written);
+
+        let records: Vec<ArchiveRecord> = String::from_utf8(jsonl)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            vec![ArchiveRecord {
+                hash: media.hash.to_string(),
+                file: RecordFile::Path(dir.path().join(media.path.content_path())),
+                tags: vec!["cat".to_string()],
+                sources: vec!["https://example.com/1".to_string()],
+                rating: None,
+                is_public: false,
+                is_featured: false,
+            }],
+            records
+        );
+    }
+}
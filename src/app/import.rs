@@ -0,0 +1,497 @@
+//! Bulk import of a directory tree.
+//!
+//! `ImportDirectoryCommand` archives every file below a root directory and reports
+//! the outcome per file in an `ImportReport`. Files that cannot be decoded are
+//! handled according to a `FailedFilePolicy`, so a few corrupt downloads do not have
+//! to abort a long run. Systemic failures, such as an unreachable database, always
+//! abort regardless of the policy. Files whose content is archived already are
+//! skipped rather than failed, so an import can be run again over the same tree.
+
+use super::{AppError, ArchiveImageCommand};
+use crate::{
+    database::Database,
+    storage::{PixelHash, Storage, StorageError},
+};
+use futures::{StreamExt, stream};
+use glob::glob;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Decides what happens to files whose contents cannot be archived as media.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum FailedFilePolicy {
+    /// Records the failure in the report and continues.
+    #[default]
+    Skip,
+    /// Copies the file into the given directory, preserving its path relative to the
+    /// import root, writes a `.reason.txt` file next to it, and continues.
+    QuarantineFiles(PathBuf),
+    /// Stops the import with the file's error.
+    Abort,
+}
+
+/// A file that could not be archived.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedFile {
+    /// The path of the source file.
+    pub path: PathBuf,
+    /// A description of the error.
+    pub reason: String,
+}
+
+/// A file that was not archived, as its content is archived already.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedFile {
+    /// The path of the source file.
+    pub path: PathBuf,
+    /// The hash the content is archived under.
+    pub hash: PixelHash,
+}
+
+/// A file that was copied into the quarantine directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedFile {
+    /// The path of the source file.
+    pub source: PathBuf,
+    /// The path of the copy inside the quarantine directory.
+    pub destination: PathBuf,
+    /// A description of the error, also written to the reason file.
+    pub reason: String,
+}
+
+/// The outcome of an `ImportDirectoryCommand`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ImportReport {
+    /// The hashes of the archived files, in import order.
+    pub imported: Vec<PixelHash>,
+    /// Files whose content is archived already.
+    pub skipped: Vec<SkippedFile>,
+    /// Files that were not archived, including quarantined ones.
+    pub failed: Vec<FailedFile>,
+    /// Files that were copied into the quarantine directory.
+    pub quarantined: Vec<QuarantinedFile>,
+}
+
+/// Represents a command for archiving every file below a directory.
+///
+/// Use builder-style methods (`with_tags`, `with_policy`, `with_extensions`,
+/// `with_recursive`, `with_concurrency`) to customize the import before calling
+/// `execute()`.
+pub struct ImportDirectoryCommand {
+    /// The directory to import.
+    pub root: PathBuf,
+    /// Tags attached to every imported file.
+    pub tags: Vec<String>,
+    /// How files that cannot be archived as media are handled.
+    pub policy: FailedFilePolicy,
+    /// The lowercase extensions of the files to import, or every file if empty.
+    pub extensions: Vec<String>,
+    /// Whether subdirectories are imported as well.
+    pub recursive: bool,
+    /// The maximum number of files read and archived at once.
+    pub concurrency: usize,
+}
+
+impl ImportDirectoryCommand {
+    /// Creates a new `ImportDirectoryCommand` for the given directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory to import recursively.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ImportDirectoryCommand {
+            root: root.into(),
+            tags: vec![],
+            policy: FailedFilePolicy::default(),
+            extensions: vec![],
+            recursive: true,
+            concurrency: 1,
+        }
+    }
+
+    /// Adds tags attached to every imported file.
+    pub fn with_tags<T: IntoIterator<Item = String>>(mut self, tags: T) -> Self {
+        self.tags = tags.into_iter().collect();
+        self
+    }
+
+    /// Sets how files that cannot be archived as media are handled.
+    pub fn with_policy(mut self, policy: FailedFilePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Restricts the import to files with the given extensions, compared case
+    /// insensitively and without the leading dot. Every file is imported by default.
+    pub fn with_extensions<T: IntoIterator<Item = String>>(mut self, extensions: T) -> Self {
+        self.extensions = extensions
+            .into_iter()
+            .map(|e| e.trim_start_matches('.').to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Sets whether subdirectories are imported as well, which is the default.
+    pub fn with_recursive(mut self, recursive: bool) -> Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// Sets how many files are read and archived at once, at least one.
+    ///
+    /// Only that many files are open at a time, however large the tree, and they are
+    /// decoded in parallel. The report keeps path order either way. Under `FailedFilePolicy::Abort`, files after the
+    /// failed one may have been archived already when the import stops.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Executes the import, archiving files in path order.
+    ///
+    /// # Arguments
+    ///
+    /// * `storage` - Reference to the storage system where files will be stored.
+    /// * `db` - Reference to the database where metadata will be recorded.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `ImportReport`, or an `AppError` if a systemic
+    /// error occurs or a media error occurs under `FailedFilePolicy::Abort`.
+    pub async fn execute(self, storage: &Storage, db: &Database) -> Result<ImportReport, AppError> {
+        let mut report = ImportReport::default();
+
+        let files = list_files(&self.root, self.recursive)?
+            .into_iter()
+            .filter(|path| self.matches_extension(path));
+        // Each file is archived in a task of its own, so that files are decoded and
+        // recorded on all worker threads rather than interleaved on this one.
+        let mut results = stream::iter(files)
+            .map(|path| {
+                let (storage, db, tags) = (storage.clone(), db.clone(), self.tags.clone());
+                let task = tokio::spawn(async move {
+                    let result = match tokio::fs::read(&path).await {
+                        Ok(bytes) => Ok(ArchiveImageCommand::new(&bytes)
+                            .with_tags(tags)
+                            .execute(&storage, &db)
+                            .await),
+                        Err(e) => Err(e),
+                    };
+                    (path, result)
+                });
+                async move {
+                    task.await
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+                }
+            })
+            .buffered(self.concurrency);
+
+        while let Some((path, result)) = results.next().await {
+            let result = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    report.failed.push(FailedFile {
+                        path,
+                        reason: e.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            match result {
+                Ok(media) => report.imported.push(media.hash),
+                Err(e) if is_media_error(&e) => {
+                    let reason = e.to_string();
+
+                    match &self.policy {
+                        FailedFilePolicy::Skip => {}
+                        FailedFilePolicy::QuarantineFiles(dir) => {
+                            report
+                                .quarantined
+                                .push(quarantine(&self.root, dir, &path, &reason)?);
+                        }
+                        FailedFilePolicy::Abort => return Err(e),
+                    }
+
+                    report.failed.push(FailedFile { path, reason });
+                }
+                Err(AppError::Storage(StorageError::HashCollision { hash, .. })) => {
+                    report.skipped.push(SkippedFile { path, hash });
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Returns whether the file has one of the extensions to import.
+    fn matches_extension(&self, path: &Path) -> bool {
+        self.extensions.is_empty()
+            || path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| self.extensions.contains(&e.to_lowercase()))
+    }
+}
+
+/// Returns whether the error is caused by the contents of a single file.
+fn is_media_error(e: &AppError) -> bool {
+    matches!(
+        e,
+        AppError::Storage(
+            StorageError::EmptyInput
+                | StorageError::UnsupportedFile { .. }
+                | StorageError::Image(_)
+                | StorageError::Video(_)
+                | StorageError::Thumbnail { .. }
+                | StorageError::TrueCollision { .. }
+        )
+    )
+}
+
+/// Lists every file in `root`, and below it if `recursive`, sorted by path.
+fn list_files(root: &Path, recursive: bool) -> Result<Vec<PathBuf>, StorageError> {
+    let pattern = if recursive {
+        root.join("**").join("*")
+    } else {
+        root.join("*")
+    };
+    let mut files: Vec<PathBuf> = glob(&pattern.to_string_lossy())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+        .filter_map(Result::ok)
+        .filter(|p| p.is_file())
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+/// Copies a failed file into the quarantine directory and writes its reason file.
+fn quarantine(
+    root: &Path,
+    dir: &Path,
+    path: &Path,
+    reason: &str,
+) -> Result<QuarantinedFile, StorageError> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let destination = dir.join(relative);
+
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(path, &destination)?;
+
+    let mut reason_path = destination.clone().into_os_string();
+    reason_path.push(".reason.txt");
+    fs::write(
+        reason_path,
+        format!("source: {}\nerror: {}\n", path.display(), reason),
+    )?;
+
+    Ok(QuarantinedFile {
+        source: path.to_path_buf(),
+        destination,
+        reason: reason.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FailedFilePolicy, ImportDirectoryCommand};
+    use crate::{
+        app::{AppError, tests::png_bytes},
+        database::{Database, MIGRATOR, Pool},
+        query::ImageQuery,
+        storage::Storage,
+    };
+    use std::{fs, path::Path};
+    use tempfile::TempDir;
+
+    /// Writes two good images, one truncated PNG, and one HTML page saved as JPEG.
+    fn write_fixtures(root: &Path) {
+        fs::create_dir_all(root.join("broken")).unwrap();
+        fs::write(root.join("a.png"), png_bytes(1)).unwrap();
+        fs::write(root.join("b.png"), png_bytes(2)).unwrap();
+
+        let truncated = png_bytes(3);
+        fs::write(
+            root.join("broken/truncated.png"),
+            &truncated[..truncated.len() / 2],
+        )
+        .unwrap();
+
+        fs::write(root.join("page.jpg"), b"<html><body>404</body></html>").unwrap();
+    }
+
+    async fn setup(pool: Pool) -> (Database, Storage, TempDir, TempDir) {
+        let db = Database::new(pool);
+        let storage_dir = TempDir::new().unwrap();
+        let storage = Storage::new(storage_dir.path().to_path_buf());
+        let import_dir = TempDir::new().unwrap();
+        write_fixtures(import_dir.path());
+
+        (db, storage, storage_dir, import_dir)
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_import_skip(pool: Pool) {
+        let (db, storage, _storage_dir, import_dir) = setup(pool).await;
+
+        let report = ImportDirectoryCommand::new(import_dir.path())
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        assert_eq!(2, report.imported.len());
+        assert_eq!(
+            vec![
+                import_dir.path().join("broken/truncated.png"),
+                import_dir.path().join("page.jpg"),
+            ],
+            report
+                .failed
+                .iter()
+                .map(|f| f.path.clone())
+                .collect::<Vec<_>>()
+        );
+        assert!(report.quarantined.is_empty());
+        assert_eq!(2, db.count_image(ImageQuery::all()).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_import_quarantine(pool: Pool) {
+        let (db, storage, _storage_dir, import_dir) = setup(pool).await;
+        let quarantine_dir = TempDir::new().unwrap();
+
+        let report = ImportDirectoryCommand::new(import_dir.path())
+            .with_policy(FailedFilePolicy::QuarantineFiles(
+                quarantine_dir.path().to_path_buf(),
+            ))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        assert_eq!(2, report.imported.len());
+        assert_eq!(2, report.quarantined.len());
+        assert_eq!(2, db.count_image(ImageQuery::all()).await.unwrap());
+
+        let truncated = quarantine_dir.path().join("broken/truncated.png");
+        assert!(truncated.exists());
+        let reason = fs::read_to_string(
+            quarantine_dir
+                .path()
+                .join("broken/truncated.png.reason.txt"),
+        )
+        .unwrap();
+        assert!(
+            reason.contains(
+                &import_dir
+                    .path()
+                    .join("broken/truncated.png")
+                    .display()
+                    .to_string()
+            )
+        );
+        assert!(reason.contains("Image processing error"));
+
+        let reason = fs::read_to_string(quarantine_dir.path().join("page.jpg.reason.txt")).unwrap();
+        assert!(reason.contains("Unsupported or undetectable file format"));
+        assert!(!quarantine_dir.path().join("a.png").exists());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_import_skips_archived(pool: Pool) {
+        let (db, storage, _storage_dir, import_dir) = setup(pool).await;
+
+        let first = ImportDirectoryCommand::new(import_dir.path())
+            .with_concurrency(4)
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        let report = ImportDirectoryCommand::new(import_dir.path())
+            .with_concurrency(4)
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        assert!(report.imported.is_empty());
+        assert_eq!(
+            vec![
+                (import_dir.path().join("a.png"), first.imported[0].clone()),
+                (import_dir.path().join("b.png"), first.imported[1].clone()),
+            ],
+            report
+                .skipped
+                .iter()
+                .map(|f| (f.path.clone(), f.hash.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(2, report.failed.len());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_import_filters(pool: Pool) {
+        let (db, storage, _storage_dir, import_dir) = setup(pool).await;
+        fs::write(import_dir.path().join("broken/c.PNG"), png_bytes(4)).unwrap();
+
+        let report = ImportDirectoryCommand::new(import_dir.path())
+            .with_extensions([".png".to_string()])
+            .with_recursive(false)
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(2, report.imported.len());
+        assert!(report.failed.is_empty());
+
+        let report = ImportDirectoryCommand::new(import_dir.path())
+            .with_extensions(["png".to_string()])
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(1, report.imported.len());
+        assert_eq!(2, report.skipped.len());
+        assert_eq!(
+            vec![import_dir.path().join("broken/truncated.png")],
+            report
+                .failed
+                .iter()
+                .map(|f| f.path.clone())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_import_abort(pool: Pool) {
+        let (db, storage, _storage_dir, import_dir) = setup(pool).await;
+
+        let result = ImportDirectoryCommand::new(import_dir.path())
+            .with_policy(FailedFilePolicy::Abort)
+            .execute(&storage, &db)
+            .await;
+
+        assert!(matches!(result, Err(AppError::Storage(_))));
+        assert_eq!(2, db.count_image(ImageQuery::all()).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_import_aborts_on_database_outage(pool: Pool) {
+        let (db, storage, _storage_dir, import_dir) = setup(pool).await;
+        db.pool.close().await;
+
+        for policy in [
+            FailedFilePolicy::Skip,
+            FailedFilePolicy::QuarantineFiles(TempDir::new().unwrap().path().to_path_buf()),
+            FailedFilePolicy::Abort,
+        ] {
+            let result = ImportDirectoryCommand::new(import_dir.path())
+                .with_policy(policy)
+                .execute(&storage, &db)
+                .await;
+
+            assert!(matches!(result, Err(AppError::Database(_))));
+        }
+    }
+}
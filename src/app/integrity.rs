@@ -0,0 +1,299 @@
+//! Verification of the storage against the database.
+//!
+//! Files are stored under the pixel hash of their content, and every archived file
+//! has a row in the database. `verify_integrity` checks both: it recomputes the hash
+//! of every stored file, and compares the stored hashes with the recorded ones.
+//! `fix_integrity` then repairs what can be repaired without guessing: files missing
+//! from the database are recorded, and rows without a file are removed.
+//!
+//! `import_from_storage` only records the files missing from the database, without
+//! decoding every stored file, e.g. to rebuild a lost database from the storage.
+
+use super::AppError;
+use crate::{
+    database::{Database, DatabaseError},
+    storage::{PixelHash, Storage, StorageError},
+};
+use std::collections::BTreeSet;
+
+/// The outcome of `verify_integrity`.
+///
+/// Every list is in ascending hash order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IntegrityReport {
+    /// Files stored without a row in the database.
+    pub missing_from_db: Vec<PixelHash>,
+    /// Files whose content hashes differ from the hash they are stored under, as
+    /// pairs of the stored and the recomputed hash.
+    pub hash_mismatch: Vec<(PixelHash, PixelHash)>,
+    /// Rows in the database without a stored file.
+    pub orphaned_db_entries: Vec<PixelHash>,
+    /// Files that cannot be decoded, so their hash cannot be recomputed.
+    pub unreadable: Vec<PixelHash>,
+    /// Files in a lossy format, whose hash cannot be reproduced, see
+    /// `Storage::recompute_hash`.
+    pub unverified: Vec<PixelHash>,
+}
+
+impl IntegrityReport {
+    /// Returns whether no problem was found.
+    ///
+    /// Unverified files are not a problem by themselves.
+    pub fn is_clean(&self) -> bool {
+        self.missing_from_db.is_empty()
+            && self.hash_mismatch.is_empty()
+            && self.orphaned_db_entries.is_empty()
+            && self.unreadable.is_empty()
+    }
+}
+
+/// Verifies that every stored file matches its hash and is recorded in the database.
+///
+/// Every stored file is decoded, so this takes as long as archiving them again.
+///
+/// # Arguments
+///
+/// * `storage` - Reference to the storage whose files are verified.
+/// * `db` - Reference to the database the files are compared with.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `IntegrityReport`, or an `AppError` if the
+/// storage cannot be listed or a query fails.
+pub async fn verify_integrity(
+    storage: &Storage,
+    db: &Database,
+) -> Result<IntegrityReport, AppError> {
+    let stored = storage.list_hashes()?;
+    let recorded: BTreeSet<PixelHash> = db.list_images().await?.into_iter().collect();
+    let mut report = IntegrityReport::default();
+
+    for hash in &stored {
+        if !recorded.contains(hash) {
+            report.missing_from_db.push(hash.clone());
+        }
+
+        match storage.recompute_hash(hash) {
+            Ok(Some(actual)) if actual != *hash => {
+                report.hash_mismatch.push((hash.clone(), actual));
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => report.unverified.push(hash.clone()),
+            Err(_) => report.unreadable.push(hash.clone()),
+        }
+    }
+
+    let stored: BTreeSet<PixelHash> = stored.into_iter().collect();
+    report.orphaned_db_entries = recorded
+        .into_iter()
+        .filter(|hash| !stored.contains(hash))
+        .collect();
+
+    Ok(report)
+}
+
+/// Repairs the problems of an `IntegrityReport` that have an unambiguous fix.
+///
+/// Files missing from the database are recorded along with their metadata and
+/// perceptual hash, but without tags or a source. Rows without a stored file are
+/// removed. Mismatching and unreadable files are left alone, since removing them
+/// would lose the only copy.
+///
+/// # Arguments
+///
+/// * `storage` - Reference to the storage the report was made for.
+/// * `db` - Reference to the database the report was made for.
+/// * `report` - The report of `verify_integrity`.
+///
+/// # Returns
+///
+/// Returns `Ok(())` if every fix was applied, or an `AppError` on the first error.
+/// Fixes applied before the error stay applied.
+pub async fn fix_integrity(
+    storage: &Storage,
+    db: &Database,
+    report: &IntegrityReport,
+) -> Result<(), AppError> {
+    if db.is_read_only() {
+        return Err(DatabaseError::ReadOnly.into());
+    }
+
+    for hash in &report.missing_from_db {
+        record_stored(storage, db, hash).await?;
+    }
+    for hash in &report.orphaned_db_entries {
+        db.ensure_image_removed(hash).await?;
+    }
+
+    Ok(())
+}
+
+/// Records every stored file that has no row in the database.
+///
+/// Files are recorded along with their metadata and perceptual hash, but without
+/// tags or a source, like `fix_integrity` does. Their hashes are not recomputed, so
+/// this is much faster than `verify_integrity`. Files that are not named after a
+/// pixel hash are skipped, see `Storage::list_all`.
+///
+/// # Arguments
+///
+/// * `storage` - Reference to the storage whose files are recorded.
+/// * `db` - Reference to the database the files are recorded in.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the hashes of the recorded files in ascending
+/// order, or an `AppError` on the first error. Files recorded before the error stay
+/// recorded.
+pub async fn import_from_storage(
+    storage: &Storage,
+    db: &Database,
+) -> Result<Vec<PixelHash>, AppError> {
+    if db.is_read_only() {
+        return Err(DatabaseError::ReadOnly.into());
+    }
+
+    let recorded: BTreeSet<PixelHash> = db.list_images().await?.into_iter().collect();
+    let mut imported = vec![];
+    for hash in storage.list_all().filter(|hash| !recorded.contains(hash)) {
+        record_stored(storage, db, &hash).await?;
+        imported.push(hash);
+    }
+
+    Ok(imported)
+}
+
+/// Records a stored file along with its metadata and perceptual hash, which are read
+/// on the blocking thread pool.
+async fn record_stored(storage: &Storage, db: &Database, hash: &PixelHash) -> Result<(), AppError> {
+    let stored = hash.clone();
+    let (metadata, phash) = storage
+        .run_blocking(move |storage| {
+            Ok::<_, StorageError>((storage.get_metadata(&stored)?, storage.get_phash(&stored)?))
+        })
+        .await?;
+
+    db.ensure_image(hash).await?;
+    if let Some(phash) = phash {
+        db.ensure_image_has_phash(hash, phash).await?;
+    }
+    db.ensure_image_has_metadata(hash, &metadata).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IntegrityReport, fix_integrity, import_from_storage, verify_integrity};
+    use crate::{
+        app::{AppError, ArchiveImageCommand, tests::png_bytes},
+        database::{Database, DatabaseError, MIGRATOR, Pool},
+        storage::{PixelHash, Storage},
+    };
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_verify_integrity(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        // A complete image.
+        ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        // A file stored without a row.
+        let (unrecorded, _) = storage.create_file(&png_bytes(2)).unwrap();
+
+        // A row without a file.
+        let orphaned = ArchiveImageCommand::new(&png_bytes(3))
+            .execute(&storage, &db)
+            .await
+            .unwrap()
+            .hash;
+        storage.ensure_deleted(&orphaned).unwrap();
+
+        // A file stored under another hash, and a file that cannot be decoded.
+        let (mismatched, unreadable) = (PixelHash::from(1), PixelHash::from(2));
+        fs::create_dir_all(dir.path().join("00/00")).unwrap();
+        fs::write(
+            dir.path().join(format!("00/00/{mismatched}.png")),
+            png_bytes(4),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join(format!("00/00/{unreadable}.png")),
+            b"broken",
+        )
+        .unwrap();
+        for hash in [&mismatched, &unreadable] {
+            db.ensure_image(hash).await.unwrap();
+        }
+        let (actual, _) = Storage::new(TempDir::new().unwrap().path().to_path_buf())
+            .create_file(&png_bytes(4))
+            .unwrap();
+
+        let report = verify_integrity(&storage, &db).await.unwrap();
+        assert_eq!(
+            IntegrityReport {
+                missing_from_db: vec![unrecorded.clone()],
+                hash_mismatch: vec![(mismatched, actual)],
+                orphaned_db_entries: vec![orphaned.clone()],
+                unreadable: vec![unreadable],
+                unverified: vec![],
+            },
+            report
+        );
+        assert!(!report.is_clean());
+
+        fix_integrity(&storage, &db, &report).await.unwrap();
+        let fixed = verify_integrity(&storage, &db).await.unwrap();
+        assert!(fixed.missing_from_db.is_empty());
+        assert!(fixed.orphaned_db_entries.is_empty());
+        assert!(db.get_metadata(&unrecorded).await.unwrap().is_some());
+        assert!(!db.image_exists(&orphaned).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_import_from_storage(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        let archived = ArchiveImageCommand::new(&png_bytes(1))
+            .with_tags(["cat".to_string()])
+            .execute(&storage, &db)
+            .await
+            .unwrap()
+            .hash;
+        let mut unrecorded = vec![];
+        for seed in 2..5 {
+            unrecorded.push(storage.create_file(&png_bytes(seed)).unwrap().0);
+        }
+        unrecorded.sort();
+        fs::create_dir_all(dir.path().join("00/00")).unwrap();
+        fs::write(dir.path().join("00/00/notes.txt"), b"notes").unwrap();
+
+        assert_eq!(
+            unrecorded,
+            import_from_storage(&storage, &db).await.unwrap()
+        );
+        for hash in &unrecorded {
+            assert!(db.get_metadata(hash).await.unwrap().is_some());
+        }
+        assert_eq!(
+            vec!["cat".to_string()],
+            db.get_tags(&archived).await.unwrap()
+        );
+        assert!(verify_integrity(&storage, &db).await.unwrap().is_clean());
+
+        assert!(import_from_storage(&storage, &db).await.unwrap().is_empty());
+        assert!(matches!(
+            import_from_storage(&storage, &db.with_read_only(true)).await,
+            Err(AppError::Database(DatabaseError::ReadOnly))
+        ));
+    }
+}
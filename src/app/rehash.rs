@@ -0,0 +1,173 @@
+//! Migration of an archive to another pixel hash configuration.
+//!
+//! Changing how pixel hashes are computed, e.g. with `Storage::with_hash_seed`,
+//! changes the hash of every stored file. `rehash_archive` moves each file to its new
+//! hash and moves its database rows along with it. Each file is migrated on its own:
+//! the file is written under its new hash first, then its rows are moved in a single
+//! transaction, and only then is the old file deleted. An interrupted run can thus
+//! simply be started again.
+
+use super::AppError;
+use crate::{
+    database::{Database, DatabaseError},
+    storage::{PixelHash, Priority, Storage, StorageError},
+};
+
+/// A file that was moved to a new hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RehashedFile {
+    /// The hash the file was stored under.
+    pub old: PixelHash,
+    /// The hash the file is stored under now.
+    pub new: PixelHash,
+}
+
+/// The outcome of `rehash_archive`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RehashReport {
+    /// Files that were moved to a new hash, in the order of their old hash.
+    pub migrated: Vec<RehashedFile>,
+    /// Files already stored under their new hash, e.g. by an earlier run.
+    pub unchanged: Vec<PixelHash>,
+}
+
+/// Moves every file of an archive to the hash computed by another storage.
+///
+/// `new_storage` decides how the new hashes are computed and where the files go. It
+/// may share its root with `old_storage`, in which case files already stored under
+/// their new hash are left alone.
+///
+/// # Arguments
+///
+/// * `old_storage` - The storage the archive is currently stored in.
+/// * `new_storage` - The storage the archive is migrated to.
+/// * `db` - Reference to the database where the rows will be moved.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `RehashReport`, or an `AppError` on the first
+/// error. Files migrated before the error stay migrated.
+pub async fn rehash_archive(
+    old_storage: &Storage,
+    new_storage: &Storage,
+    db: &Database,
+) -> Result<RehashReport, AppError> {
+    if db.is_read_only() {
+        return Err(DatabaseError::ReadOnly.into());
+    }
+
+    let same_root = old_storage.root() == new_storage.root();
+    let mut report = RehashReport::default();
+
+    for old in old_storage.list_hashes()? {
+        let bytes = old_storage.read_file(&old)?;
+
+        // The file may already be there if an earlier run was interrupted.
+        let created = new_storage
+            .run_blocking(move |storage| {
+                storage.create_file_with_priority(&bytes, Priority::Maintenance)
+            })
+            .await;
+        let new = match created {
+            Ok(hash) => hash,
+            Err(StorageError::HashCollision { hash, .. }) => hash,
+            Err(e) => return Err(e.into()),
+        };
+
+        if new == old && same_root {
+            report.unchanged.push(old);
+            continue;
+        }
+
+        new_storage.push_to_backend(&new).await?;
+        db.rehash_image(&old, &new).await?;
+        old_storage.delete(&old).await?;
+
+        report.migrated.push(RehashedFile { old, new });
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RehashedFile, rehash_archive};
+    use crate::{
+        app::{ArchiveImageCommand, find_image_by_hash, set_featured, tests::png_bytes},
+        database::{Database, MIGRATOR, Pool, Rating},
+        query::{ImageQuery, image},
+        storage::Storage,
+    };
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_rehash_archive(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let old_storage = Storage::new(dir.path().to_path_buf());
+        let new_storage = Storage::new(dir.path().to_path_buf()).with_hash_seed(42);
+
+        let mut before = vec![];
+        for (seed, tag) in [(1, "cat"), (2, "dog")] {
+            let media = ArchiveImageCommand::new(&png_bytes(seed))
+                .with_tags(vec![tag.to_string(), "pet".to_string()])
+                .with_source(&format!("https://example.com/{tag}"))
+                .execute(&old_storage, &db)
+                .await
+                .unwrap();
+            before.push(media);
+        }
+        set_featured(&db, &old_storage, &before[0].hash, true)
+            .await
+            .unwrap();
+        db.set_visibility(&before[1].hash, false).await.unwrap();
+        db.ensure_image_has_rating(&before[1].hash, Rating::Explicit)
+            .await
+            .unwrap();
+
+        let report = rehash_archive(&old_storage, &new_storage, &db)
+            .await
+            .unwrap();
+        assert_eq!(2, report.migrated.len());
+        assert!(report.unchanged.is_empty());
+
+        let mut migrated = vec![];
+        for media in &before {
+            let RehashedFile { new, .. } = report
+                .migrated
+                .iter()
+                .find(|r| r.old == media.hash)
+                .unwrap();
+            assert_ne!(media.hash, *new);
+            assert_eq!(None, old_storage.index_file(&media.hash));
+
+            let after = find_image_by_hash(&db, &new_storage, new).await.unwrap();
+            assert_eq!(media.tags, after.tags);
+            assert_eq!(media.source, after.source);
+            assert_eq!(media.metadata, after.metadata);
+            migrated.push(after);
+        }
+        assert!(migrated[0].is_public);
+        assert!(!migrated[1].is_public);
+        assert_eq!(Some(Rating::Explicit), migrated[1].rating);
+        assert_eq!(
+            vec![migrated[0].hash.clone()],
+            db.query_image(ImageQuery::filter(image::featured()))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            2,
+            db.count_image(ImageQuery::all().with_private(true))
+                .await
+                .unwrap()
+        );
+
+        // Running again finds every file under its new hash.
+        let again = rehash_archive(&old_storage, &new_storage, &db)
+            .await
+            .unwrap();
+        assert!(again.migrated.is_empty());
+        assert_eq!(2, again.unchanged.len());
+    }
+}
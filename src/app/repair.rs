@@ -0,0 +1,126 @@
+//! Detection of incomplete records.
+//!
+//! An interrupted archival or a failed thumbnail generation can leave an image
+//! recorded without metadata, or a video stored without its thumbnail.
+//! `find_incomplete` reports both kinds of defects per hash in a single pass, so one
+//! report can drive every repair.
+
+use super::AppError;
+use crate::{
+    database::Database,
+    storage::{PixelHash, Storage},
+};
+use std::collections::BTreeMap;
+
+/// An image with at least one missing piece.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncompleteRecord {
+    /// The hash of the image.
+    pub hash: PixelHash,
+    /// Whether the image is recorded in the database without metadata.
+    pub missing_metadata: bool,
+    /// Whether the image is a stored video without a thumbnail.
+    pub missing_thumbnail: bool,
+}
+
+/// Finds images that are missing their metadata or their thumbnail.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database to check for missing metadata.
+/// * `storage` - Reference to the storage to check for missing thumbnails.
+///
+/// # Returns
+///
+/// Returns a `Result` containing one record per incomplete image in ascending hash
+/// order, or an `AppError` if either check fails.
+pub async fn find_incomplete(
+    db: &Database,
+    storage: &Storage,
+) -> Result<Vec<IncompleteRecord>, AppError> {
+    let mut records: BTreeMap<PixelHash, IncompleteRecord> = BTreeMap::new();
+
+    for hash in db.images_without_metadata().await? {
+        record(&mut records, hash).missing_metadata = true;
+    }
+    for hash in storage.missing_thumbnails()? {
+        record(&mut records, hash).missing_thumbnail = true;
+    }
+
+    Ok(records.into_values().collect())
+}
+
+/// Returns the record of the hash, adding a complete one if there is none yet.
+fn record(
+    records: &mut BTreeMap<PixelHash, IncompleteRecord>,
+    hash: PixelHash,
+) -> &mut IncompleteRecord {
+    records
+        .entry(hash.clone())
+        .or_insert_with(|| IncompleteRecord {
+            hash,
+            missing_metadata: false,
+            missing_thumbnail: false,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IncompleteRecord, find_incomplete};
+    use crate::{
+        app::{ArchiveImageCommand, tests::png_bytes},
+        database::{Database, MIGRATOR, Pool},
+        storage::{ImageMetadata, PixelHash, Storage},
+    };
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_find_incomplete(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        // A complete image.
+        ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        // An image recorded without metadata.
+        let (no_metadata, _) = storage.create_file(&png_bytes(2)).unwrap();
+        db.ensure_image(&no_metadata).await.unwrap();
+
+        // Videos stored without a thumbnail, one of them also without metadata.
+        let (no_thumbnail, neither) = (PixelHash::from(1), PixelHash::from(2));
+        fs::create_dir_all(dir.path().join("00/00")).unwrap();
+        for hash in [&no_thumbnail, &neither] {
+            fs::write(dir.path().join(format!("00/00/{hash}.mp4")), b"video").unwrap();
+        }
+        db.ensure_image_has_metadata(&no_thumbnail, &ImageMetadata::default())
+            .await
+            .unwrap();
+        db.ensure_image(&neither).await.unwrap();
+
+        let mut expected = vec![
+            IncompleteRecord {
+                hash: no_metadata,
+                missing_metadata: true,
+                missing_thumbnail: false,
+            },
+            IncompleteRecord {
+                hash: no_thumbnail,
+                missing_metadata: false,
+                missing_thumbnail: true,
+            },
+            IncompleteRecord {
+                hash: neither,
+                missing_metadata: true,
+                missing_thumbnail: true,
+            },
+        ];
+        expected.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+        assert_eq!(expected, find_incomplete(&db, &storage).await.unwrap());
+    }
+}
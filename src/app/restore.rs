@@ -0,0 +1,466 @@
+//! Restore of a JSONL export.
+//!
+//! `restore` reads the records written by `export_jsonl`, one JSON object per line,
+//! and archives each image with its tags, sources, rating and flags. Still images
+//! are written under their recorded hash without decoding them, see
+//! `Storage::create_file_with_hash`. Videos, animations and camera RAW files are
+//! decoded as usual, since their thumbnail has to be generated, and must reproduce
+//! the recorded hash. Files of an undetectable type are stored as raw files, whose
+//! hash is computed from their bytes.
+//!
+//! A record is restored completely or not at all: if any row fails, the file and
+//! the rows written for it are removed again. Records of images that are archived
+//! already are skipped by default, so an interrupted restore resumes by running it
+//! again on the same export.
+
+use super::{
+    AppError,
+    export::{ArchiveRecord, RecordFile},
+    remove_image,
+};
+use crate::{
+    database::{Database, Rating},
+    storage::{self, PixelHash, Priority, Storage},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use std::{
+    fs,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+/// Decides what happens to records of images that are archived already.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExistingRecordPolicy {
+    /// Keeps the archived image and reports the record as skipped.
+    #[default]
+    Skip,
+    /// Removes the archived image, then restores the record.
+    Replace,
+}
+
+/// Options of `restore_with_options`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RestoreOptions {
+    /// What happens to records of images that are archived already.
+    pub existing: ExistingRecordPolicy,
+    /// The directory relative file paths are resolved against, the working
+    /// directory if `None`.
+    pub base_dir: Option<PathBuf>,
+}
+
+/// A record that could not be restored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedRecord {
+    /// The line of the record, counted from one.
+    pub line: usize,
+    /// A description of the error.
+    pub reason: String,
+}
+
+/// The outcome of `restore`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RestoreReport {
+    /// The hashes of the restored images, in input order.
+    pub restored: Vec<PixelHash>,
+    /// The hashes of images that were archived already, in input order.
+    pub skipped: Vec<PixelHash>,
+    /// Records that were not restored.
+    pub failed: Vec<FailedRecord>,
+}
+
+/// Restores every record of a JSONL export with the default options.
+///
+/// See `restore_with_options`.
+pub async fn restore<R: BufRead>(
+    db: &Database,
+    storage: &Storage,
+    reader: R,
+) -> Result<RestoreReport, AppError> {
+    restore_with_options(db, storage, reader, &RestoreOptions::default()).await
+}
+
+/// Restores every record of a JSONL export.
+///
+/// Blank lines are ignored. Records that cannot be parsed, whose file cannot be
+/// read, or whose file does not match the recorded hash are reported as failed,
+/// and the restore continues. Systemic failures, such as an unreachable database,
+/// abort the restore after the current record is rolled back.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the rows will be recorded.
+/// * `storage` - Reference to the storage where the files will be stored.
+/// * `reader` - The export, as written by `export_jsonl`.
+/// * `options` - How existing images and relative paths are handled.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `RestoreReport`, or an `AppError` if reading
+/// the export or a database operation fails.
+pub async fn restore_with_options<R: BufRead>(
+    db: &Database,
+    storage: &Storage,
+    reader: R,
+    options: &RestoreOptions,
+) -> Result<RestoreReport, AppError> {
+    let mut report = RestoreReport::default();
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fail = |reason: String| FailedRecord {
+            line: index + 1,
+            reason,
+        };
+
+        let record: ArchiveRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(e) => {
+                report.failed.push(fail(e.to_string()));
+                continue;
+            }
+        };
+        let hash = match PixelHash::try_from(record.hash.as_str()) {
+            Ok(hash) => hash,
+            Err(e) => {
+                report.failed.push(fail(e.to_string()));
+                continue;
+            }
+        };
+        let rating = match record.rating.as_deref().map(str::parse::<Rating>) {
+            Some(Err(reason)) => {
+                report.failed.push(fail(reason));
+                continue;
+            }
+            rating => rating.and_then(Result::ok),
+        };
+
+        if is_archived(db, storage, &hash).await? {
+            match options.existing {
+                ExistingRecordPolicy::Skip => {
+                    report.skipped.push(hash);
+                    continue;
+                }
+                ExistingRecordPolicy::Replace => remove_image(storage, db, hash.clone()).await?,
+            }
+        }
+
+        let bytes = match read_file(&record.file, options.base_dir.as_deref()) {
+            Ok(bytes) => bytes,
+            Err(reason) => {
+                report.failed.push(fail(reason));
+                continue;
+            }
+        };
+        // Storing waits for a decode slot, which blocks the thread.
+        let stored_hash = hash.clone();
+        let extension = match &record.file {
+            RecordFile::Path(path) => path.extension().and_then(|e| e.to_str()),
+            RecordFile::Data(_) => None,
+        }
+        .map(str::to_string);
+        if storage.index_file(&hash).is_none()
+            && let Err(reason) = storage
+                .run_blocking(move |storage| {
+                    store_file(&storage, &bytes, &stored_hash, extension.as_deref())
+                })
+                .await
+        {
+            report.failed.push(fail(reason));
+            continue;
+        }
+
+        let recorded = match storage.push_to_backend(&hash).await {
+            Ok(()) => record_rows(db, storage, &hash, &record, rating).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = recorded {
+            remove_image(storage, db, hash).await?;
+            return Err(e);
+        }
+        report.restored.push(hash);
+    }
+
+    Ok(report)
+}
+
+/// Returns whether an image is stored and completely recorded.
+///
+/// Images whose registration is incomplete are restored over.
+async fn is_archived(db: &Database, storage: &Storage, hash: &PixelHash) -> Result<bool, AppError> {
+    Ok(storage.index_file(hash).is_some()
+        && db.image_exists(hash).await?
+        && db.get_metadata(hash).await?.is_some())
+}
+
+/// Reads the bytes of a record's file.
+fn read_file(file: &RecordFile, base_dir: Option<&Path>) -> Result<Vec<u8>, String> {
+    match file {
+        RecordFile::Path(path) => {
+            let path = match base_dir {
+                Some(base) => base.join(path),
+                None => path.clone(),
+            };
+            fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))
+        }
+        RecordFile::Data(data) => STANDARD.decode(data).map_err(|e| e.to_string()),
+    }
+}
+
+/// Stores a file under its recorded hash.
+///
+/// Files of an undetectable type are kept as raw files, with the given extension if
+/// any.
+fn store_file(
+    storage: &Storage,
+    bytes: &[u8],
+    hash: &PixelHash,
+    extension: Option<&str>,
+) -> Result<(), String> {
+    let actual = match infer::get(bytes) {
+        // Animations and camera RAW files are stored like videos, with a thumbnail
+        // generated by decoding.
+        Some(kind)
+            if kind.matcher_type() == infer::MatcherType::Image
+                && !storage::is_stored_as_video(bytes, kind).map_err(|e| e.to_string())? =>
+        {
+            return storage
+                .create_file_with_hash(bytes, hash, kind)
+                .map_err(|e| e.to_string());
+        }
+        Some(_) => storage.create_file_with_priority(bytes, Priority::Maintenance),
+        None => storage.create_raw_file(bytes, extension.unwrap_or("bin"), Priority::Maintenance),
+    }
+    .map_err(|e| e.to_string())?;
+
+    if actual != *hash {
+        storage.ensure_deleted(&actual).map_err(|e| e.to_string())?;
+        return Err(format!("file has pixel hash {actual}, not {hash}"));
+    }
+
+    Ok(())
+}
+
+/// Records the rows of a stored file.
+async fn record_rows(
+    db: &Database,
+    storage: &Storage,
+    hash: &PixelHash,
+    record: &ArchiveRecord,
+    rating: Option<Rating>,
+) -> Result<(), AppError> {
+    let metadata = storage.get_metadata(hash)?;
+    let phash = storage.get_phash(hash)?;
+
+    db.ensure_image(hash).await?;
+    if let Some(phash) = phash {
+        db.ensure_image_has_phash(hash, phash).await?;
+    }
+    db.ensure_image_has_metadata(hash, &metadata).await?;
+    let tags: Vec<&str> = record.tags.iter().map(String::as_str).collect();
+    db.ensure_image_has_tags(hash, &tags).await?;
+    db.set_sources(hash, &record.sources).await?;
+    if let Some(rating) = rating {
+        db.ensure_image_has_rating(hash, rating).await?;
+    }
+    db.set_visibility(hash, record.is_public).await?;
+    db.set_featured(hash, record.is_featured).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExistingRecordPolicy, RestoreOptions, restore, restore_with_options};
+    use crate::{
+        app::{
+            ArchiveImageCommand, FileReference, export_jsonl, find_image_by_hash, remove_image,
+            tests::png_bytes,
+        },
+        database::{Database, MIGRATOR, Pool, Rating},
+        query::{ImageQuery, ImageQueryExpr},
+        storage::{MediaPath, Priority, Storage, tests::animated_webp},
+    };
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_restore_round_trip(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        let mut archived = vec![];
+        for seed in 1..=3 {
+            let media = ArchiveImageCommand::new(&png_bytes(seed))
+                .with_tags([format!("tag{seed}"), "shared".to_string()])
+                .with_source(&format!("https://example.com/{seed}"))
+                .with_sources(vec![format!("https://example.org/{seed}")])
+                .with_rating(Rating::Questionable)
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+            archived.push(media);
+        }
+        db.set_visibility(&archived[1].hash, false).await.unwrap();
+        db.set_featured(&archived[2].hash, true).await.unwrap();
+        let originals = {
+            let mut originals = vec![];
+            for media in &archived {
+                originals.push(
+                    find_image_by_hash(&db, &storage, &media.hash)
+                        .await
+                        .unwrap(),
+                );
+            }
+            originals
+        };
+
+        let mut export = vec![];
+        assert_eq!(
+            3,
+            export_jsonl(&db, &storage, &mut export, FileReference::Inline)
+                .await
+                .unwrap()
+        );
+
+        for media in &archived {
+            remove_image(&storage, &db, media.hash.clone())
+                .await
+                .unwrap();
+        }
+        assert!(db.list_images().await.unwrap().is_empty());
+
+        let report = restore(&db, &storage, Cursor::new(&export)).await.unwrap();
+        assert_eq!(3, report.restored.len());
+        assert!(report.failed.is_empty());
+
+        for original in &originals {
+            let restored = find_image_by_hash(&db, &storage, &original.hash)
+                .await
+                .unwrap();
+            assert_eq!(original.tags, restored.tags);
+            assert_eq!(original.sources, restored.sources);
+            assert_eq!(original.rating, restored.rating);
+            assert_eq!(original.is_public, restored.is_public);
+            assert_eq!(original.metadata.width, restored.metadata.width);
+            assert_eq!(original.metadata.format, restored.metadata.format);
+        }
+        let featured = db
+            .query_image(ImageQuery::filter(ImageQueryExpr::featured()).with_private(true))
+            .await
+            .unwrap();
+        assert_eq!(vec![archived[2].hash.clone()], featured);
+
+        // Running the restore again resumes it, skipping restored records.
+        let report = restore(&db, &storage, Cursor::new(&export)).await.unwrap();
+        assert!(report.restored.is_empty());
+        assert_eq!(3, report.skipped.len());
+
+        let options = RestoreOptions {
+            existing: ExistingRecordPolicy::Replace,
+            ..RestoreOptions::default()
+        };
+        let report = restore_with_options(&db, &storage, Cursor::new(&export), &options)
+            .await
+            .unwrap();
+        assert_eq!(3, report.restored.len());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_restore_failed_records(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+        let files = TempDir::new().unwrap();
+        std::fs::write(files.path().join("1.png"), png_bytes(1)).unwrap();
+        let (hash, _) = Storage::new(TempDir::new().unwrap().path().to_path_buf())
+            .create_file(&png_bytes(1))
+            .unwrap();
+
+        let export = [
+            format!(r#"{{"hash":"{hash}","file":{{"path":"1.png"}},"rating":"e"}}"#),
+            String::new(),
+            "not json".to_string(),
+            r#"{"hash":"xyz","file":{"path":"1.png"}}"#.to_string(),
+            r#"{"hash":"0000000000000000","file":{"path":"missing.png"}}"#.to_string(),
+        ]
+        .join("\n");
+        let options = RestoreOptions {
+            base_dir: Some(files.path().to_path_buf()),
+            ..RestoreOptions::default()
+        };
+        let report = restore_with_options(&db, &storage, Cursor::new(export), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(vec![hash.clone()], report.restored);
+        assert_eq!(
+            vec![3, 4, 5],
+            report.failed.iter().map(|f| f.line).collect::<Vec<_>>()
+        );
+        assert_eq!(vec![hash.clone()], db.list_images().await.unwrap());
+        assert_eq!(Some(Rating::Explicit), db.get_rating(&hash).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_restore_undetectable_file(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+        let files = TempDir::new().unwrap();
+        let notes = b"notes of no detectable type";
+        std::fs::write(files.path().join("notes.txt"), notes).unwrap();
+        let hash = Storage::new(TempDir::new().unwrap().path().to_path_buf())
+            .create_raw_file(notes, "txt", Priority::Maintenance)
+            .unwrap();
+
+        let export = format!(r#"{{"hash":"{hash}","file":{{"path":"notes.txt"}}}}"#);
+        let options = RestoreOptions {
+            base_dir: Some(files.path().to_path_buf()),
+            ..RestoreOptions::default()
+        };
+        let report = restore_with_options(&db, &storage, Cursor::new(export), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(vec![hash.clone()], report.restored);
+        let Some(MediaPath::Raw(path)) = storage.index_file(&hash) else {
+            panic!(
+                "Expected a raw file, but got {:?}",
+                storage.index_file(&hash)
+            );
+        };
+        assert_eq!(Some("txt"), path.extension().and_then(|e| e.to_str()));
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_restore_animated_webp(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        let webp = animated_webp(&[[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]]);
+        let hash = ArchiveImageCommand::new(&webp)
+            .execute(&storage, &db)
+            .await
+            .unwrap()
+            .hash;
+        let mut export = vec![];
+        export_jsonl(&db, &storage, &mut export, FileReference::Inline)
+            .await
+            .unwrap();
+        remove_image(&storage, &db, hash.clone()).await.unwrap();
+
+        let report = restore(&db, &storage, Cursor::new(&export)).await.unwrap();
+        assert_eq!(vec![hash.clone()], report.restored);
+        assert!(matches!(
+            storage.index_file(&hash),
+            Some(MediaPath::Video { .. })
+        ));
+        assert!(storage.get_metadata(&hash).unwrap().duration.is_some());
+    }
+}
@@ -0,0 +1,179 @@
+//! Lookup of visually similar images.
+//!
+//! Every archived image records a perceptual hash, see `PHash`, next to its pixel
+//! hash. Two images whose perceptual hashes differ in few bits look alike, even if
+//! they were re-encoded, rescaled or slightly edited. The comparison runs in the
+//! database, see `Database::query_images_by_similarity`.
+//! `ArchiveImageCommand::with_duplicate_check` runs it for each archived image.
+
+use super::AppError;
+use crate::{
+    database::Database,
+    storage::{NearDuplicate, PHash, PixelHash, Storage},
+};
+
+/// Finds archived images that look like the given one, closest first.
+///
+/// The perceptual hash of the image is read from the database. Images archived
+/// before perceptual hashes were recorded are hashed from storage instead, but are
+/// not found as similar to others until their hash is recorded. Raw files have no
+/// perceptual hash, and no similar images.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the comparison will be executed.
+/// * `storage` - Reference to the storage system for hashing unrecorded images.
+/// * `hash` - The hash of the image to compare with.
+/// * `threshold` - The maximum number of differing bits (0-64), e.g. 8.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the hashes of similar images without the image
+/// itself, or an `AppError` if the image is not found or a query fails.
+pub async fn find_similar_images(
+    db: &Database,
+    storage: &Storage,
+    hash: &PixelHash,
+    threshold: u32,
+) -> Result<Vec<PixelHash>, AppError> {
+    let phash = match db.get_phash(hash).await? {
+        Some(phash) => phash,
+        None => match storage.get_phash(hash)? {
+            Some(phash) => phash,
+            // Raw files have no pixels to compare.
+            None => return Ok(vec![]),
+        },
+    };
+
+    Ok(db
+        .query_images_by_similarity(phash, threshold)
+        .await?
+        .into_iter()
+        .filter(|h| h != hash)
+        .collect())
+}
+
+/// Returns the archived images other than `hash` whose perceptual hash is within
+/// `max_distance` of `phash`, closest first.
+///
+/// The database only returns the matches, so the distance to each of them is
+/// computed from its recorded hash.
+pub(super) async fn near_duplicates(
+    db: &Database,
+    hash: &PixelHash,
+    phash: PHash,
+    max_distance: u32,
+) -> Result<Vec<NearDuplicate>, AppError> {
+    let mut near_duplicates = vec![];
+    for similar in db.query_images_by_similarity(phash, max_distance).await? {
+        if &similar == hash {
+            continue;
+        }
+        if let Some(other) = db.get_phash(&similar).await? {
+            near_duplicates.push(NearDuplicate {
+                hash: similar,
+                distance: phash.distance(&other),
+            });
+        }
+    }
+
+    Ok(near_duplicates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_similar_images;
+    use crate::{
+        app::{ArchiveImageCommand, ArchiveWarning, tests::png_bytes},
+        database::{Database, MIGRATOR, Pool},
+        storage::Storage,
+    };
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_find_similar_images(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        // Flat images have no brightness changes, so they all look alike.
+        let first = ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        let second = ArchiveImageCommand::new(&png_bytes(2))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+        ArchiveImageCommand::new(include_bytes!("../../testdata/44a5b6f94f4f6445.png"))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![second.hash.clone()],
+            find_similar_images(&db, &storage, &first.hash, 0)
+                .await
+                .unwrap()
+        );
+
+        // An image without a recorded perceptual hash is hashed from storage.
+        let (unrecorded, _) = storage.create_file(&png_bytes(3)).unwrap();
+        db.ensure_image(&unrecorded).await.unwrap();
+        let mut expected = vec![first.hash, second.hash];
+        expected.sort();
+        assert_eq!(
+            expected,
+            find_similar_images(&db, &storage, &unrecorded, 0)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_archive_duplicate_check(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+        let near_duplicates = |warnings: Vec<ArchiveWarning>| {
+            warnings
+                .into_iter()
+                .filter(|w| matches!(w, ArchiveWarning::NearDuplicate { .. }))
+                .collect::<Vec<_>>()
+        };
+
+        let first = ArchiveImageCommand::new(&png_bytes(1))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        let unchecked = ArchiveImageCommand::new(&png_bytes(2))
+            .execute_with_warnings(&storage, &db)
+            .await
+            .unwrap();
+        assert!(near_duplicates(unchecked.warnings).is_empty());
+
+        let checked = ArchiveImageCommand::new(&png_bytes(3))
+            .with_duplicate_check(0)
+            .execute_with_warnings(&storage, &db)
+            .await
+            .unwrap();
+        let mut expected = vec![first.hash, unchecked.media.hash];
+        expected.sort();
+        assert_eq!(
+            expected
+                .into_iter()
+                .map(|hash| ArchiveWarning::NearDuplicate { hash, distance: 0 })
+                .collect::<Vec<_>>(),
+            near_duplicates(checked.warnings)
+        );
+
+        let distinct =
+            ArchiveImageCommand::new(include_bytes!("../../testdata/44a5b6f94f4f6445.png"))
+                .with_duplicate_check(0)
+                .execute_with_warnings(&storage, &db)
+                .await
+                .unwrap();
+        assert!(near_duplicates(distinct.warnings).is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+//! Validation of source URLs.
+//!
+//! Sources are stored as given by default. A `SourcePolicy` can require sources to
+//! be URLs with an allowed scheme, and restrict the hosts they point to, so that
+//! garbage or `javascript:` links never reach the database. Use it with
+//! `ArchiveImageCommand::with_source_policy` or `attach_source_with_policy`.
+
+use super::AppError;
+use url::Url;
+
+/// Decides which source strings are accepted.
+///
+/// The default policy is permissive and accepts any string without parsing it. Any
+/// restriction makes the policy parse sources as URLs first.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SourcePolicy {
+    schemes: Vec<String>,
+    allowed_hosts: Option<Vec<String>>,
+    denied_hosts: Vec<String>,
+}
+
+impl SourcePolicy {
+    /// Creates a permissive policy that accepts any string.
+    pub fn permissive() -> SourcePolicy {
+        SourcePolicy::default()
+    }
+
+    /// Creates a policy that accepts `http` and `https` URLs to any host.
+    pub fn web() -> SourcePolicy {
+        SourcePolicy::default().with_schemes(["http", "https"])
+    }
+
+    /// Restricts sources to URLs with one of the given schemes.
+    ///
+    /// An empty list accepts URLs with any scheme.
+    pub fn with_schemes<S: Into<String>>(
+        mut self,
+        schemes: impl IntoIterator<Item = S>,
+    ) -> SourcePolicy {
+        self.schemes = schemes
+            .into_iter()
+            .map(|s| s.into().to_lowercase())
+            .collect();
+        self
+    }
+
+    /// Restricts sources to URLs pointing to one of the given hosts or their
+    /// subdomains.
+    pub fn with_allowed_hosts<S: Into<String>>(
+        mut self,
+        hosts: impl IntoIterator<Item = S>,
+    ) -> SourcePolicy {
+        self.allowed_hosts = Some(
+            hosts
+                .into_iter()
+                .map(|h| normalize_host(h.into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Rejects URLs pointing to one of the given hosts or their subdomains, even if
+    /// they are allowed.
+    pub fn with_denied_hosts<S: Into<String>>(
+        mut self,
+        hosts: impl IntoIterator<Item = S>,
+    ) -> SourcePolicy {
+        self.denied_hosts = hosts
+            .into_iter()
+            .map(|h| normalize_host(h.into()))
+            .collect();
+        self
+    }
+
+    /// Returns whether the policy accepts any string.
+    pub fn is_permissive(&self) -> bool {
+        self.schemes.is_empty() && self.allowed_hosts.is_none() && self.denied_hosts.is_empty()
+    }
+
+    /// Checks a source against the policy.
+    ///
+    /// # Returns
+    /// * `Ok(())` if the policy accepts the source.
+    /// * `Err(reason)` if the source is not a URL or violates a restriction.
+    pub fn check(&self, src: &str) -> Result<(), String> {
+        if self.is_permissive() {
+            return Ok(());
+        }
+
+        let url = Url::parse(src).map_err(|e| e.to_string())?;
+
+        if !self.schemes.is_empty() && !self.schemes.iter().any(|s| s == url.scheme()) {
+            return Err(format!("scheme '{}' is not allowed", url.scheme()));
+        }
+
+        if self.allowed_hosts.is_none() && self.denied_hosts.is_empty() {
+            return Ok(());
+        }
+
+        let host = url
+            .host_str()
+            .map(|h| normalize_host(h.to_string()))
+            .ok_or_else(|| "URL has no host".to_string())?;
+
+        if self.denied_hosts.iter().any(|h| matches_host(&host, h)) {
+            return Err(format!("host '{host}' is denied"));
+        }
+        if let Some(allowed) = &self.allowed_hosts
+            && !allowed.iter().any(|h| matches_host(&host, h))
+        {
+            return Err(format!("host '{host}' is not allowed"));
+        }
+
+        Ok(())
+    }
+
+    /// Converts a rejected source into `AppError::InvalidSource`.
+    pub(super) fn invalid(src: &str, reason: String) -> AppError {
+        AppError::InvalidSource {
+            src: src.to_string(),
+            reason,
+        }
+    }
+}
+
+/// Lowercases a host and strips a trailing dot.
+fn normalize_host(host: String) -> String {
+    host.trim_end_matches('.').to_lowercase()
+}
+
+/// Returns whether the host is the given domain or one of its subdomains.
+fn matches_host(host: &str, domain: &str) -> bool {
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SourcePolicy;
+
+    #[test]
+    fn test_permissive_accepts_anything() {
+        let policy = SourcePolicy::default();
+
+        assert!(policy.is_permissive());
+        assert!(policy.check("javascript:alert(1)").is_ok());
+        assert!(policy.check("not a url").is_ok());
+    }
+
+    #[test]
+    fn test_web_policy() {
+        let policy = SourcePolicy::web();
+
+        assert!(policy.check("https://example.com/posts/1").is_ok());
+        assert!(policy.check("HTTP://example.com").is_ok());
+        assert_eq!(
+            Err("scheme 'javascript' is not allowed".to_string()),
+            policy.check("javascript:alert(1)")
+        );
+        assert!(policy.check("ftp://example.com/a.png").is_err());
+        assert!(policy.check("not a url").is_err());
+    }
+
+    #[test]
+    fn test_host_lists() {
+        let policy = SourcePolicy::web()
+            .with_allowed_hosts(["example.com", "pixiv.net"])
+            .with_denied_hosts(["ads.example.com"]);
+
+        assert!(policy.check("https://example.com/a").is_ok());
+        assert!(policy.check("https://www.Pixiv.net./a").is_ok());
+        assert!(policy.check("https://ads.example.com/a").is_err());
+        assert!(policy.check("https://badexample.com/a").is_err());
+        assert!(policy.check("https://example.org/a").is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Ahead-of-time generation of downscaled variants.
+//!
+//! Deployments without a resizing CDN serve variants straight from the storage root.
+//! `pregenerate_variants` generates every configured `VariantSize` of every image up
+//! front, trading disk space and a long-running job for lower request latency.
+//! Variants that already exist are skipped, so an interrupted run can simply be
+//! started again.
+
+use super::AppError;
+use crate::{
+    database::Database,
+    query::ImageQuery,
+    storage::{PixelHash, Storage, StorageError, VariantSize},
+};
+use std::{collections::HashMap, path::PathBuf};
+use tokio::task::{self, JoinSet};
+
+/// A variant that could not be generated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedVariant {
+    /// The hash of the image.
+    pub hash: PixelHash,
+    /// The size of the variant.
+    pub size: VariantSize,
+    /// A description of the error.
+    pub reason: String,
+}
+
+/// The outcome of `pregenerate_variants`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VariantReport {
+    /// The number of variants generated by this run.
+    pub generated: usize,
+    /// The number of variants that already existed.
+    pub skipped: usize,
+    /// Variants that could not be generated, in no particular order.
+    pub failed: Vec<FailedVariant>,
+}
+
+/// Generates every configured variant of every image that does not exist yet.
+///
+/// The sizes are configured with `Storage::with_variant_sizes`. Private images are
+/// included. A variant that fails to generate, e.g. because its file is corrupt, is
+/// recorded in the report and does not stop the run.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database to list the images from.
+/// * `storage` - Reference to the storage to generate the variants in.
+/// * `concurrency` - The maximum number of variants generated at once, at least 1.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `VariantReport`, or an `AppError` if the images
+/// cannot be listed.
+pub async fn pregenerate_variants(
+    db: &Database,
+    storage: &Storage,
+    concurrency: usize,
+) -> Result<VariantReport, AppError> {
+    let concurrency = concurrency.max(1);
+    let mut report = VariantReport::default();
+    let mut set = JoinSet::new();
+    let mut keys = HashMap::new();
+
+    let hashes = db.query_image(ImageQuery::all().with_private(true)).await?;
+    let pending = hashes.into_iter().flat_map(|hash| {
+        storage
+            .variant_sizes()
+            .iter()
+            .map(move |size| (hash.clone(), *size))
+    });
+
+    for (hash, size) in pending {
+        if storage.has_variant(&hash, size) {
+            report.skipped += 1;
+            continue;
+        }
+
+        if set.len() >= concurrency {
+            join_one(&mut set, &mut keys, &mut report).await;
+        }
+
+        let storage = storage.clone();
+        let key = (hash.clone(), size);
+        let task = set.spawn_blocking(move || storage.create_variant(&hash, size));
+        keys.insert(task.id(), key);
+    }
+
+    while !set.is_empty() {
+        join_one(&mut set, &mut keys, &mut report).await;
+    }
+
+    Ok(report)
+}
+
+/// Waits for the next finished task and records its outcome.
+///
+/// A task that panics is recorded as a failed variant like any other error.
+async fn join_one(
+    set: &mut JoinSet<Result<PathBuf, StorageError>>,
+    keys: &mut HashMap<task::Id, (PixelHash, VariantSize)>,
+    report: &mut VariantReport,
+) {
+    let (id, reason) = match set.join_next_with_id().await {
+        Some(Ok((_, Ok(_)))) => {
+            report.generated += 1;
+            return;
+        }
+        Some(Ok((id, Err(e)))) => (id, e.to_string()),
+        Some(Err(join_err)) => (join_err.id(), join_err.to_string()),
+        None => return,
+    };
+
+    if let Some((hash, size)) = keys.remove(&id) {
+        report.failed.push(FailedVariant { hash, size, reason });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pregenerate_variants;
+    use crate::{
+        app::{ArchiveImageCommand, tests::png_bytes},
+        database::{Database, MIGRATOR, Pool},
+        storage::{Storage, VariantSize},
+    };
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_pregenerate_variants(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let sizes = vec![VariantSize::new(2, 2), VariantSize::new(180, 180)];
+        let storage = Storage::new(dir.path().to_path_buf()).with_variant_sizes(sizes.clone());
+
+        let mut hashes = vec![];
+        for seed in 1..=3 {
+            let media = ArchiveImageCommand::new(&png_bytes(seed))
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+            hashes.push(media.hash);
+        }
+        db.set_visibility(&hashes[2], false).await.unwrap();
+
+        // One variant exists already.
+        storage.create_variant(&hashes[0], sizes[0]).unwrap();
+
+        let report = pregenerate_variants(&db, &storage, 2).await.unwrap();
+        assert_eq!(5, report.generated);
+        assert_eq!(1, report.skipped);
+        assert!(report.failed.is_empty());
+        for hash in &hashes {
+            for size in &sizes {
+                assert!(storage.has_variant(hash, *size));
+            }
+        }
+
+        // A corrupt file fails its variants only.
+        let path = dir
+            .path()
+            .join(storage.index_file(&hashes[1]).unwrap().content_path());
+        fs::write(path, b"corrupt").unwrap();
+        let extra = storage
+            .clone()
+            .with_variant_sizes(vec![VariantSize::new(1, 1)]);
+
+        let report = pregenerate_variants(&db, &extra, 1).await.unwrap();
+        assert_eq!(2, report.generated);
+        assert_eq!(0, report.skipped);
+        assert_eq!(1, report.failed.len());
+        assert_eq!(hashes[1], report.failed[0].hash);
+
+        let again = pregenerate_variants(&db, &storage, 4).await.unwrap();
+        assert_eq!(0, again.generated);
+        assert_eq!(6, again.skipped);
+    }
+}
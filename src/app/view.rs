@@ -0,0 +1,184 @@
+//! Data for a gallery viewer page.
+//!
+//! A viewer shows one image together with links to its neighbors within the active
+//! query and its position in the results. `view_context` gathers all of it in one
+//! call. The neighbors are found with the keyset cursor of the image, and the
+//! position by counting the results ahead of it, so no query loads more than one
+//! hash however many images match.
+
+use super::{AppError, Media, find_image_by_hash};
+use crate::{
+    database::Database,
+    query::ImageQuery,
+    storage::{PixelHash, Storage},
+};
+
+/// An image and where it sits within a query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewContext {
+    /// The image being viewed.
+    pub media: Media,
+    /// The image before it in the query order, `None` at the first position.
+    pub prev: Option<PixelHash>,
+    /// The image after it in the query order, `None` at the last position.
+    pub next: Option<PixelHash>,
+    /// The zero-based position of the image within the query results, `None` if the
+    /// image does not match the query.
+    pub position: Option<usize>,
+    /// The number of images matching the query.
+    pub total: u64,
+}
+
+/// Loads an image along with its neighbors, position and total within a query.
+///
+/// The limit, offset and cursor of the query are ignored, so neighbors are found
+/// across pages. An image that does not match the query is still returned, without
+/// neighbors or position. Neither are returned with `OrderBy::Random`, which has no
+/// cursor to find them by.
+///
+/// # Arguments
+///
+/// * `db` - Reference to the database where the query will be executed.
+/// * `storage` - Reference to the storage system for image file access.
+/// * `query` - The query the viewer was opened from.
+/// * `hash` - The hash of the image being viewed.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the `ViewContext`, or an `AppError` if the image is
+/// not found or a query fails.
+pub async fn view_context(
+    db: &Database,
+    storage: &Storage,
+    query: ImageQuery,
+    hash: &PixelHash,
+) -> Result<ViewContext, AppError> {
+    let query = ImageQuery {
+        limit: None,
+        offset: None,
+        cursor: None,
+        ..query
+    };
+
+    let order = query.cursor_order();
+
+    let (media, cursor, total) = tokio::try_join!(
+        find_image_by_hash(db, storage, hash),
+        async { db.cursor_of(hash, &order).await.map_err(AppError::from) },
+        async { db.count_image(query.clone()).await.map_err(AppError::from) },
+    )?;
+    let mut context = ViewContext {
+        media,
+        prev: None,
+        next: None,
+        position: None,
+        total,
+    };
+    let Some(cursor) = cursor else {
+        return Ok(context);
+    };
+
+    let before = query.clone().before(cursor.clone());
+    let after = query.after(cursor);
+    let (ahead, behind, prev, next) = tokio::try_join!(
+        db.count_image(before.clone()),
+        db.count_image(after.clone()),
+        db.query_image(before.with_limit(1)),
+        db.query_image(after.with_limit(1)),
+    )?;
+
+    // The results on either side add up to the total only if the image matches too.
+    if ahead + behind + 1 == total {
+        context.prev = prev.into_iter().next();
+        context.next = next.into_iter().next();
+        context.position = Some(ahead as usize);
+    }
+
+    Ok(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::view_context;
+    use crate::{
+        app::{ArchiveImageCommand, tests::png_bytes},
+        database::{Database, MIGRATOR, Pool},
+        query::{ImageQuery, OrderBy, image::tag},
+        storage::Storage,
+    };
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_view_context(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+
+        for seed in 1..=3 {
+            ArchiveImageCommand::new(&png_bytes(seed))
+                .with_tags(vec!["cat".to_string()])
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+        }
+        let outside = ArchiveImageCommand::new(&png_bytes(4))
+            .execute(&storage, &db)
+            .await
+            .unwrap();
+
+        let query = ImageQuery::filter(tag("cat"))
+            .with_order(OrderBy::CreatedAtAsc)
+            .with_limit(1);
+        let ordered = db
+            .query_image(ImageQuery {
+                limit: None,
+                ..query.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(3, ordered.len());
+
+        let first = view_context(&db, &storage, query.clone(), &ordered[0])
+            .await
+            .unwrap();
+        assert_eq!(ordered[0], first.media.hash);
+        assert_eq!(None, first.prev);
+        assert_eq!(Some(ordered[1].clone()), first.next);
+        assert_eq!(Some(0), first.position);
+        assert_eq!(3, first.total);
+
+        let middle = view_context(&db, &storage, query.clone(), &ordered[1])
+            .await
+            .unwrap();
+        assert_eq!(Some(ordered[0].clone()), middle.prev);
+        assert_eq!(Some(ordered[2].clone()), middle.next);
+        assert_eq!(Some(1), middle.position);
+
+        let last = view_context(&db, &storage, query.clone(), &ordered[2])
+            .await
+            .unwrap();
+        assert_eq!(Some(ordered[1].clone()), last.prev);
+        assert_eq!(None, last.next);
+        assert_eq!(Some(2), last.position);
+
+        let unmatched = view_context(&db, &storage, query.clone(), &outside.hash)
+            .await
+            .unwrap();
+        assert_eq!(outside.hash, unmatched.media.hash);
+        assert_eq!(
+            (None, None, None),
+            (unmatched.prev, unmatched.next, unmatched.position)
+        );
+        assert_eq!(3, unmatched.total);
+
+        let random = query.with_order(OrderBy::Random);
+        let random = view_context(&db, &storage, random, &ordered[1])
+            .await
+            .unwrap();
+        assert_eq!(
+            (None, None, None),
+            (random.prev, random.next, random.position)
+        );
+        assert_eq!(3, random.total);
+    }
+}
@@ -0,0 +1,123 @@
+//! Advisories about archived images that are accepted but look like mis-uploads.
+//!
+//! Archiving never fails because of a warning. `ArchiveImageCommand::execute_with_warnings`
+//! returns the warnings alongside the archived `Media`, judged against the
+//! `WarningThresholds` of the command, so that the CLI or the web UI can point out e.g.
+//! a thumbnail uploaded instead of the full image.
+
+use super::Media;
+use crate::storage::PixelHash;
+use std::fmt;
+
+/// A condition of an archived image worth pointing out, but not worth rejecting it for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArchiveWarning {
+    /// The image is smaller than `WarningThresholds::min_dimension` on a side, and
+    /// likely a thumbnail.
+    TinyImage { width: u32, height: u32 },
+    /// The JPEG takes fewer bits per pixel than `WarningThresholds::min_jpeg_bits_per_pixel`,
+    /// so it was likely compressed heavily, e.g. by repeated re-uploads.
+    LowQualityJpeg { bits_per_pixel: f64 },
+    /// The tag is longer than `WarningThresholds::max_tag_length` characters, and
+    /// likely a sentence pasted by mistake.
+    LongTag { tag: String, length: usize },
+    /// An archived image looks like this one, as their perceptual hashes differ in
+    /// `distance` bits, see `ArchiveImageCommand::with_duplicate_check`.
+    NearDuplicate { hash: PixelHash, distance: u32 },
+}
+
+impl fmt::Display for ArchiveWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveWarning::TinyImage { width, height } => {
+                write!(f, "tiny image of {}x{} pixels", width, height)
+            }
+            ArchiveWarning::LowQualityJpeg { bits_per_pixel } => {
+                write!(
+                    f,
+                    "low quality JPEG at {:.2} bits per pixel",
+                    bits_per_pixel
+                )
+            }
+            ArchiveWarning::LongTag { tag, length } => {
+                write!(f, "tag of {} characters: {}", length, tag)
+            }
+            ArchiveWarning::NearDuplicate { hash, distance } => {
+                write!(
+                    f,
+                    "looks like archived image {} ({} bits apart)",
+                    hash, distance
+                )
+            }
+        }
+    }
+}
+
+/// The limits beyond which `ArchiveWarning`s are raised, except near-duplicates, which
+/// need the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WarningThresholds {
+    /// Images narrower or lower than this many pixels are tiny.
+    pub min_dimension: u32,
+    /// JPEGs with fewer bits per pixel than this are low quality.
+    pub min_jpeg_bits_per_pixel: f64,
+    /// Tags with more characters than this are long.
+    pub max_tag_length: usize,
+}
+
+impl Default for WarningThresholds {
+    fn default() -> Self {
+        WarningThresholds {
+            min_dimension: 64,
+            min_jpeg_bits_per_pixel: 0.3,
+            max_tag_length: 64,
+        }
+    }
+}
+
+impl WarningThresholds {
+    /// Returns the warnings raised by an archived image and the tags it was given.
+    pub fn check(&self, media: &Media, tags: &[String]) -> Vec<ArchiveWarning> {
+        let mut warnings = vec![];
+        let metadata = &media.metadata;
+
+        if metadata.width > 0
+            && metadata.height > 0
+            && metadata.width.min(metadata.height) < self.min_dimension
+        {
+            warnings.push(ArchiveWarning::TinyImage {
+                width: metadata.width,
+                height: metadata.height,
+            });
+        }
+
+        let pixels = u64::from(metadata.width) * u64::from(metadata.height);
+        if matches!(metadata.format.as_str(), "jpg" | "jpeg") && pixels > 0 {
+            let bits_per_pixel = (metadata.file_size * 8) as f64 / pixels as f64;
+            if bits_per_pixel < self.min_jpeg_bits_per_pixel {
+                warnings.push(ArchiveWarning::LowQualityJpeg { bits_per_pixel });
+            }
+        }
+
+        for tag in tags {
+            let length = tag.chars().count();
+            if length > self.max_tag_length {
+                warnings.push(ArchiveWarning::LongTag {
+                    tag: tag.clone(),
+                    length,
+                });
+            }
+        }
+
+        warnings
+    }
+}
+
+/// The result of `ArchiveImageCommand::execute_with_warnings`.
+#[derive(Debug)]
+pub struct ArchiveOutcome {
+    /// The archived image.
+    pub media: Media,
+    /// The advisories about the image, empty if nothing looked off.
+    pub warnings: Vec<ArchiveWarning>,
+}
@@ -0,0 +1,96 @@
+//! # Capabilities Module
+//!
+//! This module describes what a particular deployment supports, so clients can
+//! discover compiled-in features, accepted search tokens, and configured limits
+//! instead of probing endpoints.
+//!
+//! `Capabilities` is assembled at runtime by `app::capabilities` from cfg flags,
+//! database probes, the parser's token registry, and the `Limits` supplied by the
+//! caller.
+//!
+//! ## Stability
+//!
+//! Serialized field names are part of the public API. Fields may be added in any
+//! release, but are never renamed or removed without bumping `API_VERSION`.
+
+use serde::Serialize;
+
+/// The version of the serialized capabilities format and the API it describes.
+pub const API_VERSION: u32 = 1;
+
+/// Describes what this deployment supports.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// The version of this crate.
+    pub version: String,
+    /// The API flavor the web layer imitates.
+    pub api_flavor: String,
+    /// See `API_VERSION`.
+    pub api_version: u32,
+    /// The database backend in use.
+    pub database: DatabaseInfo,
+    /// Optional features compiled into this build.
+    pub features: Features,
+    /// The search syntax accepted by the query parser.
+    pub search: SearchSyntax,
+    /// Limits enforced by the active configuration.
+    pub limits: Limits,
+}
+
+/// The database backend in use.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DatabaseInfo {
+    /// The backend name, e.g. `sqlite` or `postgres`.
+    pub backend: String,
+    /// The version reported by the server, if the probe succeeded.
+    pub server_version: Option<String>,
+}
+
+/// Optional features compiled into this build.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Features {
+    /// Video archival with thumbnail extraction.
+    pub video: bool,
+    /// Full-text search over tags.
+    pub full_text_search: bool,
+    /// Regular expression tag queries.
+    pub regex_tags: bool,
+    /// Signed file URLs.
+    pub signed_urls: bool,
+}
+
+impl Features {
+    /// Returns the features compiled into this build.
+    pub fn compiled() -> Self {
+        Features {
+            video: true,
+            full_text_search: false,
+            regex_tags: false,
+            signed_urls: false,
+        }
+    }
+}
+
+/// The search syntax accepted by the query parser.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchSyntax {
+    /// Boolean keywords, e.g. `AND`.
+    pub keywords: Vec<String>,
+    /// Meta tokens, e.g. `is:animated`.
+    pub meta_tokens: Vec<String>,
+}
+
+/// Limits enforced by the active configuration. `None` means unlimited.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct Limits {
+    /// The maximum accepted upload size in bytes.
+    pub max_upload_bytes: Option<u64>,
+    /// The maximum number of results per page.
+    pub max_page_size: Option<u32>,
+    /// The maximum number of concurrent image decodes.
+    pub max_image_decodes: Option<usize>,
+    /// The maximum number of concurrent video thumbnail generations.
+    pub max_video_thumbnails: Option<usize>,
+    /// The maximum number of uploads waiting for a decode slot.
+    pub decode_queue_depth: Option<usize>,
+}
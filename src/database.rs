@@ -11,6 +11,9 @@
 //!   database operations with context to identify the failed operation.
 //! - Support for SQLx, particularly with SQLite for executing migrations and
 //!   querying the database.
+//! - Space reporting and compaction, see the `maintenance` submodule.
+//! - With the `metrics` feature, counters and latencies of every operation, see the
+//!   `instrument` submodule.
 //!
 //! The implementation is designed to be SQL dialect agnostic and
 //! leverages the `Dialect` trait, which encapsulates database-specific
@@ -25,22 +28,41 @@
 
 use crate::{
     dialect::{CurrentDialect, CurrentRow, Db, Dialect},
-    query::{ImageQuery, TagQuery},
-    storage::{ImageMetadata, PixelHash},
+    query::{Cursor, ImageQuery, OrderBy, TagQuery},
+    storage::{ExifData, HashPrefix, ImageMetadata, MAX_PREFIX_MATCHES, PHash, PixelHash},
 };
 use chrono::{DateTime, Utc};
+use futures::StreamExt;
 use sqlx::{Execute, FromRow, Row};
-use std::str::FromStr;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    ops::ControlFlow,
+    str::FromStr,
+};
 use thiserror::Error;
 
+#[cfg(feature = "metrics")]
+mod instrument;
+mod maintenance;
+mod retry;
+
+pub use maintenance::{CompactMode, CompactOutcome, SpaceReport};
+pub use retry::RetryPolicy;
+
 pub type Pool = sqlx::Pool<Db>;
 
-#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+/// The scale factors between the sizes matched by `Database::find_resolution_variants`.
+pub const RESOLUTION_FACTORS: [u32; 3] = [2, 3, 4];
+
+#[cfg(all(feature = "sqlite", not(any(feature = "postgres", feature = "mysql"))))]
 pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("migrations/sqlite");
 
-#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+#[cfg(all(feature = "postgres", not(any(feature = "sqlite", feature = "mysql"))))]
 pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("migrations/postgres");
 
+#[cfg(all(feature = "mysql", not(any(feature = "sqlite", feature = "postgres"))))]
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("migrations/mysql");
+
 /// Run database migrations using the pool provided.
 ///
 /// # Arguments
@@ -56,6 +78,181 @@ pub async fn run_migration(pool: &sqlx::Pool<Db>) -> Result<(), sqlx::Error> {
     Ok(())
 }
 
+/// The content rating of an image, as used by Danbooru.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rating {
+    /// Safe for work.
+    General,
+    /// Mildly suggestive.
+    Sensitive,
+    /// Not safe for work, but not explicit.
+    Questionable,
+    /// Explicit content.
+    Explicit,
+}
+
+impl Rating {
+    /// Every rating, from the mildest to the most explicit.
+    pub const ALL: [Rating; 4] = [
+        Rating::General,
+        Rating::Sensitive,
+        Rating::Questionable,
+        Rating::Explicit,
+    ];
+
+    /// Returns the single-letter code stored in the database, e.g. `g`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rating::General => "g",
+            Rating::Sensitive => "s",
+            Rating::Questionable => "q",
+            Rating::Explicit => "e",
+        }
+    }
+
+    /// Returns the full name of the rating, e.g. `general`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rating::General => "general",
+            Rating::Sensitive => "sensitive",
+            Rating::Questionable => "questionable",
+            Rating::Explicit => "explicit",
+        }
+    }
+}
+
+impl FromStr for Rating {
+    type Err = String;
+
+    /// Parses the single-letter code or the full name, e.g. `g` or `general`.
+    ///
+    /// `safe` is accepted for `General`, its name before Danbooru split off
+    /// `Sensitive`. Its old code `s` is not, since it now stands for `Sensitive`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "safe" {
+            return Ok(Rating::General);
+        }
+
+        Rating::ALL
+            .into_iter()
+            .find(|rating| rating.as_str() == s || rating.name() == s)
+            .ok_or_else(|| format!("unknown rating: {s}"))
+    }
+}
+
+/// The category of a tag, as used by Danbooru.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TagCategory {
+    /// Describes what is depicted, the category of new tags.
+    #[default]
+    General,
+    /// Names the creator of the image.
+    Artist,
+    /// Names a character depicted in the image.
+    Character,
+    /// Names the work or franchise the image belongs to.
+    Copyright,
+    /// Describes the image itself rather than its content, e.g. `highres`.
+    Meta,
+}
+
+impl TagCategory {
+    /// Every category, in the order Danbooru lists them.
+    pub const ALL: [TagCategory; 5] = [
+        TagCategory::General,
+        TagCategory::Artist,
+        TagCategory::Character,
+        TagCategory::Copyright,
+        TagCategory::Meta,
+    ];
+
+    /// Returns the name of the category stored in the database, e.g. `artist`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TagCategory::General => "general",
+            TagCategory::Artist => "artist",
+            TagCategory::Character => "character",
+            TagCategory::Copyright => "copyright",
+            TagCategory::Meta => "meta",
+        }
+    }
+}
+
+impl FromStr for TagCategory {
+    type Err = String;
+
+    /// Parses the name of the category, e.g. `artist`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TagCategory::ALL
+            .into_iter()
+            .find(|category| category.as_str() == s)
+            .ok_or_else(|| format!("unknown tag category: {s}"))
+    }
+}
+
+/// The order the tags of an image are listed in, see `Database::get_tags_ordered`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TagOrder {
+    /// The canonical order of `canonical_tags`, ascending by Unicode code point.
+    #[default]
+    Alphabetical,
+    /// The order the tags were attached in. Tags attached at once, and tags attached
+    /// before their time was recorded, are in alphabetical order among themselves.
+    InsertedAt,
+}
+
+/// Everything recorded about an image besides its file, see `Database::get_images_bulk`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageRecord {
+    /// The metadata, or `None` if none is recorded.
+    pub metadata: Option<ImageMetadata>,
+    /// The tags and their categories, sorted by tag.
+    pub tags: Vec<(String, TagCategory)>,
+    /// The source URLs, in the order they were added.
+    pub sources: Vec<String>,
+    pub rating: Option<Rating>,
+    pub is_public: bool,
+}
+
+impl Default for ImageRecord {
+    /// An image that is not recorded: public, unrated and without tags or sources.
+    fn default() -> Self {
+        ImageRecord {
+            metadata: None,
+            tags: vec![],
+            sources: vec![],
+            rating: None,
+            is_public: true,
+        }
+    }
+}
+
+/// The wiki entry of a tag, describing what the tag means.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagWiki {
+    /// The tag the entry describes.
+    pub tag: String,
+    /// The description, as entered.
+    pub body: String,
+    /// When the description was last set.
+    pub updated_at: DateTime<Utc>,
+}
+
+impl FromRow<'_, CurrentRow> for TagWiki {
+    fn from_row(row: &CurrentRow) -> Result<Self, sqlx::Error> {
+        let updated_at: String = row.try_get("updated_at")?;
+
+        Ok(TagWiki {
+            tag: row.try_get("tag_name")?,
+            body: row.try_get("body")?,
+            updated_at: DateTime::from_str(&updated_at).map_err(|e| sqlx::Error::ColumnDecode {
+                index: "updated_at".to_string(),
+                source: Box::new(e),
+            })?,
+        })
+    }
+}
+
 impl FromRow<'_, CurrentRow> for ImageMetadata {
     fn from_row(row: &CurrentRow) -> Result<Self, sqlx::Error> {
         let width: i32 = row.try_get("width")?;
@@ -63,9 +260,25 @@ impl FromRow<'_, CurrentRow> for ImageMetadata {
         let format: String = row.try_get("format")?;
         let color_type: String = row.try_get("color_type")?;
         let file_size: i64 = row.try_get("file_size")?;
-        let created_at: String = row.try_get("created_at")?;
-        let created_at = DateTime::from_str(&created_at).expect("");
+        let created_at: Option<String> = row.try_get("created_at")?;
+        let created_at = created_at
+            .map(|t| DateTime::from_str(&t))
+            .transpose()
+            .map_err(|e| sqlx::Error::ColumnDecode {
+                index: "created_at".to_string(),
+                source: Box::new(e),
+            })?;
         let duration: Option<f64> = row.try_get("duration")?;
+        let taken_at: Option<String> = row.try_get("taken_at")?;
+        let orientation: Option<i16> = row.try_get("orientation")?;
+        let exif = ExifData {
+            camera_make: row.try_get("camera_make")?,
+            camera_model: row.try_get("camera_model")?,
+            gps_lat: row.try_get("gps_lat")?,
+            gps_lon: row.try_get("gps_lon")?,
+            taken_at: taken_at.and_then(|t| DateTime::from_str(&t).ok()),
+            exposure: row.try_get("exposure")?,
+        };
 
         Ok(ImageMetadata {
             width: width as u32,
@@ -73,12 +286,52 @@ impl FromRow<'_, CurrentRow> for ImageMetadata {
             format,
             color_type,
             file_size: file_size as u64,
-            created_at: Some(created_at),
+            created_at,
             duration,
+            exif: (exif != ExifData::default()).then(|| Box::new(exif)),
+            orientation: orientation.and_then(|o| u8::try_from(o).ok()),
+            digest: row.try_get("digest")?,
+        })
+    }
+}
+
+/// A row of `query_images_with_metadata_by_images_statement`.
+struct ImageRecordRow {
+    hash: String,
+    is_public: bool,
+    rating: Option<String>,
+    metadata: Option<ImageMetadata>,
+}
+
+impl FromRow<'_, CurrentRow> for ImageRecordRow {
+    fn from_row(row: &CurrentRow) -> Result<Self, sqlx::Error> {
+        // The metadata columns are all `NULL` if the image has no metadata.
+        let image_hash: Option<String> = row.try_get("image_hash")?;
+
+        Ok(ImageRecordRow {
+            hash: row.try_get("hash")?,
+            is_public: row.try_get("is_public")?,
+            rating: row.try_get("rating")?,
+            metadata: match image_hash {
+                Some(_) => Some(ImageMetadata::from_row(row)?),
+                None => None,
+            },
         })
     }
 }
 
+/// Sorts tags into the canonical order and removes duplicates.
+///
+/// The canonical order is ascending by Unicode code point, i.e. plain `String`
+/// ordering, so it is case-sensitive and independent of locale and database
+/// collation. Every tag list returned to callers goes through this function.
+pub fn canonical_tags<I: IntoIterator<Item = String>>(tags: I) -> Vec<String> {
+    tags.into_iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
 /// A database abstraction for storing and querying image-tag relationships.
 ///
 /// This struct wraps an SQLx connection pool and provides high-level methods
@@ -87,37 +340,129 @@ impl FromRow<'_, CurrentRow> for ImageMetadata {
 #[derive(Debug, Clone)]
 pub struct Database {
     pub pool: Pool,
+    read_only: bool,
+    retry_policy: RetryPolicy,
+    /// The operation of every attempt so far, for tests counting statements.
+    #[cfg(test)]
+    attempts: std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>,
 }
 
 impl Database {
     pub fn new(pool: sqlx::Pool<Db>) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            read_only: false,
+            retry_policy: RetryPolicy::default(),
+            #[cfg(test)]
+            attempts: Default::default(),
+        }
+    }
+
+    /// Returns the operations attempted since the last call, in order.
+    #[cfg(test)]
+    pub(crate) fn take_attempts(&self) -> Vec<&'static str> {
+        std::mem::take(&mut self.attempts.lock().unwrap())
+    }
+
+    /// Refuses all writes when `read_only` is set.
+    ///
+    /// Write methods then return `DatabaseError::ReadOnly` without touching the
+    /// database, while reads proceed. This protects e.g. a mounted backup from
+    /// accidental mutation.
+    ///
+    /// # Arguments
+    ///
+    /// * `read_only` - Whether writes are refused.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets how operations failing with a transient error are retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry_policy` - The number of attempts and the backoff between them.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Returns whether writes are refused.
+    ///
+    /// Callers that write to other systems before the database, such as the
+    /// storage, check this first so a refused write leaves nothing behind.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
     pub async fn migrate(&self) -> Result<(), sqlx::Error> {
         run_migration(&self.pool).await
     }
 
-    async fn retry<F, Fut, T>(&self, mut op: F) -> Result<T, DatabaseError>
+    /// Runs a database operation, retrying transient failures as the `RetryPolicy`
+    /// of this database allows.
+    ///
+    /// With the `metrics` feature, every attempt is recorded under `operation`, which
+    /// is the name of the calling method.
+    async fn retry<F, Fut, T>(
+        &self,
+        #[cfg_attr(not(any(test, feature = "metrics")), allow(unused_variables))]
+        operation: &'static str,
+        mut op: F,
+    ) -> Result<T, DatabaseError>
     where
         F: FnMut() -> Fut,
         Fut: std::future::Future<Output = Result<T, DatabaseError>>,
     {
-        let max_retries = 3;
-        for attempt in 0..max_retries {
+        let mut attempt = 0;
+        loop {
+            #[cfg(feature = "metrics")]
+            let started = std::time::Instant::now();
+
             let result = op().await;
+
+            #[cfg(test)]
+            self.attempts.lock().unwrap().push(operation);
+
+            #[cfg(feature = "metrics")]
+            instrument::record_attempt(operation, attempt, started.elapsed(), result.is_ok());
+
             match result {
                 Ok(v) => return Ok(v),
-                Err(ref e) if e.is_retryable() && attempt + 1 < max_retries => {
-                    // backoff: simple fixed delay or exponential if needed
-                    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
-                    continue;
+                Err(ref e) if e.is_retryable() && attempt + 1 < self.retry_policy.max_attempts => {
+                    tokio::time::sleep(self.retry_policy.delay(attempt)).await;
+                    attempt += 1;
                 }
                 Err(e) => return Err(e),
             }
         }
+    }
+
+    /// Returns the name of the database backend this crate was compiled for.
+    pub fn backend_name(&self) -> &'static str {
+        CurrentDialect::name()
+    }
 
-        unreachable!("Retry loop should return before exceeding max_retries")
+    /// Probes the database server for its version string.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the version reported by the server.
+    pub async fn server_version(&self) -> Result<String, DatabaseError> {
+        let stmt = CurrentDialect::server_version_statement();
+
+        self.retry("server_version", || async {
+            sqlx::query_scalar(&stmt)
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::ProbeServer,
+                    sql: stmt.to_string(),
+                    source: e,
+                })
+        })
+        .await
     }
 
     /// Determines if an image exists in the database by its pixel hash.
@@ -139,7 +484,7 @@ impl Database {
         let stmt = CurrentDialect::exists_image();
 
         let res = self
-            .retry(|| async {
+            .retry("image_exists", || async {
                 let query = sqlx::query_scalar(&stmt).bind(hash.clone().to_string());
                 let sql = query.sql();
                 query
@@ -156,6 +501,202 @@ impl Database {
         Ok(res)
     }
 
+    /// Returns the subset of the given hashes that exist in the `images` table and are
+    /// not soft-deleted.
+    ///
+    /// Hashes are looked up with `IN` queries, split into chunks so that no single
+    /// statement exceeds the dialect's bind parameter limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` - The pixel hashes to check.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the set of hashes of active images in the database.
+    pub async fn filter_existing(
+        &self,
+        hashes: &[PixelHash],
+    ) -> Result<HashSet<PixelHash>, DatabaseError> {
+        let mut existing = HashSet::new();
+
+        for chunk in hashes.chunks(CurrentDialect::max_bind_params()) {
+            let stmt = CurrentDialect::filter_existing_images_statement(chunk.len());
+
+            let rows = self
+                .retry("filter_existing", || async {
+                    let mut q = sqlx::query_scalar::<_, String>(&stmt);
+
+                    for hash in chunk {
+                        q = q.bind(hash.to_string());
+                    }
+
+                    q.fetch_all(&self.pool)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::QueryImages,
+                            sql: stmt.to_string(),
+                            source: e,
+                        })
+                })
+                .await?;
+
+            existing.extend(rows.into_iter().filter_map(|s| PixelHash::try_from(s).ok()));
+        }
+
+        Ok(existing)
+    }
+
+    /// Lists every recorded image, in ascending order.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the hashes of all images, possibly empty.
+    pub async fn list_images(&self) -> Result<Vec<PixelHash>, DatabaseError> {
+        let stmt = CurrentDialect::query_all_images_statement();
+
+        let rows = self
+            .retry("list_images", || async {
+                sqlx::query_scalar::<_, String>(&stmt)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|s| PixelHash::try_from(s).ok())
+            .collect())
+    }
+
+    /// Lists recorded images that have no metadata, in ascending order.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the hashes of images without metadata, possibly empty.
+    pub async fn images_without_metadata(&self) -> Result<Vec<PixelHash>, DatabaseError> {
+        let stmt = CurrentDialect::query_images_without_metadata_statement();
+
+        let rows = self
+            .retry("images_without_metadata", || async {
+                sqlx::query_scalar::<_, String>(&stmt)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|s| PixelHash::try_from(s).ok())
+            .collect())
+    }
+
+    /// Finds images whose hash starts with the given prefix.
+    ///
+    /// At most `MAX_PREFIX_MATCHES` hashes are returned, in ascending order.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The hex prefix to match.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the matching hashes, possibly empty.
+    pub async fn find_by_hash_prefix(
+        &self,
+        prefix: &HashPrefix,
+    ) -> Result<Vec<PixelHash>, DatabaseError> {
+        let stmt = CurrentDialect::query_hash_prefix_statement();
+
+        let rows = self
+            .retry("find_by_hash_prefix", || async {
+                sqlx::query_scalar::<_, String>(&stmt)
+                    .bind(format!("{}%", prefix))
+                    .bind(MAX_PREFIX_MATCHES as i64)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|s| PixelHash::try_from(s).ok())
+            .collect())
+    }
+
+    /// Finds images that are likely the same image at another resolution.
+    ///
+    /// An image matches if it has the same aspect ratio and its width is the width
+    /// of the given image multiplied or divided by one of `RESOLUTION_FACTORS`, so
+    /// a 1000x500 image matches 2000x1000 and 500x250, but not 1500x750. Images of
+    /// the same size do not match.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image to compare against.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the matching hashes in ascending order, empty if the
+    /// image has no recorded metadata.
+    pub async fn find_resolution_variants(
+        &self,
+        hash: &PixelHash,
+    ) -> Result<Vec<PixelHash>, DatabaseError> {
+        let Some(metadata) = self.get_metadata(hash).await? else {
+            return Ok(vec![]);
+        };
+        let (width, height) = (metadata.width as i64, metadata.height as i64);
+
+        let mut dimensions = vec![];
+        for factor in RESOLUTION_FACTORS.map(i64::from) {
+            dimensions.push((width * factor, height * factor));
+            if width % factor == 0 && height % factor == 0 {
+                dimensions.push((width / factor, height / factor));
+            }
+        }
+
+        let stmt = CurrentDialect::query_dimensions_statement(dimensions.len());
+
+        let rows = self
+            .retry("find_resolution_variants", || async {
+                let mut query = sqlx::query_scalar::<_, String>(&stmt);
+                for (width, height) in &dimensions {
+                    query = query.bind(width).bind(height);
+                }
+
+                query
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|s| PixelHash::try_from(s).ok())
+            .collect())
+    }
+
     /// Ensures that an image is present in the `images` table.
     ///
     /// This will insert the image hash if it does not already exist.
@@ -168,13 +709,17 @@ impl Database {
     ///
     /// This function returns a `Result` indicating success or failure.
     pub async fn ensure_image(&self, hash: &PixelHash) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
         if self.image_exists(hash).await? {
             return Ok(());
         }
 
         let stmt = CurrentDialect::ensure_image_statement();
 
-        self.retry(|| async {
+        self.retry("ensure_image", || async {
             let query = sqlx::query(&stmt).bind(hash.clone().to_string());
             let sql = query.sql();
             query
@@ -206,23 +751,76 @@ impl Database {
         hash: &PixelHash,
         metadata: &ImageMetadata,
     ) -> Result<(), DatabaseError> {
+        self.ensure_image_has_metadata_returning(hash, metadata)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Ensures that metadata is associated with an image and returns the stored metadata.
+    ///
+    /// Unlike `ensure_image_has_metadata`, this returns the row as persisted, which is
+    /// the existing row if metadata was already present. Dialects supporting `RETURNING`
+    /// do this in the insert itself. Otherwise, or if the insert was ignored, the row is
+    /// selected afterwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    /// * `metadata` - The metadata to insert.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the stored metadata.
+    pub async fn ensure_image_has_metadata_returning(
+        &self,
+        hash: &PixelHash,
+        metadata: &ImageMetadata,
+    ) -> Result<ImageMetadata, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
         self.ensure_image(hash).await?;
 
-        let stmt = CurrentDialect::ensure_metadata_statement();
+        match self.insert_metadata(hash, metadata).await? {
+            Some(inserted) => Ok(inserted),
+            None => self.fetch_metadata(hash).await,
+        }
+    }
 
-        self.retry(|| async {
-            let query = sqlx::query(&stmt)
+    /// Inserts the metadata of an image unless it has some already, returning the
+    /// inserted row if the dialect supports `RETURNING`.
+    async fn insert_metadata(
+        &self,
+        hash: &PixelHash,
+        metadata: &ImageMetadata,
+    ) -> Result<Option<ImageMetadata>, DatabaseError> {
+        let stmt = CurrentDialect::ensure_metadata_returning_statement()
+            .unwrap_or_else(CurrentDialect::ensure_metadata_statement);
+        let exif = metadata.exif.as_deref();
+
+        self.retry("ensure_image_has_metadata", || async {
+            let query = sqlx::query_as(&stmt)
                 .bind(hash.clone().to_string())
                 .bind(metadata.width as i64)
                 .bind(metadata.height as i64)
                 .bind(&metadata.format)
                 .bind(&metadata.color_type)
                 .bind(metadata.file_size as i64)
-                .bind(metadata.created_at.unwrap_or(Utc::now()).to_rfc3339())
-                .bind(metadata.duration);
+                .bind(metadata.created_at.map(|t| t.to_rfc3339()))
+                .bind(metadata.duration)
+                .bind(exif.and_then(|e| e.camera_make.as_deref()))
+                .bind(exif.and_then(|e| e.camera_model.as_deref()))
+                .bind(exif.and_then(|e| e.gps_lat))
+                .bind(exif.and_then(|e| e.gps_lon))
+                .bind(exif.and_then(|e| e.taken_at).map(|t| t.to_rfc3339()))
+                .bind(exif.and_then(|e| e.exposure))
+                .bind(metadata.orientation.map(i16::from))
+                .bind(metadata.digest.as_deref());
             let sql = query.sql();
             query
-                .execute(&self.pool)
+                .fetch_optional(&self.pool)
                 .await
                 .map_err(|e| DatabaseError::QueryFailed {
                     operation: DbOperation::InsertMetadata {
@@ -232,13 +830,14 @@ impl Database {
                     source: e,
                 })
         })
-        .await?;
-
-        Ok(())
+        .await
     }
 
     /// Ensures that a set of tags is present in the `tags` table.
     ///
+    /// The tags are inserted with as few statements as the bind parameter limit of
+    /// the database allows, in one transaction.
+    ///
     /// # Arguments
     ///
     /// * `tags` - A slice of tag strings to ensure existence in the database.
@@ -247,26 +846,36 @@ impl Database {
     ///
     /// A `Result` indicating success or failure.
     pub async fn ensure_tags(&self, tags: &[&str]) -> Result<(), DatabaseError> {
-        let stmt = CurrentDialect::ensure_tag_statement();
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        // One statement per chunk, staying within the bind parameter limit.
+        let chunks: Vec<_> = tags
+            .chunks(CurrentDialect::max_bind_params())
+            .map(|chunk| (chunk, CurrentDialect::ensure_tags_statement(chunk.len())))
+            .collect();
 
-        self.retry(|| async {
+        self.retry("ensure_tags", || async {
             let mut tx = self
                 .pool
                 .begin()
                 .await
                 .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-            for tag in tags.iter() {
-                let query = sqlx::query(&stmt).bind(tag);
-                let sql = query.sql();
+            for (chunk, stmt) in &chunks {
+                let mut query = sqlx::query(stmt);
+                for tag in chunk.iter() {
+                    query = query.bind(tag);
+                }
                 query
                     .execute(&mut *tx)
                     .await
                     .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::InsertTag {
-                            tag: tag.to_string(),
+                        operation: DbOperation::InsertTags {
+                            tags: chunk.iter().map(|t| t.to_string()).collect(),
                         },
-                        sql: sql.to_string(),
+                        sql: stmt.to_string(),
                         source: e,
                     })?;
             }
@@ -279,47 +888,64 @@ impl Database {
         Ok(())
     }
 
-    /// Ensures that an image is associated with given tags.
+    /// Ensures that a set of tags is present in the `tags` table with the given
+    /// categories.
+    ///
+    /// Missing tags are created, and existing tags are moved to the given category,
+    /// all in one transaction.
     ///
     /// # Arguments
     ///
-    /// * `hash` - The pixel hash of the image.
-    /// * `tags` - A slice of tag strings to associate with the image.
+    /// * `tags` - Pairs of a tag and the category it belongs to.
     ///
     /// # Returns
     ///
     /// A `Result` indicating success or failure.
-    pub async fn ensure_image_has_tags(
+    pub async fn ensure_tags_with_categories(
         &self,
-        hash: &PixelHash,
-        tags: &[&str],
+        tags: &[(&str, TagCategory)],
     ) -> Result<(), DatabaseError> {
-        self.ensure_image(hash).await?;
-        self.ensure_tags(tags).await?;
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
 
-        let stmt = CurrentDialect::ensure_image_tag_statement();
+        let ensure_stmt = CurrentDialect::ensure_tag_statement();
+        let update_stmt = CurrentDialect::update_tag_category_statement();
 
-        self.retry(|| async {
+        self.retry("ensure_tags_with_categories", || async {
             let mut tx = self
                 .pool
                 .begin()
                 .await
                 .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-            for tag in tags.iter() {
-                let query = sqlx::query(&stmt).bind(hash.to_string()).bind(tag);
+            for (tag, category) in tags.iter() {
+                let query = sqlx::query(&ensure_stmt).bind(tag);
                 let sql = query.sql();
                 query
                     .execute(&mut *tx)
                     .await
                     .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::InsertImageTag {
-                            hash: hash.clone(),
+                        operation: DbOperation::InsertTag {
                             tag: tag.to_string(),
                         },
                         sql: sql.to_string(),
                         source: e,
                     })?;
+
+                let query = sqlx::query(&update_stmt).bind(category.as_str()).bind(tag);
+                let sql = query.sql();
+                query
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::UpdateTagCategory {
+                            tag: tag.to_string(),
+                            category: *category,
+                        },
+                        sql: sql.to_string(),
+                        source: e,
+                    })?;
             }
 
             tx.commit()
@@ -327,198 +953,212 @@ impl Database {
                 .map_err(|e| DatabaseError::TransactionFailed { source: e })
         })
         .await?;
-
         Ok(())
     }
 
-    /// Ensures that an image is associated with a source string.
+    /// Ensures that an image is associated with given tags.
+    ///
+    /// The tags implied by the given ones are associated as well, see
+    /// `implied_tags`. Like `ensure_tags`, the associations are inserted with as few
+    /// statements as the bind parameter limit allows.
     ///
     /// # Arguments
     ///
     /// * `hash` - The pixel hash of the image.
-    /// * `source` - The source string to associate with the image.
+    /// * `tags` - A slice of tag strings to associate with the image.
     ///
     /// # Returns
     ///
     /// A `Result` indicating success or failure.
-    pub async fn ensure_image_has_source(
+    pub async fn ensure_image_has_tags(
         &self,
         hash: &PixelHash,
-        source: &str,
+        tags: &[&str],
     ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let tags = self.implied_tags(tags).await?;
+        let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+
         self.ensure_image(hash).await?;
+        self.ensure_tags(&tags).await?;
+
+        // Every row binds the hash and a tag, so a chunk holds half as many tags as
+        // the bind parameter limit.
+        let chunks: Vec<_> = tags
+            .chunks(CurrentDialect::max_bind_params() / 2)
+            .map(|chunk| {
+                let stmt = CurrentDialect::ensure_image_tags_statement(chunk.len());
+                (chunk, stmt)
+            })
+            .collect();
 
-        let stmt = CurrentDialect::update_source_statement();
+        self.retry("ensure_image_has_tags", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-        self.retry(|| async {
-            let query = sqlx::query(&stmt)
-                .bind(source)
-                .bind(hash.clone().to_string());
-            let sql = query.sql();
+            for (chunk, stmt) in &chunks {
+                let mut query = sqlx::query(stmt);
+                for tag in chunk.iter() {
+                    query = query.bind(hash.to_string()).bind(tag);
+                }
+                query
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::InsertImageTags {
+                            hash: hash.clone(),
+                            tags: chunk.iter().map(|t| t.to_string()).collect(),
+                        },
+                        sql: stmt.to_string(),
+                        source: e,
+                    })?;
+            }
 
-            query
-                .execute(&self.pool)
+            tx.commit()
                 .await
-                .map_err(|e| DatabaseError::QueryFailed {
-                    operation: DbOperation::UpdateImageSource {
-                        hash: hash.clone(),
-                        source: source.to_string(),
-                    },
-                    sql: sql.to_string(),
-                    source: e,
-                })
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })
         })
         .await?;
 
         Ok(())
     }
 
-    /// Performs a tag-based query on images using an expression tree.
+    /// Ensures that an image is associated with a source string.
+    ///
+    /// The source replaces any sources the image had, see `set_sources` and
+    /// `add_source`.
     ///
     /// # Arguments
     ///
-    /// * `query` - The query expression representing the image search criteria.
+    /// * `hash` - The pixel hash of the image.
+    /// * `source` - The source string to associate with the image.
     ///
     /// # Returns
     ///
-    /// A `Result` containing a vector of image hashes that match the query.
-    pub async fn query_image(&self, query: ImageQuery) -> Result<Vec<PixelHash>, DatabaseError> {
-        let (sql, params) = query.to_sql();
-        let stmt = CurrentDialect::query_image_statement(sql);
-
-        let hashes = self
-            .retry(|| async {
-                let mut q = sqlx::query_scalar::<_, String>(&stmt);
-
-                for param in &params {
-                    q = q.bind(param);
-                }
-
-                q.fetch_all(&self.pool)
-                    .await
-                    .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::QueryImages,
-                        sql: stmt.to_string(),
-                        source: e,
-                    })
-            })
-            .await?
-            .into_iter()
-            .filter_map(|s| PixelHash::try_from(s).ok())
-            .collect();
-
-        Ok(hashes)
+    /// A `Result` indicating success or failure.
+    pub async fn ensure_image_has_source(
+        &self,
+        hash: &PixelHash,
+        source: &str,
+    ) -> Result<(), DatabaseError> {
+        self.set_sources(hash, &[source.to_string()]).await
     }
 
-    /// Performs a count of images that match a given query expression.
+    /// Replaces the sources of an image, in the given order.
+    ///
+    /// A missing image is inserted. Duplicate sources are recorded once, at their
+    /// first position.
     ///
     /// # Arguments
     ///
-    /// * `query` - The query expression representing the image search criteria.
+    /// * `hash` - The pixel hash of the image.
+    /// * `sources` - The sources of the image, most relevant first.
     ///
     /// # Returns
     ///
-    /// A `Result` containing the count of images that match the query.
-    pub async fn count_image(&self, query: ImageQuery) -> Result<u64, DatabaseError> {
-        let (sql, params) = query.to_sql();
-        let stmt = CurrentDialect::count_image_statement(sql);
-
-        let count = self
-            .retry(|| async {
-                let mut q = sqlx::query_scalar(&stmt);
-
-                for param in &params {
-                    q = q.bind(param);
-                }
-
-                // cast into signed because some DBs do not support unsigned types.
-                let count: i64 =
-                    q.fetch_one(&self.pool)
-                        .await
-                        .map_err(|e| DatabaseError::QueryFailed {
-                            operation: DbOperation::QueryImages,
-                            sql: stmt.to_string(),
-                            source: e,
-                        })?;
-
-                Ok(count as u64)
-            })
-            .await?;
-
-        Ok(count)
+    /// A `Result` indicating success or failure.
+    pub async fn set_sources(
+        &self,
+        hash: &PixelHash,
+        sources: &[String],
+    ) -> Result<(), DatabaseError> {
+        let sources = vec![(hash.clone(), sources.to_vec())];
+        self.replace_sources("set_sources", &sources).await
     }
 
-    /// Counts the number of images associated with a given tag.
+    /// Associates many images with source strings in a single transaction.
     ///
-    /// This method queries the database to find how many images are related
-    /// to the specified tag. It provides a simple way to retrieve statistics
-    /// about image-tag associations within the database.
+    /// Missing images are inserted, and existing sources are overwritten. Either all
+    /// sources are applied or none are.
     ///
     /// # Arguments
     ///
-    /// * `tag` - A string slice that holds the tag for which the image count
-    ///           is to be determined.
+    /// * `sources` - Pairs of an image hash and the source to associate with it.
     ///
     /// # Returns
     ///
-    /// This function returns a `Result` containing a `u64` representing the
-    /// count of images associated with the given tag. If an error occurs
-    /// during the query execution, the `Result` will contain a `DatabaseError`.
-    pub async fn count_image_by_tag(&self, tag: &str) -> Result<u64, DatabaseError> {
-        let stmt = CurrentDialect::count_image_by_tag_statement();
-
-        let count = self
-            .retry(|| async {
-                let q = sqlx::query_scalar(&stmt).bind(tag);
-
-                let count: i64 = q
-                    .fetch_optional(&self.pool)
-                    .await
-                    .map(|r| r.unwrap_or_default())
-                    .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::QueryImages,
-                        sql: stmt.to_string(),
-                        source: e,
-                    })?;
+    /// A `Result` indicating success or failure.
+    pub async fn set_sources_bulk(
+        &self,
+        sources: &[(PixelHash, String)],
+    ) -> Result<(), DatabaseError> {
+        let sources: Vec<(PixelHash, Vec<String>)> = sources
+            .iter()
+            .map(|(hash, source)| (hash.clone(), vec![source.clone()]))
+            .collect();
+        self.replace_sources("set_sources_bulk", &sources).await
+    }
 
-                Ok(count as u64)
-            })
-            .await?;
+    /// Replaces the sources of many images in a single transaction, inserting
+    /// missing images.
+    async fn replace_sources(
+        &self,
+        name: &'static str,
+        sources: &[(PixelHash, Vec<String>)],
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
 
-        Ok(count)
-    }
+        let ensure_stmt = CurrentDialect::ensure_image_statement();
+        let delete_stmt = CurrentDialect::delete_sources_by_image_statement();
+        let insert_stmt = CurrentDialect::insert_source_statement();
 
-    /// Refreshes the count of images associated with each tag in the database.
-    ///
-    /// This method recalculates the number of images associated with each tag and updates
-    /// the database to reflect the current counts. It's useful for maintaining accurate statistics
-    /// on image-tag associations after operations that change these relationships, such as adding
-    /// or removing tags from images.
-    ///
-    /// # Returns
-    ///
-    /// A `Result` indicating success or failure of the operation.
-    ///
-    /// On success, it returns `Ok(())`. On failure, it returns a `DatabaseError` with context
-    /// about the failed operation.
-    pub async fn refresh_image_count(&self) -> Result<(), DatabaseError> {
-        self.retry(|| async {
+        self.retry(name, || async {
             let mut tx = self
                 .pool
                 .begin()
                 .await
                 .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-            for stmt in CurrentDialect::refresh_tag_counts_statement() {
-                let q = sqlx::query(&stmt);
+            for (hash, urls) in sources {
+                let query = sqlx::query(&ensure_stmt).bind(hash.to_string());
+                let sql = query.sql();
+                query
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::InsertImage { hash: hash.clone() },
+                        sql: sql.to_string(),
+                        source: e,
+                    })?;
 
-                q.execute(&mut *tx)
+                let query = sqlx::query(&delete_stmt).bind(hash.to_string());
+                let sql = query.sql();
+                query
+                    .execute(&mut *tx)
                     .await
                     .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::QueryImages,
-                        sql: stmt.to_string(),
+                        operation: DbOperation::DeleteImageSources { hash: hash.clone() },
+                        sql: sql.to_string(),
                         source: e,
                     })?;
+
+                let mut seen = HashSet::new();
+                for url in urls.iter().filter(|url| seen.insert(url.as_str())) {
+                    let query = sqlx::query(&insert_stmt)
+                        .bind(hash.to_string())
+                        .bind(url)
+                        .bind(hash.to_string());
+                    let sql = query.sql();
+                    query
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::UpdateImageSource {
+                                hash: hash.clone(),
+                                source: url.clone(),
+                            },
+                            sql: sql.to_string(),
+                            source: e,
+                        })?;
+                }
             }
 
             tx.commit()
@@ -530,238 +1170,2189 @@ impl Database {
         Ok(())
     }
 
-    /// Performs a query on tags using a query expression tree.
+    /// Appends a source to the sources of an image.
+    ///
+    /// A missing image is inserted. A source the image already has keeps its
+    /// position.
     ///
     /// # Arguments
     ///
-    /// * `query` - The query expression representing the tag search criteria.
+    /// * `hash` - The pixel hash of the image.
+    /// * `source` - The source to add.
     ///
     /// # Returns
     ///
-    /// A `Result` containing a vector of tag strings that match the query.
-    pub async fn query_tags(&self, query: TagQuery) -> Result<Vec<String>, DatabaseError> {
-        let (sql, params) = query.to_sql();
-        let stmt = CurrentDialect::query_tag_statement(sql);
+    /// A `Result` containing `true` if the source was added, or `false` if the image
+    /// already had it.
+    pub async fn add_source(&self, hash: &PixelHash, source: &str) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
 
-        let hashes = self
-            .retry(|| async {
-                let mut q = sqlx::query_scalar::<_, String>(&stmt);
+        let operation = || DbOperation::UpdateImageSource {
+            hash: hash.clone(),
+            source: source.to_string(),
+        };
+        let ensure_stmt = CurrentDialect::ensure_image_statement();
+        let query_stmt = CurrentDialect::query_sources_statement();
+        let insert_stmt = CurrentDialect::insert_source_statement();
 
-                for param in &params {
-                    q = q.bind(param);
-                }
+        self.retry("add_source", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-                q.fetch_all(&self.pool)
-                    .await
-                    .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::QueryTags,
-                        sql: stmt.to_string(),
-                        source: e,
-                    })
-            })
-            .await?
-            .into_iter()
-            .collect();
+            sqlx::query(&ensure_stmt)
+                .bind(hash.to_string())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::InsertImage { hash: hash.clone() },
+                    sql: ensure_stmt.to_string(),
+                    source: e,
+                })?;
+
+            let existing: Vec<String> = sqlx::query_scalar(&query_stmt)
+                .bind(hash.to_string())
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: operation(),
+                    sql: query_stmt.to_string(),
+                    source: e,
+                })?;
+            if existing.iter().any(|url| url == source) {
+                return Ok(false);
+            }
+
+            sqlx::query(&insert_stmt)
+                .bind(hash.to_string())
+                .bind(source)
+                .bind(hash.to_string())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: operation(),
+                    sql: insert_stmt.to_string(),
+                    source: e,
+                })?;
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Removes a source from the sources of an image.
+    ///
+    /// The remaining sources keep their order.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    /// * `source` - The source to remove.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if the source was removed, or `false` if the image
+    /// did not have it.
+    pub async fn remove_source(
+        &self,
+        hash: &PixelHash,
+        source: &str,
+    ) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let stmt = CurrentDialect::delete_source_statement();
+
+        let result = self
+            .retry("remove_source", || async {
+                sqlx::query(&stmt)
+                    .bind(hash.to_string())
+                    .bind(source)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::RemoveImageSource {
+                            hash: hash.clone(),
+                            source: source.to_string(),
+                        },
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Associates many images with tags in a single transaction.
+    ///
+    /// Unlike `ensure_image_has_tags`, neither the images nor the tags are inserted,
+    /// so both must already exist, e.g. after `ensure_tags` with the union of the
+    /// tags. Either all associations are applied or none are.
+    ///
+    /// # Arguments
+    ///
+    /// * `tags` - Pairs of an image hash and the tags to associate with it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn add_tags_bulk(
+        &self,
+        tags: &[(PixelHash, Vec<String>)],
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let stmt = CurrentDialect::ensure_image_tag_statement();
+
+        self.retry("add_tags_bulk", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            for (hash, tags) in tags {
+                for tag in tags {
+                    let query = sqlx::query(&stmt).bind(hash.to_string()).bind(tag);
+                    let sql = query.sql();
+                    query
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::InsertImageTag {
+                                hash: hash.clone(),
+                                tag: tag.to_string(),
+                            },
+                            sql: sql.to_string(),
+                            source: e,
+                        })?;
+                }
+            }
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks or unmarks an image as featured.
+    ///
+    /// Featured images match `ImageQueryExpr::Featured` and sort first under
+    /// `OrderBy::FeaturedFirst`.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    /// * `featured` - Whether the image is featured.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn set_featured(
+        &self,
+        hash: &PixelHash,
+        featured: bool,
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        self.ensure_image(hash).await?;
+
+        let stmt = CurrentDialect::update_featured_statement();
+
+        self.retry("set_featured", || async {
+            let query = sqlx::query(&stmt)
+                .bind(featured)
+                .bind(hash.clone().to_string());
+            let sql = query.sql();
+
+            query
+                .execute(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::UpdateImageFeatured {
+                        hash: hash.clone(),
+                        featured,
+                    },
+                    sql: sql.to_string(),
+                    source: e,
+                })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sets whether an image is public.
+    ///
+    /// Private images are left out of `query_image` and `count_image` unless the
+    /// query includes them with `ImageQuery::with_private`.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    /// * `is_public` - Whether the image is public.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn set_visibility(
+        &self,
+        hash: &PixelHash,
+        is_public: bool,
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        self.ensure_image(hash).await?;
+
+        let stmt = CurrentDialect::update_visibility_statement();
+
+        self.retry("set_visibility", || async {
+            let query = sqlx::query(&stmt)
+                .bind(is_public)
+                .bind(hash.clone().to_string());
+            let sql = query.sql();
+
+            query
+                .execute(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::UpdateImageVisibility {
+                        hash: hash.clone(),
+                        is_public,
+                    },
+                    sql: sql.to_string(),
+                    source: e,
+                })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Retrieves whether an image is public.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing whether the image is public. Images that are not
+    /// recorded yet are public, as they will be once recorded.
+    pub async fn is_public(&self, hash: &PixelHash) -> Result<bool, DatabaseError> {
+        let stmt = CurrentDialect::query_visibility_statement();
+
+        let is_public: Option<bool> = self
+            .retry("is_public", || async {
+                let query = sqlx::query_scalar(&stmt).bind(hash.clone().to_string());
+                let sql = query.sql();
+
+                query
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: sql.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(is_public.unwrap_or(true))
+    }
+
+    /// Marks an image as deleted, keeping its records.
+    ///
+    /// Soft-deleted images are left out of `query_image` and `count_image` unless the
+    /// query includes them with `ImageQuery::including_deleted`.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if the image was marked, or `false` if it is not
+    /// recorded or already deleted.
+    pub async fn soft_delete_image(&self, hash: &PixelHash) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let stmt = CurrentDialect::soft_delete_image_statement();
+        let deleted_at = Utc::now().to_rfc3339();
+
+        let result = self
+            .retry("soft_delete_image", || async {
+                sqlx::query(&stmt)
+                    .bind(&deleted_at)
+                    .bind(hash.to_string())
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::UpdateImageDeleted {
+                            hash: hash.clone(),
+                            deleted: true,
+                        },
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Restores a soft-deleted image, see `soft_delete_image`.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if the image was restored, or `false` if it is
+    /// not recorded or not deleted.
+    pub async fn restore_image(&self, hash: &PixelHash) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let stmt = CurrentDialect::restore_image_statement();
+
+        let result = self
+            .retry("restore_image", || async {
+                sqlx::query(&stmt)
+                    .bind(hash.to_string())
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::UpdateImageDeleted {
+                            hash: hash.clone(),
+                            deleted: false,
+                        },
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Retrieves when an image was soft-deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the time the image was deleted, or `None` if it is not
+    /// deleted or not recorded.
+    pub async fn deleted_at(
+        &self,
+        hash: &PixelHash,
+    ) -> Result<Option<DateTime<Utc>>, DatabaseError> {
+        let stmt = CurrentDialect::query_deleted_at_statement();
+
+        let deleted_at: Option<Option<String>> = self
+            .retry("deleted_at", || async {
+                sqlx::query_scalar(&stmt)
+                    .bind(hash.to_string())
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(deleted_at
+            .flatten()
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+            .map(|d| d.with_timezone(&Utc)))
+    }
+
+    /// Performs a tag-based query on images using an expression tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query expression representing the image search criteria.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a vector of image hashes that match the query.
+    pub async fn query_image(&self, query: ImageQuery) -> Result<Vec<PixelHash>, DatabaseError> {
+        let (sql, params) = query.to_sql();
+        let stmt = CurrentDialect::query_image_statement(sql);
+
+        let hashes = self
+            .retry("query_image", || async {
+                let mut q = sqlx::query_scalar::<_, String>(&stmt);
+
+                for param in &params {
+                    q = q.bind(param);
+                }
+
+                q.fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?
+            .into_iter()
+            .filter_map(|s| PixelHash::try_from(s).ok())
+            .collect();
+
+        Ok(hashes)
+    }
+
+    /// Reads the values of the order keys of an image, to resume a query after it.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    /// * `order` - The order of the query, see `OrderBy::keys`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Cursor` of the image, or `None` if the image is not
+    /// recorded or the order is random.
+    pub async fn cursor_of(
+        &self,
+        hash: &PixelHash,
+        order: &OrderBy,
+    ) -> Result<Option<Cursor>, DatabaseError> {
+        let Some((columns, _)) = order.keys() else {
+            return Ok(None);
+        };
+        if columns.is_empty() {
+            return Ok(Some(Cursor::from(hash.clone())));
+        }
+        let stmt = CurrentDialect::query_order_keys_statement(columns);
+
+        let row = self
+            .retry("cursor_of", || async {
+                sqlx::query(&stmt)
+                    .bind(hash.to_string())
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        let keys = (0..columns.len())
+            .map(|i| row.try_get::<String, _>(i))
+            .collect::<Result<_, _>>()
+            .map_err(|e| DatabaseError::QueryFailed {
+                operation: DbOperation::QueryImages,
+                sql: stmt.to_string(),
+                source: e,
+            })?;
+
+        Ok(Some(Cursor {
+            hash: hash.clone(),
+            keys,
+        }))
+    }
+
+    /// Performs a count of images that match a given query expression.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query expression representing the image search criteria.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the count of images that match the query.
+    pub async fn count_image(&self, query: ImageQuery) -> Result<u64, DatabaseError> {
+        let (sql, params) = query.to_sql();
+        let stmt = CurrentDialect::count_image_statement(sql);
+
+        let count = self
+            .retry("count_image", || async {
+                let mut q = sqlx::query_scalar(&stmt);
+
+                for param in &params {
+                    q = q.bind(param);
+                }
+
+                // cast into signed because some DBs do not support unsigned types.
+                let count: i64 =
+                    q.fetch_one(&self.pool)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::QueryImages,
+                            sql: stmt.to_string(),
+                            source: e,
+                        })?;
+
+                Ok(count as u64)
+            })
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Counts the number of images associated with a given tag.
+    ///
+    /// This method queries the database to find how many images are related
+    /// to the specified tag. It provides a simple way to retrieve statistics
+    /// about image-tag associations within the database.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - A string slice that holds the tag for which the image count
+    ///           is to be determined.
+    ///
+    /// # Returns
+    ///
+    /// This function returns a `Result` containing a `u64` representing the
+    /// count of images associated with the given tag. If an error occurs
+    /// during the query execution, the `Result` will contain a `DatabaseError`.
+    pub async fn count_image_by_tag(&self, tag: &str) -> Result<u64, DatabaseError> {
+        let stmt = CurrentDialect::count_image_by_tag_statement();
+
+        let count = self
+            .retry("count_image_by_tag", || async {
+                let q = sqlx::query_scalar(&stmt).bind(tag);
+
+                let count: i64 = q
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map(|r| r.unwrap_or_default())
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })?;
+
+                Ok(count as u64)
+            })
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Refreshes the count of images associated with each tag in the database.
+    ///
+    /// This method recalculates the number of images associated with each tag and updates
+    /// the database to reflect the current counts. It's useful for maintaining accurate statistics
+    /// on image-tag associations after operations that change these relationships, such as adding
+    /// or removing tags from images.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure of the operation.
+    ///
+    /// On success, it returns `Ok(())`. On failure, it returns a `DatabaseError` with context
+    /// about the failed operation.
+    pub async fn refresh_image_count(&self) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        self.retry("refresh_image_count", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            for stmt in CurrentDialect::refresh_tag_counts_statement() {
+                let q = sqlx::query(&stmt);
+
+                q.execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })?;
+            }
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Visits every tag with the count of images it was attached to at the last
+    /// `refresh_image_count`, ordered by name.
+    ///
+    /// Rows are streamed from the database, so the tags are never collected in memory.
+    /// Tags created after the last refresh have a count of zero.
+    ///
+    /// # Arguments
+    ///
+    /// * `visit` - Called with each tag name and count. Stops the iteration on `Break`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Break` of `visit` if it stopped early, or a
+    /// `DatabaseError` if fetching a row fails.
+    pub async fn for_each_tag_count<B>(
+        &self,
+        mut visit: impl FnMut(&str, u64) -> ControlFlow<B>,
+    ) -> Result<ControlFlow<B>, DatabaseError> {
+        let stmt = CurrentDialect::query_tag_counts_statement();
+        let mut rows = sqlx::query_as::<_, (String, i64)>(&stmt).fetch(&self.pool);
+
+        while let Some(row) = rows.next().await {
+            let (name, count) = row.map_err(|e| DatabaseError::QueryFailed {
+                operation: DbOperation::QueryTags,
+                sql: stmt.to_string(),
+                source: e,
+            })?;
+            if let ControlFlow::Break(b) = visit(&name, count as u64) {
+                return Ok(ControlFlow::Break(b));
+            }
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Performs a query on tags using a query expression tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query expression representing the tag search criteria.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a vector of tag strings that match the query.
+    pub async fn query_tags(&self, query: TagQuery) -> Result<Vec<String>, DatabaseError> {
+        let (sql, params) = query.to_sql();
+        let stmt = CurrentDialect::query_tag_statement(sql);
+
+        let hashes = self
+            .retry("query_tags", || async {
+                let mut q = sqlx::query_scalar::<_, String>(&stmt);
+
+                for param in &params {
+                    q = q.bind(param);
+                }
+
+                q.fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryTags,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?
+            .into_iter()
+            .collect();
 
         Ok(hashes)
     }
 
-    /// Returns a list of tags associated with the given image hash.
-    ///
-    /// # Arguments
-    ///
-    /// * `hash` - The pixel hash of the image to lookup.
-    ///
-    /// # Returns
-    ///
-    /// A `Result` containing a vector of tag strings associated with the image.
-    pub async fn get_tags(&self, hash: &PixelHash) -> Result<Vec<String>, DatabaseError> {
-        let stmt = CurrentDialect::query_tags_by_image_statement();
+    /// Returns a list of tags associated with the given image hash, in canonical order.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image to lookup.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a vector of tag strings associated with the image.
+    pub async fn get_tags(&self, hash: &PixelHash) -> Result<Vec<String>, DatabaseError> {
+        let stmt = CurrentDialect::query_tags_by_image_statement();
+
+        let rows: Vec<String> = self
+            .retry("get_tags", || async {
+                sqlx::query_scalar(&stmt)
+                    .bind(hash.clone().to_string())
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(canonical_tags(rows))
+    }
+
+    /// Returns a list of tags associated with the given image hash, in the given order.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image to lookup.
+    /// * `order` - The `TagOrder` the tags are listed in.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a vector of tag strings associated with the image.
+    pub async fn get_tags_ordered(
+        &self,
+        hash: &PixelHash,
+        order: TagOrder,
+    ) -> Result<Vec<String>, DatabaseError> {
+        let stmt = match order {
+            TagOrder::Alphabetical => return self.get_tags(hash).await,
+            TagOrder::InsertedAt => {
+                CurrentDialect::query_tags_by_image_in_insertion_order_statement()
+            }
+        };
+
+        self.retry("get_tags_ordered", || async {
+            sqlx::query_scalar(&stmt)
+                .bind(hash.clone().to_string())
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::QueryImages,
+                    sql: stmt.to_string(),
+                    source: e,
+                })
+        })
+        .await
+    }
+
+    /// Returns the tags associated with the given image hash along with their
+    /// categories, in canonical order.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image to lookup.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a vector of tags and their categories. Tags of an unknown
+    /// category are reported as `TagCategory::General`.
+    pub async fn get_tags_with_categories(
+        &self,
+        hash: &PixelHash,
+    ) -> Result<Vec<(String, TagCategory)>, DatabaseError> {
+        let stmt = CurrentDialect::query_tags_with_categories_by_image_statement();
+
+        let rows: Vec<(String, String)> = self
+            .retry("get_tags_with_categories", || async {
+                sqlx::query_as(&stmt)
+                    .bind(hash.clone().to_string())
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        let mut tags: Vec<(String, TagCategory)> = rows
+            .into_iter()
+            .map(|(tag, category)| (tag, category.parse().unwrap_or_default()))
+            .collect();
+        tags.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(tags)
+    }
+
+    /// Returns the tags of every given image in canonical order, looked up in bulk.
+    ///
+    /// Every requested hash has an entry in the result, which is empty for images
+    /// without tags. Lookups are split into chunks so that no single statement
+    /// exceeds the dialect's bind parameter limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` - The pixel hashes of the images to lookup.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a map from each hash to its tags.
+    pub async fn tags_for_images(
+        &self,
+        hashes: &[PixelHash],
+    ) -> Result<HashMap<PixelHash, Vec<String>>, DatabaseError> {
+        let mut tags: HashMap<PixelHash, Vec<String>> =
+            hashes.iter().map(|h| (h.clone(), vec![])).collect();
+
+        for chunk in hashes.chunks(CurrentDialect::max_bind_params()) {
+            let stmt = CurrentDialect::query_tags_by_images_statement(chunk.len());
+
+            let rows: Vec<(String, String)> = self
+                .retry("tags_for_images", || async {
+                    let mut q = sqlx::query_as(&stmt);
+
+                    for hash in chunk {
+                        q = q.bind(hash.to_string());
+                    }
+
+                    q.fetch_all(&self.pool)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::QueryImages,
+                            sql: stmt.to_string(),
+                            source: e,
+                        })
+                })
+                .await?;
+
+            for (hash, tag) in rows {
+                if let Ok(hash) = PixelHash::try_from(hash) {
+                    tags.entry(hash).or_default().push(tag);
+                }
+            }
+        }
+
+        Ok(tags
+            .into_iter()
+            .map(|(hash, tags)| (hash, canonical_tags(tags)))
+            .collect())
+    }
+
+    /// Returns the metadata, tags, sources, rating and visibility of every given image,
+    /// looked up in bulk.
+    ///
+    /// Each chunk of hashes takes three queries, regardless of how many hashes it has,
+    /// instead of one query per hash and property. Chunks are sized so that no single
+    /// statement exceeds the dialect's bind parameter limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` - The pixel hashes of the images to lookup.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a map from each hash to its record. Every requested hash
+    /// has an entry, which is `ImageRecord::default()` for images that are not recorded.
+    pub async fn get_images_bulk(
+        &self,
+        hashes: &[PixelHash],
+    ) -> Result<HashMap<PixelHash, ImageRecord>, DatabaseError> {
+        let mut records: HashMap<PixelHash, ImageRecord> = hashes
+            .iter()
+            .map(|h| (h.clone(), ImageRecord::default()))
+            .collect();
+
+        for chunk in hashes.chunks(CurrentDialect::max_bind_params()) {
+            let stmt = CurrentDialect::query_images_with_metadata_by_images_statement(chunk.len());
+            let rows: Vec<ImageRecordRow> = self
+                .retry("get_images_bulk", || async {
+                    let mut q = sqlx::query_as(&stmt);
+                    for hash in chunk {
+                        q = q.bind(hash.to_string());
+                    }
+
+                    q.fetch_all(&self.pool)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::QueryImages,
+                            sql: stmt.to_string(),
+                            source: e,
+                        })
+                })
+                .await?;
+
+            for row in rows {
+                if let Ok(hash) = PixelHash::try_from(row.hash) {
+                    let record = records.entry(hash).or_default();
+                    record.is_public = row.is_public;
+                    record.rating = row.rating.and_then(|r| r.parse().ok());
+                    record.metadata = row.metadata;
+                }
+            }
+
+            let stmt = CurrentDialect::query_tags_with_categories_by_images_statement(chunk.len());
+            let rows: Vec<(String, String, String)> = self
+                .retry("get_images_bulk", || async {
+                    let mut q = sqlx::query_as(&stmt);
+                    for hash in chunk {
+                        q = q.bind(hash.to_string());
+                    }
+
+                    q.fetch_all(&self.pool)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::QueryImages,
+                            sql: stmt.to_string(),
+                            source: e,
+                        })
+                })
+                .await?;
+
+            for (hash, tag, category) in rows {
+                if let Ok(hash) = PixelHash::try_from(hash) {
+                    let category = category.parse().unwrap_or_default();
+                    records.entry(hash).or_default().tags.push((tag, category));
+                }
+            }
+
+            let stmt = CurrentDialect::query_sources_by_images_statement(chunk.len());
+            let rows: Vec<(String, String)> = self
+                .retry("get_images_bulk", || async {
+                    let mut q = sqlx::query_as(&stmt);
+                    for hash in chunk {
+                        q = q.bind(hash.to_string());
+                    }
+
+                    q.fetch_all(&self.pool)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::QueryImages,
+                            sql: stmt.to_string(),
+                            source: e,
+                        })
+                })
+                .await?;
+
+            for (hash, url) in rows {
+                if let Ok(hash) = PixelHash::try_from(hash) {
+                    records.entry(hash).or_default().sources.push(url);
+                }
+            }
+        }
+
+        for record in records.values_mut() {
+            record.tags.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+
+        Ok(records)
+    }
+
+    /// Returns the tags present on every given image, in canonical order.
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` - The pixel hashes of the images to lookup.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the intersection of the images' tags.
+    /// The result is empty if `hashes` is empty.
+    pub async fn common_tags(&self, hashes: &[PixelHash]) -> Result<Vec<String>, DatabaseError> {
+        let common = self
+            .tags_for_images(hashes)
+            .await?
+            .into_values()
+            .map(BTreeSet::from_iter)
+            .reduce(|acc, tags| acc.intersection(&tags).cloned().collect())
+            .unwrap_or_default();
+
+        Ok(canonical_tags(common))
+    }
+
+    /// Returns the tags present on any of the given images, in canonical order.
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` - The pixel hashes of the images to lookup.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the union of the images' tags.
+    pub async fn all_tags(&self, hashes: &[PixelHash]) -> Result<Vec<String>, DatabaseError> {
+        let all = self.tags_for_images(hashes).await?.into_values().flatten();
+
+        Ok(canonical_tags(all))
+    }
+
+    /// Retrieves metadata for a given image hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an `Option` of `ImageMetadata`.
+    /// The `Option` will be `None` if metadata is not found.
+    pub async fn get_metadata(
+        &self,
+        hash: &PixelHash,
+    ) -> Result<Option<ImageMetadata>, DatabaseError> {
+        let stmt = CurrentDialect::query_metadata_statement();
+
+        let metadata: Option<ImageMetadata> = self
+            .retry("get_metadata", || async {
+                sqlx::query_as(&stmt)
+                    .bind(hash.clone().to_string())
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(metadata)
+    }
+
+    /// Retrieves metadata that must exist, failing with `RowNotFound` otherwise.
+    async fn fetch_metadata(&self, hash: &PixelHash) -> Result<ImageMetadata, DatabaseError> {
+        let stmt = CurrentDialect::query_metadata_statement();
+
+        self.retry("fetch_metadata", || async {
+            sqlx::query_as(&stmt)
+                .bind(hash.clone().to_string())
+                .fetch_one(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::QueryImages,
+                    sql: stmt.to_string(),
+                    source: e,
+                })
+        })
+        .await
+    }
+
+    /// Ensures that an image has the given rating, replacing any previous one.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    /// * `rating` - The rating to associate with the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn ensure_image_has_rating(
+        &self,
+        hash: &PixelHash,
+        rating: Rating,
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        self.ensure_image(hash).await?;
+
+        let stmt = CurrentDialect::update_rating_statement();
+
+        self.retry("ensure_image_has_rating", || async {
+            let query = sqlx::query(&stmt)
+                .bind(rating.as_str())
+                .bind(hash.clone().to_string());
+            let sql = query.sql();
+
+            query
+                .execute(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::UpdateImageRating {
+                        hash: hash.clone(),
+                        rating,
+                    },
+                    sql: sql.to_string(),
+                    source: e,
+                })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ensures that a tag has the given category, replacing any previous one.
+    ///
+    /// The tag is created if it does not exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `tag` - The tag to categorize.
+    /// * `category` - The category to associate with the tag.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn set_tag_category(
+        &self,
+        tag: &str,
+        category: TagCategory,
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        self.ensure_tags(&[tag]).await?;
+
+        let stmt = CurrentDialect::update_tag_category_statement();
+
+        self.retry("set_tag_category", || async {
+            let query = sqlx::query(&stmt).bind(category.as_str()).bind(tag);
+            let sql = query.sql();
+
+            query
+                .execute(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::UpdateTagCategory {
+                        tag: tag.to_string(),
+                        category,
+                    },
+                    sql: sql.to_string(),
+                    source: e,
+                })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Retrieves the rating of a given image hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an `Option` of the rating. The `Option` will be `None`
+    /// if the image is unrated or not recorded, or if the stored value is not a known
+    /// rating.
+    pub async fn get_rating(&self, hash: &PixelHash) -> Result<Option<Rating>, DatabaseError> {
+        let stmt = CurrentDialect::query_rating_statement();
+
+        let rating: Option<Option<String>> = self
+            .retry("get_rating", || async {
+                let query = sqlx::query_scalar(&stmt).bind(hash.clone().to_string());
+                let sql = query.sql();
+
+                query
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: sql.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(rating.flatten().and_then(|r| r.parse().ok()))
+    }
+
+    /// Records the perceptual hash of an image, replacing any previous one.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    /// * `phash` - The perceptual hash of the image, see `PHash::from_image`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure with a `DatabaseError`.
+    pub async fn ensure_image_has_phash(
+        &self,
+        hash: &PixelHash,
+        phash: PHash,
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        self.ensure_image(hash).await?;
+
+        let stmt = CurrentDialect::update_phash_statement();
+
+        self.retry("ensure_image_has_phash", || async {
+            let query = sqlx::query(&stmt)
+                .bind(phash.to_signed())
+                .bind(hash.clone().to_string());
+            let sql = query.sql();
+
+            query
+                .execute(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::UpdateImagePHash {
+                        hash: hash.clone(),
+                        phash,
+                    },
+                    sql: sql.to_string(),
+                    source: e,
+                })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Retrieves the perceptual hash of a given image hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an `Option` of the perceptual hash. The `Option` will be
+    /// `None` if the image has no perceptual hash or is not recorded.
+    pub async fn get_phash(&self, hash: &PixelHash) -> Result<Option<PHash>, DatabaseError> {
+        let stmt = CurrentDialect::query_phash_statement();
+
+        let phash: Option<Option<i64>> = self
+            .retry("get_phash", || async {
+                let query = sqlx::query_scalar(&stmt).bind(hash.clone().to_string());
+                let sql = query.sql();
+
+                query
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: sql.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(phash.flatten().map(PHash::from_signed))
+    }
+
+    /// Queries images whose perceptual hash is within a Hamming distance of the given
+    /// one, closest first.
+    ///
+    /// The distance is computed in SQL as the bit count of the XOR of both hashes.
+    /// Images without a perceptual hash never match.
+    ///
+    /// # Arguments
+    ///
+    /// * `phash` - The perceptual hash to compare with.
+    /// * `max_hamming_distance` - The maximum number of differing bits (0-64).
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the matching image hashes, ordered by distance and then
+    /// by hash.
+    pub async fn query_images_by_similarity(
+        &self,
+        phash: PHash,
+        max_hamming_distance: u32,
+    ) -> Result<Vec<PixelHash>, DatabaseError> {
+        let stmt = CurrentDialect::query_images_by_phash_statement();
+
+        let rows: Vec<String> = self
+            .retry("query_images_by_similarity", || async {
+                let query = sqlx::query_scalar(&stmt)
+                    .bind(phash.to_signed())
+                    .bind(max_hamming_distance as i64);
+                let sql = query.sql();
+
+                query
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::QueryImages,
+                        sql: sql.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|s| PixelHash::try_from(s).ok())
+            .collect())
+    }
+
+    /// Retrieves the first source of an image.
+    ///
+    /// Images may have many sources, see `get_sources`.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing an `Option` of the source string.
+    /// The `Option` will be `None` if the image has no source.
+    pub async fn get_source(&self, hash: &PixelHash) -> Result<Option<String>, DatabaseError> {
+        Ok(self.get_sources(hash).await?.into_iter().next())
+    }
+
+    /// Retrieves the sources of an image, in the order they were added.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the sources of the image, empty if it has none.
+    pub async fn get_sources(&self, hash: &PixelHash) -> Result<Vec<String>, DatabaseError> {
+        let stmt = CurrentDialect::query_sources_statement();
+
+        self.retry("get_sources", || async {
+            sqlx::query_scalar(&stmt)
+                .bind(hash.to_string())
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::QueryImages,
+                    sql: stmt.to_string(),
+                    source: e,
+                })
+        })
+        .await
+    }
+
+    /// Ensures that specific tags are removed from the image.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image.
+    /// * `tags` - A slice of tag strings to remove from the image.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn ensure_tags_removed(
+        &self,
+        hash: &PixelHash,
+        tags: &[&str],
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let stmt = CurrentDialect::delete_image_tag_statement();
+
+        self.retry("ensure_tags_removed", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            for tag in tags.iter() {
+                let query = sqlx::query(&stmt).bind(hash.to_string()).bind(tag);
+                let sql = query.sql();
+                query
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::DeleteImageTag {
+                            hash: hash.clone(),
+                            tag: tag.to_string(),
+                        },
+                        sql: sql.to_string(),
+                        source: e,
+                    })?;
+            }
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ensures that an image and all its tag and source relations are removed.
+    ///
+    /// This is a transactional operation that:
+    /// 1. Deletes all related rows in `image_tags` and `image_sources`
+    /// 2. Deletes the image row in `images`
+    ///
+    /// If any step fails, the entire transaction is rolled back.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - The pixel hash of the image to be removed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn ensure_image_removed(&self, hash: &PixelHash) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let stmt_tags = CurrentDialect::delete_tags_by_image_statement();
+        let stmt_sources = CurrentDialect::delete_sources_by_image_statement();
+        let stmt_image = CurrentDialect::delete_image_statement();
+
+        self.retry("ensure_image_removed", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            sqlx::query(&stmt_tags)
+                .bind(hash.clone().to_string())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::DeleteImageTags { hash: hash.clone() },
+                    sql: stmt_tags.to_string(),
+                    source: e,
+                })?;
+
+            sqlx::query(&stmt_sources)
+                .bind(hash.clone().to_string())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::DeleteImageSources { hash: hash.clone() },
+                    sql: stmt_sources.to_string(),
+                    source: e,
+                })?;
+
+            sqlx::query(&stmt_image)
+                .bind(hash.clone().to_string())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::DeleteImage { hash: hash.clone() },
+                    sql: stmt_image.to_string(),
+                    source: e,
+                })?;
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })
+        })
+        .await?;
+
+        Ok(())
+    }
+    /// Moves an image and all its rows to a new hash.
+    ///
+    /// This is a transactional operation that:
+    /// 1. Copies the `images`, `image_tags`, `image_sources` and `image_metadatas` rows
+    ///    to the new hash
+    /// 2. Deletes the rows of the old hash
+    ///
+    /// Rows that already exist under the new hash are kept, so tags of both hashes
+    /// are merged, and sources of the old hash are only kept at positions the new hash
+    /// does not use. Nothing happens if the old hash is not recorded, which makes it
+    /// safe to repeat after an interruption.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The pixel hash the image is recorded under.
+    /// * `new` - The pixel hash the image is moved to.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` indicating success or failure.
+    pub async fn rehash_image(
+        &self,
+        old: &PixelHash,
+        new: &PixelHash,
+    ) -> Result<(), DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let copy_stmts = [
+            CurrentDialect::copy_image_statement(),
+            CurrentDialect::copy_image_tags_statement(),
+            CurrentDialect::copy_image_sources_statement(),
+            CurrentDialect::copy_metadata_statement(),
+        ];
+        let delete_stmts = [
+            CurrentDialect::delete_tags_by_image_statement(),
+            CurrentDialect::delete_sources_by_image_statement(),
+            CurrentDialect::delete_image_statement(),
+        ];
+
+        self.retry("rehash_image", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            for stmt in &copy_stmts {
+                sqlx::query(stmt)
+                    .bind(new.to_string())
+                    .bind(old.to_string())
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::RehashImage {
+                            old: old.clone(),
+                            new: new.clone(),
+                        },
+                        sql: stmt.to_string(),
+                        source: e,
+                    })?;
+            }
+
+            // Metadata rows of the old hash are removed by `ON DELETE CASCADE`.
+            for stmt in &delete_stmts {
+                sqlx::query(stmt)
+                    .bind(old.to_string())
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::RehashImage {
+                            old: old.clone(),
+                            new: new.clone(),
+                        },
+                        sql: stmt.to_string(),
+                        source: e,
+                    })?;
+            }
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Renames a tag, moving every image it is associated with to the new name.
+    ///
+    /// The new tag is created if it does not exist yet, so renaming into an existing
+    /// tag merges both. Images associated with both tags keep a single association.
+    /// The old tag is removed, and the tag counts are refreshed, all in one
+    /// transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The tag to rename.
+    /// * `new` - The name the tag is renamed to.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if the tag was renamed, or `false` if the old tag
+    /// does not exist, in which case nothing is changed.
+    pub async fn rename_tag(&self, old: &str, new: &str) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let operation = || DbOperation::RenameTag {
+            old: old.to_string(),
+            new: new.to_string(),
+        };
+        let exists_stmt = CurrentDialect::query_tag_statement(format!(
+            "WHERE name = {}",
+            CurrentDialect::placeholder(1)
+        ));
+        let ensure_stmt = CurrentDialect::ensure_tag_statement();
+        let rename_stmt = CurrentDialect::rename_image_tags_statement();
+        let delete_stmts = [
+            CurrentDialect::delete_image_tags_by_tag_statement(),
+            CurrentDialect::delete_tag_statement(),
+        ];
+
+        self.retry("rename_tag", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            let exists = sqlx::query_scalar::<_, String>(&exists_stmt)
+                .bind(old)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: operation(),
+                    sql: exists_stmt.to_string(),
+                    source: e,
+                })?;
+            if exists.is_none() {
+                return Ok(false);
+            }
 
-        let rows = self
-            .retry(|| async {
-                sqlx::query_scalar(&stmt)
-                    .bind(hash.clone().to_string())
-                    .fetch_all(&self.pool)
+            sqlx::query(&ensure_stmt)
+                .bind(new)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: operation(),
+                    sql: ensure_stmt.to_string(),
+                    source: e,
+                })?;
+
+            // Images already associated with the new tag are skipped on conflict.
+            sqlx::query(&rename_stmt)
+                .bind(new)
+                .bind(old)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: operation(),
+                    sql: rename_stmt.to_string(),
+                    source: e,
+                })?;
+
+            for stmt in &delete_stmts {
+                sqlx::query(stmt)
+                    .bind(old)
+                    .execute(&mut *tx)
                     .await
                     .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::QueryImages,
+                        operation: operation(),
                         sql: stmt.to_string(),
                         source: e,
-                    })
-            })
-            .await?;
+                    })?;
+            }
+
+            for stmt in CurrentDialect::refresh_tag_counts_statement() {
+                sqlx::query(&stmt).execute(&mut *tx).await.map_err(|e| {
+                    DatabaseError::QueryFailed {
+                        operation: operation(),
+                        sql: stmt.to_string(),
+                        source: e,
+                    }
+                })?;
+            }
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-        Ok(rows)
+            Ok(true)
+        })
+        .await
     }
 
-    /// Retrieves metadata for a given image hash.
+    /// Merges a tag into another existing one.
+    ///
+    /// Unlike `rename_tag`, the target must exist already. Every image associated with
+    /// `from` is associated with `to`, skipping images that already have both, so `to`
+    /// ends up with the union of the images of both tags. The `from` tag is then
+    /// removed, and the tag counts are refreshed, all in one transaction. Merging a
+    /// tag into itself changes nothing.
     ///
     /// # Arguments
     ///
-    /// * `hash` - The pixel hash of the image.
+    /// * `from` - The tag merged away.
+    /// * `to` - The tag that keeps the images of both.
     ///
     /// # Returns
     ///
-    /// A `Result` containing an `Option` of `ImageMetadata`.
-    /// The `Option` will be `None` if metadata is not found.
-    pub async fn get_metadata(
-        &self,
-        hash: &PixelHash,
-    ) -> Result<Option<ImageMetadata>, DatabaseError> {
-        let stmt = CurrentDialect::query_metadata_statement();
+    /// A `Result` containing `true` if the tags were merged, or `false` if either tag
+    /// does not exist, in which case nothing is changed.
+    pub async fn merge_tags(&self, from: &str, to: &str) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
 
-        let metadata: Option<ImageMetadata> = self
-            .retry(|| async {
-                sqlx::query_as(&stmt)
-                    .bind(hash.clone().to_string())
-                    .fetch_optional(&self.pool)
+        let operation = || DbOperation::MergeTags {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        let exists_stmt = CurrentDialect::query_tag_statement(format!(
+            "WHERE name = {}",
+            CurrentDialect::placeholder(1)
+        ));
+        let merge_stmt = CurrentDialect::rename_image_tags_statement();
+        let delete_stmts = [
+            CurrentDialect::delete_image_tags_by_tag_statement(),
+            CurrentDialect::delete_tag_statement(),
+        ];
+
+        self.retry("merge_tags", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            for tag in [from, to] {
+                let exists = sqlx::query_scalar::<_, String>(&exists_stmt)
+                    .bind(tag)
+                    .fetch_optional(&mut *tx)
                     .await
                     .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::QueryImages,
+                        operation: operation(),
+                        sql: exists_stmt.to_string(),
+                        source: e,
+                    })?;
+                if exists.is_none() {
+                    return Ok(false);
+                }
+            }
+            if from == to {
+                return Ok(true);
+            }
+
+            // Images already associated with the target are skipped on conflict.
+            sqlx::query(&merge_stmt)
+                .bind(to)
+                .bind(from)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: operation(),
+                    sql: merge_stmt.to_string(),
+                    source: e,
+                })?;
+
+            for stmt in &delete_stmts {
+                sqlx::query(stmt)
+                    .bind(from)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: operation(),
                         sql: stmt.to_string(),
                         source: e,
-                    })
-            })
-            .await?;
+                    })?;
+            }
 
-        Ok(metadata)
+            for stmt in CurrentDialect::refresh_tag_counts_statement() {
+                sqlx::query(&stmt).execute(&mut *tx).await.map_err(|e| {
+                    DatabaseError::QueryFailed {
+                        operation: operation(),
+                        sql: stmt.to_string(),
+                        source: e,
+                    }
+                })?;
+            }
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            Ok(true)
+        })
+        .await
     }
 
-    /// Retrieves the source information for a given image hash.
+    /// Makes a tag an alias of another one.
+    ///
+    /// Aliases are resolved when tags are attached, see `resolve_alias`, and when
+    /// images are queried by tag, so archiving with an alias stores the canonical tag,
+    /// and querying an alias finds images tagged with the canonical tag.
+    ///
+    /// If `canonical` is an alias itself, the new alias points to the tag it resolves
+    /// to, and aliases of `alias` are pointed there as well, so every alias resolves in
+    /// a single lookup. Images tagged with `alias` are retagged with the canonical tag,
+    /// as `rename_tag` does. All of this happens in one transaction.
     ///
     /// # Arguments
     ///
-    /// * `hash` - The pixel hash of the image.
+    /// * `alias` - The tag that becomes an alias.
+    /// * `canonical` - The tag the alias stands for.
     ///
     /// # Returns
     ///
-    /// A `Result` containing an `Option` of the source string.
-    /// The `Option` will be `None` if the source is not found.
-    pub async fn get_source(&self, hash: &PixelHash) -> Result<Option<String>, DatabaseError> {
-        let stmt = CurrentDialect::query_source_statement();
+    /// A `Result` containing `true` if the alias was created, or `false` if `canonical`
+    /// resolves to `alias`, which would make the alias a cycle. Nothing is changed then.
+    pub async fn create_tag_alias(
+        &self,
+        alias: &str,
+        canonical: &str,
+    ) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
 
-        let soruce: Option<String> = self
-            .retry(|| async {
-                let query = sqlx::query_scalar(&stmt).bind(hash.clone().to_string());
-                let sql = query.sql();
+        let operation = || DbOperation::CreateTagAlias {
+            alias: alias.to_string(),
+            canonical: canonical.to_string(),
+        };
+        let resolve_stmt = CurrentDialect::query_tag_alias_statement();
+        let ensure_stmt = CurrentDialect::ensure_tag_statement();
+        let alias_stmts = [
+            CurrentDialect::delete_tag_alias_statement(),
+            CurrentDialect::insert_tag_alias_statement(),
+            CurrentDialect::repoint_tag_aliases_statement(),
+        ];
+        let retag_stmt = CurrentDialect::rename_image_tags_statement();
+        let delete_stmts = [
+            CurrentDialect::delete_image_tags_by_tag_statement(),
+            CurrentDialect::delete_tag_statement(),
+        ];
+
+        self.retry("create_tag_alias", || async {
+            let mut tx = self
+                .pool
+                .begin()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-                query
-                    .fetch_one(&self.pool)
+            let mut target = canonical.to_string();
+            let mut visited = HashSet::from([target.clone()]);
+            loop {
+                let next = sqlx::query_scalar::<_, String>(&resolve_stmt)
+                    .bind(&target)
+                    .fetch_optional(&mut *tx)
                     .await
                     .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::QueryImages,
-                        sql: sql.to_string(),
+                        operation: operation(),
+                        sql: resolve_stmt.to_string(),
                         source: e,
-                    })
-            })
-            .await?;
+                    })?;
+                match next {
+                    Some(next) if visited.insert(next.clone()) => target = next,
+                    _ => break,
+                }
+            }
+            if visited.contains(alias) {
+                return Ok(false);
+            }
+            let target = target.as_str();
+
+            let [delete_alias, insert_alias, repoint_aliases] = &alias_stmts;
+            for (stmt, binds) in [
+                (delete_alias, &[alias][..]),
+                (insert_alias, &[alias, target][..]),
+                (repoint_aliases, &[target, alias][..]),
+                (&ensure_stmt, &[target][..]),
+                (&retag_stmt, &[target, alias][..]),
+                (&delete_stmts[0], &[alias][..]),
+                (&delete_stmts[1], &[alias][..]),
+            ] {
+                let mut q = sqlx::query(stmt);
+                for bind in binds {
+                    q = q.bind(*bind);
+                }
+                q.execute(&mut *tx)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: operation(),
+                        sql: stmt.to_string(),
+                        source: e,
+                    })?;
+            }
+
+            for stmt in CurrentDialect::refresh_tag_counts_statement() {
+                sqlx::query(&stmt).execute(&mut *tx).await.map_err(|e| {
+                    DatabaseError::QueryFailed {
+                        operation: operation(),
+                        sql: stmt.to_string(),
+                        source: e,
+                    }
+                })?;
+            }
+
+            tx.commit()
+                .await
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-        Ok(soruce)
+            Ok(true)
+        })
+        .await
     }
 
-    /// Ensures that specific tags are removed from the image.
+    /// Sets the wiki entry of a tag, replacing any previous entry.
+    ///
+    /// The tag is created if it does not exist yet, so an entry can describe a tag
+    /// before any image is tagged with it.
     ///
     /// # Arguments
     ///
-    /// * `hash` - The pixel hash of the image.
-    /// * `tags` - A slice of tag strings to remove from the image.
+    /// * `tag` - The tag the entry describes.
+    /// * `body` - The description of the tag.
     ///
     /// # Returns
     ///
-    /// A `Result` indicating success or failure.
-    pub async fn ensure_tags_removed(
-        &self,
-        hash: &PixelHash,
-        tags: &[&str],
-    ) -> Result<(), DatabaseError> {
-        let stmt = CurrentDialect::delete_image_tag_statement();
+    /// A `Result` containing the stored `TagWiki`.
+    pub async fn set_tag_wiki(&self, tag: &str, body: &str) -> Result<TagWiki, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
 
-        self.retry(|| async {
+        let wiki = TagWiki {
+            tag: tag.to_string(),
+            body: body.to_string(),
+            updated_at: Utc::now(),
+        };
+        let operation = || DbOperation::SetTagWiki {
+            tag: tag.to_string(),
+        };
+        let ensure_stmt = CurrentDialect::ensure_tag_statement();
+        let delete_stmt = CurrentDialect::delete_tag_wiki_statement();
+        let insert_stmt = CurrentDialect::insert_tag_wiki_statement();
+
+        self.retry("set_tag_wiki", || async {
             let mut tx = self
                 .pool
                 .begin()
                 .await
                 .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-            for tag in tags.iter() {
-                let query = sqlx::query(&stmt).bind(hash.to_string()).bind(tag);
-                let sql = query.sql();
-                query
+            for stmt in [&ensure_stmt, &delete_stmt] {
+                sqlx::query(stmt)
+                    .bind(tag)
                     .execute(&mut *tx)
                     .await
                     .map_err(|e| DatabaseError::QueryFailed {
-                        operation: DbOperation::DeleteImageTag {
-                            hash: hash.clone(),
-                            tag: tag.to_string(),
-                        },
-                        sql: sql.to_string(),
+                        operation: operation(),
+                        sql: stmt.to_string(),
                         source: e,
                     })?;
             }
 
+            sqlx::query(&insert_stmt)
+                .bind(tag)
+                .bind(body)
+                .bind(wiki.updated_at.to_rfc3339())
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: operation(),
+                    sql: insert_stmt.to_string(),
+                    source: e,
+                })?;
+
             tx.commit()
                 .await
                 .map_err(|e| DatabaseError::TransactionFailed { source: e })
         })
         .await?;
 
-        Ok(())
+        Ok(wiki)
     }
 
-    /// Ensures that an image and all its tag relations are removed.
+    /// Retrieves the wiki entry of a tag.
     ///
-    /// This is a transactional operation that:
-    /// 1. Deletes all related rows in `image_tags`
-    /// 2. Deletes the image row in `images`
+    /// # Arguments
     ///
-    /// If any step fails, the entire transaction is rolled back.
+    /// * `tag` - The tag whose entry is retrieved.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `TagWiki`, or `None` if the tag has no entry.
+    pub async fn get_tag_wiki(&self, tag: &str) -> Result<Option<TagWiki>, DatabaseError> {
+        let stmt = CurrentDialect::query_tag_wiki_statement();
+
+        self.retry("get_tag_wiki", || async {
+            sqlx::query_as::<_, TagWiki>(&stmt)
+                .bind(tag)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::QueryTagWiki {
+                        tag: tag.to_string(),
+                    },
+                    sql: stmt.to_string(),
+                    source: e,
+                })
+        })
+        .await
+    }
+
+    /// Resolves a tag to the tag it is an alias of, see `create_tag_alias`.
+    ///
+    /// Chains of aliases are followed to their end. A chain running into a cycle,
+    /// which `create_tag_alias` never creates, ends before the first repeated tag.
     ///
     /// # Arguments
     ///
-    /// * `hash` - The pixel hash of the image to be removed.
+    /// * `tag` - The tag to resolve.
     ///
     /// # Returns
     ///
-    /// A `Result` indicating success or failure.
-    pub async fn ensure_image_removed(&self, hash: &PixelHash) -> Result<(), DatabaseError> {
-        let stmt_tags = CurrentDialect::delete_tags_by_image_statement();
-        let stmt_image = CurrentDialect::delete_image_statement();
+    /// A `Result` containing the canonical tag, which is `tag` itself if it is not an
+    /// alias.
+    pub async fn resolve_alias(&self, tag: &str) -> Result<String, DatabaseError> {
+        let stmt = CurrentDialect::query_tag_alias_statement();
+
+        let mut resolved = tag.to_string();
+        let mut visited = HashSet::from([resolved.clone()]);
+        loop {
+            let next = self
+                .retry("resolve_alias", || async {
+                    sqlx::query_scalar::<_, String>(&stmt)
+                        .bind(&resolved)
+                        .fetch_optional(&self.pool)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::ResolveTagAlias {
+                                tag: tag.to_string(),
+                            },
+                            sql: stmt.to_string(),
+                            source: e,
+                        })
+                })
+                .await?;
+            match next {
+                Some(next) if visited.insert(next.clone()) => resolved = next,
+                _ => break,
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Performs a query on tag aliases, matching the query against the aliases.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query expression representing the alias search criteria.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing pairs of an alias and the tag it stands for.
+    pub async fn query_tag_aliases(
+        &self,
+        query: TagQuery,
+    ) -> Result<Vec<(String, String)>, DatabaseError> {
+        let (sql, params) = query.to_sql();
+        let stmt = CurrentDialect::query_tag_aliases_statement(sql);
+
+        self.retry("query_tag_aliases", || async {
+            let mut q = sqlx::query_as::<_, (String, String)>(&stmt);
 
-        self.retry(|| async {
+            for param in &params {
+                q = q.bind(param);
+            }
+
+            q.fetch_all(&self.pool)
+                .await
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: DbOperation::QueryTagAliases,
+                    sql: stmt.to_string(),
+                    source: e,
+                })
+        })
+        .await
+    }
+
+    /// Makes a tag imply another one, e.g. `cat` implying `animal`.
+    ///
+    /// Images are given the tags implied by their tags when tags are attached, see
+    /// `ensure_image_has_tags`. Implications are followed transitively, and images
+    /// tagged before an implication is added are not retagged.
+    ///
+    /// # Arguments
+    ///
+    /// * `antecedent` - The tag that implies the other one.
+    /// * `consequent` - The tag implied by `antecedent`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if the implication holds, or `false` if
+    /// `consequent` is or implies `antecedent`, which would make the implication a
+    /// cycle. Nothing is changed then.
+    pub async fn add_implication(
+        &self,
+        antecedent: &str,
+        consequent: &str,
+    ) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let operation = || DbOperation::CreateTagImplication {
+            antecedent: antecedent.to_string(),
+            consequent: consequent.to_string(),
+        };
+        let implied_stmt = CurrentDialect::query_implied_tags_statement(1);
+        let insert_stmt = CurrentDialect::ensure_tag_implication_statement();
+
+        self.retry("add_implication", || async {
             let mut tx = self
                 .pool
                 .begin()
                 .await
                 .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
 
-            sqlx::query(&stmt_tags)
-                .bind(hash.clone().to_string())
-                .execute(&mut *tx)
+            let implied = sqlx::query_scalar::<_, String>(&implied_stmt)
+                .bind(consequent)
+                .fetch_all(&mut *tx)
                 .await
                 .map_err(|e| DatabaseError::QueryFailed {
-                    operation: DbOperation::DeleteImageTags { hash: hash.clone() },
-                    sql: stmt_tags.to_string(),
+                    operation: operation(),
+                    sql: implied_stmt.to_string(),
                     source: e,
                 })?;
+            if consequent == antecedent || implied.iter().any(|tag| tag == antecedent) {
+                return Ok(false);
+            }
 
-            sqlx::query(&stmt_image)
-                .bind(hash.clone().to_string())
+            sqlx::query(&insert_stmt)
+                .bind(antecedent)
+                .bind(consequent)
                 .execute(&mut *tx)
                 .await
-                .map_err(|e| DatabaseError::QueryFailed {
-                    operation: DbOperation::DeleteImage { hash: hash.clone() },
-                    sql: stmt_image.to_string(),
+                .map_err(|e| DatabaseError::QueryFailed {
+                    operation: operation(),
+                    sql: insert_stmt.to_string(),
                     source: e,
                 })?;
 
             tx.commit()
                 .await
-                .map_err(|e| DatabaseError::TransactionFailed { source: e })
+                .map_err(|e| DatabaseError::TransactionFailed { source: e })?;
+
+            Ok(true)
         })
-        .await?;
+        .await
+    }
 
-        Ok(())
+    /// Expands tags by the tags they imply, see `add_implication`.
+    ///
+    /// # Arguments
+    ///
+    /// * `tags` - The tags to expand.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the given tags and every tag they imply transitively,
+    /// in canonical order.
+    pub async fn implied_tags(&self, tags: &[&str]) -> Result<Vec<String>, DatabaseError> {
+        let mut expanded: HashSet<String> = tags.iter().map(|tag| tag.to_string()).collect();
+
+        for chunk in tags.chunks(CurrentDialect::max_bind_params()) {
+            let stmt = CurrentDialect::query_implied_tags_statement(chunk.len());
+            let implied = self
+                .retry("implied_tags", || async {
+                    let mut q = sqlx::query_scalar::<_, String>(&stmt);
+                    for tag in chunk {
+                        q = q.bind(*tag);
+                    }
+
+                    q.fetch_all(&self.pool)
+                        .await
+                        .map_err(|e| DatabaseError::QueryFailed {
+                            operation: DbOperation::QueryImpliedTags,
+                            sql: stmt.to_string(),
+                            source: e,
+                        })
+                })
+                .await?;
+            expanded.extend(implied);
+        }
+
+        Ok(canonical_tags(expanded))
     }
 }
 
@@ -785,6 +3376,10 @@ pub enum DatabaseError {
         #[source]
         source: sqlx::Error,
     },
+
+    /// A write was refused because the database is read-only.
+    #[error("Database is read-only")]
+    ReadOnly,
 }
 
 /// Enum representing the kind of database operation being performed.
@@ -812,6 +3407,18 @@ pub enum DbOperation {
         /// The tag string to associate with the image.
         tag: String,
     },
+    /// Operation for inserting several entries into the `tags` table in one statement.
+    InsertTags {
+        /// The tag strings to be inserted into the database.
+        tags: Vec<String>,
+    },
+    /// Operation for associating an image with several tags in one statement.
+    InsertImageTags {
+        /// The hash of the image to associate with the tags.
+        hash: PixelHash,
+        /// The tag strings to associate with the image.
+        tags: Vec<String>,
+    },
     /// Operation for deleting a specific tag association from the `image_tags` table.
     DeleteImageTag {
         /// The hash of the image from which to remove the tag.
@@ -830,6 +3437,27 @@ pub enum DbOperation {
         /// The hash of the image for which all tags are to be removed.
         hash: PixelHash,
     },
+    /// Operation for moving an image and all its rows to a new hash.
+    RehashImage {
+        /// The hash the image was recorded under.
+        old: PixelHash,
+        /// The hash the image is moved to.
+        new: PixelHash,
+    },
+    /// Operation for renaming a tag, moving its rows in `image_tags` to the new name.
+    RenameTag {
+        /// The tag being renamed.
+        old: String,
+        /// The name the tag is renamed to.
+        new: String,
+    },
+    /// Operation for merging a tag into another, moving its rows in `image_tags`.
+    MergeTags {
+        /// The tag merged away.
+        from: String,
+        /// The tag that keeps the images of both.
+        to: String,
+    },
     /// Operation for querying tags associated with a specific image hash
     /// from the `image_tags` table.
     QueryImageTags {
@@ -844,16 +3472,108 @@ pub enum DbOperation {
         /// The `ImageMetadata` struct containing details about the image.
         metadata: ImageMetadata,
     },
-    /// Operation for updating the source information of an image
-    /// in the `images` table.
+    /// Operation for adding a source of an image to the `image_sources` table.
     UpdateImageSource {
         /// The hash of the image whose source information is to be updated.
         hash: PixelHash,
         /// The new source string to associate with the image.
         source: String,
     },
+    /// Operation for removing a source of an image from the `image_sources` table.
+    RemoveImageSource {
+        /// The hash of the image whose source is removed.
+        hash: PixelHash,
+        /// The source to remove.
+        source: String,
+    },
+    /// Operation for deleting all sources of an image from the `image_sources` table.
+    DeleteImageSources {
+        /// The hash of the image whose sources are deleted.
+        hash: PixelHash,
+    },
+    /// Operation for updating the rating of an image in the `images` table.
+    UpdateImageRating {
+        /// The hash of the image to update.
+        hash: PixelHash,
+        /// The new rating of the image.
+        rating: Rating,
+    },
+    /// Operation for updating the perceptual hash of an image in the `images` table.
+    UpdateImagePHash {
+        /// The hash of the image to update.
+        hash: PixelHash,
+        /// The new perceptual hash of the image.
+        phash: PHash,
+    },
+    /// Operation for marking or unmarking an image as featured.
+    UpdateImageFeatured {
+        /// The hash of the image to update.
+        hash: PixelHash,
+        /// Whether the image is featured.
+        featured: bool,
+    },
+    /// Operation for making an image public or private.
+    UpdateImageVisibility {
+        /// The hash of the image to update.
+        hash: PixelHash,
+        /// Whether the image is public.
+        is_public: bool,
+    },
+    /// Operation for soft-deleting or restoring an image.
+    UpdateImageDeleted {
+        /// The hash of the image to update.
+        hash: PixelHash,
+        /// Whether the image is deleted.
+        deleted: bool,
+    },
     /// Operation for querying tags from the `tags` table.
     QueryTags,
+    /// Operation for updating the category of a tag in the `tags` table.
+    UpdateTagCategory {
+        /// The tag to update.
+        tag: String,
+        /// The new category of the tag.
+        category: TagCategory,
+    },
+    /// Operation for making a tag an alias of another one.
+    CreateTagAlias {
+        /// The tag that becomes an alias.
+        alias: String,
+        /// The tag the alias stands for.
+        canonical: String,
+    },
+    /// Operation for resolving a tag through the `tag_aliases` table.
+    ResolveTagAlias {
+        /// The tag being resolved.
+        tag: String,
+    },
+    /// Operation for querying aliases from the `tag_aliases` table.
+    QueryTagAliases,
+    /// Operation for making a tag imply another one.
+    CreateTagImplication {
+        /// The tag that implies the other one.
+        antecedent: String,
+        /// The tag implied by the antecedent.
+        consequent: String,
+    },
+    /// Operation for expanding tags through the `tag_implications` table.
+    QueryImpliedTags,
+    /// Operation for setting the entry of a tag in the `tag_wikis` table.
+    SetTagWiki {
+        /// The tag the entry describes.
+        tag: String,
+    },
+    /// Operation for querying the entry of a tag from the `tag_wikis` table.
+    QueryTagWiki {
+        /// The tag whose entry is queried.
+        tag: String,
+    },
+    /// Operation for probing the database server, e.g. for its version.
+    ProbeServer,
+    /// Operation for inspecting how the database file is used.
+    InspectSpace,
+    /// Operation for reclaiming free pages from the database file.
+    Compact,
 }
 
 impl DatabaseError {
@@ -871,6 +3591,7 @@ impl DatabaseError {
                 operation: _,
             } => is_retryable_kind(source),
             DatabaseError::TransactionFailed { source } => is_retryable_kind(source),
+            DatabaseError::ReadOnly => false,
         }
     }
 }
@@ -878,9 +3599,16 @@ impl DatabaseError {
 #[cfg(test)]
 mod tests {
     use crate::{
-        database::{Database, MIGRATOR, Pool},
-        query::{ImageQuery, ImageQueryExpr, ImageQueryKind, TagQuery, TagQueryExpr, TagQueryKind},
-        storage::{ImageMetadata, PixelHash},
+        database::{
+            CompactMode, Database, DatabaseError, MIGRATOR, Pool, Rating, TagCategory, TagOrder,
+        },
+        dialect::{CurrentDialect, Dialect},
+        parser::parse_query,
+        query::{
+            Comparison, ImageQuery, ImageQueryExpr, ImageQueryKind, MediaGroup, MetadataField,
+            OrderBy, TagQuery, TagQueryExpr, TagQueryKind,
+        },
+        storage::{ExifData, HashPrefix, ImageMetadata, MAX_PREFIX_MATCHES, PHash, PixelHash},
     };
     use chrono::DateTime;
     use std::str::FromStr;
@@ -917,6 +3645,317 @@ mod tests {
         );
     }
 
+    /// Ensures that sources are kept in order without duplicates, and removed along
+    /// with their image.
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_sources(pool: Pool) {
+        let db = Database::new(pool);
+
+        let image = PixelHash::try_from("329435e5e66be809").unwrap();
+        assert!(db.get_sources(&image).await.unwrap().is_empty());
+
+        assert!(db.add_source(&image, "https://pixiv.net/1").await.unwrap());
+        assert!(db.add_source(&image, "https://x.com/1").await.unwrap());
+        assert!(!db.add_source(&image, "https://pixiv.net/1").await.unwrap());
+        assert!(
+            db.add_source(&image, "https://example.com/1")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec![
+                "https://pixiv.net/1",
+                "https://x.com/1",
+                "https://example.com/1"
+            ],
+            db.get_sources(&image).await.unwrap()
+        );
+
+        assert!(
+            db.remove_source(&image, "https://pixiv.net/1")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !db.remove_source(&image, "https://pixiv.net/1")
+                .await
+                .unwrap()
+        );
+        assert!(db.add_source(&image, "https://pixiv.net/1").await.unwrap());
+        assert_eq!(
+            vec![
+                "https://x.com/1",
+                "https://example.com/1",
+                "https://pixiv.net/1"
+            ],
+            db.get_sources(&image).await.unwrap()
+        );
+        assert_eq!(
+            Some("https://x.com/1".to_string()),
+            db.get_source(&image).await.unwrap()
+        );
+
+        db.set_sources(&image, &["b".to_string(), "a".to_string(), "b".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(vec!["b", "a"], db.get_sources(&image).await.unwrap());
+
+        db.ensure_image_removed(&image).await.unwrap();
+        db.ensure_image(&image).await.unwrap();
+        assert!(db.get_sources(&image).await.unwrap().is_empty());
+    }
+
+    /// Ensures that a rating can be set, replaced and read back, and that unrated or
+    /// unrecorded images have no rating instead of an error.
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_ensure_rating(pool: Pool) {
+        let db = Database::new(pool);
+
+        let image = PixelHash::try_from("329435e5e66be809").unwrap();
+        let unrated = PixelHash::try_from("a1b2c3d4e5f60718").unwrap();
+        db.ensure_image(&unrated).await.unwrap();
+
+        assert_eq!(None, db.get_rating(&unrated).await.unwrap());
+        assert_eq!(None, db.get_rating(&image).await.unwrap());
+
+        db.ensure_image_has_rating(&image, Rating::Questionable)
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(Rating::Questionable),
+            db.get_rating(&image).await.unwrap()
+        );
+
+        db.ensure_image_has_rating(&image, Rating::General)
+            .await
+            .unwrap();
+        assert_eq!(Some(Rating::General), db.get_rating(&image).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_images_by_similarity(pool: Pool) {
+        let db = Database::new(pool);
+
+        let near = PixelHash::try_from("329435e5e66be809").unwrap();
+        let exact = PixelHash::try_from("a1b2c3d4e5f60718").unwrap();
+        let far = PixelHash::try_from("0000000000000001").unwrap();
+        let without = PixelHash::try_from("0000000000000002").unwrap();
+        let phash = PHash::from(0xf000_0000_0000_00ff);
+
+        db.ensure_image_has_phash(&exact, phash).await.unwrap();
+        db.ensure_image_has_phash(&near, PHash::from(0x7000_0000_0000_00fc))
+            .await
+            .unwrap();
+        db.ensure_image_has_phash(&far, PHash::from(0x0fff_ffff_ffff_ff00))
+            .await
+            .unwrap();
+        db.ensure_image(&without).await.unwrap();
+
+        assert_eq!(Some(phash), db.get_phash(&exact).await.unwrap());
+        assert_eq!(None, db.get_phash(&without).await.unwrap());
+
+        assert_eq!(
+            vec![exact.clone(), near.clone()],
+            db.query_images_by_similarity(phash, 3).await.unwrap()
+        );
+        assert_eq!(
+            vec![exact.clone()],
+            db.query_images_by_similarity(phash, 2).await.unwrap()
+        );
+        assert_eq!(
+            vec![exact, near, far],
+            db.query_images_by_similarity(phash, 64).await.unwrap()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_rating(pool: Pool) {
+        let db = Database::new(pool);
+
+        let safe_cat = PixelHash::try_from("329435e5e66be809").unwrap();
+        let explicit_cat = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        let explicit_dog = PixelHash::try_from("a1b2c3d4e5f60718").unwrap();
+        let unrated_cat = PixelHash::try_from("0000000000000001").unwrap();
+        for (hash, tag, rating) in [
+            (&safe_cat, "cat", Some(Rating::General)),
+            (&explicit_cat, "cat", Some(Rating::Explicit)),
+            (&explicit_dog, "dog", Some(Rating::Explicit)),
+            (&unrated_cat, "cat", None),
+        ] {
+            db.ensure_tags(&[tag]).await.unwrap();
+            db.ensure_image_has_tags(hash, &[tag]).await.unwrap();
+            if let Some(rating) = rating {
+                db.ensure_image_has_rating(hash, rating).await.unwrap();
+            }
+        }
+
+        let query = async |expr: ImageQueryExpr| {
+            let mut hashes = db.query_image(ImageQuery::filter(expr)).await.unwrap();
+            hashes.sort();
+            hashes
+        };
+        let sorted = |mut hashes: Vec<PixelHash>| {
+            hashes.sort();
+            hashes
+        };
+
+        assert_eq!(
+            sorted(vec![explicit_cat.clone(), explicit_dog.clone()]),
+            query(ImageQueryExpr::rating("explicit")).await
+        );
+        assert_eq!(
+            vec![explicit_cat.clone()],
+            query(ImageQueryExpr::tag("cat").and(ImageQueryExpr::rating("e"))).await
+        );
+        assert_eq!(
+            sorted(vec![safe_cat.clone(), explicit_dog.clone()]),
+            query(ImageQueryExpr::rating("general").or(ImageQueryExpr::tag("dog"))).await
+        );
+        // Unrated images are not excluded by a negated rating.
+        assert_eq!(
+            sorted(vec![safe_cat.clone(), unrated_cat.clone()]),
+            query(
+                ImageQueryExpr::tag("cat")
+                    .and(ImageQueryExpr::not(ImageQueryExpr::rating("explicit")))
+            )
+            .await
+        );
+        assert!(query(ImageQueryExpr::rating("unknown")).await.is_empty());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_source_count(pool: Pool) {
+        let db = Database::new(pool);
+
+        let sourced_cat = PixelHash::try_from("329435e5e66be809").unwrap();
+        let unsourced_cat = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        let unsourced_dog = PixelHash::try_from("a1b2c3d4e5f60718").unwrap();
+        for (hash, tag) in [
+            (&sourced_cat, "cat"),
+            (&unsourced_cat, "cat"),
+            (&unsourced_dog, "dog"),
+        ] {
+            db.ensure_tags(&[tag]).await.unwrap();
+            db.ensure_image_has_tags(hash, &[tag]).await.unwrap();
+        }
+        db.ensure_image_has_source(&sourced_cat, "https://example.com/1")
+            .await
+            .unwrap();
+
+        let query = async |input: &str| {
+            let expr = parse_query(input).unwrap();
+            let mut hashes = db.query_image(ImageQuery::filter(expr)).await.unwrap();
+            hashes.sort();
+            hashes
+        };
+
+        assert_eq!(
+            vec![unsourced_cat.clone()],
+            query("cat AND sources:0").await
+        );
+        assert_eq!(vec![sourced_cat.clone()], query("sources:>=1").await);
+        assert_eq!(
+            vec![unsourced_dog.clone()],
+            query("dog AND NOT sources:>0").await
+        );
+        assert!(query("sources:>1").await.is_empty());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_metadata(pool: Pool) {
+        let db = Database::new(pool);
+
+        let wide = PixelHash::try_from("329435e5e66be809").unwrap();
+        let tall = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        let small = PixelHash::try_from("a1b2c3d4e5f60718").unwrap();
+        for (hash, width, height, file_size) in [
+            (&wide, 1920, 1080, 2_000_000),
+            (&tall, 1080, 1920, 900_000),
+            (&small, 64, 64, 1_000),
+        ] {
+            let metadata = ImageMetadata {
+                width,
+                height,
+                file_size,
+                format: "png".to_string(),
+                ..Default::default()
+            };
+            db.ensure_image_has_metadata(hash, &metadata).await.unwrap();
+        }
+
+        let query = async |expr: ImageQueryExpr| {
+            let mut hashes = db.query_image(ImageQuery::filter(expr)).await.unwrap();
+            hashes.sort();
+            hashes
+        };
+
+        assert_eq!(
+            vec![wide.clone()],
+            query(ImageQueryExpr::width_gte(1920)).await
+        );
+        assert_eq!(
+            vec![small.clone()],
+            query(ImageQueryExpr::width_lt(1000).and(ImageQueryExpr::height_lt(1000))).await
+        );
+        assert_eq!(
+            vec![tall.clone()],
+            query(
+                ImageQueryExpr::height_gte(1080)
+                    .and(ImageQueryExpr::not(ImageQueryExpr::filesize_gte(1_000_000)))
+            )
+            .await
+        );
+        // Dates are compared the same way, so they are covered here as well.
+        assert_eq!(
+            3,
+            query(ImageQueryExpr::date_until("9999-01-01T00:00:00Z"))
+                .await
+                .len()
+        );
+        assert!(
+            query(ImageQueryExpr::date_since("9999-01-01T00:00:00Z"))
+                .await
+                .is_empty()
+        );
+
+        let mut expected = vec![wide.clone(), small.clone()];
+        expected.sort();
+        assert_eq!(
+            expected,
+            query(
+                ImageQueryExpr::filesize_lt(1_001).or(ImageQueryExpr::metadata(
+                    MetadataField::Width,
+                    Comparison::Eq,
+                    1920
+                ))
+            )
+            .await
+        );
+
+        assert_eq!(
+            vec![wide.clone()],
+            query(ImageQueryExpr::aspect_ratio(16, 9)).await
+        );
+        assert_eq!(
+            vec![tall.clone()],
+            query(ImageQueryExpr::aspect_ratio(9, 16)).await
+        );
+        assert_eq!(
+            vec![small.clone()],
+            query(ImageQueryExpr::aspect_ratio(1, 1)).await
+        );
+        assert!(query(ImageQueryExpr::aspect_ratio(4, 3)).await.is_empty());
+    }
+
+    #[test]
+    fn test_parse_rating() {
+        for rating in Rating::ALL {
+            assert_eq!(Ok(rating), rating.as_str().parse());
+            assert_eq!(Ok(rating), rating.name().parse());
+        }
+        assert!("x".parse::<Rating>().is_err());
+    }
+
     /// Ensures that inserting the same metadata multiple times does not result in an error.
     ///
     /// This test validates both the success of metadata insertion and idempotency,
@@ -934,6 +3973,16 @@ mod tests {
             file_size: 1337,
             created_at: Some(DateTime::from_str("2025-05-02T01:18:49.678809123Z").unwrap()),
             duration: Some(1.0),
+            exif: Some(Box::new(ExifData {
+                camera_make: Some("Canon".to_string()),
+                camera_model: None,
+                gps_lat: Some(35.5),
+                gps_lon: Some(-139.75),
+                taken_at: Some(DateTime::from_str("2024-05-05T22:08:09Z").unwrap()),
+                exposure: Some(0.004),
+            })),
+            orientation: Some(6),
+            digest: Some("9f3c2a7d41e0b8c6d5a4f3e2b1c0d9e8".to_string()),
         };
 
         db.ensure_image_has_metadata(&image, &metadata)
@@ -946,6 +3995,48 @@ mod tests {
         assert_eq!(Some(metadata), db.get_metadata(&image).await.unwrap());
     }
 
+    /// Ensures that the returning insert yields the stored row, and keeps returning it
+    /// once metadata is present.
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_ensure_metadata_returning(pool: Pool) {
+        let db = Database::new(pool);
+
+        let image = PixelHash::try_from("329435e5e66be809").unwrap();
+        let metadata = ImageMetadata {
+            width: 200,
+            height: 200,
+            format: "png".to_string(),
+            color_type: "rgba".to_string(),
+            file_size: 1337,
+            created_at: None,
+            duration: None,
+            exif: None,
+            orientation: None,
+            digest: None,
+        };
+
+        let stored = db
+            .ensure_image_has_metadata_returning(&image, &metadata)
+            .await
+            .unwrap();
+
+        assert_eq!(None, stored.created_at);
+        assert_eq!(Some(stored.clone()), db.get_metadata(&image).await.unwrap());
+
+        let again = db
+            .ensure_image_has_metadata_returning(
+                &image,
+                &ImageMetadata {
+                    width: 1,
+                    ..metadata
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(stored, again);
+    }
+
     /// Ensures that metadata can be inserted and retrieved correctly without a `created_at` value.
     ///
     /// This test confirms that `ensure_image_has_metadata` correctly handles metadata entries
@@ -963,11 +4054,14 @@ mod tests {
             file_size: 1337,
             created_at: None,
             duration: None,
+            exif: None,
+            orientation: None,
+            digest: None,
         };
         db.ensure_image_has_metadata(&image, &metadata)
             .await
             .unwrap();
-        assert!(db.get_metadata(&image).await.unwrap().is_some());
+        assert_eq!(Some(metadata), db.get_metadata(&image).await.unwrap());
     }
 
     /// Performs a comprehensive test of image tag operations including:
@@ -979,21 +4073,172 @@ mod tests {
     async fn test_operate_image_tag(pool: Pool) {
         let db = Database::new(pool);
 
-        let image = PixelHash::try_from("329435e5e66be809").unwrap();
+        let image = PixelHash::try_from("329435e5e66be809").unwrap();
+
+        assert!(db.ensure_image_has_tags(&image, &["cat"]).await.is_ok());
+        assert!(db.ensure_image_has_tags(&image, &["cat"]).await.is_ok());
+        assert!(db.ensure_image_has_tags(&image, &["dog"]).await.is_ok());
+
+        assert_eq!(
+            vec!["cat".to_string(), "dog".to_string()],
+            db.get_tags(&image).await.unwrap()
+        );
+
+        db.ensure_tags_removed(&image, &["dog"]).await.unwrap();
+        db.ensure_tags_removed(&image, &["dog"]).await.unwrap();
+
+        assert_eq!(vec!["cat".to_string()], db.get_tags(&image).await.unwrap());
+    }
+
+    /// Walks every page of each cursor order one image at a time, over file sizes that
+    /// sort differently as text and an image without metadata, and checks that every
+    /// image comes back exactly once.
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_cursor_pages_cover_every_image(pool: Pool) {
+        let db = Database::new(pool);
+
+        let mut hashes = vec![];
+        for (i, file_size) in [5, 40, 300, 40].into_iter().enumerate() {
+            let hash = PixelHash::from(i as u64 + 1);
+            let created_at = format!("2025-05-0{}T00:00:00Z", i % 2 + 1);
+            let metadata = ImageMetadata {
+                width: 1,
+                height: 1,
+                format: "png".to_string(),
+                color_type: "rgba".to_string(),
+                file_size,
+                created_at: Some(DateTime::from_str(&created_at).unwrap()),
+                duration: None,
+                exif: None,
+                orientation: None,
+                digest: None,
+            };
+            db.ensure_image_has_metadata(&hash, &metadata)
+                .await
+                .unwrap();
+            hashes.push(hash);
+        }
+        db.set_featured(&hashes[2], true).await.unwrap();
+        let bare = PixelHash::from(9u64);
+        db.ensure_image(&bare).await.unwrap();
+        hashes.push(bare);
+        hashes.sort();
+
+        for order in [
+            OrderBy::CreatedAtAsc,
+            OrderBy::CreatedAtDesc,
+            OrderBy::FileSizeAsc,
+            OrderBy::FileSizeDesc,
+            OrderBy::FeaturedFirst,
+            OrderBy::HashAsc,
+            OrderBy::HashDesc,
+        ] {
+            let query = ImageQuery::all().with_order(order.clone()).with_limit(1);
+            let mut paged = vec![];
+            let mut page = db.query_image(query.clone()).await.unwrap();
+            while let Some(hash) = page.pop() {
+                assert!(paged.len() < hashes.len(), "{order:?} repeats a page");
+                let cursor = db.cursor_of(&hash, &order).await.unwrap().unwrap();
+                paged.push(hash);
+                page = db.query_image(query.clone().after(cursor)).await.unwrap();
+            }
+            paged.sort();
+            assert_eq!(hashes, paged, "{order:?}");
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_get_tags_ordered(pool: Pool) {
+        let db = Database::new(pool);
+
+        let image = PixelHash::try_from("329435e5e66be809").unwrap();
+
+        db.ensure_image_has_tags(&image, &["zebra", "dog", "cat", "ant"])
+            .await
+            .unwrap();
+        // The attach times are set explicitly, as the clock may not advance between
+        // inserts. Tags attached together share a time and are ordered by name.
+        for (tags, created_at) in [
+            (&["zebra"][..], "2025-01-01 00:00:00"),
+            (&["dog", "cat"], "2025-01-02 00:00:00"),
+            (&["ant"], "2025-01-03 00:00:00"),
+        ] {
+            // A literal rather than a bound string, which Postgres would not take as a
+            // timestamp.
+            let stmt = format!(
+                "UPDATE image_tags SET created_at = '{created_at}' WHERE tag_name = {}",
+                CurrentDialect::placeholder(1)
+            );
+            for tag in tags {
+                sqlx::query(&stmt)
+                    .bind(*tag)
+                    .execute(&db.pool)
+                    .await
+                    .unwrap();
+            }
+        }
+        db.rename_tag("zebra", "yak").await.unwrap();
+
+        assert_eq!(
+            vec!["ant", "cat", "dog", "yak"],
+            db.get_tags_ordered(&image, TagOrder::Alphabetical)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["yak", "cat", "dog", "ant"],
+            db.get_tags_ordered(&image, TagOrder::InsertedAt)
+                .await
+                .unwrap()
+        );
+    }
+
+    /// Tests bulk tag retrieval along with the intersection and union helpers,
+    /// using three images with overlapping tag sets.
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_tags_for_images(pool: Pool) {
+        let db = Database::new(pool);
+
+        let first = PixelHash::try_from("329435e5e66be809").unwrap();
+        let second = PixelHash::try_from("229435e5e66be809").unwrap();
+        let third = PixelHash::try_from("129435e5e66be809").unwrap();
+        let untagged = PixelHash::try_from("029435e5e66be809").unwrap();
+
+        db.ensure_image_has_tags(&first, &["cat", "cute", "black"])
+            .await
+            .unwrap();
+        db.ensure_image_has_tags(&second, &["cat", "cute"])
+            .await
+            .unwrap();
+        db.ensure_image_has_tags(&third, &["cat", "dog"])
+            .await
+            .unwrap();
+        db.ensure_image(&untagged).await.unwrap();
 
-        assert!(db.ensure_image_has_tags(&image, &["cat"]).await.is_ok());
-        assert!(db.ensure_image_has_tags(&image, &["cat"]).await.is_ok());
-        assert!(db.ensure_image_has_tags(&image, &["dog"]).await.is_ok());
+        let selected = [first.clone(), second.clone(), third.clone()];
+
+        let mut tags = db.tags_for_images(&selected).await.unwrap();
+        assert_eq!(3, tags.len());
+        tags.get_mut(&third).unwrap().sort();
+        assert_eq!(vec!["cat".to_string(), "dog".to_string()], tags[&third]);
 
         assert_eq!(
-            vec!["cat".to_string(), "dog".to_string()],
-            db.get_tags(&image).await.unwrap()
+            vec!["cat".to_string()],
+            db.common_tags(&selected).await.unwrap()
+        );
+        assert_eq!(
+            vec!["black", "cat", "cute", "dog"],
+            db.all_tags(&selected).await.unwrap()
+        );
+        assert_eq!(
+            vec!["cat", "cute"],
+            db.common_tags(&[first.clone(), second.clone()])
+                .await
+                .unwrap()
         );
 
-        db.ensure_tags_removed(&image, &["dog"]).await.unwrap();
-        db.ensure_tags_removed(&image, &["dog"]).await.unwrap();
-
-        assert_eq!(vec!["cat".to_string()], db.get_tags(&image).await.unwrap());
+        assert!(db.common_tags(&[first, untagged]).await.unwrap().is_empty());
+        assert!(db.common_tags(&[]).await.unwrap().is_empty());
     }
 
     /// Tests image querying based on tags, verifying that images are returned
@@ -1034,6 +4279,275 @@ mod tests {
         let mut res = db.query_image(query_cat_and_dog).await.unwrap();
         res.sort();
         assert_eq!(vec![image_cat_and_dog], res);
+
+        let query_cat_xor_dog = ImageQuery::new(ImageQueryKind::Where(
+            ImageQueryExpr::tag("cat").xor(ImageQueryExpr::tag("dog")),
+        ));
+        let mut res = db.query_image(query_cat_xor_dog).await.unwrap();
+        res.sort();
+        assert_eq!(vec![image_dog, image_cat], res);
+    }
+
+    /// Tests that the `Animated` media group matches videos and GIFs but not static PNGs.
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_image_by_media_group(pool: Pool) {
+        let db = Database::new(pool);
+
+        let video = PixelHash::try_from("329435e5e66be809").unwrap();
+        let gif = PixelHash::try_from("229435e5e66be809").unwrap();
+        let png = PixelHash::try_from("129435e5e66be809").unwrap();
+
+        for (hash, format, duration) in [
+            (&video, "mp4", Some(3.0)),
+            (&gif, "gif", None),
+            (&png, "png", None),
+        ] {
+            let metadata = ImageMetadata {
+                width: 200,
+                height: 200,
+                format: format.to_string(),
+                color_type: "Rgba8".to_string(),
+                file_size: 1337,
+                created_at: None,
+                duration,
+                exif: None,
+                orientation: None,
+                digest: None,
+            };
+            db.ensure_image_has_metadata(hash, &metadata).await.unwrap();
+        }
+
+        let query = ImageQuery::filter(ImageQueryExpr::media_group(MediaGroup::Animated));
+        let mut res = db.query_image(query).await.unwrap();
+        res.sort();
+        assert_eq!(vec![gif, video], res);
+
+        let query = ImageQuery::filter(ImageQueryExpr::media_group(MediaGroup::Lossless));
+        assert_eq!(vec![png], db.query_image(query).await.unwrap());
+    }
+
+    /// Tests that `filter_existing` returns only stored hashes, including inputs
+    /// that span more than one chunk of bind parameters.
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_filter_existing(pool: Pool) {
+        let db = Database::new(pool);
+
+        let hashes: Vec<PixelHash> = (0..1200_u64).map(PixelHash::from).collect();
+        for hash in hashes.iter().step_by(2) {
+            db.ensure_image(hash).await.unwrap();
+        }
+
+        let existing = db.filter_existing(&hashes).await.unwrap();
+
+        assert_eq!(600, existing.len());
+        assert!(existing.contains(&PixelHash::from(1198)));
+        assert!(!existing.contains(&PixelHash::from(1199)));
+        assert!(db.filter_existing(&[]).await.unwrap().is_empty());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_set_sources_bulk(pool: Pool) {
+        let db = Database::new(pool);
+
+        let hashes: Vec<PixelHash> = (0..5_u64).map(PixelHash::from).collect();
+        db.ensure_image(&hashes[0]).await.unwrap();
+        db.ensure_image_has_source(&hashes[1], "old").await.unwrap();
+
+        let sources: Vec<(PixelHash, String)> = hashes
+            .iter()
+            .map(|h| (h.clone(), format!("https://example.com/{h}")))
+            .collect();
+        db.set_sources_bulk(&sources).await.unwrap();
+
+        for (hash, source) in &sources {
+            assert_eq!(Some(source.clone()), db.get_source(hash).await.unwrap());
+        }
+
+        let sources: Vec<(PixelHash, String)> = hashes
+            .iter()
+            .map(|h| (h.clone(), format!("https://example.org/{h}")))
+            .collect();
+        db.set_sources_bulk(&sources).await.unwrap();
+
+        for (hash, source) in &sources {
+            assert_eq!(Some(source.clone()), db.get_source(hash).await.unwrap());
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_find_resolution_variants(pool: Pool) {
+        let db = Database::new(pool);
+
+        let sizes = [
+            (1, 1000, 500),
+            (2, 2000, 1000),
+            (3, 500, 250),
+            (4, 1500, 750),
+            (5, 2000, 999),
+            (6, 1000, 500),
+        ];
+        for (hash, width, height) in sizes {
+            let metadata = ImageMetadata {
+                width,
+                height,
+                ..Default::default()
+            };
+            db.ensure_image_has_metadata(&PixelHash::from(hash), &metadata)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            vec![PixelHash::from(2), PixelHash::from(3)],
+            db.find_resolution_variants(&PixelHash::from(1))
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec![PixelHash::from(1), PixelHash::from(3), PixelHash::from(6)],
+            db.find_resolution_variants(&PixelHash::from(2))
+                .await
+                .unwrap()
+        );
+        assert!(
+            db.find_resolution_variants(&PixelHash::from(7))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_private_hidden_by_default(pool: Pool) {
+        let db = Database::new(pool);
+
+        let public = PixelHash::try_from("329435e5e66be809").unwrap();
+        let private = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        db.ensure_image_has_tags(&public, &["cat"]).await.unwrap();
+        db.ensure_image_has_tags(&private, &["cat"]).await.unwrap();
+        assert!(db.is_public(&private).await.unwrap());
+
+        db.set_visibility(&private, false).await.unwrap();
+        assert!(!db.is_public(&private).await.unwrap());
+
+        let query = ImageQuery::filter(ImageQueryExpr::tag("cat"));
+        assert_eq!(
+            vec![public.clone()],
+            db.query_image(query.clone()).await.unwrap()
+        );
+        assert_eq!(1, db.count_image(query.clone()).await.unwrap());
+
+        let query = query.with_private(true);
+        assert_eq!(2, db.count_image(query.clone()).await.unwrap());
+        assert_eq!(
+            vec![private.clone()],
+            db.query_image(ImageQuery::filter(ImageQueryExpr::private()).with_private(true))
+                .await
+                .unwrap()
+        );
+        assert!(
+            db.query_image(ImageQuery::filter(ImageQueryExpr::private()))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        db.set_visibility(&private, true).await.unwrap();
+        assert_eq!(2, db.count_image(ImageQuery::all()).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_featured_first(pool: Pool) {
+        let db = Database::new(pool);
+
+        let older = PixelHash::try_from("329435e5e66be809").unwrap();
+        let newer = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        for (hash, created_at) in [
+            (&older, "2024-01-01T00:00:00Z"),
+            (&newer, "2025-01-01T00:00:00Z"),
+        ] {
+            let metadata = ImageMetadata {
+                created_at: Some(DateTime::from_str(created_at).unwrap()),
+                ..Default::default()
+            };
+            db.ensure_image_has_metadata(hash, &metadata).await.unwrap();
+        }
+
+        db.set_featured(&older, true).await.unwrap();
+
+        let query = ImageQuery::all().with_order(OrderBy::FeaturedFirst);
+        assert_eq!(
+            vec![older.clone(), newer.clone()],
+            db.query_image(query).await.unwrap()
+        );
+        let query = ImageQuery::filter(ImageQueryExpr::featured());
+        assert_eq!(vec![older.clone()], db.query_image(query).await.unwrap());
+
+        db.set_featured(&older, false).await.unwrap();
+
+        let query = ImageQuery::all().with_order(OrderBy::FeaturedFirst);
+        assert_eq!(vec![newer, older], db.query_image(query).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_read_only(pool: Pool) {
+        let hash = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        let other = PixelHash::try_from("44a5c00000000000").unwrap();
+        let db = Database::new(pool);
+        db.ensure_image(&hash).await.unwrap();
+        db.ensure_tags(&["cat"]).await.unwrap();
+        db.ensure_image_has_tags(&hash, &["cat"]).await.unwrap();
+
+        let db = db.with_read_only(true);
+        let metadata = ImageMetadata::default();
+        let results = [
+            db.ensure_image(&other).await,
+            db.ensure_image_has_metadata(&hash, &metadata).await,
+            db.ensure_image_has_metadata_returning(&hash, &metadata)
+                .await
+                .map(|_| ()),
+            db.ensure_tags(&["dog"]).await,
+            db.ensure_image_has_tags(&hash, &["dog"]).await,
+            db.ensure_image_has_source(&hash, "https://example.com")
+                .await,
+            db.refresh_image_count().await,
+            db.ensure_tags_removed(&hash, &["cat"]).await,
+            db.ensure_image_removed(&hash).await,
+            db.compact(CompactMode::Full).await.map(|_| ()),
+            db.enable_incremental_compaction().await.map(|_| ()),
+        ];
+
+        for result in results {
+            assert!(matches!(result, Err(DatabaseError::ReadOnly)));
+        }
+
+        assert!(db.image_exists(&hash).await.unwrap());
+        assert!(!db.image_exists(&other).await.unwrap());
+        assert_eq!(vec!["cat"], db.get_tags(&hash).await.unwrap());
+        assert_eq!(None, db.get_source(&hash).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_find_by_hash_prefix(pool: Pool) {
+        let db = Database::new(pool);
+
+        let hash = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        db.ensure_image(&hash).await.unwrap();
+        db.ensure_image(&PixelHash::try_from("44a5c00000000000").unwrap())
+            .await
+            .unwrap();
+        for hash in (0..200_u64).map(PixelHash::from) {
+            db.ensure_image(&hash).await.unwrap();
+        }
+
+        let prefix = HashPrefix::try_from("44A5B").unwrap();
+        assert_eq!(vec![hash], db.find_by_hash_prefix(&prefix).await.unwrap());
+
+        let prefix = HashPrefix::try_from("0").unwrap();
+        assert_eq!(
+            MAX_PREFIX_MATCHES,
+            db.find_by_hash_prefix(&prefix).await.unwrap().len()
+        );
     }
 
     /// Tests the image counting functionality based on specific query criteria,
@@ -1091,6 +4605,278 @@ mod tests {
         assert_eq!(2, db.count_image_by_tag("dog").await.unwrap());
     }
 
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_rename_tag(pool: Pool) {
+        let db = Database::new(pool);
+
+        let image_cat = PixelHash::try_from("329435e5e66be809").unwrap();
+        let image_both = PixelHash::try_from("129435e5e66be809").unwrap();
+
+        db.ensure_image_has_tags(&image_cat, &["cat"])
+            .await
+            .unwrap();
+        db.ensure_image_has_tags(&image_both, &["cat", "kitten"])
+            .await
+            .unwrap();
+        db.refresh_image_count().await.unwrap();
+
+        // An image with both tags must not abort the rename.
+        assert!(db.rename_tag("cat", "kitten").await.unwrap());
+        assert!(!db.rename_tag("cat", "kitten").await.unwrap());
+
+        assert_eq!(
+            vec!["kitten".to_string()],
+            db.query_tags(TagQuery::new(TagQueryKind::All))
+                .await
+                .unwrap()
+        );
+        assert_eq!(vec!["kitten"], db.get_tags(&image_cat).await.unwrap());
+        assert_eq!(vec!["kitten"], db.get_tags(&image_both).await.unwrap());
+        assert_eq!(2, db.count_image_by_tag("kitten").await.unwrap());
+        assert_eq!(0, db.count_image_by_tag("cat").await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_ensure_many_tags(pool: Pool) {
+        let db = Database::new(pool);
+        let image = PixelHash::try_from("329435e5e66be809").unwrap();
+
+        // More tags than fit into a single statement, with duplicates.
+        let tags: Vec<String> = (0..1200).map(|i| format!("tag_{:04}", i % 1100)).collect();
+        let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+        db.ensure_image_has_tags(&image, &tags).await.unwrap();
+        db.ensure_image_has_tags(&image, &tags).await.unwrap();
+        db.ensure_image_has_tags(&image, &[]).await.unwrap();
+
+        let stored = db.get_tags(&image).await.unwrap();
+        assert_eq!(1100, stored.len());
+        assert!(stored.contains(&"tag_1099".to_string()));
+        assert_eq!(
+            1100,
+            db.query_tags(TagQuery::new(TagQueryKind::All))
+                .await
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_merge_tags(pool: Pool) {
+        let db = Database::new(pool);
+
+        let image_cat = PixelHash::try_from("329435e5e66be809").unwrap();
+        let image_kitten = PixelHash::try_from("229435e5e66be809").unwrap();
+        let image_both = PixelHash::try_from("129435e5e66be809").unwrap();
+
+        db.ensure_image_has_tags(&image_cat, &["cat"])
+            .await
+            .unwrap();
+        db.ensure_image_has_tags(&image_kitten, &["kitten"])
+            .await
+            .unwrap();
+        db.ensure_image_has_tags(&image_both, &["cat", "kitten"])
+            .await
+            .unwrap();
+        db.refresh_image_count().await.unwrap();
+
+        // The target must exist, unlike with a rename.
+        assert!(!db.merge_tags("cat", "kitty").await.unwrap());
+        assert!(!db.merge_tags("kitty", "cat").await.unwrap());
+        assert_eq!(2, db.count_image_by_tag("cat").await.unwrap());
+
+        assert!(db.merge_tags("cat", "kitten").await.unwrap());
+        assert!(!db.merge_tags("cat", "kitten").await.unwrap());
+
+        assert_eq!(
+            vec!["kitten".to_string()],
+            db.query_tags(TagQuery::new(TagQueryKind::All))
+                .await
+                .unwrap()
+        );
+        let mut res = db
+            .query_image(ImageQuery::new(ImageQueryKind::Where(ImageQueryExpr::tag(
+                "kitten",
+            ))))
+            .await
+            .unwrap();
+        res.sort();
+        assert_eq!(vec![image_both.clone(), image_kitten, image_cat], res);
+        assert_eq!(vec!["kitten"], db.get_tags(&image_both).await.unwrap());
+        assert_eq!(3, db.count_image_by_tag("kitten").await.unwrap());
+        assert_eq!(0, db.count_image_by_tag("cat").await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_tag_aliases(pool: Pool) {
+        let db = Database::new(pool);
+
+        let image_cat = PixelHash::try_from("329435e5e66be809").unwrap();
+        let image_kitty = PixelHash::try_from("129435e5e66be809").unwrap();
+
+        db.ensure_image_has_tags(&image_cat, &["cat"])
+            .await
+            .unwrap();
+        db.ensure_image_has_tags(&image_kitty, &["kitty"])
+            .await
+            .unwrap();
+
+        // Images tagged with the alias are retagged, and the alias finds them all.
+        assert!(db.create_tag_alias("kitty", "cat").await.unwrap());
+        assert_eq!(vec!["cat"], db.get_tags(&image_kitty).await.unwrap());
+        let mut res = db
+            .query_image(ImageQuery::new(ImageQueryKind::Where(ImageQueryExpr::tag(
+                "kitty",
+            ))))
+            .await
+            .unwrap();
+        res.sort();
+        assert_eq!(vec![image_kitty.clone(), image_cat.clone()], res);
+
+        // Chains resolve to their end, and cycles are refused.
+        assert!(db.create_tag_alias("kitten", "kitty").await.unwrap());
+        assert_eq!("cat", db.resolve_alias("kitten").await.unwrap());
+        assert!(!db.create_tag_alias("cat", "kitten").await.unwrap());
+        assert_eq!("dog", db.resolve_alias("dog").await.unwrap());
+
+        // Aliases of a tag that becomes an alias follow it.
+        assert!(db.create_tag_alias("cat", "feline").await.unwrap());
+        let mut aliases = db
+            .query_tag_aliases(TagQuery::new(TagQueryKind::All))
+            .await
+            .unwrap();
+        aliases.sort();
+        assert_eq!(
+            vec![
+                ("cat".to_string(), "feline".to_string()),
+                ("kitten".to_string(), "feline".to_string()),
+                ("kitty".to_string(), "feline".to_string()),
+            ],
+            aliases
+        );
+        let res = db
+            .query_image(ImageQuery::new(ImageQueryKind::Where(ImageQueryExpr::tag(
+                "kitten",
+            ))))
+            .await
+            .unwrap();
+        assert_eq!(2, res.len());
+
+        let query = TagQuery::new(TagQueryKind::Where(TagQueryExpr::Prefix("kit".to_string())));
+        assert_eq!(2, db.query_tag_aliases(query).await.unwrap().len());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_tag_implications(pool: Pool) {
+        let db = Database::new(pool);
+
+        let image = PixelHash::try_from("329435e5e66be809").unwrap();
+
+        assert!(db.add_implication("a", "b").await.unwrap());
+        assert!(db.add_implication("b", "c").await.unwrap());
+        assert!(db.add_implication("a", "b").await.unwrap());
+        assert_eq!(vec!["a", "b", "c"], db.implied_tags(&["a"]).await.unwrap());
+        assert_eq!(
+            vec!["b", "c", "d"],
+            db.implied_tags(&["d", "b"]).await.unwrap()
+        );
+
+        // Implications closing a cycle, even through the chain, are refused.
+        assert!(!db.add_implication("c", "a").await.unwrap());
+        assert!(!db.add_implication("b", "b").await.unwrap());
+        assert_eq!(vec!["c"], db.implied_tags(&["c"]).await.unwrap());
+
+        db.ensure_image_has_tags(&image, &["a", "x"]).await.unwrap();
+        assert_eq!(vec!["a", "b", "c", "x"], db.get_tags(&image).await.unwrap());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_tag_wiki(pool: Pool) {
+        let db = Database::new(pool);
+
+        assert_eq!(None, db.get_tag_wiki("cat").await.unwrap());
+
+        let first = db.set_tag_wiki("cat", "A small feline.").await.unwrap();
+        assert_eq!(Some(first.clone()), db.get_tag_wiki("cat").await.unwrap());
+        assert_eq!(
+            vec!["cat".to_string()],
+            db.query_tags(TagQuery::new(TagQueryKind::All))
+                .await
+                .unwrap()
+        );
+
+        let second = db
+            .set_tag_wiki("cat", "A small domesticated feline.")
+            .await
+            .unwrap();
+        let stored = db.get_tag_wiki("cat").await.unwrap().unwrap();
+        assert_eq!(second, stored);
+        assert_eq!("A small domesticated feline.", stored.body);
+        assert!(stored.updated_at >= first.updated_at);
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_tag_category(pool: Pool) {
+        let db = Database::new(pool);
+        let image = PixelHash::from(1);
+        db.ensure_image_has_tags(&image, &["cat", "alice"])
+            .await
+            .unwrap();
+
+        // New tags are general tags.
+        assert_eq!(
+            vec![
+                ("alice".to_string(), TagCategory::General),
+                ("cat".to_string(), TagCategory::General),
+            ],
+            db.get_tags_with_categories(&image).await.unwrap()
+        );
+
+        db.set_tag_category("alice", TagCategory::Artist)
+            .await
+            .unwrap();
+        db.set_tag_category("highres", TagCategory::Meta)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("alice".to_string(), TagCategory::Artist),
+                ("cat".to_string(), TagCategory::General),
+            ],
+            db.get_tags_with_categories(&image).await.unwrap()
+        );
+        // Categorizing a tag creates it.
+        let highres = TagQuery::new(TagQueryKind::Where(TagQueryExpr::Exact(
+            "highres".to_string(),
+        )));
+        assert_eq!(
+            vec!["highres".to_string()],
+            db.query_tags(highres).await.unwrap()
+        );
+
+        db.ensure_tags_with_categories(&[
+            ("cat", TagCategory::Character),
+            ("example", TagCategory::Copyright),
+        ])
+        .await
+        .unwrap();
+        db.ensure_image_has_tags(&image, &["example"])
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("alice".to_string(), TagCategory::Artist),
+                ("cat".to_string(), TagCategory::Character),
+                ("example".to_string(), TagCategory::Copyright),
+            ],
+            db.get_tags_with_categories(&image).await.unwrap()
+        );
+
+        for category in TagCategory::ALL {
+            assert_eq!(Ok(category), category.as_str().parse());
+        }
+        assert!("x".parse::<TagCategory>().is_err());
+    }
+
     /// Tests the querying of tags ensuring they can be accurately retrieved based on different query types.
     ///
     /// This confirms the correct behavior for exact match, containment, and retrieval of all tag entries.
@@ -1127,6 +4913,25 @@ mod tests {
         );
     }
 
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_tags_with_not(pool: Pool) {
+        let db = Database::new(pool);
+
+        db.ensure_tags(&["apple", "apple_pie", "avocado", "banana", "a_b"])
+            .await
+            .unwrap();
+
+        let query = TagQuery::new(TagQueryKind::Where(
+            TagQueryExpr::Prefix("a".to_string())
+                .and(TagQueryExpr::not(TagQueryExpr::Contains("_".to_string()))),
+        ));
+
+        assert_eq!(
+            vec!["apple".to_string(), "avocado".to_string()],
+            db.query_tags(query).await.unwrap()
+        );
+    }
+
     #[sqlx::test(migrator = "MIGRATOR")]
     async fn test_get_source(pool: Pool) {
         let db = Database::new(pool);
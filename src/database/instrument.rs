@@ -0,0 +1,139 @@
+//! Metrics of database operations.
+//!
+//! Every attempt of an operation is recorded through the `metrics` facade, labelled
+//! with the name of the `Database` method. Install a recorder, e.g. a Prometheus
+//! exporter, to collect them:
+//!
+//! - `buru_db_operation_total{operation}`: attempts, including retries.
+//! - `buru_db_errors_total{operation}`: failed attempts.
+//! - `buru_db_retries_total{operation}`: attempts after a retryable failure.
+//! - `buru_db_operation_duration_seconds{operation}`: latency of each attempt.
+//!
+//! Without the feature this module is not compiled and nothing is measured.
+
+use std::time::Duration;
+
+/// Records one attempt of an operation.
+pub(super) fn record_attempt(operation: &'static str, attempt: u32, elapsed: Duration, ok: bool) {
+    ::metrics::counter!("buru_db_operation_total", "operation" => operation).increment(1);
+    ::metrics::histogram!("buru_db_operation_duration_seconds", "operation" => operation)
+        .record(elapsed.as_secs_f64());
+
+    if attempt > 0 {
+        ::metrics::counter!("buru_db_retries_total", "operation" => operation).increment(1);
+    }
+    if !ok {
+        ::metrics::counter!("buru_db_errors_total", "operation" => operation).increment(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        app::{ArchiveImageCommand, query_image},
+        database::{Database, MIGRATOR, Pool},
+        query::ImageQuery,
+        storage::{PixelHash, Storage},
+    };
+    use image::{ImageBuffer, ImageFormat, Rgb};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+    use std::{collections::HashMap, io::Cursor};
+    use tempfile::TempDir;
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_record_attempt(pool: Pool) {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = ::metrics::set_default_local_recorder(&recorder);
+
+        let db = Database::new(pool);
+        db.query_image(ImageQuery::all()).await.unwrap();
+        db.query_image(ImageQuery::all()).await.unwrap();
+        db.image_exists(&PixelHash::from(1)).await.unwrap();
+
+        let counters: HashMap<(String, String), u64> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| {
+                let DebugValue::Counter(count) = value else {
+                    return None;
+                };
+                let key = key.key();
+                let operation = key.labels().find(|l| l.key() == "operation")?;
+                Some((
+                    (key.name().to_string(), operation.value().to_string()),
+                    count,
+                ))
+            })
+            .collect();
+
+        let total = |operation: &str| {
+            counters
+                .get(&("buru_db_operation_total".to_string(), operation.to_string()))
+                .copied()
+        };
+        assert_eq!(Some(2), total("query_image"));
+        assert_eq!(Some(1), total("image_exists"));
+        assert!(
+            !counters
+                .keys()
+                .any(|(name, _)| name == "buru_db_errors_total")
+        );
+    }
+
+    /// Loading a page of images takes the same number of queries, however many
+    /// images the page has.
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_query_image_count(pool: Pool) {
+        let db = Database::new(pool);
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf());
+        for seed in 0..20u8 {
+            let mut bytes = vec![];
+            ImageBuffer::from_pixel(4, 4, Rgb([seed, 0, 0]))
+                .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+                .unwrap();
+            ArchiveImageCommand::new(&bytes)
+                .with_tags(["cat".to_string(), format!("seed_{seed}")])
+                .with_source(&format!("https://example.com/{seed}"))
+                .execute(&storage, &db)
+                .await
+                .unwrap();
+        }
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = ::metrics::set_default_local_recorder(&recorder);
+
+        let images = query_image(&db, &storage, ImageQuery::all()).await.unwrap();
+        assert_eq!(20, images.len());
+        assert!(images.iter().all(|image| image.tags.len() == 2));
+
+        let operations: HashMap<String, u64> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter_map(|(key, _, _, value)| {
+                let DebugValue::Counter(count) = value else {
+                    return None;
+                };
+                let key = key.key();
+                if key.name() != "buru_db_operation_total" {
+                    return None;
+                }
+                let operation = key.labels().find(|l| l.key() == "operation")?;
+                Some((operation.value().to_string(), count))
+            })
+            .collect();
+
+        // One query for the hashes, and one each for metadata, tags and sources.
+        assert_eq!(
+            HashMap::from([
+                ("query_image".to_string(), 1),
+                ("get_images_bulk".to_string(), 3)
+            ]),
+            operations
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Space reporting and compaction.
+//!
+//! SQLite does not return free pages to the operating system after deletions, so a
+//! database file keeps its peak size until it is compacted. `Database::space_report`
+//! shows how much of the file is free, and `Database::compact` reclaims it.
+//!
+//! Backends that reclaim space on their own, such as PostgreSQL with autovacuum,
+//! report `CompactOutcome::Skipped`, so callers need no backend-specific branches.
+
+use super::{Database, DatabaseError, DbOperation};
+use crate::dialect::{CurrentDialect, Dialect};
+use serde::Serialize;
+
+/// How `Database::compact` reclaims free pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactMode {
+    /// Returns up to the given number of free pages to the operating system.
+    ///
+    /// This is cheap and can run regularly, but requires SQLite's incremental
+    /// auto-vacuum mode. The first incremental compaction of a database enables that
+    /// mode, which runs a one-time full `VACUUM` (see `CompactMode::Full`).
+    Incremental(u32),
+    /// Rebuilds the whole database file with `VACUUM`.
+    ///
+    /// This holds an exclusive lock for the duration, blocking all other writers and
+    /// readers, and needs free temporary disk space up to the size of the database.
+    Full,
+}
+
+/// A snapshot of how the database file is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct SpaceReport {
+    /// The number of pages in the database file.
+    pub page_count: u64,
+    /// The number of unused pages that compaction can reclaim.
+    pub freelist_pages: u64,
+    /// The size of a page in bytes.
+    pub page_size: u64,
+    /// The size of the database in bytes.
+    pub bytes_on_disk: u64,
+    /// The number of bytes in pages holding data.
+    pub estimated_live_bytes: u64,
+}
+
+/// The result of `Database::compact`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactOutcome {
+    /// Free pages were reclaimed.
+    Compacted {
+        before: SpaceReport,
+        after: SpaceReport,
+    },
+    /// The backend reclaims space on its own, so nothing was done.
+    Skipped { reason: &'static str },
+}
+
+impl Database {
+    /// Reports the size of the database and how much of it is free.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `SpaceReport`.
+    pub async fn space_report(&self) -> Result<SpaceReport, DatabaseError> {
+        let stmt = CurrentDialect::space_report_statement();
+
+        let (page_count, freelist_pages, page_size): (i64, i64, i64) = self
+            .retry("space_report", || async {
+                sqlx::query_as(&stmt)
+                    .fetch_one(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::InspectSpace,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        let (page_count, freelist_pages, page_size) = (
+            page_count.max(0) as u64,
+            freelist_pages.max(0) as u64,
+            page_size.max(0) as u64,
+        );
+
+        Ok(SpaceReport {
+            page_count,
+            freelist_pages,
+            page_size,
+            bytes_on_disk: page_count * page_size,
+            estimated_live_bytes: page_count.saturating_sub(freelist_pages) * page_size,
+        })
+    }
+
+    /// Reclaims free pages from the database file.
+    ///
+    /// See `CompactMode` for the cost of each mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `mode` - How free pages are reclaimed.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the space reports before and after compaction, or
+    /// `CompactOutcome::Skipped` if the backend does not need explicit compaction.
+    pub async fn compact(&self, mode: CompactMode) -> Result<CompactOutcome, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let script = match CurrentDialect::compact_script(mode) {
+            Ok(script) => script,
+            Err(reason) => return Ok(CompactOutcome::Skipped { reason }),
+        };
+
+        let before = self.space_report().await?;
+
+        if let CompactMode::Incremental(_) = mode {
+            self.enable_incremental_compaction().await?;
+        }
+
+        sqlx::raw_sql(&script)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed {
+                operation: DbOperation::Compact,
+                sql: script.clone(),
+                source: e,
+            })?;
+
+        let after = self.space_report().await?;
+
+        Ok(CompactOutcome::Compacted { before, after })
+    }
+
+    /// Switches the database to incremental compaction if it is not active yet.
+    ///
+    /// Activation runs a one-time full `VACUUM` with the costs described in
+    /// `CompactMode::Full`. Later calls only check the current mode.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `true` if the mode was switched by this call.
+    pub async fn enable_incremental_compaction(&self) -> Result<bool, DatabaseError> {
+        if self.read_only {
+            return Err(DatabaseError::ReadOnly);
+        }
+
+        let Some(stmt) = CurrentDialect::incremental_compaction_enabled_statement() else {
+            return Ok(false);
+        };
+
+        let enabled: bool = self
+            .retry("enable_incremental_compaction", || async {
+                sqlx::query_scalar(&stmt)
+                    .fetch_one(&self.pool)
+                    .await
+                    .map_err(|e| DatabaseError::QueryFailed {
+                        operation: DbOperation::InspectSpace,
+                        sql: stmt.to_string(),
+                        source: e,
+                    })
+            })
+            .await?;
+
+        if enabled {
+            return Ok(false);
+        }
+
+        let script = CurrentDialect::enable_incremental_compaction_script();
+        sqlx::raw_sql(&script)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| DatabaseError::QueryFailed {
+                operation: DbOperation::Compact,
+                sql: script.clone(),
+                source: e,
+            })?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "sqlite",
+    not(any(feature = "postgres", feature = "mysql"))
+))]
+mod tests {
+    use super::{CompactMode, CompactOutcome};
+    use crate::database::{Database, Pool};
+    use tempfile::TempDir;
+
+    /// Opens a migrated database backed by a file, since compaction of in-memory
+    /// databases does not reflect on-disk behavior.
+    async fn file_database(dir: &TempDir) -> Database {
+        let url = format!("sqlite://{}?mode=rwc", dir.path().join("buru.db").display());
+        let db = Database::new(Pool::connect(&url).await.unwrap());
+        db.migrate().await.unwrap();
+        db
+    }
+
+    /// Archives and then deletes enough rows to leave free pages behind.
+    async fn archive_and_delete(db: &Database) {
+        sqlx::raw_sql(
+            r#"
+            WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 5000)
+            INSERT INTO images (hash) SELECT printf('%016x', i) FROM n;
+            INSERT INTO image_sources (image_hash, position, url)
+            SELECT hash, 0, hex(randomblob(64)) FROM images;
+            DELETE FROM image_sources;
+            DELETE FROM images;
+            "#,
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_incremental_compaction() {
+        let dir = TempDir::new().unwrap();
+        let db = file_database(&dir).await;
+
+        assert!(db.enable_incremental_compaction().await.unwrap());
+        assert!(!db.enable_incremental_compaction().await.unwrap());
+
+        archive_and_delete(&db).await;
+        let report = db.space_report().await.unwrap();
+        assert!(report.freelist_pages > 0);
+        assert_eq!(report.page_count * report.page_size, report.bytes_on_disk);
+
+        let CompactOutcome::Compacted { before, after } =
+            db.compact(CompactMode::Incremental(10)).await.unwrap()
+        else {
+            panic!("sqlite must compact");
+        };
+
+        assert_eq!(report, before);
+        assert_eq!(
+            before.freelist_pages.saturating_sub(10),
+            after.freelist_pages
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_compaction() {
+        let dir = TempDir::new().unwrap();
+        let db = file_database(&dir).await;
+
+        archive_and_delete(&db).await;
+
+        let CompactOutcome::Compacted { before, after } =
+            db.compact(CompactMode::Full).await.unwrap()
+        else {
+            panic!("sqlite must compact");
+        };
+
+        assert!(before.freelist_pages > 0);
+        assert_eq!(0, after.freelist_pages);
+        assert!(after.bytes_on_disk < before.bytes_on_disk);
+    }
+}
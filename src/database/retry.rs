@@ -0,0 +1,137 @@
+//! Backoff between attempts of a database operation.
+//!
+//! Operations failing with a transient error, see `DatabaseError::is_retryable`, are
+//! attempted again after a delay. The delay doubles with every retry up to a cap, so
+//! a contended pool is given increasingly more time to recover. Jitter spreads the
+//! retries of concurrent operations, which would otherwise collide again.
+
+use std::{
+    hash::{BuildHasher, Hasher, RandomState},
+    time::Duration,
+};
+
+/// How `Database` retries operations that fail with a transient error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of attempts, including the first one. At least one attempt is
+    /// always made.
+    pub max_attempts: u32,
+    /// The delay before the first retry, doubled for every further retry.
+    pub base_delay: Duration,
+    /// The longest delay between two attempts.
+    pub max_delay: Duration,
+    /// Whether each delay is shortened by a random amount of up to half of it.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    /// Makes three attempts with a flat delay of 300ms between them.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(300),
+            max_delay: Duration::from_millis(300),
+            jitter: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry, counted from zero.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+
+        if self.jitter {
+            let random = RandomState::new().build_hasher().finish();
+            delay.mul_f64(1.0 - random as f64 / u64::MAX as f64 / 2.0)
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use crate::database::{Database, DatabaseError, MIGRATOR, Pool};
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_delay() {
+        let flat = RetryPolicy::default();
+        assert_eq!(Duration::from_millis(300), flat.delay(0));
+        assert_eq!(Duration::from_millis(300), flat.delay(5));
+
+        let exponential = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: false,
+        };
+        assert_eq!(Duration::from_millis(100), exponential.delay(0));
+        assert_eq!(Duration::from_millis(200), exponential.delay(1));
+        assert_eq!(Duration::from_millis(800), exponential.delay(3));
+        assert_eq!(Duration::from_secs(1), exponential.delay(4));
+        assert_eq!(Duration::from_secs(1), exponential.delay(u32::MAX));
+
+        let jittered = RetryPolicy {
+            jitter: true,
+            ..exponential
+        };
+        for retry in 0..10 {
+            let delay = jittered.delay(retry);
+            assert!(delay <= exponential.delay(retry));
+            assert!(delay >= exponential.delay(retry) / 2);
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_retry(pool: Pool) {
+        let db = Database::new(pool).with_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(60),
+            max_delay: Duration::from_secs(60),
+            jitter: false,
+        });
+
+        // A non-retryable error returns without waiting for a retry.
+        let attempts = AtomicU32::new(0);
+        let started = Instant::now();
+        let result: Result<(), _> = db
+            .retry("test_retry", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(DatabaseError::ReadOnly)
+            })
+            .await;
+        assert!(matches!(result, Err(DatabaseError::ReadOnly)));
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+        assert!(started.elapsed() < Duration::from_secs(60));
+
+        // A retryable error is attempted again until the attempts are used up.
+        let db = db.with_retry_policy(RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        });
+        let attempts = AtomicU32::new(0);
+        let result: Result<(), _> = db
+            .retry("test_retry", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(DatabaseError::TransactionFailed {
+                    source: sqlx::Error::PoolTimedOut,
+                })
+            })
+            .await;
+        assert!(matches!(
+            result,
+            Err(DatabaseError::TransactionFailed { .. })
+        ));
+        assert_eq!(3, attempts.load(Ordering::SeqCst));
+    }
+}
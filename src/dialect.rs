@@ -7,7 +7,8 @@
 //!
 //! The module includes a compile-time determination of the current SQL dialect used,
 //! driven by feature flags. When the `sqlite` feature is enabled, the `CurrentDialect`
-//! type alias is set to `sqlite::SqliteDialect`.
+//! type alias is set to `sqlite::SqliteDialect`, and likewise for `postgres` and
+//! `mysql`. Exactly one of these features must be enabled.
 //!
 //! ## Key Components
 //! - **`Dialect` Trait**: Outlines methods for generating SQL statements and queries
@@ -20,32 +21,47 @@
 //! to the underlying SQL dialect, making it simpler to add support for additional
 //! databases in the future.
 
-#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+use crate::database::CompactMode;
+
+#[cfg(all(feature = "sqlite", not(any(feature = "postgres", feature = "mysql"))))]
 mod sqlite;
 
 /// The current SQL dialect used at compile time, determined by feature flags.
-#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[cfg(all(feature = "sqlite", not(any(feature = "postgres", feature = "mysql"))))]
 pub type CurrentDialect = sqlite::SqliteDialect;
 
-#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[cfg(all(feature = "sqlite", not(any(feature = "postgres", feature = "mysql"))))]
 pub type Db = sqlx::Sqlite;
 
-#[cfg(all(feature = "sqlite", not(feature = "postgres")))]
+#[cfg(all(feature = "sqlite", not(any(feature = "postgres", feature = "mysql"))))]
 pub type CurrentRow = sqlx::sqlite::SqliteRow;
 
-#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+#[cfg(all(feature = "postgres", not(any(feature = "sqlite", feature = "mysql"))))]
 mod postgres;
 
 /// The current SQL dialect used at compile time, determined by feature flags.
-#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+#[cfg(all(feature = "postgres", not(any(feature = "sqlite", feature = "mysql"))))]
 pub type CurrentDialect = postgres::PostgresDialect;
 
-#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+#[cfg(all(feature = "postgres", not(any(feature = "sqlite", feature = "mysql"))))]
 pub type Db = sqlx::Postgres;
 
-#[cfg(all(feature = "postgres", not(feature = "sqlite")))]
+#[cfg(all(feature = "postgres", not(any(feature = "sqlite", feature = "mysql"))))]
 pub type CurrentRow = sqlx::postgres::PgRow;
 
+#[cfg(all(feature = "mysql", not(any(feature = "sqlite", feature = "postgres"))))]
+mod mysql;
+
+/// The current SQL dialect used at compile time, determined by feature flags.
+#[cfg(all(feature = "mysql", not(any(feature = "sqlite", feature = "postgres"))))]
+pub type CurrentDialect = mysql::MysqlDialect;
+
+#[cfg(all(feature = "mysql", not(any(feature = "sqlite", feature = "postgres"))))]
+pub type Db = sqlx::MySql;
+
+#[cfg(all(feature = "mysql", not(any(feature = "sqlite", feature = "postgres"))))]
+pub type CurrentRow = sqlx::mysql::MySqlRow;
+
 /// A trait for SQL dialects to support database-specific query generation.
 ///
 /// This trait provides methods that return SQL strings compatible with the
@@ -53,8 +69,49 @@ pub type CurrentRow = sqlx::postgres::PgRow;
 /// away differences in placeholder syntax, conditional insert behavior, and
 /// DELETE/SELECT semantics so that higher-level logic can remain dialect-agnostic.
 pub trait Dialect {
+    /// The name of the database backend, e.g. `sqlite`.
+    fn name() -> &'static str;
+
     fn placeholder(idx: usize) -> String;
 
+    /// The maximum number of bind parameters a single statement may carry.
+    fn max_bind_params() -> usize {
+        999
+    }
+
+    fn placeholders(idxs: std::ops::RangeInclusive<usize>) -> String {
+        idxs.map(Self::placeholder).collect::<Vec<_>>().join(", ")
+    }
+
+    fn server_version_statement() -> String;
+
+    /// Returns the page count, free page count and page size of the database file.
+    fn space_report_statement() -> String {
+        "SELECT page_count, freelist_count, page_size \
+        FROM pragma_page_count(), pragma_freelist_count(), pragma_page_size()"
+            .to_string()
+    }
+
+    /// Returns a statement yielding whether incremental compaction is active, or
+    /// `None` if the backend has no such mode.
+    fn incremental_compaction_enabled_statement() -> Option<String> {
+        Some("SELECT auto_vacuum = 2 FROM pragma_auto_vacuum()".to_string())
+    }
+
+    /// Returns the script switching the database to incremental compaction.
+    fn enable_incremental_compaction_script() -> String {
+        "PRAGMA auto_vacuum = INCREMENTAL; VACUUM;".to_string()
+    }
+
+    /// Returns the script reclaiming free pages, or the reason the backend does not
+    /// need explicit compaction.
+    fn compact_script(mode: CompactMode) -> Result<String, &'static str> {
+        match mode {
+            CompactMode::Incremental(pages) => Ok(format!("PRAGMA incremental_vacuum({pages});")),
+            CompactMode::Full => Ok("VACUUM;".to_string()),
+        }
+    }
+
     fn exists_image() -> String {
         format!(
             "SELECT EXISTS (SELECT 1 FROM images WHERE hash = {})",
@@ -62,27 +119,225 @@ pub trait Dialect {
         )
     }
 
+    /// Matches images tagged with the bound tag, or with the tag it is an alias of.
     fn exists_tag_query(idx: usize) -> String {
         format!(
-            "EXISTS (SELECT 1 FROM image_tags WHERE image_tags.image_hash = image_with_metadata.hash AND image_tags.tag_name = {})",
+            "EXISTS (SELECT 1 FROM image_tags LEFT JOIN tag_aliases ON tag_aliases.canonical = image_tags.tag_name WHERE image_tags.image_hash = image_with_metadata.hash AND {} IN (image_tags.tag_name, tag_aliases.alias))",
             Self::placeholder(idx)
         )
     }
 
     fn exists_date_until_query(idx: usize) -> String {
         format!(
-            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = images.hash AND created_at <= {})",
+            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = image_with_metadata.hash AND created_at <= {})",
             Self::placeholder(idx)
         )
     }
 
     fn exists_date_since_query(idx: usize) -> String {
         format!(
-            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = images.hash AND created_at >= {})",
+            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = image_with_metadata.hash AND created_at >= {})",
+            Self::placeholder(idx)
+        )
+    }
+
+    /// Returns a condition comparing a numeric `image_metadatas` column, e.g. `width`,
+    /// with a parameter using an SQL operator such as `>=`.
+    fn exists_metadata_comparison_query(column: &str, operator: &str, idx: usize) -> String {
+        format!(
+            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = image_with_metadata.hash AND {} {} {})",
+            column,
+            operator,
+            Self::placeholder(idx)
+        )
+    }
+
+    /// Returns a condition matching images whose width to height ratio is within 1% of
+    /// a parameter. Images without a height match nothing.
+    fn exists_aspect_ratio_query(idx: usize) -> String {
+        format!(
+            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = image_with_metadata.hash AND ABS(width / (NULLIF(height, 0) * CAST({} AS REAL)) - 1) <= 0.01)",
             Self::placeholder(idx)
         )
     }
 
+    /// Returns a comparison of the number of sources of an image with a count.
+    fn source_count_comparison_query(operator: &str, idx: usize) -> String {
+        // Parameters are bound as text, which is cast as the count has no affinity.
+        format!(
+            "(SELECT COUNT(*) FROM image_sources WHERE image_sources.image_hash = image_with_metadata.hash) {} CAST({} AS INTEGER)",
+            operator,
+            Self::placeholder(idx)
+        )
+    }
+
+    /// Returns the `LIMIT` and `OFFSET` clauses, pushing the parameters they bind.
+    fn limit_offset_query(
+        limit: Option<u32>,
+        offset: Option<u32>,
+        params: &mut Vec<String>,
+    ) -> String {
+        let mut sql = String::new();
+
+        if let Some(limit) = limit {
+            params.push(limit.to_string());
+            sql.push_str(&format!(
+                " LIMIT CAST({} AS INTEGER)",
+                Self::placeholder(params.len())
+            ));
+        }
+
+        if let Some(offset) = offset {
+            params.push(offset.to_string());
+            sql.push_str(&format!(
+                " OFFSET CAST({} AS INTEGER)",
+                Self::placeholder(params.len())
+            ));
+        }
+
+        sql
+    }
+
+    /// Returns a `LIKE` match of the column against a pattern whose wildcards are
+    /// escaped with a backslash.
+    fn like_query(column: &str, idx: usize) -> String {
+        format!("{column} LIKE {} ESCAPE '\\'", Self::placeholder(idx))
+    }
+
+    /// Returns the function ordering rows randomly.
+    fn random_query() -> String {
+        "RANDOM()".to_string()
+    }
+
+    fn format_in_query(idxs: std::ops::RangeInclusive<usize>) -> String {
+        format!("format IN ({})", Self::placeholders(idxs))
+    }
+
+    fn has_duration_query() -> String {
+        "duration IS NOT NULL".to_string()
+    }
+
+    fn is_featured_query() -> String {
+        "is_featured".to_string()
+    }
+
+    fn is_public_query() -> String {
+        "is_public".to_string()
+    }
+
+    fn is_deleted_query() -> String {
+        "deleted_at IS NOT NULL".to_string()
+    }
+
+    /// Returns a comparison of the order keys and the hash with the cursor of keyset
+    /// pagination, whose values are bound in the same order from `first_idx` on. The
+    /// rows following the cursor in ascending order are greater, in descending order
+    /// less. Hashes are stored as fixed-width lowercase hex, so they compare like the
+    /// numbers.
+    fn keyset_cursor_clause(columns: &[&str], first_idx: usize, descending: bool) -> String {
+        let operator = if descending { "<" } else { ">" };
+        if columns.is_empty() {
+            return format!("hash {} {}", operator, Self::placeholder(first_idx));
+        }
+
+        let keys = columns
+            .iter()
+            .map(|column| Self::order_key(column))
+            .collect::<Vec<_>>();
+        let values = columns
+            .iter()
+            .chain(&["hash"])
+            .enumerate()
+            .map(|(i, column)| Self::cursor_value(column, first_idx + i))
+            .collect::<Vec<_>>();
+        format!(
+            "({}, hash) {} ({})",
+            keys.join(", "),
+            operator,
+            values.join(", ")
+        )
+    }
+
+    /// Returns the expression an order key column is sorted and compared by. Images
+    /// without metadata have neither a creation date nor a file size, and sort before
+    /// all others in ascending order, as NULL would not compare with the cursor.
+    fn order_key(column: &str) -> String {
+        match column {
+            "created_at" => "COALESCE(created_at, '')".to_string(),
+            "file_size" => "COALESCE(file_size, -1)".to_string(),
+            _ => column.to_string(),
+        }
+    }
+
+    /// Returns the placeholder of a cursor value compared with the column. Values are
+    /// bound as text.
+    fn cursor_value(_column: &str, idx: usize) -> String {
+        Self::placeholder(idx)
+    }
+
+    /// Selects the order keys of an image as text, the values of its `Cursor`.
+    fn query_order_keys_statement(columns: &[&str]) -> String {
+        let keys = columns
+            .iter()
+            .map(|column| format!("CAST({} AS TEXT)", Self::order_key(column)))
+            .collect::<Vec<_>>();
+        format!(
+            "SELECT {} FROM image_with_metadata WHERE hash = {}",
+            keys.join(", "),
+            Self::placeholder(1)
+        )
+    }
+
+    /// Returns a match of the rating code. Unrated images do not match it, and thus
+    /// match its negation.
+    fn exists_rating_query(idx: usize) -> String {
+        format!("COALESCE(rating = {}, FALSE)", Self::placeholder(idx))
+    }
+
+    fn filter_existing_images_statement(count: usize) -> String {
+        format!(
+            "SELECT hash FROM images WHERE deleted_at IS NULL AND hash IN ({})",
+            Self::placeholders(1..=count)
+        )
+    }
+
+    fn query_dimensions_statement(count: usize) -> String {
+        let conditions = (0..count)
+            .map(|i| {
+                format!(
+                    "(width = {} AND height = {})",
+                    Self::placeholder(2 * i + 1),
+                    Self::placeholder(2 * i + 2)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" OR ");
+
+        format!(
+            "SELECT image_hash FROM image_metadatas WHERE {} ORDER BY image_hash",
+            conditions
+        )
+    }
+
+    fn query_all_images_statement() -> String {
+        "SELECT hash FROM images ORDER BY hash".to_string()
+    }
+
+    fn query_images_without_metadata_statement() -> String {
+        r#"SELECT hash FROM images
+        LEFT JOIN image_metadatas ON images.hash = image_metadatas.image_hash
+        WHERE image_metadatas.image_hash IS NULL ORDER BY hash"#
+            .to_string()
+    }
+
+    fn query_hash_prefix_statement() -> String {
+        format!(
+            "SELECT hash FROM images WHERE hash LIKE {} ORDER BY hash LIMIT {}",
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
     fn ensure_image_statement() -> String {
         format!(
             "INSERT OR IGNORE INTO images (hash) VALUES ({})",
@@ -97,11 +352,29 @@ pub trait Dialect {
         )
     }
 
+    /// Returns a statement inserting `count` tags at once, ignoring existing ones.
+    fn ensure_tags_statement(count: usize) -> String {
+        format!(
+            "INSERT OR IGNORE INTO tags (name) VALUES {}",
+            Self::values_rows(count, 1)
+        )
+    }
+
+    /// Returns `count` rows of `width` placeholders each, e.g. `(?, ?), (?, ?)`.
+    fn values_rows(count: usize, width: usize) -> String {
+        (0..count)
+            .map(|i| format!("({})", Self::placeholders(i * width + 1..=(i + 1) * width)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     fn ensure_metadata_statement() -> String {
         format!(
             r#"INSERT OR IGNORE INTO image_metadatas
-            (image_hash, width, height, format, color_type, file_size, created_at, duration)
-            VALUES ({}, {}, {}, {}, {}, {}, {}, {})"#,
+            (image_hash, width, height, format, color_type, file_size, created_at, duration,
+            camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation,
+            digest)
+            VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {})"#,
             Self::placeholder(1),
             Self::placeholder(2),
             Self::placeholder(3),
@@ -109,21 +382,153 @@ pub trait Dialect {
             Self::placeholder(5),
             Self::placeholder(6),
             Self::placeholder(7),
-            Self::placeholder(8)
+            Self::placeholder(8),
+            Self::placeholder(9),
+            Self::placeholder(10),
+            Self::placeholder(11),
+            Self::placeholder(12),
+            Self::placeholder(13),
+            Self::placeholder(14),
+            Self::placeholder(15),
+            Self::placeholder(16)
+        )
+    }
+
+    /// Returns a metadata insert that yields the stored row, or `None` if the
+    /// backend lacks `RETURNING` and callers must select the row afterwards.
+    fn ensure_metadata_returning_statement() -> Option<String> {
+        None
+    }
+
+    /// Returns an insert appending a source after the existing sources of an image.
+    ///
+    /// Binds the image hash, the source, and the image hash again.
+    fn insert_source_statement() -> String {
+        format!(
+            r#"INSERT INTO image_sources (image_hash, position, url)
+            SELECT {}, COALESCE(MAX(position) + 1, 0), {} FROM image_sources WHERE image_hash = {}"#,
+            Self::placeholder(1),
+            Self::placeholder(2),
+            Self::placeholder(3)
+        )
+    }
+
+    fn delete_source_statement() -> String {
+        format!(
+            "DELETE FROM image_sources WHERE image_hash = {} AND url = {}",
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn delete_sources_by_image_statement() -> String {
+        format!(
+            "DELETE FROM image_sources WHERE image_hash = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    fn update_featured_statement() -> String {
+        format!(
+            "UPDATE images SET is_featured = {} WHERE hash = {}",
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn update_visibility_statement() -> String {
+        format!(
+            "UPDATE images SET is_public = {} WHERE hash = {}",
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn query_visibility_statement() -> String {
+        format!(
+            "SELECT is_public FROM images WHERE hash = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    /// Returns a statement marking an image as deleted at a time, unless it is already.
+    fn soft_delete_image_statement() -> String {
+        format!(
+            "UPDATE images SET deleted_at = {} WHERE hash = {} AND deleted_at IS NULL",
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn restore_image_statement() -> String {
+        format!(
+            "UPDATE images SET deleted_at = NULL WHERE hash = {} AND deleted_at IS NOT NULL",
+            Self::placeholder(1)
+        )
+    }
+
+    fn query_deleted_at_statement() -> String {
+        format!(
+            "SELECT deleted_at FROM images WHERE hash = {}",
+            Self::placeholder(1)
         )
     }
 
-    fn update_source_statement() -> String {
+    fn update_rating_statement() -> String {
         format!(
-            "UPDATE images SET source = {} WHERE hash = {}",
+            "UPDATE images SET rating = {} WHERE hash = {}",
             Self::placeholder(1),
             Self::placeholder(2)
         )
     }
 
-    fn query_source_statement() -> String {
+    fn query_rating_statement() -> String {
         format!(
-            "SELECT source FROM images WHERE hash = {}",
+            "SELECT rating FROM images WHERE hash = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    fn update_phash_statement() -> String {
+        format!(
+            "UPDATE images SET phash = {} WHERE hash = {}",
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn query_phash_statement() -> String {
+        format!(
+            "SELECT phash FROM images WHERE hash = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    /// Returns the hashes of images whose perceptual hash is within the Hamming
+    /// distance bound second of the one bound first, closest first.
+    fn query_images_by_phash_statement() -> String {
+        // SQLite has neither XOR nor a bit count: `(a | b) - (a & b)` is the XOR, and
+        // the bits are summed one by one. `>>` keeps the sign, so each bit is masked.
+        let bits = (0..64)
+            .map(|i| format!("((x >> {i}) & 1)"))
+            .collect::<Vec<_>>()
+            .join(" + ");
+
+        format!(
+            r#"SELECT hash FROM (
+                SELECT hash, {bits} AS distance FROM (
+                    SELECT hash, (phash | q.p) - (phash & q.p) AS x
+                    FROM images, (SELECT {} AS p) AS q WHERE phash IS NOT NULL
+                )
+            ) WHERE distance <= {} ORDER BY distance, hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn query_sources_statement() -> String {
+        format!(
+            "SELECT url FROM image_sources WHERE image_hash = {} ORDER BY position",
             Self::placeholder(1)
         )
     }
@@ -136,12 +541,77 @@ pub trait Dialect {
         )
     }
 
+    /// Returns a statement associating an image with `count` tags at once, ignoring
+    /// existing associations. Each row binds the image hash and a tag.
+    fn ensure_image_tags_statement(count: usize) -> String {
+        format!(
+            "INSERT OR IGNORE INTO image_tags (image_hash, tag_name) VALUES {}",
+            Self::values_rows(count, 2)
+        )
+    }
+
+    fn copy_image_statement() -> String {
+        format!(
+            r#"INSERT OR IGNORE INTO images (hash, is_featured, is_public, rating, phash, deleted_at)
+            SELECT {}, is_featured, is_public, rating, phash, deleted_at FROM images
+            WHERE hash = {}"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_image_tags_statement() -> String {
+        format!(
+            r#"INSERT OR IGNORE INTO image_tags (image_hash, tag_name, created_at)
+            SELECT {}, tag_name, created_at FROM image_tags WHERE image_hash = {}"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_image_sources_statement() -> String {
+        format!(
+            r#"INSERT OR IGNORE INTO image_sources (image_hash, position, url)
+            SELECT {}, position, url FROM image_sources WHERE image_hash = {}"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn rename_image_tags_statement() -> String {
+        format!(
+            r#"INSERT OR IGNORE INTO image_tags (image_hash, tag_name, created_at)
+            SELECT image_hash, {}, created_at FROM image_tags WHERE tag_name = {}"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_metadata_statement() -> String {
+        format!(
+            r#"INSERT OR IGNORE INTO image_metadatas
+            (image_hash, width, height, format, color_type, file_size, created_at, duration,
+            camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation,
+            digest)
+            SELECT {}, width, height, format, color_type, file_size, created_at, duration,
+            camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation, digest
+            FROM image_metadatas WHERE image_hash = {}"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
     fn query_image_statement(condition: String) -> String {
         format!("SELECT hash FROM image_with_metadata {}", condition)
     }
 
+    /// Counts the results of a query. The query is nested, as some databases reject
+    /// an `ORDER BY`, which a cursor brings along, in an aggregate query.
     fn count_image_statement(condition: String) -> String {
-        format!("SELECT COUNT(*) FROM image_with_metadata {}", condition)
+        format!(
+            "SELECT COUNT(*) FROM (SELECT hash FROM image_with_metadata {}) AS results",
+            condition
+        )
     }
 
     fn count_image_by_tag_statement() -> String {
@@ -159,17 +629,162 @@ pub trait Dialect {
         ]
     }
 
+    fn query_tag_counts_statement() -> String {
+        "SELECT tags.name, COALESCE(tag_counts.count, 0) FROM tags \
+         LEFT JOIN tag_counts ON tag_counts.tag_name = tags.name ORDER BY tags.name"
+            .to_string()
+    }
+
     fn query_tag_statement(condition: String) -> String {
         format!("SELECT name FROM tags {}", condition)
     }
 
+    fn query_tag_wiki_statement() -> String {
+        format!(
+            "SELECT tag_name, body, updated_at FROM tag_wikis WHERE tag_name = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    fn insert_tag_wiki_statement() -> String {
+        format!(
+            "INSERT INTO tag_wikis (tag_name, body, updated_at) VALUES ({})",
+            Self::placeholders(1..=3)
+        )
+    }
+
+    fn delete_tag_wiki_statement() -> String {
+        format!(
+            "DELETE FROM tag_wikis WHERE tag_name = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    fn query_tag_alias_statement() -> String {
+        format!(
+            "SELECT canonical FROM tag_aliases WHERE alias = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    fn query_tag_aliases_statement(condition: String) -> String {
+        format!(
+            "SELECT name, canonical FROM (SELECT alias AS name, canonical FROM tag_aliases) AS aliases {}",
+            condition
+        )
+    }
+
+    fn insert_tag_alias_statement() -> String {
+        format!(
+            "INSERT INTO tag_aliases (alias, canonical) VALUES ({})",
+            Self::placeholders(1..=2)
+        )
+    }
+
+    fn delete_tag_alias_statement() -> String {
+        format!(
+            "DELETE FROM tag_aliases WHERE alias = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    fn repoint_tag_aliases_statement() -> String {
+        format!(
+            "UPDATE tag_aliases SET canonical = {} WHERE canonical = {}",
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn ensure_tag_implication_statement() -> String {
+        format!(
+            "INSERT OR IGNORE INTO tag_implications (antecedent, consequent) VALUES ({})",
+            Self::placeholders(1..=2)
+        )
+    }
+
+    /// Returns a statement selecting every tag implied by `count` tags, following
+    /// implications transitively. `UNION` drops repeated tags, so it ends on cycles.
+    fn query_implied_tags_statement(count: usize) -> String {
+        format!(
+            r#"WITH RECURSIVE implied (tag) AS (
+                SELECT consequent FROM tag_implications WHERE antecedent IN ({})
+                UNION
+                SELECT tag_implications.consequent FROM tag_implications
+                JOIN implied ON tag_implications.antecedent = implied.tag
+            )
+            SELECT tag FROM implied"#,
+            Self::placeholders(1..=count)
+        )
+    }
+
     fn query_tags_by_image_statement() -> String {
         format!(
-            "SELECT tag_name FROM image_tags WHERE image_hash = {}",
+            "SELECT tag_name FROM image_tags WHERE image_hash = {} ORDER BY tag_name",
             Self::placeholder(1)
         )
     }
 
+    /// Returns a statement selecting the tags of an image in the order they were
+    /// attached, tags attached at once in alphabetical order.
+    fn query_tags_by_image_in_insertion_order_statement() -> String {
+        format!(
+            "SELECT tag_name FROM image_tags WHERE image_hash = {} ORDER BY created_at, tag_name",
+            Self::placeholder(1)
+        )
+    }
+
+    fn query_tags_with_categories_by_image_statement() -> String {
+        format!(
+            r#"SELECT image_tags.tag_name, tags.category FROM image_tags
+            JOIN tags ON tags.name = image_tags.tag_name
+            WHERE image_tags.image_hash = {}"#,
+            Self::placeholder(1)
+        )
+    }
+
+    fn update_tag_category_statement() -> String {
+        format!(
+            "UPDATE tags SET category = {} WHERE name = {}",
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn query_tags_by_images_statement(count: usize) -> String {
+        format!(
+            "SELECT image_hash, tag_name FROM image_tags WHERE image_hash IN ({})",
+            Self::placeholders(1..=count)
+        )
+    }
+
+    /// Returns the visibility, rating and metadata of the given images. The metadata
+    /// columns are `NULL` for images without metadata.
+    fn query_images_with_metadata_by_images_statement(count: usize) -> String {
+        format!(
+            r#"SELECT images.hash, images.is_public, images.rating, image_metadatas.* FROM images
+            LEFT JOIN image_metadatas ON image_metadatas.image_hash = images.hash
+            WHERE images.hash IN ({})"#,
+            Self::placeholders(1..=count)
+        )
+    }
+
+    fn query_tags_with_categories_by_images_statement(count: usize) -> String {
+        format!(
+            r#"SELECT image_tags.image_hash, image_tags.tag_name, tags.category FROM image_tags
+            JOIN tags ON tags.name = image_tags.tag_name
+            WHERE image_tags.image_hash IN ({})"#,
+            Self::placeholders(1..=count)
+        )
+    }
+
+    fn query_sources_by_images_statement(count: usize) -> String {
+        format!(
+            "SELECT image_hash, url FROM image_sources WHERE image_hash IN ({}) ORDER BY image_hash, position",
+            Self::placeholders(1..=count)
+        )
+    }
+
     fn query_metadata_statement() -> String {
         format!(
             "SELECT * FROM image_metadatas WHERE image_hash = {}",
@@ -185,6 +800,17 @@ pub trait Dialect {
         )
     }
 
+    fn delete_image_tags_by_tag_statement() -> String {
+        format!(
+            "DELETE FROM image_tags WHERE tag_name = {}",
+            Self::placeholder(1)
+        )
+    }
+
+    fn delete_tag_statement() -> String {
+        format!("DELETE FROM tags WHERE name = {}", Self::placeholder(1))
+    }
+
     fn delete_image_statement() -> String {
         format!("DELETE FROM images WHERE hash = {}", Self::placeholder(1))
     }
@@ -0,0 +1,246 @@
+use super::Dialect;
+use crate::database::CompactMode;
+
+/// MySQL dialect implementation of the `Dialect` trait, also used for MariaDB.
+pub struct MysqlDialect;
+
+impl Dialect for MysqlDialect {
+    fn name() -> &'static str {
+        "mysql"
+    }
+
+    fn placeholder(_idx: usize) -> String {
+        "?".to_string()
+    }
+
+    fn server_version_statement() -> String {
+        "SELECT VERSION()".to_string()
+    }
+
+    fn max_bind_params() -> usize {
+        65535
+    }
+
+    fn space_report_statement() -> String {
+        "SELECT CAST(CEIL(COALESCE(SUM(data_length + index_length + data_free), 0) / @@innodb_page_size) AS SIGNED) \
+        AS page_count, CAST(CEIL(COALESCE(SUM(data_free), 0) / @@innodb_page_size) AS SIGNED) AS freelist_count, \
+        CAST(@@innodb_page_size AS SIGNED) AS page_size \
+        FROM information_schema.tables WHERE table_schema = DATABASE()"
+            .to_string()
+    }
+
+    fn incremental_compaction_enabled_statement() -> Option<String> {
+        None
+    }
+
+    fn compact_script(_mode: CompactMode) -> Result<String, &'static str> {
+        Err("InnoDB reuses freed pages; run OPTIMIZE TABLE manually if a table is bloated")
+    }
+
+    fn limit_offset_query(
+        limit: Option<u32>,
+        offset: Option<u32>,
+        _params: &mut Vec<String>,
+    ) -> String {
+        // MySQL only binds integers in LIMIT and cannot CAST there, and OFFSET needs
+        // a LIMIT. Both values are numbers, so they are inlined.
+        match (limit, offset) {
+            (Some(limit), Some(offset)) => format!(" LIMIT {limit} OFFSET {offset}"),
+            (Some(limit), None) => format!(" LIMIT {limit}"),
+            (None, Some(offset)) => format!(" LIMIT {} OFFSET {offset}", u64::MAX),
+            (None, None) => String::new(),
+        }
+    }
+
+    fn like_query(column: &str, idx: usize) -> String {
+        // Backslash is the default escape character, and `'\'` would not terminate.
+        format!("{column} LIKE {}", Self::placeholder(idx))
+    }
+
+    fn random_query() -> String {
+        "RAND()".to_string()
+    }
+
+    fn query_order_keys_statement(columns: &[&str]) -> String {
+        // MySQL casts to text as CHAR rather than TEXT.
+        let keys = columns
+            .iter()
+            .map(|column| format!("CAST({} AS CHAR)", Self::order_key(column)))
+            .collect::<Vec<_>>();
+        format!(
+            "SELECT {} FROM image_with_metadata WHERE hash = {}",
+            keys.join(", "),
+            Self::placeholder(1)
+        )
+    }
+
+    fn exists_aspect_ratio_query(idx: usize) -> String {
+        // MySQL converts text to a number in arithmetic, and divides without truncating.
+        format!(
+            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = image_with_metadata.hash AND ABS(width / (NULLIF(height, 0) * {}) - 1) <= 0.01)",
+            Self::placeholder(idx)
+        )
+    }
+
+    fn source_count_comparison_query(operator: &str, idx: usize) -> String {
+        // MySQL casts to integers as SIGNED rather than INTEGER.
+        format!(
+            "(SELECT COUNT(*) FROM image_sources WHERE image_sources.image_hash = image_with_metadata.hash) {} CAST({} AS SIGNED)",
+            operator,
+            Self::placeholder(idx)
+        )
+    }
+
+    fn ensure_image_statement() -> String {
+        format!(
+            "INSERT INTO images (hash) VALUES ({}) ON DUPLICATE KEY UPDATE hash = hash",
+            Self::placeholder(1)
+        )
+    }
+
+    fn ensure_tag_statement() -> String {
+        format!(
+            "INSERT INTO tags (name) VALUES ({}) ON DUPLICATE KEY UPDATE name = name",
+            Self::placeholder(1)
+        )
+    }
+
+    fn ensure_tags_statement(count: usize) -> String {
+        format!(
+            "INSERT INTO tags (name) VALUES {} ON DUPLICATE KEY UPDATE name = name",
+            Self::values_rows(count, 1)
+        )
+    }
+
+    fn ensure_tag_implication_statement() -> String {
+        format!(
+            r#"INSERT INTO tag_implications (antecedent, consequent) VALUES ({})
+            ON DUPLICATE KEY UPDATE antecedent = antecedent"#,
+            Self::placeholders(1..=2)
+        )
+    }
+
+    fn ensure_metadata_statement() -> String {
+        format!(
+            r#"INSERT INTO image_metadatas
+            (image_hash, width, height, format, color_type, file_size, created_at, duration,
+            camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation,
+            digest)
+            VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {})
+            ON DUPLICATE KEY UPDATE image_hash = image_hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2),
+            Self::placeholder(3),
+            Self::placeholder(4),
+            Self::placeholder(5),
+            Self::placeholder(6),
+            Self::placeholder(7),
+            Self::placeholder(8),
+            Self::placeholder(9),
+            Self::placeholder(10),
+            Self::placeholder(11),
+            Self::placeholder(12),
+            Self::placeholder(13),
+            Self::placeholder(14),
+            Self::placeholder(15),
+            Self::placeholder(16)
+        )
+    }
+
+    fn ensure_image_tag_statement() -> String {
+        format!(
+            r#"INSERT INTO image_tags (image_hash, tag_name) VALUES ({}, {})
+            ON DUPLICATE KEY UPDATE image_hash = image_hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn ensure_image_tags_statement(count: usize) -> String {
+        format!(
+            r#"INSERT INTO image_tags (image_hash, tag_name) VALUES {}
+            ON DUPLICATE KEY UPDATE image_hash = image_hash"#,
+            Self::values_rows(count, 2)
+        )
+    }
+
+    fn query_images_by_phash_statement() -> String {
+        format!(
+            r#"SELECT hash FROM (
+                SELECT hash, CAST(BIT_COUNT(phash ^ {}) AS SIGNED) AS distance
+                FROM images WHERE phash IS NOT NULL
+            ) AS d WHERE distance <= {} ORDER BY distance, hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_image_statement() -> String {
+        format!(
+            r#"INSERT INTO images (hash, is_featured, is_public, rating, phash, deleted_at)
+            SELECT * FROM (
+                SELECT {} AS hash, is_featured, is_public, rating, phash, deleted_at
+                FROM images WHERE hash = {}
+            ) AS copied
+            ON DUPLICATE KEY UPDATE images.hash = images.hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_image_tags_statement() -> String {
+        format!(
+            r#"INSERT INTO image_tags (image_hash, tag_name, created_at)
+            SELECT * FROM (
+                SELECT {} AS image_hash, tag_name, created_at
+                FROM image_tags WHERE image_hash = {}
+            ) AS copied
+            ON DUPLICATE KEY UPDATE image_tags.image_hash = image_tags.image_hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_image_sources_statement() -> String {
+        format!(
+            r#"INSERT INTO image_sources (image_hash, position, url)
+            SELECT * FROM (
+                SELECT {} AS image_hash, position, url FROM image_sources WHERE image_hash = {}
+            ) AS copied
+            ON DUPLICATE KEY UPDATE image_sources.image_hash = image_sources.image_hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn rename_image_tags_statement() -> String {
+        format!(
+            r#"INSERT INTO image_tags (image_hash, tag_name, created_at)
+            SELECT * FROM (
+                SELECT image_hash, {} AS tag_name, created_at
+                FROM image_tags WHERE tag_name = {}
+            ) AS renamed
+            ON DUPLICATE KEY UPDATE image_tags.image_hash = image_tags.image_hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_metadata_statement() -> String {
+        format!(
+            r#"INSERT INTO image_metadatas
+            (image_hash, width, height, format, color_type, file_size, created_at, duration,
+            camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation,
+            digest)
+            SELECT * FROM (
+                SELECT {} AS image_hash, width, height, format, color_type, file_size,
+                created_at, duration, camera_make, camera_model, gps_lat, gps_lon, taken_at,
+                exposure, orientation, digest
+                FROM image_metadatas WHERE image_hash = {}
+            ) AS copied
+            ON DUPLICATE KEY UPDATE image_metadatas.image_hash = image_metadatas.image_hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+}
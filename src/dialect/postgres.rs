@@ -1,13 +1,69 @@
 use super::Dialect;
+use crate::database::CompactMode;
 
 /// Postgres dialect implementation of the `Dialect` trait.
 pub struct PostgresDialect;
 
 impl Dialect for PostgresDialect {
+    fn name() -> &'static str {
+        "postgres"
+    }
+
     fn placeholder(idx: usize) -> String {
         format!("${idx}")
     }
 
+    fn server_version_statement() -> String {
+        "SHOW server_version".to_string()
+    }
+
+    fn max_bind_params() -> usize {
+        65535
+    }
+
+    fn space_report_statement() -> String {
+        "SELECT pg_database_size(current_database()) / current_setting('block_size')::bigint \
+        AS page_count, 0::bigint AS freelist_count, current_setting('block_size')::bigint AS page_size"
+            .to_string()
+    }
+
+    fn incremental_compaction_enabled_statement() -> Option<String> {
+        None
+    }
+
+    fn compact_script(_mode: CompactMode) -> Result<String, &'static str> {
+        Err(
+            "postgres reclaims space with autovacuum; run VACUUM FULL manually if a table is bloated",
+        )
+    }
+
+    fn exists_metadata_comparison_query(column: &str, operator: &str, idx: usize) -> String {
+        // Parameters are bound as text, which Postgres does not compare with integers.
+        format!(
+            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = image_with_metadata.hash AND {} {} CAST({} AS BIGINT))",
+            column,
+            operator,
+            Self::placeholder(idx)
+        )
+    }
+
+    fn exists_aspect_ratio_query(idx: usize) -> String {
+        // Parameters are bound as text, which Postgres does not multiply with integers.
+        format!(
+            "EXISTS (SELECT 1 FROM image_metadatas WHERE image_metadatas.image_hash = image_with_metadata.hash AND ABS(width / (NULLIF(height, 0) * CAST({} AS DOUBLE PRECISION)) - 1) <= 0.01)",
+            Self::placeholder(idx)
+        )
+    }
+
+    fn cursor_value(column: &str, idx: usize) -> String {
+        // Parameters are bound as text, which Postgres does not compare with other types.
+        match column {
+            "file_size" => format!("CAST({} AS BIGINT)", Self::placeholder(idx)),
+            "is_featured" => format!("CAST({} AS BOOLEAN)", Self::placeholder(idx)),
+            _ => Self::placeholder(idx),
+        }
+    }
+
     fn ensure_image_statement() -> String {
         format!(
             "INSERT INTO images (hash) VALUES ({}) ON CONFLICT DO NOTHING",
@@ -15,6 +71,76 @@ impl Dialect for PostgresDialect {
         )
     }
 
+    fn query_images_by_phash_statement() -> String {
+        // `bit_count` needs PostgreSQL 14, so the set bits are counted as text.
+        format!(
+            r#"SELECT hash FROM (
+                SELECT hash,
+                CAST(length(replace(CAST(CAST(phash # {} AS bit(64)) AS text), '0', '')) AS BIGINT)
+                AS distance
+                FROM images WHERE phash IS NOT NULL
+            ) AS d WHERE distance <= {} ORDER BY distance, hash"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_image_statement() -> String {
+        format!(
+            r#"INSERT INTO images (hash, is_featured, is_public, rating, phash, deleted_at)
+            SELECT {}, is_featured, is_public, rating, phash, deleted_at FROM images
+            WHERE hash = {}
+            ON CONFLICT DO NOTHING"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_image_tags_statement() -> String {
+        format!(
+            r#"INSERT INTO image_tags (image_hash, tag_name, created_at)
+            SELECT {}, tag_name, created_at FROM image_tags WHERE image_hash = {}
+            ON CONFLICT DO NOTHING"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_image_sources_statement() -> String {
+        format!(
+            r#"INSERT INTO image_sources (image_hash, position, url)
+            SELECT {}, position, url FROM image_sources WHERE image_hash = {}
+            ON CONFLICT DO NOTHING"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn rename_image_tags_statement() -> String {
+        format!(
+            r#"INSERT INTO image_tags (image_hash, tag_name, created_at)
+            SELECT image_hash, {}, created_at FROM image_tags WHERE tag_name = {}
+            ON CONFLICT DO NOTHING"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
+    fn copy_metadata_statement() -> String {
+        format!(
+            r#"INSERT INTO image_metadatas
+            (image_hash, width, height, format, color_type, file_size, created_at, duration,
+            camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation,
+            digest)
+            SELECT {}, width, height, format, color_type, file_size, created_at, duration,
+            camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation, digest
+            FROM image_metadatas WHERE image_hash = {}
+            ON CONFLICT DO NOTHING"#,
+            Self::placeholder(1),
+            Self::placeholder(2)
+        )
+    }
+
     fn ensure_tag_statement() -> String {
         format!(
             "INSERT INTO tags (name) VALUES ({}) ON CONFLICT DO NOTHING",
@@ -22,11 +148,27 @@ impl Dialect for PostgresDialect {
         )
     }
 
+    fn ensure_tags_statement(count: usize) -> String {
+        format!(
+            "INSERT INTO tags (name) VALUES {} ON CONFLICT DO NOTHING",
+            Self::values_rows(count, 1)
+        )
+    }
+
+    fn ensure_tag_implication_statement() -> String {
+        format!(
+            "INSERT INTO tag_implications (antecedent, consequent) VALUES ({}) ON CONFLICT DO NOTHING",
+            Self::placeholders(1..=2)
+        )
+    }
+
     fn ensure_metadata_statement() -> String {
         format!(
             r#"INSERT INTO image_metadatas
-            (image_hash, width, height, format, color_type, file_size, created_at, duration)
-            VALUES ({}, {}, {}, {}, {}, {}, {}, {}) ON CONFLICT DO NOTHING"#,
+            (image_hash, width, height, format, color_type, file_size, created_at, duration,
+            camera_make, camera_model, gps_lat, gps_lon, taken_at, exposure, orientation,
+            digest)
+            VALUES ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}) ON CONFLICT DO NOTHING"#,
             Self::placeholder(1),
             Self::placeholder(2),
             Self::placeholder(3),
@@ -34,10 +176,22 @@ impl Dialect for PostgresDialect {
             Self::placeholder(5),
             Self::placeholder(6),
             Self::placeholder(7),
-            Self::placeholder(8)
+            Self::placeholder(8),
+            Self::placeholder(9),
+            Self::placeholder(10),
+            Self::placeholder(11),
+            Self::placeholder(12),
+            Self::placeholder(13),
+            Self::placeholder(14),
+            Self::placeholder(15),
+            Self::placeholder(16)
         )
     }
 
+    fn ensure_metadata_returning_statement() -> Option<String> {
+        Some(format!("{} RETURNING *", Self::ensure_metadata_statement()))
+    }
+
     fn ensure_image_tag_statement() -> String {
         format!(
             "INSERT INTO image_tags (image_hash, tag_name) VALUES ({}, {}) ON CONFLICT DO NOTHING",
@@ -45,4 +199,11 @@ impl Dialect for PostgresDialect {
             Self::placeholder(2)
         )
     }
+
+    fn ensure_image_tags_statement(count: usize) -> String {
+        format!(
+            "INSERT INTO image_tags (image_hash, tag_name) VALUES {} ON CONFLICT DO NOTHING",
+            Self::values_rows(count, 2)
+        )
+    }
 }
@@ -4,7 +4,24 @@ use super::Dialect;
 pub struct SqliteDialect;
 
 impl Dialect for SqliteDialect {
+    fn name() -> &'static str {
+        "sqlite"
+    }
+
     fn placeholder(_idx: usize) -> String {
         "?".to_string()
     }
+
+    fn server_version_statement() -> String {
+        "SELECT sqlite_version()".to_string()
+    }
+
+    fn cursor_value(column: &str, idx: usize) -> String {
+        // Parameters are bound as text, which SQLite orders after every number, as the
+        // coalesced order keys have no column affinity to convert them.
+        match column {
+            "file_size" | "is_featured" => format!("CAST({} AS INTEGER)", Self::placeholder(idx)),
+            _ => Self::placeholder(idx),
+        }
+    }
 }
@@ -41,16 +41,51 @@
 //!
 
 pub mod app;
+pub mod capabilities;
 pub mod database;
 mod dialect;
 pub mod parser;
 pub mod query;
 pub mod storage;
 
+/// Common imports for users of this crate.
+///
+/// `use buru::prelude::*;` brings in everything needed to archive and look up media:
+///
+/// - the `app` operations, `ArchiveImageCommand`, `Media` and `AppError`,
+/// - `Database`, `DatabaseError` and the maintenance types,
+/// - `Storage`, `StorageError`, `PixelHash` and the chrono `DateTime` and `Utc` types,
+/// - `ImageQuery`, `TagQuery`, their expression and kind types, `OrderBy`, and the
+///   `image` module of query builder functions,
+/// - the `Capabilities` report.
+///
+/// ```
+/// use buru::prelude::*;
+///
+/// async fn recent_cats(db: &Database, storage: &Storage) -> Result<Vec<Media>, AppError> {
+///     let query = ImageQuery::filter(image::tag("cat").and(image::not(image::tag("dog"))))
+///         .with_order(OrderBy::CreatedAtDesc)
+///         .with_limit(10);
+///
+///     query_image(db, storage, query).await
+/// }
+///
+/// async fn cat_tags(db: &Database) -> Result<Vec<String>, AppError> {
+///     let query = TagQuery::new(TagQueryKind::Where(TagQueryExpr::Prefix("cat".to_string())));
+///
+///     query_tags(db, query).await
+/// }
+///
+/// let hash = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+/// assert_eq!("44a5b6f94f4f6445", hash.to_string());
+/// assert!(Utc::now() > DateTime::UNIX_EPOCH);
+/// let _ = ArchiveImageCommand::new(&[]).with_source("https://example.com");
+/// ```
 pub mod prelude {
-    use crate::{app, database, query, storage};
+    use crate::{app, capabilities, database, query, storage};
 
     pub use app::*;
+    pub use capabilities::*;
     pub use database::*;
     pub use query::*;
     pub use storage::*;
@@ -12,13 +12,26 @@
 //! - **OR Expression**: Multiple `AND` expressions separated by the `OR` keyword.
 //! - **AND Expression**: Multiple `NOT` expressions separated by the `AND` keyword.
 //! - **NOT Expression**: An optional negation, followed by a primary expression.
-//! - **Primary Expression**: Can be a date expression, a tag, or a nested query expression.
+//! - **Primary Expression**: Can be a date expression, a relative age metatag such as
+//!   `age:<7d`, a media group metatag such as `is:animated`, a rating metatag such as
+//!   `rating:explicit` or `rating:e`, a source count metatag such as `sources:>1`, a
+//!   metadata comparison such as `width:>=1920` or `filesize < 2MB`, an inclusive
+//!   metadata range such as `filesize:100kb..5mb`, an aspect ratio metatag such as
+//!   `ratio:16:9`, a tag, or a nested query expression.
+//!
+//! An age is a whole number followed by a unit: `d` (days), `w` (weeks), `mo` (30
+//! days) or `y` (365 days). `age:<7d` matches media created at or after seven days
+//! before the query was parsed, i.e. `date >=` that instant; `age:>7d` matches media
+//! created at or before it. Both bounds are inclusive.
 //!
 //! ## Components
 //!
 //! - `parse_query`: Function that accepts a string input and returns a parsed `ImageQueryExpr`
 //!   or an error, which can be further processed or translated to other formats like SQL.
 //!
+//! - `parse_tag_query`: The same boolean grammar over tag name patterns, returning a
+//!   `TagQueryExpr`. `cat` matches exactly, `ca*` by prefix, and `*at*` by substring.
+//!
 //! - Internal helper functions like `query_expr`, `or_expr`, `and_expr`, and `not_expr`
 //!   manage the parsing of different parts of the query string.
 //!
@@ -41,8 +54,11 @@
 //!
 //! This example demonstrates parsing a complex logical query string into an `ImageQueryExpr`.
 
-use crate::query::ImageQueryExpr;
-use chrono::DateTime;
+use crate::{
+    database::Rating,
+    query::{Comparison, ImageQueryExpr, MediaGroup, MetadataField, TagQueryExpr},
+};
+use chrono::{DateTime, Duration};
 use nom::{
     AsChar, IResult, Parser,
     branch::alt,
@@ -50,17 +66,107 @@ use nom::{
     character::complete::{char, multispace0},
     combinator::opt,
     multi::many0,
-    sequence::{delimited, preceded},
+    sequence::{delimited, preceded, separated_pair},
 };
 use std::str::FromStr;
 
+/// Boolean keywords accepted by `parse_query`.
+pub const KEYWORDS: &[&str] = &["AND", "OR", "XOR", "NOT"];
+
+/// Comparison operators accepted after the `date` field.
+pub const DATE_OPERATORS: &[&str] = &[">=", "<="];
+
+/// Comparison operators accepted after the `age:` metatag.
+pub const AGE_OPERATORS: &[&str] = &["<", ">"];
+
+/// Units accepted in an age, with their length in days.
+pub const AGE_UNITS: &[(&str, i64)] = &[("d", 1), ("w", 7), ("mo", 30), ("y", 365)];
+
+/// Metadata fields accepted as metatags, e.g. `width:>=1920`.
+pub const METADATA_FIELDS: &[(&str, MetadataField)] = &[
+    ("width", MetadataField::Width),
+    ("height", MetadataField::Height),
+    ("filesize", MetadataField::FileSize),
+];
+
+/// Comparison operators accepted after a metadata metatag, longest first.
+pub const METADATA_OPERATORS: &[&str] = &[">=", "<=", ">", "<", "="];
+
+/// The metatag comparing the number of sources, e.g. `sources:>1`. It accepts the
+/// `METADATA_OPERATORS`, and a bare count such as `sources:0` means `=`.
+pub const SOURCE_COUNT_METATAG: &str = "sources";
+
+/// The metatag matching an aspect ratio within 1%, e.g. `ratio:16:9`.
+pub const ASPECT_RATIO_METATAG: &str = "ratio";
+
+/// Suffixes accepted after a metadata value, with their multiplier.
+pub const SIZE_UNITS: &[(&str, u64)] = &[
+    ("K", 1 << 10),
+    ("KB", 1 << 10),
+    ("M", 1 << 20),
+    ("MB", 1 << 20),
+    ("G", 1 << 30),
+    ("GB", 1 << 30),
+];
+
+/// Returns every meta token accepted by `parse_query`, besides plain tags and keywords.
+///
+/// This is the registry the parser itself validates against, so it can be
+/// advertised to clients without drifting from what is actually accepted.
+pub fn meta_tokens() -> Vec<String> {
+    DATE_OPERATORS
+        .iter()
+        .map(|op| format!("date {op}"))
+        .chain(AGE_OPERATORS.iter().map(|op| format!("age:{op}")))
+        .chain(
+            MediaGroup::ALL
+                .iter()
+                .map(|group| format!("is:{}", group.name())),
+        )
+        .chain(["is:featured", "is:private"].map(String::from))
+        .chain(
+            Rating::ALL
+                .iter()
+                .map(|rating| format!("rating:{}", rating.name())),
+        )
+        .chain(METADATA_FIELDS.iter().flat_map(|(name, _)| {
+            METADATA_OPERATORS
+                .iter()
+                .map(move |op| format!("{name}:{op}"))
+        }))
+        .chain(
+            METADATA_OPERATORS
+                .iter()
+                .map(|op| format!("{SOURCE_COUNT_METATAG}:{op}")),
+        )
+        .chain([format!("{ASPECT_RATIO_METATAG}:")])
+        .collect()
+}
+
 // <query>    ::= <or_expr>
-// <or_expr>  ::= <and_expr> { "OR" <and_expr> }
+// <or_expr>  ::= <xor_expr> { "OR" <xor_expr> }
+// <xor_expr> ::= <and_expr> { "XOR" <and_expr> }
 // <and_expr> ::= <not_expr> { "AND" <not_expr> }
 // <not_expr> ::= [ "NOT" ] <primary>
 // <primary>  ::= <date_expr>
+//              | <age_expr>
+//              | <meta_expr>
+//              | <source_count>
+//              | <aspect_ratio>
+//              | <media_group>
+//              | <rating>
 //              | "(" <query> ")"
 //              | <tag>
+// <age_expr> ::= "age:" ( "<" | ">" ) <number> ( "d" | "w" | "mo" | "y" )
+// <meta_expr> ::= ( "width" | "height" | "filesize" ) [ ":" ]
+//                 ( ">=" | "<=" | ">" | "<" | "=" ) <size>
+//               | ( "width" | "height" | "filesize" ) ":" <size> ".." <size>
+// <size>     ::= <number> [ "K" | "M" | "G" | "KB" | "MB" | "GB" ]
+// <source_count> ::= "sources:" [ ">=" | "<=" | ">" | "<" | "=" ] <number>
+// <aspect_ratio> ::= "ratio:" <number> ":" <number>
+// <media_group> ::= "is:" ( "animated" | "photo" | "lossless" | "featured" | "private" )
+// <rating>   ::= "rating:" ( "general" | "sensitive" | "questionable" | "explicit"
+//                          | "safe" | "g" | "s" | "q" | "e" )
 pub fn parse_query(input: &str) -> Result<ImageQueryExpr, ParseErrorDetail> {
     let (rest, query) = query_expr(input).map_err(|e| match e {
         nom::Err::Error(e) | nom::Err::Failure(e) => e,
@@ -82,8 +188,8 @@ pub fn parse_query(input: &str) -> Result<ImageQueryExpr, ParseErrorDetail> {
 
 fn query_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
     fn or_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
-        let (input, init) = and_expr(input)?;
-        many0(preceded(ws(t("OR")), and_expr))
+        let (input, init) = xor_expr(input)?;
+        many0(preceded(ws(t("OR")), xor_expr))
             .parse(input)
             .map(|(input, rest)| {
                 let expr = rest.into_iter().fold(init, |acc, e| acc.or(e));
@@ -91,6 +197,16 @@ fn query_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
             })
     }
 
+    fn xor_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
+        let (input, init) = and_expr(input)?;
+        many0(preceded(ws(t("XOR")), and_expr))
+            .parse(input)
+            .map(|(input, rest)| {
+                let expr = rest.into_iter().fold(init, |acc, e| acc.xor(e));
+                (input, expr)
+            })
+    }
+
     fn and_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
         let (input, init) = not_expr(input)?;
         many0(preceded(ws(t("AND")), not_expr))
@@ -110,7 +226,18 @@ fn query_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
     }
 
     fn primary(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
-        alt((date_expr, paren_expr, tag)).parse(input)
+        alt((
+            date_expr,
+            age_expr,
+            meta_expr,
+            source_count_expr,
+            aspect_ratio_expr,
+            media_group_expr,
+            rating_expr,
+            paren_expr,
+            tag,
+        ))
+        .parse(input)
     }
 
     fn tag(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
@@ -126,7 +253,7 @@ fn query_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
 
         let (input, (_field, op, date_str)) = (
             ws(t("date")),
-            ws(alt((t(">="), t("<=")))),
+            ws(date_operator),
             ws(take_while1(is_datetime_char)),
         )
             .parse(input)?;
@@ -140,6 +267,197 @@ fn query_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
         }
     }
 
+    fn date_operator(input: &str) -> IResult<&str, &str, ParseErrorDetail> {
+        strip_operator(DATE_OPERATORS, input).ok_or_else(|| {
+            nom::Err::Error(ParseErrorDetail {
+                kind: ParseErrorKind::UnexpectedToken,
+                location: input.to_string(),
+            })
+        })
+    }
+
+    fn age_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
+        let (input, (op, age)) = ws(preceded(
+            t("age:"),
+            (age_operator, take_while1(|c: char| c.is_alphanumeric())),
+        ))
+        .parse(input)?;
+
+        let age = parse_age(age).map_err(nom::Err::Failure)?;
+
+        match op {
+            "<" => Ok((input, ImageQueryExpr::age_less_than(age))),
+            ">" => Ok((input, ImageQueryExpr::age_greater_than(age))),
+            _ => unreachable!(),
+        }
+    }
+
+    fn age_operator(input: &str) -> IResult<&str, &str, ParseErrorDetail> {
+        strip_operator(AGE_OPERATORS, input).ok_or_else(|| {
+            nom::Err::Failure(ParseErrorDetail {
+                kind: ParseErrorKind::InvalidMetatag,
+                location: input.to_string(),
+            })
+        })
+    }
+
+    fn meta_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
+        let (rest, (name, colon)) =
+            ws((take_while1(|c: char| c.is_alphabetic()), opt(char(':')))).parse(input)?;
+        let Some((_, field)) = METADATA_FIELDS.iter().find(|(n, _)| *n == name) else {
+            return Err(nom::Err::Error(ParseErrorDetail {
+                kind: ParseErrorKind::UnexpectedToken,
+                location: input.to_string(),
+            }));
+        };
+
+        // A range such as `filesize:100kb..5mb` includes both ends.
+        if colon.is_some() {
+            let value = || take_while1(|c: char| c.is_alphanumeric());
+            let (rest, range) = opt(ws(separated_pair(value(), t(".."), value()))).parse(rest)?;
+            if let Some((min, max)) = range {
+                let min = parse_size(min).map_err(nom::Err::Failure)?;
+                let max = parse_size(max).map_err(nom::Err::Failure)?;
+                return Ok((
+                    rest,
+                    ImageQueryExpr::metadata(*field, Comparison::Gte, min)
+                        .and(ImageQueryExpr::metadata(*field, Comparison::Lte, max)),
+                ));
+            }
+        }
+
+        // Without a colon the field is a plain tag unless an operator follows, as
+        // in `width >= 1920`.
+        let (rest, op) = match colon {
+            Some(_) => ws(meta_operator).parse(rest)?,
+            None => strip_operator(METADATA_OPERATORS, rest).ok_or_else(|| {
+                nom::Err::Error(ParseErrorDetail {
+                    kind: ParseErrorKind::UnexpectedToken,
+                    location: input.to_string(),
+                })
+            })?,
+        };
+        let (rest, value) = ws(take_while1(|c: char| c.is_alphanumeric())).parse(rest)?;
+        let value = parse_size(value).map_err(nom::Err::Failure)?;
+
+        Ok((
+            rest,
+            ImageQueryExpr::metadata(*field, comparison(op), value),
+        ))
+    }
+
+    fn source_count_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
+        let (rest, (op, count)) = ws(preceded(
+            (t(SOURCE_COUNT_METATAG), char(':')),
+            (
+                opt(|input| {
+                    strip_operator(METADATA_OPERATORS, input).ok_or_else(|| {
+                        nom::Err::Error(ParseErrorDetail {
+                            kind: ParseErrorKind::UnexpectedToken,
+                            location: input.to_string(),
+                        })
+                    })
+                }),
+                take_while1(|c: char| c.is_alphanumeric()),
+            ),
+        ))
+        .parse(input)?;
+        let count = count.parse().map_err(|_| {
+            nom::Err::Failure(ParseErrorDetail {
+                kind: ParseErrorKind::InvalidNumber,
+                location: count.to_string(),
+            })
+        })?;
+
+        Ok((
+            rest,
+            ImageQueryExpr::source_count(comparison(op.unwrap_or("=")), count),
+        ))
+    }
+
+    fn aspect_ratio_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
+        let (rest, ratio) = ws(preceded(
+            (t(ASPECT_RATIO_METATAG), char(':')),
+            take_while1(|c: char| c.is_alphanumeric() || c == ':'),
+        ))
+        .parse(input)?;
+
+        // Both sides are positive whole numbers, as in `16:9`.
+        let side = |side: &str| side.parse::<u32>().ok().filter(|n| *n > 0);
+        let Some((width, height)) = ratio
+            .split_once(':')
+            .and_then(|(width, height)| Some((side(width)?, side(height)?)))
+        else {
+            return Err(nom::Err::Failure(ParseErrorDetail {
+                kind: ParseErrorKind::InvalidNumber,
+                location: ratio.to_string(),
+            }));
+        };
+
+        Ok((rest, ImageQueryExpr::aspect_ratio(width, height)))
+    }
+
+    /// Maps one of the `METADATA_OPERATORS` to its comparison.
+    fn comparison(op: &str) -> Comparison {
+        match op {
+            ">=" => Comparison::Gte,
+            "<=" => Comparison::Lte,
+            ">" => Comparison::Gt,
+            "<" => Comparison::Lt,
+            "=" => Comparison::Eq,
+            _ => unreachable!(),
+        }
+    }
+
+    fn meta_operator(input: &str) -> IResult<&str, &str, ParseErrorDetail> {
+        strip_operator(METADATA_OPERATORS, input).ok_or_else(|| {
+            nom::Err::Failure(ParseErrorDetail {
+                kind: ParseErrorKind::InvalidMetatag,
+                location: input.to_string(),
+            })
+        })
+    }
+
+    fn media_group_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
+        let (input, name) = ws(preceded(
+            t("is:"),
+            take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+        ))
+        .parse(input)?;
+
+        match name {
+            "featured" => return Ok((input, ImageQueryExpr::Featured)),
+            "private" => return Ok((input, ImageQueryExpr::private())),
+            _ => (),
+        }
+
+        let group = MediaGroup::from_str(name).map_err(|_| {
+            nom::Err::Failure(ParseErrorDetail {
+                kind: ParseErrorKind::InvalidMetatag,
+                location: name.to_string(),
+            })
+        })?;
+
+        Ok((input, ImageQueryExpr::MediaGroup(group)))
+    }
+
+    fn rating_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
+        let (input, name) = ws(preceded(
+            t("rating:"),
+            take_while1(|c: char| c.is_alphanumeric()),
+        ))
+        .parse(input)?;
+
+        Rating::from_str(name).map_err(|_| {
+            nom::Err::Failure(ParseErrorDetail {
+                kind: ParseErrorKind::InvalidMetatag,
+                location: name.to_string(),
+            })
+        })?;
+
+        Ok((input, ImageQueryExpr::Rating(name.to_string())))
+    }
+
     fn paren_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
         delimited(ws(char('(')), query_expr, ws(char(')'))).parse(input)
     }
@@ -147,6 +465,155 @@ fn query_expr(input: &str) -> IResult<&str, ImageQueryExpr, ParseErrorDetail> {
     or_expr(input)
 }
 
+/// Parses a size such as `1920` or `1M` into a number.
+///
+/// See `SIZE_UNITS` for the accepted suffixes, which are binary multiples and
+/// case-insensitive.
+pub fn parse_size(input: &str) -> Result<u64, ParseErrorDetail> {
+    let invalid = || ParseErrorDetail {
+        kind: ParseErrorKind::InvalidNumber,
+        location: input.to_string(),
+    };
+
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    let (count, unit) = input.split_at(split);
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    if unit.is_empty() {
+        return Ok(count);
+    }
+    let (_, multiplier) = SIZE_UNITS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(unit))
+        .ok_or_else(invalid)?;
+
+    count.checked_mul(*multiplier).ok_or_else(invalid)
+}
+
+/// Parses an age such as `7d` or `1mo` into a duration.
+///
+/// See `AGE_UNITS` for the accepted units. Months and years have a fixed length
+/// of 30 and 365 days.
+pub fn parse_age(input: &str) -> Result<Duration, ParseErrorDetail> {
+    let invalid = || ParseErrorDetail {
+        kind: ParseErrorKind::InvalidDuration,
+        location: input.to_string(),
+    };
+
+    let split = input
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (count, unit) = input.split_at(split);
+    let count: i64 = count.parse().map_err(|_| invalid())?;
+    let (_, days) = AGE_UNITS
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .ok_or_else(invalid)?;
+
+    count
+        .checked_mul(*days)
+        .and_then(Duration::try_days)
+        .ok_or_else(invalid)
+}
+
+// <query>    ::= <or_expr>
+// <or_expr>  ::= <and_expr> { "OR" <and_expr> }
+// <and_expr> ::= <not_expr> { "AND" <not_expr> }
+// <not_expr> ::= [ "NOT" ] <primary>
+// <primary>  ::= "(" <query> ")"
+//              | <pattern>
+// <pattern>  ::= <name> | <name> "*" | "*" <name> "*"
+pub fn parse_tag_query(input: &str) -> Result<TagQueryExpr, ParseErrorDetail> {
+    let (rest, query) = tag_query_expr(input).map_err(|e| match e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => e,
+        nom::Err::Incomplete(_) => ParseErrorDetail {
+            kind: ParseErrorKind::UnexpectedToken,
+            location: "<incomplete>".to_string(),
+        },
+    })?;
+
+    if !rest.trim().is_empty() {
+        return Err(ParseErrorDetail {
+            kind: ParseErrorKind::UnexpectedToken,
+            location: rest.to_string(),
+        });
+    }
+
+    Ok(query)
+}
+
+fn tag_query_expr(input: &str) -> IResult<&str, TagQueryExpr, ParseErrorDetail> {
+    fn or_expr(input: &str) -> IResult<&str, TagQueryExpr, ParseErrorDetail> {
+        let (input, init) = and_expr(input)?;
+        many0(preceded(ws(t("OR")), and_expr))
+            .parse(input)
+            .map(|(input, rest)| {
+                let expr = rest.into_iter().fold(init, |acc, e| acc.or(e));
+                (input, expr)
+            })
+    }
+
+    fn and_expr(input: &str) -> IResult<&str, TagQueryExpr, ParseErrorDetail> {
+        let (input, init) = not_expr(input)?;
+        many0(preceded(ws(t("AND")), not_expr))
+            .parse(input)
+            .map(|(input, rest)| {
+                let expr = rest.into_iter().fold(init, |acc, e| acc.and(e));
+                (input, expr)
+            })
+    }
+
+    fn not_expr(input: &str) -> IResult<&str, TagQueryExpr, ParseErrorDetail> {
+        let (input, not_opt) = opt(preceded(ws(t("NOT")), primary)).parse(input)?;
+        match not_opt {
+            Some(expr) => Ok((input, TagQueryExpr::not(expr))),
+            None => primary(input),
+        }
+    }
+
+    fn primary(input: &str) -> IResult<&str, TagQueryExpr, ParseErrorDetail> {
+        alt((paren_expr, pattern)).parse(input)
+    }
+
+    fn pattern(input: &str) -> IResult<&str, TagQueryExpr, ParseErrorDetail> {
+        let (input, pattern) = ws(take_while1(|c: char| {
+            c.is_alphanumeric() || c == '_' || c == '*'
+        }))
+        .parse(input)?;
+
+        let invalid = || {
+            nom::Err::Failure(ParseErrorDetail {
+                kind: ParseErrorKind::ExpectedTag,
+                location: pattern.to_string(),
+            })
+        };
+
+        let expr = match (pattern.strip_prefix('*'), pattern.strip_suffix('*')) {
+            (Some(rest), Some(_)) => rest
+                .strip_suffix('*')
+                .filter(|name| !name.is_empty() && !name.contains('*'))
+                .map(|name| TagQueryExpr::Contains(name.to_string())),
+            (None, Some(name)) if !name.is_empty() && !name.contains('*') => {
+                Some(TagQueryExpr::Prefix(name.to_string()))
+            }
+            (None, None) if !pattern.contains('*') => {
+                Some(TagQueryExpr::Exact(pattern.to_string()))
+            }
+            _ => None,
+        }
+        .ok_or_else(invalid)?;
+
+        Ok((input, expr))
+    }
+
+    fn paren_expr(input: &str) -> IResult<&str, TagQueryExpr, ParseErrorDetail> {
+        delimited(ws(char('(')), tag_query_expr, ws(char(')'))).parse(input)
+    }
+
+    or_expr(input)
+}
+
 fn ws<'a, F: 'a>(inner: F) -> impl Parser<&'a str, Output = F::Output, Error = F::Error>
 where
     F: Parser<&'a str>,
@@ -154,6 +621,16 @@ where
     delimited(multispace0, inner, multispace0)
 }
 
+/// Strips the first of `operators` that `input` starts with.
+fn strip_operator<'a>(
+    operators: &[&'static str],
+    input: &'a str,
+) -> Option<(&'a str, &'static str)> {
+    operators
+        .iter()
+        .find_map(|op| input.strip_prefix(op).map(|rest| (rest, *op)))
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ParseErrorKind {
     UnexpectedToken,
@@ -161,6 +638,9 @@ pub enum ParseErrorKind {
     ExpectedDate,
     ExpectedExpr,
     InvalidDateFormat,
+    InvalidMetatag,
+    InvalidDuration,
+    InvalidNumber,
 }
 
 #[derive(Debug, PartialEq)]
@@ -184,8 +664,14 @@ impl nom::error::ParseError<&str> for ParseErrorDetail {
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::parse_query;
-    use crate::query::image;
+    use crate::parser::{
+        ASPECT_RATIO_METATAG, KEYWORDS, METADATA_FIELDS, ParseErrorDetail, ParseErrorKind,
+        SOURCE_COUNT_METATAG, meta_tokens, parse_age, parse_query, parse_tag_query,
+    };
+    use crate::query::{
+        Comparison, ImageQueryExpr, MediaGroup, MetadataField, TagQueryExpr, image,
+    };
+    use chrono::{Duration, Utc};
 
     #[test]
     fn test_parse_query_expr() {
@@ -198,4 +684,287 @@ mod tests {
             parse_query(input).unwrap()
         );
     }
+
+    #[test]
+    fn test_parse_xor() {
+        assert_eq!(
+            image::tag("cat")
+                .xor(image::tag("dog").and(image::tag("cute")))
+                .or(image::tag("bird")),
+            parse_query("cat XOR dog AND cute OR bird").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        assert_eq!(
+            ImageQueryExpr::width_gte(1920)
+                .and(image::tag("cat"))
+                .and(ImageQueryExpr::filesize_lt(1 << 20))
+                .or(image::metadata(
+                    MetadataField::Height,
+                    Comparison::Eq,
+                    5 << 30
+                )),
+            parse_query("width:>=1920 AND cat AND filesize:<1M OR height:=5g").unwrap()
+        );
+        assert_eq!(image::tag("width"), parse_query("width").unwrap());
+        assert_eq!(
+            image::tag("width").and(image::tag("cat")),
+            parse_query("width AND cat").unwrap()
+        );
+        assert_eq!(
+            image::width_at_least(1920)
+                .and(image::tag("cat"))
+                .and(image::filesize_less_than(2 << 20)),
+            parse_query("width >= 1920 AND cat AND filesize<2MB").unwrap()
+        );
+        assert_eq!(
+            image::height_less_than(1080),
+            parse_query("height:< 1080").unwrap()
+        );
+        assert_eq!(
+            image::metadata(MetadataField::FileSize, Comparison::Gte, 100 << 10)
+                .and(image::metadata(
+                    MetadataField::FileSize,
+                    Comparison::Lte,
+                    5 << 20
+                ))
+                .and(image::tag("cat")),
+            parse_query("filesize:100kb..5mb AND cat").unwrap()
+        );
+        assert_eq!(
+            image::metadata(MetadataField::FileSize, Comparison::Gt, 1 << 20)
+                .or(image::filesize_less_than(500 << 10)),
+            parse_query("filesize:>1mb OR filesize:<500kb").unwrap()
+        );
+        assert_eq!(
+            ParseErrorKind::InvalidNumber,
+            parse_query("filesize:1mb..5tb").unwrap_err().kind
+        );
+        assert_eq!(
+            ParseErrorKind::InvalidMetatag,
+            parse_query("width:!1920").unwrap_err().kind
+        );
+        assert_eq!(
+            ParseErrorKind::InvalidNumber,
+            parse_query("filesize:>1T").unwrap_err().kind
+        );
+        assert_eq!(
+            ParseErrorKind::InvalidNumber,
+            parse_query("filesize:>99999999999999999999")
+                .unwrap_err()
+                .kind
+        );
+    }
+
+    #[test]
+    fn test_parse_source_count() {
+        assert_eq!(
+            image::tag("cat").and(image::source_count(Comparison::Gt, 1)),
+            parse_query("cat AND sources:>1").unwrap()
+        );
+        assert_eq!(
+            image::not(image::source_count(Comparison::Eq, 0)),
+            parse_query("NOT sources:0").unwrap()
+        );
+        assert_eq!(image::tag("sources"), parse_query("sources").unwrap());
+        assert_eq!(
+            ParseErrorKind::InvalidNumber,
+            parse_query("sources:>many").unwrap_err().kind
+        );
+    }
+
+    #[test]
+    fn test_parse_aspect_ratio() {
+        assert_eq!(
+            image::tag("cat").and(image::aspect_ratio(16, 9)),
+            parse_query("cat AND ratio:16:9").unwrap()
+        );
+        assert_eq!(
+            image::aspect_ratio(1, 1).and(image::width_at_least(1920)),
+            parse_query("ratio:1:1 AND width:>=1920").unwrap()
+        );
+        assert_eq!(image::tag("ratio"), parse_query("ratio").unwrap());
+        for input in ["ratio:16", "ratio:16:0", "ratio:wide", "ratio:16:9:1"] {
+            assert_eq!(
+                ParseErrorKind::InvalidNumber,
+                parse_query(input).unwrap_err().kind
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_media_group() {
+        assert_eq!(
+            image::tag("cat").and(image::not(image::media_group(MediaGroup::Animated))),
+            parse_query("cat AND NOT is:animated").unwrap()
+        );
+        assert_eq!(
+            image::media_group(MediaGroup::Photo).or(image::media_group(MediaGroup::Lossless)),
+            parse_query("is:photo OR is:lossless").unwrap()
+        );
+        assert_eq!(
+            image::featured().and(image::tag("cat")),
+            parse_query("is:featured AND cat").unwrap()
+        );
+        assert_eq!(
+            image::private().or(image::tag("draft")),
+            parse_query("is:private OR draft").unwrap()
+        );
+        assert_eq!(
+            Err(ParseErrorDetail {
+                kind: ParseErrorKind::InvalidMetatag,
+                location: "vector".to_string(),
+            }),
+            parse_query("is:vector")
+        );
+    }
+
+    #[test]
+    fn test_parse_rating() {
+        assert_eq!(
+            image::tag("cat").and(image::rating("explicit")),
+            parse_query("cat AND rating:explicit").unwrap()
+        );
+        assert_eq!(
+            image::rating("g").or(image::not(image::rating("questionable")).and(image::tag("dog"))),
+            parse_query("rating:g OR NOT rating:questionable AND dog").unwrap()
+        );
+        assert_eq!(image::rating("safe"), parse_query("rating:safe").unwrap());
+        assert_eq!(
+            Err(ParseErrorDetail {
+                kind: ParseErrorKind::InvalidMetatag,
+                location: "nsfw".to_string(),
+            }),
+            parse_query("rating:nsfw")
+        );
+    }
+
+    #[test]
+    fn test_parse_tag_query() {
+        assert_eq!(
+            TagQueryExpr::Prefix("a".to_string())
+                .and(TagQueryExpr::not(TagQueryExpr::Contains("_".to_string()))),
+            parse_tag_query("a* AND NOT *_*").unwrap()
+        );
+        assert_eq!(
+            TagQueryExpr::Exact("cat".to_string())
+                .or(TagQueryExpr::not(TagQueryExpr::Exact("dog".to_string()))),
+            parse_tag_query("cat OR (NOT dog)").unwrap()
+        );
+        for invalid in ["*", "**", "c*t", "*cat"] {
+            assert_eq!(
+                Err(ParseErrorDetail {
+                    kind: ParseErrorKind::ExpectedTag,
+                    location: invalid.to_string(),
+                }),
+                parse_tag_query(invalid)
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_age() {
+        let before = Utc::now() - Duration::days(7);
+        let expr = parse_query("cat AND age:<7d").unwrap();
+        let after = Utc::now() - Duration::days(7);
+
+        let ImageQueryExpr::And(_, since) = expr else {
+            panic!("Expected an AND expression, but got {:?}", expr);
+        };
+        let ImageQueryExpr::DateSince(bound) = *since else {
+            panic!("Expected a DateSince bound, but got {:?}", since);
+        };
+        assert!(before <= bound && bound <= after);
+
+        assert!(matches!(
+            parse_query("age:>1mo"),
+            Ok(ImageQueryExpr::DateUntil(_))
+        ));
+
+        assert_eq!(Ok(Duration::days(14)), parse_age("2w"));
+        assert_eq!(Ok(Duration::days(30)), parse_age("1mo"));
+        assert_eq!(Ok(Duration::days(365)), parse_age("1y"));
+        for invalid in ["7", "d", "7h", "-7d", "7dd", "99999999999999999y"] {
+            assert_eq!(
+                Err(ParseErrorDetail {
+                    kind: ParseErrorKind::InvalidDuration,
+                    location: invalid.to_string(),
+                }),
+                parse_age(invalid)
+            );
+        }
+        assert_eq!(
+            ParseErrorKind::InvalidMetatag,
+            parse_query("age:=7d").unwrap_err().kind
+        );
+    }
+
+    /// Returns the meta token an expression was parsed from, if any.
+    fn meta_token_of(expr: &ImageQueryExpr) -> Option<String> {
+        match expr {
+            ImageQueryExpr::DateSince(_) => Some("date >=".to_string()),
+            ImageQueryExpr::DateUntil(_) => Some("date <=".to_string()),
+            ImageQueryExpr::MediaGroup(group) => Some(format!("is:{}", group.name())),
+            ImageQueryExpr::Featured => Some("is:featured".to_string()),
+            ImageQueryExpr::Not(expr) if **expr == ImageQueryExpr::Public => {
+                Some("is:private".to_string())
+            }
+            ImageQueryExpr::Rating(rating) => Some(format!("rating:{rating}")),
+            ImageQueryExpr::Metadata(field, comparison, _) => {
+                let (name, _) = METADATA_FIELDS.iter().find(|(_, f)| f == field)?;
+                Some(format!("{name}:{}", comparison.operator()))
+            }
+            ImageQueryExpr::SourceCount(comparison, _) => {
+                Some(format!("{SOURCE_COUNT_METATAG}:{}", comparison.operator()))
+            }
+            ImageQueryExpr::AspectRatio(..) => Some(format!("{ASPECT_RATIO_METATAG}:")),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_meta_tokens_match_parser() {
+        let date = "2024-12-01T00:00:00Z";
+
+        for token in meta_tokens() {
+            let (input, expected) = match token.as_str() {
+                "age:<" => (format!("{token}7d"), "date >=".to_string()),
+                "age:>" => (format!("{token}7d"), "date <=".to_string()),
+                "ratio:" => (format!("{token}16:9"), token.clone()),
+                t if t.starts_with("date") => (format!("{token} {date}"), token.clone()),
+                t if t.ends_with(['<', '>', '=']) => (format!("{token}100"), token.clone()),
+                _ => (token.clone(), token.clone()),
+            };
+            let expr = parse_query(&input).unwrap();
+            assert_eq!(Some(expected), meta_token_of(&expr));
+        }
+
+        let candidates = [
+            "date >=", "date <=", "date >", "date <", "date =", "date !=",
+        ]
+        .into_iter()
+        .map(|t| format!("{t} {date}"))
+        .chain(
+            [
+                "animated", "photo", "lossless", "video", "vector", "static", "gif",
+            ]
+            .into_iter()
+            .map(|name| format!("is:{name}")),
+        );
+        for input in candidates {
+            if let Some(token) = parse_query(&input).ok().as_ref().and_then(meta_token_of) {
+                assert!(meta_tokens().contains(&token), "{token} is not advertised");
+            }
+        }
+
+        for keyword in KEYWORDS {
+            let input = match *keyword {
+                "NOT" => "NOT dog".to_string(),
+                infix => format!("cat {infix} dog"),
+            };
+            assert!(parse_query(&input).is_ok(), "{keyword} is not accepted");
+        }
+    }
 }
@@ -49,5 +49,8 @@
 pub mod image;
 mod tag;
 
-pub use image::{ImageQuery, ImageQueryExpr, ImageQueryKind, OrderBy};
+pub use image::{
+    Comparison, Cursor, CursorDirection, ImageQuery, ImageQueryExpr, ImageQueryKind, MediaGroup,
+    MetadataField, OrderBy,
+};
 pub use tag::{TagQuery, TagQueryExpr, TagQueryKind};
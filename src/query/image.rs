@@ -1,5 +1,10 @@
-use crate::dialect::{CurrentDialect, Dialect};
-use chrono::{DateTime, Utc};
+use crate::{
+    database::Rating,
+    dialect::{CurrentDialect, Dialect},
+    storage::PixelHash,
+};
+use chrono::{DateTime, Duration, Utc};
+use std::{fmt, str::FromStr};
 
 /// Represents a logical tag-based query expression.
 #[derive(Debug, Clone, PartialEq)]
@@ -21,6 +26,38 @@ pub enum ImageQueryExpr {
 
     /// A condition to filter results since a specific date.
     DateSince(DateTime<Utc>),
+
+    /// A condition to filter results belonging to a group of media formats.
+    MediaGroup(MediaGroup),
+
+    /// A condition to filter featured results.
+    Featured,
+
+    /// A condition to filter public results.
+    ///
+    /// Unless a query includes private results, it is added to every query.
+    Public,
+
+    /// A condition to filter soft-deleted results.
+    ///
+    /// Unless a query includes deleted results, its negation is added to every query.
+    Deleted,
+
+    /// A condition to filter results with a rating, given by its code or name such
+    /// as `e` or `explicit`. Unknown ratings match nothing.
+    Rating(String),
+
+    /// A condition comparing a numeric metadata field with a value.
+    Metadata(MetadataField, Comparison, u64),
+
+    /// A condition comparing the number of sources of a result with a value. Results
+    /// without a source have zero sources.
+    SourceCount(Comparison, u32),
+
+    /// A condition to filter results whose width to height ratio is within 1% of the
+    /// ratio of the given width and height, e.g. `16` and `9`. Results without a
+    /// height, or a ratio with a zero, match nothing.
+    AspectRatio(u32, u32),
 }
 
 impl ImageQueryExpr {
@@ -57,6 +94,21 @@ impl ImageQueryExpr {
         ImageQueryExpr::Or(Box::new(self), Box::new(other.into()))
     }
 
+    /// Combines two expressions with a logical XOR.
+    ///
+    /// There is no XOR in SQL, so this expands to `(a OR b) AND NOT (a AND b)`.
+    ///
+    /// # Arguments
+    /// - `other` - The another expression to be combined with.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression matching exactly one of the expressions.
+    pub fn xor(self, other: impl Into<ImageQueryExpr>) -> Self {
+        let other = other.into();
+        let both = self.clone().and(other.clone());
+        self.or(other).and(ImageQueryExpr::not(both))
+    }
+
     /// Negates a query expression.
     ///
     /// # Arguments
@@ -98,6 +150,162 @@ impl ImageQueryExpr {
         )
     }
 
+    /// Creates an expression to filter results created less than `age` ago.
+    ///
+    /// This is `DateSince(now - age)` with `now` captured when the expression is
+    /// built, so the bound does not move while the query is reused. The bound is
+    /// inclusive: an entry exactly `age` old matches.
+    ///
+    /// # Arguments
+    /// - `age` - The maximum age of the results.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the date condition.
+    pub fn age_less_than(age: Duration) -> Self {
+        ImageQueryExpr::DateSince(age_bound(age))
+    }
+
+    /// Creates an expression to filter results created more than `age` ago.
+    ///
+    /// This is `DateUntil(now - age)` with `now` captured when the expression is
+    /// built. The bound is inclusive: an entry exactly `age` old matches.
+    ///
+    /// # Arguments
+    /// - `age` - The minimum age of the results.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the date condition.
+    pub fn age_greater_than(age: Duration) -> Self {
+        ImageQueryExpr::DateUntil(age_bound(age))
+    }
+
+    /// Creates an expression to filter results belonging to a media group.
+    ///
+    /// # Arguments
+    /// - `group` - The media group the results should belong to.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the media group condition.
+    pub fn media_group(group: MediaGroup) -> Self {
+        ImageQueryExpr::MediaGroup(group)
+    }
+
+    /// Creates an expression to filter featured results.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the featured condition.
+    pub fn featured() -> Self {
+        ImageQueryExpr::Featured
+    }
+
+    /// Creates an expression to filter public results.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the public condition.
+    pub fn public() -> Self {
+        ImageQueryExpr::Public
+    }
+
+    /// Creates an expression to filter private results.
+    ///
+    /// Matches nothing unless the query includes private results, see
+    /// `ImageQuery::with_private`.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression negating the public condition.
+    pub fn private() -> Self {
+        ImageQueryExpr::not(ImageQueryExpr::Public)
+    }
+
+    /// Creates an expression to filter soft-deleted results.
+    ///
+    /// Matches nothing unless the query includes deleted results, see
+    /// `ImageQuery::including_deleted`.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the deleted condition.
+    pub fn deleted() -> Self {
+        ImageQueryExpr::Deleted
+    }
+
+    /// Creates an expression to filter results with a rating.
+    ///
+    /// # Arguments
+    /// - `rating` - The code or name of the rating, e.g. `e` or `explicit`.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the rating condition.
+    pub fn rating<T: Into<String>>(rating: T) -> Self {
+        ImageQueryExpr::Rating(rating.into())
+    }
+
+    /// Creates an expression comparing a numeric metadata field with a value.
+    ///
+    /// # Arguments
+    /// - `field` - The metadata field to compare.
+    /// - `comparison` - How the field is compared with the value.
+    /// - `value` - The value to compare with.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the metadata condition.
+    pub fn metadata(field: MetadataField, comparison: Comparison, value: u64) -> Self {
+        ImageQueryExpr::Metadata(field, comparison, value)
+    }
+
+    /// Creates an expression comparing the number of sources with a value.
+    ///
+    /// # Arguments
+    /// - `comparison` - How the number of sources is compared with the value.
+    /// - `count` - The number of sources to compare with.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the source count condition.
+    pub fn source_count(comparison: Comparison, count: u32) -> Self {
+        ImageQueryExpr::SourceCount(comparison, count)
+    }
+
+    /// Creates an expression to filter results with an aspect ratio of `width:height`.
+    ///
+    /// # Arguments
+    /// - `width` - The width of the ratio, e.g. `16`.
+    /// - `height` - The height of the ratio, e.g. `9`.
+    ///
+    /// # Returns
+    /// - `ImageQueryExpr` - A new expression with the aspect ratio condition.
+    pub fn aspect_ratio(width: u32, height: u32) -> Self {
+        ImageQueryExpr::AspectRatio(width, height)
+    }
+
+    /// Creates an expression to filter results at least `width` pixels wide.
+    pub fn width_gte(width: u64) -> Self {
+        ImageQueryExpr::metadata(MetadataField::Width, Comparison::Gte, width)
+    }
+
+    /// Creates an expression to filter results less than `width` pixels wide.
+    pub fn width_lt(width: u64) -> Self {
+        ImageQueryExpr::metadata(MetadataField::Width, Comparison::Lt, width)
+    }
+
+    /// Creates an expression to filter results at least `height` pixels high.
+    pub fn height_gte(height: u64) -> Self {
+        ImageQueryExpr::metadata(MetadataField::Height, Comparison::Gte, height)
+    }
+
+    /// Creates an expression to filter results less than `height` pixels high.
+    pub fn height_lt(height: u64) -> Self {
+        ImageQueryExpr::metadata(MetadataField::Height, Comparison::Lt, height)
+    }
+
+    /// Creates an expression to filter results of at least `bytes` bytes.
+    pub fn filesize_gte(bytes: u64) -> Self {
+        ImageQueryExpr::metadata(MetadataField::FileSize, Comparison::Gte, bytes)
+    }
+
+    /// Creates an expression to filter results of less than `bytes` bytes.
+    pub fn filesize_lt(bytes: u64) -> Self {
+        ImageQueryExpr::metadata(MetadataField::FileSize, Comparison::Lt, bytes)
+    }
+
     /// Converts the query expression into an SQL WHERE clause and its bound parameters.
     ///
     /// # Returns
@@ -131,6 +339,46 @@ impl ImageQueryExpr {
                 params.push(date_time.to_rfc3339());
                 CurrentDialect::exists_date_since_query(params.len())
             }
+            ImageQueryExpr::Featured => CurrentDialect::is_featured_query(),
+            ImageQueryExpr::Public => CurrentDialect::is_public_query(),
+            ImageQueryExpr::Deleted => CurrentDialect::is_deleted_query(),
+            ImageQueryExpr::Rating(rating) => {
+                // Ratings are stored by code, and unknown ones are bound as given.
+                params.push(
+                    rating
+                        .parse::<Rating>()
+                        .map(|r| r.as_str().to_string())
+                        .unwrap_or_else(|_| rating.clone()),
+                );
+                CurrentDialect::exists_rating_query(params.len())
+            }
+            ImageQueryExpr::Metadata(field, comparison, value) => {
+                params.push(value.to_string());
+                CurrentDialect::exists_metadata_comparison_query(
+                    field.column(),
+                    comparison.operator(),
+                    params.len(),
+                )
+            }
+            ImageQueryExpr::SourceCount(comparison, count) => {
+                params.push(count.to_string());
+                CurrentDialect::source_count_comparison_query(comparison.operator(), params.len())
+            }
+            ImageQueryExpr::AspectRatio(width, height) => {
+                params.push((f64::from(*width) / f64::from(*height)).to_string());
+                CurrentDialect::exists_aspect_ratio_query(params.len())
+            }
+            ImageQueryExpr::MediaGroup(group) => {
+                let start = params.len() + 1;
+                params.extend(group.formats().iter().map(|f| f.to_string()));
+                let formats = CurrentDialect::format_in_query(start..=params.len());
+
+                if group.includes_videos() {
+                    format!("({} OR {})", formats, CurrentDialect::has_duration_query())
+                } else {
+                    formats
+                }
+            }
         }
     }
 }
@@ -168,6 +416,35 @@ pub fn date_since(date: impl AsRef<str>) -> ImageQueryExpr {
     ImageQueryExpr::date_since(date)
 }
 
+/// Creates an expression to filter results created less than `age` ago.
+///
+/// # Arguments
+/// - `age` - The maximum age of the results.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing `created_at >= now - age`.
+pub fn age_less_than(age: Duration) -> ImageQueryExpr {
+    ImageQueryExpr::age_less_than(age)
+}
+
+/// Creates an expression to filter results created more than `age` ago.
+///
+/// # Arguments
+/// - `age` - The minimum age of the results.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing `created_at <= now - age`.
+pub fn age_greater_than(age: Duration) -> ImageQueryExpr {
+    ImageQueryExpr::age_greater_than(age)
+}
+
+/// Returns the creation date of an entry that is `age` old now.
+fn age_bound(age: Duration) -> DateTime<Utc> {
+    Utc::now()
+        .checked_sub_signed(age)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
 /// Negates a given query expression.
 ///
 /// This function takes a query expression, negates it, and returns a new
@@ -185,6 +462,245 @@ pub fn not(expr: impl Into<ImageQueryExpr>) -> ImageQueryExpr {
     ImageQueryExpr::not(expr.into())
 }
 
+/// Creates an expression to filter results belonging to a media group.
+///
+/// # Arguments
+/// - `group` - The media group the results should belong to.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the media group condition.
+pub fn media_group(group: MediaGroup) -> ImageQueryExpr {
+    ImageQueryExpr::media_group(group)
+}
+
+/// Creates an expression to filter featured results.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the featured condition.
+pub fn featured() -> ImageQueryExpr {
+    ImageQueryExpr::featured()
+}
+
+/// Creates an expression to filter public results.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the public condition.
+pub fn public() -> ImageQueryExpr {
+    ImageQueryExpr::public()
+}
+
+/// Creates an expression to filter private results.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the private condition.
+pub fn private() -> ImageQueryExpr {
+    ImageQueryExpr::private()
+}
+
+/// Creates an expression to filter soft-deleted results.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the deleted condition.
+pub fn deleted() -> ImageQueryExpr {
+    ImageQueryExpr::deleted()
+}
+
+/// Creates an expression to filter results with a rating.
+///
+/// # Arguments
+/// - `rating` - The code or name of the rating, e.g. `e` or `explicit`.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the rating condition.
+pub fn rating(rating: impl Into<String>) -> ImageQueryExpr {
+    ImageQueryExpr::rating(rating)
+}
+
+/// Creates an expression comparing a numeric metadata field with a value.
+///
+/// # Arguments
+/// - `field` - The metadata field to compare.
+/// - `comparison` - How the field is compared with the value.
+/// - `value` - The value to compare with.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the metadata condition.
+pub fn metadata(field: MetadataField, comparison: Comparison, value: u64) -> ImageQueryExpr {
+    ImageQueryExpr::metadata(field, comparison, value)
+}
+
+/// Creates an expression comparing the number of sources with a value.
+///
+/// # Arguments
+/// - `comparison` - How the number of sources is compared with the value.
+/// - `count` - The number of sources to compare with.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the source count condition.
+pub fn source_count(comparison: Comparison, count: u32) -> ImageQueryExpr {
+    ImageQueryExpr::source_count(comparison, count)
+}
+
+/// Creates an expression to filter results with an aspect ratio of `width:height`.
+///
+/// # Arguments
+/// - `width` - The width of the ratio, e.g. `16`.
+/// - `height` - The height of the ratio, e.g. `9`.
+///
+/// # Returns
+/// - `ImageQueryExpr` - A new expression representing the aspect ratio condition.
+pub fn aspect_ratio(width: u32, height: u32) -> ImageQueryExpr {
+    ImageQueryExpr::aspect_ratio(width, height)
+}
+
+/// Creates an expression to filter results at least `width` pixels wide.
+pub fn width_at_least(width: u64) -> ImageQueryExpr {
+    ImageQueryExpr::width_gte(width)
+}
+
+/// Creates an expression to filter results less than `width` pixels wide.
+pub fn width_less_than(width: u64) -> ImageQueryExpr {
+    ImageQueryExpr::width_lt(width)
+}
+
+/// Creates an expression to filter results at least `height` pixels high.
+pub fn height_at_least(height: u64) -> ImageQueryExpr {
+    ImageQueryExpr::height_gte(height)
+}
+
+/// Creates an expression to filter results less than `height` pixels high.
+pub fn height_less_than(height: u64) -> ImageQueryExpr {
+    ImageQueryExpr::height_lt(height)
+}
+
+/// Creates an expression to filter results of at least `bytes` bytes.
+pub fn filesize_at_least(bytes: u64) -> ImageQueryExpr {
+    ImageQueryExpr::filesize_gte(bytes)
+}
+
+/// Creates an expression to filter results of less than `bytes` bytes.
+pub fn filesize_less_than(bytes: u64) -> ImageQueryExpr {
+    ImageQueryExpr::filesize_lt(bytes)
+}
+
+/// A numeric metadata field that results can be compared by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataField {
+    /// The width in pixels.
+    Width,
+
+    /// The height in pixels.
+    Height,
+
+    /// The file size in bytes.
+    FileSize,
+}
+
+impl MetadataField {
+    /// Returns the column of `image_metadatas` holding this field.
+    pub fn column(&self) -> &'static str {
+        match self {
+            MetadataField::Width => "width",
+            MetadataField::Height => "height",
+            MetadataField::FileSize => "file_size",
+        }
+    }
+}
+
+/// A comparison between a metadata field or a count and a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    /// The field is less than the value.
+    Lt,
+
+    /// The field is less than or equal to the value.
+    Lte,
+
+    /// The field is equal to the value.
+    Eq,
+
+    /// The field is greater than or equal to the value.
+    Gte,
+
+    /// The field is greater than the value.
+    Gt,
+}
+
+impl Comparison {
+    /// Returns the SQL operator of this comparison.
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Comparison::Lt => "<",
+            Comparison::Lte => "<=",
+            Comparison::Eq => "=",
+            Comparison::Gte => ">=",
+            Comparison::Gt => ">",
+        }
+    }
+}
+
+/// A user-facing group of media formats, such as "animated" or "photo".
+///
+/// Membership is decided from the stored file extension, plus the presence of a
+/// duration for videos. Variants that share an extension with a static format
+/// (APNG, animated or lossless WebP) cannot be told apart from stored metadata
+/// and are therefore not matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaGroup {
+    /// GIFs and every video.
+    Animated,
+
+    /// JPEG photographs.
+    Photo,
+
+    /// Losslessly compressed still images (PNG, BMP, TIFF).
+    Lossless,
+}
+
+impl MediaGroup {
+    /// Every media group, in the order they are advertised.
+    pub const ALL: [MediaGroup; 3] = [
+        MediaGroup::Animated,
+        MediaGroup::Photo,
+        MediaGroup::Lossless,
+    ];
+
+    /// Returns the name used by the `is:` metatag.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MediaGroup::Animated => "animated",
+            MediaGroup::Photo => "photo",
+            MediaGroup::Lossless => "lossless",
+        }
+    }
+
+    /// Returns the file extensions that belong to this group.
+    pub fn formats(&self) -> &'static [&'static str] {
+        match self {
+            MediaGroup::Animated => &["gif"],
+            MediaGroup::Photo => &["jpg", "jpeg"],
+            MediaGroup::Lossless => &["png", "bmp", "tif", "tiff"],
+        }
+    }
+
+    /// Returns whether every video belongs to this group regardless of its format.
+    pub fn includes_videos(&self) -> bool {
+        matches!(self, MediaGroup::Animated)
+    }
+}
+
+impl FromStr for MediaGroup {
+    type Err = String;
+
+    /// Parses the name used by the `is:` metatag, e.g. `animated`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MediaGroup::ALL
+            .into_iter()
+            .find(|group| group.name() == s)
+            .ok_or_else(|| format!("unknown media group: {s}"))
+    }
+}
+
 /// Represents the kind of the image query, which can either be a query for all images or a filtered query.
 #[derive(Debug, Clone, PartialEq)]
 pub enum ImageQueryKind {
@@ -226,26 +742,124 @@ pub enum OrderBy {
     /// Orders the results by file size in descending order.
     FileSizeDesc,
 
+    /// Orders featured results first, then by creation date in descending order.
+    FeaturedFirst,
+
     /// Orders the results randomly.
     Random,
+
+    /// Orders the results by hash in ascending order, the order of keyset pagination.
+    HashAsc,
+
+    /// Orders the results by hash in descending order.
+    HashDesc,
 }
 
 impl OrderBy {
+    /// Returns the columns the results are ordered by, and whether in descending
+    /// order, or `None` for `Random`.
+    ///
+    /// Ties are broken by the hash in the same direction, so the order is total and
+    /// can be resumed from a `Cursor`.
+    pub fn keys(&self) -> Option<(&'static [&'static str], bool)> {
+        match self {
+            OrderBy::CreatedAtAsc => Some((&["created_at"], false)),
+            OrderBy::CreatedAtDesc => Some((&["created_at"], true)),
+            OrderBy::FileSizeAsc => Some((&["file_size"], false)),
+            OrderBy::FileSizeDesc => Some((&["file_size"], true)),
+            OrderBy::FeaturedFirst => Some((&["is_featured", "created_at"], true)),
+            OrderBy::Random => None,
+            OrderBy::HashAsc => Some((&[], false)),
+            OrderBy::HashDesc => Some((&[], true)),
+        }
+    }
+
     /// Converts the ordering option into its corresponding SQL string.
     ///
     /// # Returns
-    /// - `&'static str`: The SQL segment for the ORDER BY clause.
-    fn to_sql(&self) -> &'static str {
-        match self {
-            OrderBy::CreatedAtAsc => " ORDER BY created_at ASC",
-            OrderBy::CreatedAtDesc => " ORDER BY created_at DESC",
-            OrderBy::FileSizeAsc => " ORDER BY file_size ASC",
-            OrderBy::FileSizeDesc => " ORDER BY file_size DESC",
-            OrderBy::Random => " ORDER BY RANDOM()",
+    /// - `String`: The SQL segment for the ORDER BY clause.
+    fn to_sql(&self) -> String {
+        match self.keys() {
+            Some((columns, descending)) => keys_order_sql(columns, descending),
+            None => format!(" ORDER BY {}", CurrentDialect::random_query()),
         }
     }
 }
 
+/// Returns the ORDER BY clause of the columns, followed by the hash to break ties.
+fn keys_order_sql(columns: &[&str], descending: bool) -> String {
+    let direction = if descending { "DESC" } else { "ASC" };
+    let keys = columns
+        .iter()
+        .map(|column| CurrentDialect::order_key(column))
+        .chain(["hash".to_string()])
+        .map(|key| format!("{key} {direction}"))
+        .collect::<Vec<_>>();
+
+    format!(" ORDER BY {}", keys.join(", "))
+}
+
+/// The side of the cursor a page of keyset pagination lies on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorDirection {
+    /// Results following the cursor, in the order of the query.
+    After,
+
+    /// Results preceding the cursor, in the reverse order of the query.
+    Before,
+}
+
+/// A position in the results of keyset pagination, see `ImageQuery::after`.
+///
+/// It holds the hash of the last result of a page and the values of its order keys,
+/// see `OrderBy::keys`. It is passed to clients as an opaque string, and parsed back
+/// from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    /// The hash of the result, which breaks ties of the order keys.
+    pub hash: PixelHash,
+
+    /// The values of the order keys of the result, as text.
+    pub keys: Vec<String>,
+}
+
+impl From<PixelHash> for Cursor {
+    /// Creates the cursor of a result of a query ordered by hash, which has no other
+    /// order keys.
+    fn from(hash: PixelHash) -> Self {
+        Cursor { hash, keys: vec![] }
+    }
+}
+
+impl fmt::Display for Cursor {
+    /// Formats the signed hash, followed by each key after a `~`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.hash.to_signed())?;
+        for key in &self.keys {
+            write!(f, "~{}", key)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('~');
+        let hash = parts
+            .next()
+            .and_then(|hash| hash.parse::<i64>().ok())
+            .map(PixelHash::from_signed)
+            .ok_or_else(|| format!("invalid cursor: {s}"))?;
+
+        Ok(Cursor {
+            hash,
+            keys: parts.map(String::from).collect(),
+        })
+    }
+}
+
 /// Represents a full query including logical expression and pagination.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ImageQuery {
@@ -260,6 +874,15 @@ pub struct ImageQuery {
 
     /// The ordering of the results.
     pub order: Option<OrderBy>,
+
+    /// Whether private results are included. Only public results are returned by default.
+    pub include_private: bool,
+
+    /// Whether soft-deleted results are included. They are left out by default.
+    pub include_deleted: bool,
+
+    /// The cursor of keyset pagination, see `after` and `before`.
+    pub cursor: Option<(CursorDirection, Cursor)>,
 }
 
 impl ImageQuery {
@@ -276,6 +899,9 @@ impl ImageQuery {
             limit: None,
             offset: None,
             order: None,
+            include_private: false,
+            include_deleted: false,
+            cursor: None,
         }
     }
 
@@ -334,49 +960,151 @@ impl ImageQuery {
         self
     }
 
+    /// Sets whether private results are included.
+    ///
+    /// Should only be enabled for callers allowed to see private images.
+    ///
+    /// # Arguments
+    /// - `include` - Whether private results are included.
+    ///
+    /// # Returns
+    /// - `Self`: The updated `ImageQuery` instance.
+    pub fn with_private(mut self, include: bool) -> Self {
+        self.include_private = include;
+        self
+    }
+
+    /// Includes soft-deleted results, e.g. to list images that can be restored.
+    ///
+    /// # Returns
+    /// - `Self`: The updated `ImageQuery` instance.
+    pub fn including_deleted(mut self) -> Self {
+        self.include_deleted = true;
+        self
+    }
+
+    /// Restricts the results to those following the cursor in the order of the query,
+    /// which is by ascending hash if none is set.
+    ///
+    /// Unlike an offset, a cursor keeps pages stable while images are archived or
+    /// removed between fetches, and is not slowed down by the results it skips. The
+    /// cursor must come from a query of the same order, see `check_cursor`.
+    ///
+    /// # Arguments
+    /// - `cursor` - The last result of the previous page, or its hash if ordered by hash.
+    ///
+    /// # Returns
+    /// - `Self`: The updated `ImageQuery` instance.
+    pub fn after(mut self, cursor: impl Into<Cursor>) -> Self {
+        self.cursor = Some((CursorDirection::After, cursor.into()));
+        self
+    }
+
+    /// Restricts the results to those preceding the cursor, in the reverse order of
+    /// the query, see `after`.
+    ///
+    /// # Arguments
+    /// - `cursor` - The first result of the following page, or its hash if ordered by hash.
+    ///
+    /// # Returns
+    /// - `Self`: The updated `ImageQuery` instance.
+    pub fn before(mut self, cursor: impl Into<Cursor>) -> Self {
+        self.cursor = Some((CursorDirection::Before, cursor.into()));
+        self
+    }
+
+    /// Returns the order of keyset pagination, which is by ascending hash unless set.
+    pub fn cursor_order(&self) -> OrderBy {
+        self.order.clone().unwrap_or(OrderBy::HashAsc)
+    }
+
+    /// Checks that the cursor, if any, can page through the results of this query.
+    ///
+    /// # Errors
+    /// Returns the reason if the query is ordered randomly, or if the cursor holds
+    /// the keys of another order. Such a cursor is left out by `to_sql`.
+    pub fn check_cursor(&self) -> Result<(), String> {
+        let Some((_, cursor)) = &self.cursor else {
+            return Ok(());
+        };
+        let order = self.cursor_order();
+        let (columns, _) = order
+            .keys()
+            .ok_or_else(|| format!("results ordered by {order:?} cannot be paged with a cursor"))?;
+        if cursor.keys.len() != columns.len() {
+            return Err(format!(
+                "the cursor {cursor} is not of results ordered by {order:?}"
+            ));
+        }
+
+        Ok(())
+    }
+
     /// Converts the full query into an SQL string and bound parameters.
     ///
     /// # Returns
     /// - `(String, Vec<String>)`: SQL clause and ordered parameters
     ///
-    /// The generated SQL includes any specified LIMIT or OFFSET.
+    /// The generated SQL includes any specified cursor, LIMIT or OFFSET, and excludes
+    /// private and soft-deleted results unless they are included.
     pub fn to_sql(&self) -> (String, Vec<String>) {
-        let (mut where_sql, mut params) = self.expr.to_sql();
-
-        if let Some(order) = &self.order {
-            where_sql.push_str(order.to_sql());
+        let restrict = |kind: ImageQueryKind, condition: ImageQueryExpr| match kind {
+            ImageQueryKind::All => ImageQueryKind::Where(condition),
+            ImageQueryKind::Where(expr) => ImageQueryKind::Where(expr.and(condition)),
+        };
+        let mut expr = self.expr.clone();
+        if !self.include_private {
+            expr = restrict(expr, ImageQueryExpr::Public);
         }
-
-        if let Some(limit) = self.limit {
-            params.push(limit.to_string());
-            where_sql.push_str(
-                format!(
-                    " LIMIT CAST({} AS INTEGER)",
-                    CurrentDialect::placeholder(params.len())
-                )
-                .as_str(),
-            );
+        if !self.include_deleted {
+            expr = restrict(expr, ImageQueryExpr::not(ImageQueryExpr::Deleted));
         }
+        let (mut where_sql, mut params) = expr.to_sql();
 
-        if let Some(offset) = self.offset {
-            params.push(offset.to_string());
-            where_sql.push_str(
-                format!(
-                    " OFFSET CAST({} AS INTEGER)",
-                    CurrentDialect::placeholder(params.len())
-                )
-                .as_str(),
-            );
+        let order = match (&self.cursor, self.cursor_order().keys()) {
+            (Some((direction, cursor)), Some((columns, descending)))
+                if self.check_cursor().is_ok() =>
+            {
+                params.extend(cursor.keys.iter().cloned());
+                params.push(cursor.hash.to_string());
+                // Before the cursor, the results are read backwards from it.
+                let descending = descending != (*direction == CursorDirection::Before);
+                let clause = CurrentDialect::keyset_cursor_clause(
+                    columns,
+                    params.len() - columns.len(),
+                    descending,
+                );
+                where_sql = match where_sql.strip_prefix("WHERE ") {
+                    Some(condition) => format!("WHERE ({}) AND {}", condition, clause),
+                    None => format!("WHERE {}", clause),
+                };
+
+                Some(keys_order_sql(columns, descending))
+            }
+            _ => self.order.as_ref().map(OrderBy::to_sql),
+        };
+        if let Some(order) = order {
+            where_sql.push_str(&order);
         }
 
+        where_sql.push_str(&CurrentDialect::limit_offset_query(
+            self.limit,
+            self.offset,
+            &mut params,
+        ));
+
         (where_sql, params)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{CurrentDialect, Dialect, ImageQuery, date_until, not, tag};
-    use crate::query::OrderBy;
+    use super::{
+        Comparison, CurrentDialect, Cursor, Dialect, ImageQuery, ImageQueryExpr, MediaGroup,
+        MetadataField, aspect_ratio, date_until, deleted, filesize_less_than, height_at_least,
+        media_group, metadata, not, private, rating, tag, width_at_least,
+    };
+    use crate::{query::OrderBy, storage::PixelHash};
 
     #[test]
     fn test_build_query() {
@@ -392,28 +1120,266 @@ mod tests {
 
         let (sql, params) = query.to_sql();
 
+        let mut expected_params = vec![
+            "cat".to_string(),
+            "cute".to_string(),
+            "dog".to_string(),
+            "2024-12-01T00:00:00+00:00".to_string(),
+        ];
+        let limit_offset =
+            CurrentDialect::limit_offset_query(Some(10), Some(20), &mut expected_params);
+
         assert_eq!(
             format!(
-                "WHERE ((({} AND {}) OR NOT {}) AND {}) ORDER BY created_at DESC LIMIT CAST({} AS INTEGER) OFFSET CAST({} AS INTEGER)",
+                "WHERE ((((({} AND {}) OR NOT {}) AND {}) AND {}) AND NOT {}) ORDER BY {} DESC, hash DESC{}",
                 CurrentDialect::exists_tag_query(1),
                 CurrentDialect::exists_tag_query(2),
                 CurrentDialect::exists_tag_query(3),
                 CurrentDialect::exists_date_until_query(4),
-                CurrentDialect::placeholder(5),
-                CurrentDialect::placeholder(6),
+                CurrentDialect::is_public_query(),
+                CurrentDialect::is_deleted_query(),
+                CurrentDialect::order_key("created_at"),
+                limit_offset,
+            ),
+            sql
+        );
+        assert_eq!(expected_params, params);
+    }
+
+    #[test]
+    fn test_build_cursor_query() {
+        let hash = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        let cursor = Cursor {
+            hash: hash.clone(),
+            keys: vec!["2025-01-01T00:00:00+00:00".to_string()],
+        };
+        let query = ImageQuery::filter(tag("cat"))
+            .with_order(OrderBy::CreatedAtDesc)
+            .with_limit(10)
+            .after(cursor.clone());
+
+        let (sql, params) = query.to_sql();
+
+        let mut expected_params = vec!["cat".to_string(), cursor.keys[0].clone(), hash.to_string()];
+        let limit_offset = CurrentDialect::limit_offset_query(Some(10), None, &mut expected_params);
+        assert_eq!(
+            format!(
+                "WHERE ((({} AND {}) AND NOT {})) AND {} ORDER BY {} DESC, hash DESC{}",
+                CurrentDialect::exists_tag_query(1),
+                CurrentDialect::is_public_query(),
+                CurrentDialect::is_deleted_query(),
+                CurrentDialect::keyset_cursor_clause(&["created_at"], 2, true),
+                CurrentDialect::order_key("created_at"),
+                limit_offset,
             ),
             sql
         );
+        assert_eq!(expected_params, params);
+
+        // Before the cursor, the order of the query is reversed.
+        let (sql, params) = ImageQuery::all()
+            .with_private(true)
+            .including_deleted()
+            .before(hash.clone())
+            .to_sql();
+
+        assert_eq!(
+            format!(
+                "WHERE {} ORDER BY hash DESC",
+                CurrentDialect::keyset_cursor_clause(&[], 1, true),
+            ),
+            sql
+        );
+        assert_eq!(vec![hash.to_string()], params);
+
+        // Random order and cursors of another order are rejected, and left out.
+        let random = ImageQuery::all()
+            .with_order(OrderBy::Random)
+            .after(hash.clone());
+        assert!(random.check_cursor().is_err());
+        assert_eq!(
+            format!(" ORDER BY {}", CurrentDialect::random_query()),
+            random.with_private(true).including_deleted().to_sql().0
+        );
+        let mismatched = ImageQuery::all()
+            .with_order(OrderBy::FeaturedFirst)
+            .after(cursor.clone());
+        assert!(mismatched.check_cursor().is_err());
+        assert_eq!(
+            format!(
+                " ORDER BY is_featured DESC, {} DESC, hash DESC",
+                CurrentDialect::order_key("created_at")
+            ),
+            mismatched.with_private(true).including_deleted().to_sql().0
+        );
+    }
+
+    #[test]
+    fn test_cursor_string() {
+        let cursor = Cursor {
+            hash: PixelHash::try_from("44a5b6f94f4f6445").unwrap(),
+            keys: vec!["1".to_string(), "2025-01-01T00:00:00+00:00".to_string()],
+        };
+
+        assert_eq!(Ok(cursor.clone()), cursor.to_string().parse());
+        assert_eq!(
+            Ok(Cursor::from(cursor.hash.clone())),
+            cursor.hash.to_signed().to_string().parse()
+        );
+        assert!("not-a-cursor".parse::<Cursor>().is_err());
+    }
+
+    #[test]
+    fn test_build_media_group_query() {
+        let (sql, params) = tag("cat").and(media_group(MediaGroup::Animated)).to_sql();
+
+        assert_eq!(
+            format!(
+                "({} AND ({} OR {}))",
+                CurrentDialect::exists_tag_query(1),
+                CurrentDialect::format_in_query(2..=2),
+                CurrentDialect::has_duration_query(),
+            ),
+            sql
+        );
+        assert_eq!(vec!["cat", "gif"], params);
+
+        let (sql, params) = media_group(MediaGroup::Photo).to_sql();
+
+        assert_eq!(CurrentDialect::format_in_query(1..=2), sql);
+        assert_eq!(vec!["jpg", "jpeg"], params);
+    }
+
+    #[test]
+    fn test_build_rating_query() {
+        let (sql, params) = tag("cat").or(not(rating("explicit"))).to_sql();
+
+        assert_eq!(
+            format!(
+                "({} OR NOT {})",
+                CurrentDialect::exists_tag_query(1),
+                CurrentDialect::exists_rating_query(2),
+            ),
+            sql
+        );
+        assert_eq!(vec!["cat", "e"], params);
+        assert_eq!(vec!["q"], rating("q").to_sql().1);
+        assert_eq!(vec!["g"], rating("safe").to_sql().1);
+        assert_eq!(vec!["s"], rating("s").to_sql().1);
+        assert_eq!(vec!["nsfw"], rating("nsfw").to_sql().1);
+    }
+
+    #[test]
+    fn test_build_metadata_query() {
+        let (sql, params) = ImageQueryExpr::width_gte(1920)
+            .and(ImageQueryExpr::height_lt(1080))
+            .or(not(ImageQueryExpr::filesize_gte(1_000_000)))
+            .and(metadata(MetadataField::Width, Comparison::Eq, 640))
+            .to_sql();
+
+        assert_eq!(
+            format!(
+                "((({} AND {}) OR NOT {}) AND {})",
+                CurrentDialect::exists_metadata_comparison_query("width", ">=", 1),
+                CurrentDialect::exists_metadata_comparison_query("height", "<", 2),
+                CurrentDialect::exists_metadata_comparison_query("file_size", ">=", 3),
+                CurrentDialect::exists_metadata_comparison_query("width", "=", 4),
+            ),
+            sql
+        );
+        assert_eq!(vec!["1920", "1080", "1000000", "640"], params);
+    }
+
+    #[test]
+    fn test_build_aspect_ratio_query() {
+        let (sql, params) = tag("cat").and(aspect_ratio(16, 10)).to_sql();
+
+        assert_eq!(
+            format!(
+                "({} AND {})",
+                CurrentDialect::exists_tag_query(1),
+                CurrentDialect::exists_aspect_ratio_query(2),
+            ),
+            sql
+        );
+        assert_eq!(vec!["cat", "1.6"], params);
+    }
+
+    #[test]
+    fn test_build_metadata_with_tags_query() {
+        let (sql, params) = tag("cat")
+            .and(width_at_least(1920))
+            .and(not(tag("dog")).or(filesize_less_than(2 << 20)))
+            .and(height_at_least(1080))
+            .to_sql();
+
+        assert_eq!(
+            format!(
+                "((({} AND {}) AND (NOT {} OR {})) AND {})",
+                CurrentDialect::exists_tag_query(1),
+                CurrentDialect::exists_metadata_comparison_query("width", ">=", 2),
+                CurrentDialect::exists_tag_query(3),
+                CurrentDialect::exists_metadata_comparison_query("file_size", "<", 4),
+                CurrentDialect::exists_metadata_comparison_query("height", ">=", 5),
+            ),
+            sql
+        );
+        assert_eq!(vec!["cat", "1920", "dog", "2097152", "1080"], params);
+    }
+
+    #[test]
+    fn test_build_xor_query() {
+        let (sql, params) = tag("cat").xor(tag("dog")).to_sql();
+
+        assert_eq!(
+            format!(
+                "(({} OR {}) AND NOT ({} AND {}))",
+                CurrentDialect::exists_tag_query(1),
+                CurrentDialect::exists_tag_query(2),
+                CurrentDialect::exists_tag_query(3),
+                CurrentDialect::exists_tag_query(4),
+            ),
+            sql
+        );
+        assert_eq!(vec!["cat", "dog", "cat", "dog"], params);
+    }
+
+    #[test]
+    fn test_build_visibility_query() {
+        let public = CurrentDialect::is_public_query();
+        let deleted = CurrentDialect::is_deleted_query();
+
+        assert_eq!(
+            format!("WHERE ({public} AND NOT {deleted})"),
+            ImageQuery::all().to_sql().0
+        );
+        assert_eq!(
+            format!("WHERE NOT {deleted}"),
+            ImageQuery::all().with_private(true).to_sql().0
+        );
+        assert_eq!(
+            "",
+            ImageQuery::all()
+                .with_private(true)
+                .including_deleted()
+                .to_sql()
+                .0
+        );
+        assert_eq!(
+            format!("WHERE NOT {public}"),
+            ImageQuery::filter(private())
+                .with_private(true)
+                .including_deleted()
+                .to_sql()
+                .0
+        );
+        assert_eq!(
+            format!("WHERE ((NOT {public} AND {public}) AND NOT {deleted})"),
+            ImageQuery::filter(private()).to_sql().0
+        );
         assert_eq!(
-            vec![
-                "cat",
-                "cute",
-                "dog",
-                "2024-12-01T00:00:00+00:00",
-                "10",
-                "20",
-            ],
-            params
+            format!("WHERE ({deleted} AND {public})"),
+            ImageQuery::filter(deleted()).including_deleted().to_sql().0
         );
     }
 }
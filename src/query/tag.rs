@@ -33,6 +33,11 @@ impl TagQueryExpr {
         Self::Or(Box::new(self), Box::new(other))
     }
 
+    /// Negates an expression.
+    pub fn not(expr: impl Into<TagQueryExpr>) -> Self {
+        Self::Not(Box::new(expr.into()))
+    }
+
     /// Converts the logical expression to an SQL clause and parameters.
     ///
     /// # Returns
@@ -51,12 +56,12 @@ impl TagQueryExpr {
                 format!("name = {}", CurrentDialect::placeholder(params.len()))
             }
             TagQueryExpr::Prefix(prefix) => {
-                params.push(format!("{}%", prefix));
-                format!("name LIKE {}", CurrentDialect::placeholder(params.len()))
+                params.push(format!("{}%", escape_like(prefix)));
+                CurrentDialect::like_query("name", params.len())
             }
             TagQueryExpr::Contains(substr) => {
-                params.push(format!("%{}%", substr));
-                format!("name LIKE {}", CurrentDialect::placeholder(params.len()))
+                params.push(format!("%{}%", escape_like(substr)));
+                CurrentDialect::like_query("name", params.len())
             }
             TagQueryExpr::And(lhs, rhs) => {
                 format!("({} AND {})", lhs.build_sql(params), rhs.build_sql(params))
@@ -71,6 +76,14 @@ impl TagQueryExpr {
     }
 }
 
+/// Escapes `LIKE` wildcards, so that e.g. `_` in a tag matches only itself.
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 /// Represents the kind of query being performed on tags.
 #[derive(Debug, Clone)]
 pub enum TagQueryKind {
@@ -141,28 +154,36 @@ impl TagQuery {
     pub fn to_sql(&self) -> (String, Vec<String>) {
         let (mut where_sql, mut params) = self.expr.to_sql();
 
-        if let Some(limit) = self.limit {
-            params.push(limit.to_string());
-            where_sql.push_str(
-                format!(
-                    " LIMIT CAST({} AS INTEGER)",
-                    CurrentDialect::placeholder(params.len())
-                )
-                .as_str(),
-            );
-        }
-
-        if let Some(offset) = self.offset {
-            params.push(offset.to_string());
-            where_sql.push_str(
-                format!(
-                    " OFFSET CAST({} AS INTEGER)",
-                    CurrentDialect::placeholder(params.len())
-                )
-                .as_str(),
-            );
-        }
+        where_sql.push_str(&CurrentDialect::limit_offset_query(
+            self.limit,
+            self.offset,
+            &mut params,
+        ));
 
         (where_sql, params)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::TagQueryExpr;
+    use crate::dialect::{CurrentDialect, Dialect};
+
+    #[test]
+    fn test_build_not_query() {
+        let expr = TagQueryExpr::Prefix("a".to_string())
+            .and(TagQueryExpr::not(TagQueryExpr::Contains("_".to_string())));
+
+        let (sql, params) = expr.to_sql();
+
+        assert_eq!(
+            format!(
+                "({} AND NOT ({}))",
+                CurrentDialect::like_query("name", 1),
+                CurrentDialect::like_query("name", 2)
+            ),
+            sql
+        );
+        assert_eq!(vec!["a%".to_string(), "%\\_%".to_string()], params);
+    }
+}
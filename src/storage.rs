@@ -12,24 +12,183 @@
 //! The module includes operations for storing images, managing duplicate detection,
 //! retrieving file metadata, and ensuring files are correctly indexed or deleted
 //! from the storage system.
-
+//!
+//! Expensive decode work can be bounded with an `AdmissionController`, see the
+//! `admission` submodule. Metadata extraction is dispatched by `MediaKind`, see the
+//! `metadata` submodule. Near-duplicate images and videos can be found with perceptual
+//! hashes, see the `perceptual` submodule. Encoder settings per format are in the
+//! `encoding` submodule, and pre-generated downscaled variants in the `variant`
+//! submodule. Stored objects are kept in a `StorageBackend`, see the `backend`
+//! submodule. Concurrent uploads of likely identical content can be serialized so
+//! that only one decodes, see the `ingest_lock` submodule. With the `webp` feature,
+//! images can be stored in another format than their original one, see the
+//! `transcode` submodule. With the `raw-images` feature, camera RAW files are decoded
+//! and stored alongside a thumbnail, see the `camera_raw` submodule. The disk space
+//! taken by stored files can be limited, see the `quota` submodule.
+
+mod admission;
+mod backend;
+#[cfg(feature = "raw-images")]
+mod camera_raw;
+mod encoding;
+mod ingest_lock;
+mod metadata;
+mod perceptual;
+mod quota;
+#[cfg(feature = "webp")]
+mod transcode;
+mod variant;
+
+pub use admission::{
+    AdmissionController, AdmissionPermit, AdmissionStats, LaneStats, Priority, WorkKind,
+};
+#[cfg(feature = "s3")]
+pub use backend::S3Backend;
+pub use backend::{FilesystemBackend, MemoryBackend, StorageBackend};
 pub use chrono::{DateTime, Utc};
+pub use encoding::{EncoderOptions, FormatOptions};
 use glob::glob;
-use image::{DynamicImage, GenericImageView, ImageBuffer, ImageFormat, ImageReader};
+use image::{
+    AnimationDecoder, DynamicImage, Frames, ImageBuffer, ImageDecoder, ImageFormat, ImageReader,
+    codecs::{gif::GifDecoder, webp::WebPDecoder},
+    metadata::Orientation,
+};
+pub use ingest_lock::IngestLockStats;
+use ingest_lock::{IngestLocks, IngestReservation};
+pub use metadata::{CreatedAtFallback, MediaKind};
+use perceptual::PerceptualIndex;
+pub use perceptual::{NearDuplicate, PHash};
+use quota::DiskUsage;
 use std::hash::Hasher;
 use std::{
+    collections::{BTreeSet, HashMap},
     fmt::Display,
     fs::{self},
-    path::PathBuf,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 use tempfile::NamedTempFile;
 use thiserror::Error;
-use twox_hash::XxHash64;
+use tokio::io::{AsyncRead, AsyncWriteExt};
+#[cfg(feature = "webp")]
+pub use transcode::{TranscodeConfig, TranscodeFormat};
+use twox_hash::{XxHash3_128, XxHash64};
+pub use variant::{VariantSize, VariantSpec};
 use video_rs::{Decoder, Frame};
 
+/// The number of leading bytes a spooled upload's file type is inferred from.
+const SNIFF_LEN: u64 = 8 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct Storage {
     root_path: PathBuf,
+    thumbnail_root: Option<PathBuf>,
+    thumbnail_format: ThumbnailFormat,
+    admission: Option<Arc<AdmissionController>>,
+    perceptual_index: Arc<PerceptualIndex>,
+    image_near_duplicates: Option<u32>,
+    video_near_duplicates: Option<u32>,
+    ingest_locks: Option<Arc<IngestLocks>>,
+    hash_seed: u64,
+    format_options: FormatOptions,
+    variant_sizes: Vec<VariantSize>,
+    created_at_fallback: CreatedAtFallback,
+    sharding: ShardingConfig,
+    backend: Arc<dyn StorageBackend>,
+    /// Whether `backend` was set with `with_backend`, so stored files are pushed to it.
+    remote_backend: bool,
+    allow_raw: bool,
+    auto_orient: bool,
+    max_bytes: u64,
+    disk_usage: Arc<DiskUsage>,
+    #[cfg(feature = "webp")]
+    transcode: Option<TranscodeConfig>,
+}
+
+/// The directory hierarchy stored files are sharded into.
+///
+/// Each level is named after one byte of the pixel hash, e.g. `32/94/` for two
+/// levels, so a level spreads files over 256 more directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardingConfig {
+    /// The number of directory levels, from 1 to 3.
+    pub levels: u8,
+}
+
+impl Default for ShardingConfig {
+    fn default() -> Self {
+        ShardingConfig { levels: 2 }
+    }
+}
+
+/// The outcome of `Storage::create_file_with_report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateReport {
+    /// The pixel hash the file was stored under.
+    pub hash: PixelHash,
+    /// The relative path the file was stored at, as `Storage::index_file` returns it.
+    pub path: MediaPath,
+    /// The perceptual hash of the image, or of the thumbnail of a video. Raw files
+    /// have none.
+    pub phash: Option<PHash>,
+    /// Stored entries that look like the new one, closest first.
+    ///
+    /// Empty unless near-duplicate detection is enabled for the kind of the new file,
+    /// see `Storage::with_image_near_duplicates` and
+    /// `Storage::with_video_near_duplicates`.
+    pub near_duplicates: Vec<NearDuplicate>,
+    /// The content digest of the upload, a 128-bit hash of the same pixels (or bytes of
+    /// raw files) as the pixel hash, but unseeded.
+    ///
+    /// The 64-bit pixel hash may collide for different images in a large archive, which
+    /// `create_file` cannot tell from a duplicate. Callers recording the digest, like
+    /// `ArchiveImageCommand`, compare it to tell them apart. It is `None` if the upload
+    /// was not decoded, as its bytes are those of an upload before, see
+    /// `Storage::with_ingest_locks`.
+    pub digest: Option<String>,
+}
+
+/// The image format of generated video thumbnails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ThumbnailFormat {
+    /// Lossless PNG, the default.
+    #[default]
+    Png,
+    /// Lossy JPEG, much smaller for photographic frames. Alpha is discarded.
+    Jpeg,
+    /// Lossless WebP.
+    WebP,
+}
+
+impl ThumbnailFormat {
+    /// Returns the file extension used for thumbnails of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ThumbnailFormat::Png => "png",
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::WebP => "webp",
+        }
+    }
+
+    /// Encodes the thumbnail into the given file.
+    fn save(
+        self,
+        thumbnail: &DynamicImage,
+        path: PathBuf,
+        options: &FormatOptions,
+    ) -> Result<(), StorageError> {
+        match self {
+            ThumbnailFormat::Png => options.save(thumbnail, &path, ImageFormat::Png),
+            ThumbnailFormat::Jpeg => options.save(
+                &DynamicImage::ImageRgb8(thumbnail.to_rgb8()),
+                &path,
+                ImageFormat::Jpeg,
+            ),
+            ThumbnailFormat::WebP => options.save(thumbnail, &path, ImageFormat::WebP),
+        }
+    }
 }
 
 impl Storage {
@@ -38,27 +197,277 @@ impl Storage {
     /// # Arguments
     /// * `root` - Root directory path where all files will be stored.
     pub fn new(root: PathBuf) -> Storage {
-        Storage { root_path: root }
+        Storage {
+            backend: Arc::new(FilesystemBackend::new(root.clone())),
+            remote_backend: false,
+            root_path: root,
+            thumbnail_root: None,
+            thumbnail_format: ThumbnailFormat::default(),
+            admission: None,
+            perceptual_index: Arc::default(),
+            image_near_duplicates: None,
+            video_near_duplicates: None,
+            ingest_locks: None,
+            hash_seed: 0,
+            format_options: FormatOptions::default(),
+            variant_sizes: vec![],
+            created_at_fallback: CreatedAtFallback::default(),
+            sharding: ShardingConfig::default(),
+            allow_raw: false,
+            auto_orient: true,
+            max_bytes: u64::MAX,
+            disk_usage: Arc::default(),
+            #[cfg(feature = "webp")]
+            transcode: None,
+        }
+    }
+
+    /// Encodes stored images and thumbnails with the given settings per format.
+    ///
+    /// Formats without settings keep the `image` crate's defaults.
+    ///
+    /// # Arguments
+    /// * `options` - The encoder settings per format.
+    pub fn with_format_options(mut self, options: FormatOptions) -> Storage {
+        self.format_options = options;
+        self
+    }
+
+    /// Stores new images in the configured format instead of their original one.
+    ///
+    /// The pixel hash is still computed from the original pixels. Videos and
+    /// animated GIFs are stored as they are.
+    ///
+    /// # Arguments
+    /// * `config` - The format and quality images are transcoded into.
+    #[cfg(feature = "webp")]
+    pub fn with_transcode(mut self, config: TranscodeConfig) -> Storage {
+        self.transcode = Some(config);
+        self
+    }
+
+    /// Seeds the pixel hash with the given value instead of 0.
+    ///
+    /// Files stored with different seeds get unrelated hashes, so changing the seed of
+    /// an existing archive requires migrating it, see `app::rehash_archive`.
+    ///
+    /// # Arguments
+    /// * `seed` - The seed of the pixel hash.
+    pub fn with_hash_seed(mut self, seed: u64) -> Storage {
+        self.hash_seed = seed;
+        self
+    }
+
+    /// Rotates and flips new images upright according to their EXIF orientation
+    /// before they are hashed and stored. Enabled by default.
+    ///
+    /// Upright images get the same pixel hash as a copy that was rotated by other
+    /// means, and the orientation of the carried over EXIF data is reset to 1. When
+    /// disabled, images are stored with their pixels as decoded and clients apply the
+    /// recorded orientation. Toggling this changes the pixel hash of images with an
+    /// orientation other than 1.
+    ///
+    /// # Arguments
+    /// * `enabled` - Whether new images are oriented upright.
+    pub fn with_auto_orient(mut self, enabled: bool) -> Storage {
+        self.auto_orient = enabled;
+        self
+    }
+
+    /// Chooses the `created_at` of files whose creation time the filesystem does not
+    /// report, see `CreatedAtFallback`. Defaults to `CreatedAtFallback::Now`.
+    ///
+    /// # Arguments
+    /// * `fallback` - Where `get_metadata` takes missing creation times from.
+    pub fn with_created_at_fallback(mut self, fallback: CreatedAtFallback) -> Storage {
+        self.created_at_fallback = fallback;
+        self
+    }
+
+    /// Shards stored files into the given number of directory levels instead of 2.
+    ///
+    /// One level keeps the tree shallow but puts about 1/256 of the archive into each
+    /// directory, which slows down listing and lookups once the archive holds hundreds
+    /// of thousands of files. Three levels keep directories small for any archive size,
+    /// at the cost of many nearly empty directories for small ones.
+    ///
+    /// Files are only looked up at the configured depth, so an existing archive must
+    /// keep the depth it was created with. Files stored at another depth are not
+    /// found, and are not moved.
+    ///
+    /// # Arguments
+    /// * `sharding` - The directory hierarchy of new and looked up files.
+    ///
+    /// # Panics
+    /// Panics if `sharding.levels` is not between 1 and 3.
+    pub fn with_sharding(mut self, sharding: ShardingConfig) -> Storage {
+        assert!(
+            (1..=3).contains(&sharding.levels),
+            "sharding levels must be between 1 and 3, got {}",
+            sharding.levels
+        );
+        self.sharding = sharding;
+        self
+    }
+
+    /// Keeps stored objects in the given backend instead of the root directory.
+    ///
+    /// Files are still hashed, decoded and thumbnailed in the root directory, which
+    /// keeps a working copy of them. `push_to_backend` copies the files of a hash to
+    /// the backend under their relative paths, and `delete` removes them from both.
+    /// The app pushes every file it stores and deletes through the backend.
+    ///
+    /// # Arguments
+    /// * `backend` - The object store stored files are written to and read from.
+    pub fn with_backend(mut self, backend: Arc<dyn StorageBackend>) -> Storage {
+        self.backend = backend;
+        self.remote_backend = true;
+        self
+    }
+
+    /// Stores files of a detected type that is neither an image nor a video, e.g.
+    /// PDFs or ZIP archives, instead of rejecting them.
+    ///
+    /// Raw files are stored as they are, with the extension of their detected type.
+    /// They are never decoded, so their hash is computed from their bytes rather
+    /// than their pixels, and their metadata has no dimensions. Files of an
+    /// undetectable type are still rejected.
+    ///
+    /// # Arguments
+    /// * `allow` - Whether raw files are stored. Disallowed by default.
+    pub fn with_raw_files(mut self, allow: bool) -> Storage {
+        self.allow_raw = allow;
+        self
+    }
+
+    /// Returns the backend stored objects are served from.
+    pub fn backend(&self) -> &Arc<dyn StorageBackend> {
+        &self.backend
+    }
+
+    /// Returns the root directory path where files are stored.
+    pub fn root(&self) -> &Path {
+        &self.root_path
+    }
+
+    /// Stores generated thumbnails under a separate directory tree.
+    ///
+    /// The tree mirrors the layout of the main root, so a thumbnail's path relative
+    /// to `root` equals the path of its original relative to the main root, apart
+    /// from the extension. Originals stay in the main root. Without this, thumbnails
+    /// are stored next to their originals.
+    ///
+    /// # Arguments
+    /// * `root` - Root directory path where thumbnails will be stored.
+    pub fn with_thumbnail_root(mut self, root: PathBuf) -> Storage {
+        self.thumbnail_root = Some(root);
+        self
+    }
+
+    /// Encodes generated video thumbnails in the given format.
+    ///
+    /// Thumbnails stored earlier in another format are still found, so the format
+    /// can be changed on an existing archive.
+    ///
+    /// # Arguments
+    /// * `format` - The image format of new thumbnails.
+    pub fn with_thumbnail_format(mut self, format: ThumbnailFormat) -> Storage {
+        self.thumbnail_format = format;
+        self
+    }
+
+    /// Bounds decode work performed by `create_file` with the given controller.
+    ///
+    /// Without a controller every call decodes immediately.
+    ///
+    /// # Arguments
+    /// * `controller` - The admission controller shared by all clones of this storage.
+    pub fn with_admission(mut self, controller: AdmissionController) -> Storage {
+        self.admission = Some(Arc::new(controller));
+        self
+    }
+
+    /// Reports stored entries that look like a newly stored video.
+    ///
+    /// Two encodes of the same clip usually yield different thumbnails and thus
+    /// different pixel hashes. With this enabled, `create_file_with_report` compares
+    /// the perceptual hash of a new video's thumbnail with those of all stored entries
+    /// and reports the ones within `max_distance` differing bits. The video is still
+    /// stored; deciding what to do with near-duplicates is up to the caller.
+    ///
+    /// Stored entries are hashed once, on first use of the perceptual index.
+    ///
+    /// # Arguments
+    /// * `max_distance` - The maximum Hamming distance (0-64) reported, e.g. 8.
+    pub fn with_video_near_duplicates(mut self, max_distance: u32) -> Storage {
+        self.video_near_duplicates = Some(max_distance);
+        self
+    }
+
+    /// Reports stored entries that look like a newly stored image.
+    ///
+    /// Behaves like `with_video_near_duplicates` for images, which catches re-encoded,
+    /// rescaled or slightly edited copies of stored images.
+    ///
+    /// # Arguments
+    /// * `max_distance` - The maximum Hamming distance (0-64) reported, e.g. 8.
+    pub fn with_image_near_duplicates(mut self, max_distance: u32) -> Storage {
+        self.image_near_duplicates = Some(max_distance);
+        self
+    }
+
+    /// Lets concurrent uploads of likely identical content run one at a time.
+    ///
+    /// Uploads are keyed by their length and a hash of their first `prefix_len`
+    /// bytes. An upload waiting for another one with the same key, whose bytes turn
+    /// out identical, fails with `StorageError::HashCollision` without being decoded.
+    /// This saves the duplicated work of large video uploads retried in parallel.
+    ///
+    /// # Arguments
+    /// * `prefix_len` - The number of leading bytes hashed into the key, e.g. 64 KiB.
+    pub fn with_ingest_locks(mut self, prefix_len: usize) -> Storage {
+        self.ingest_locks = Some(Arc::new(IngestLocks::new(prefix_len)));
+        self
+    }
+
+    /// Returns a snapshot of the ingest locks, if they are enabled.
+    pub fn ingest_lock_stats(&self) -> Option<IngestLockStats> {
+        self.ingest_locks.as_deref().map(IngestLocks::stats)
+    }
+
+    /// Returns the admission controller, if one is configured.
+    ///
+    /// Use `AdmissionController::stats` to read current queue depths and in-flight counts.
+    pub fn admission(&self) -> Option<&AdmissionController> {
+        self.admission.as_deref()
     }
 
     /// Creates and saves a new file into storage.
     ///
     /// The file is decoded as an image, and a pixel-based hash is computed.
     /// If another file with the same visual content already exists, an error is returned.
+    /// Files are written under a temporary name and renamed once complete, so an
+    /// interrupted write never leaves a partial file that passes for a stored one.
     ///
     /// # Arguments
     ///
     /// * `bytes` - The raw byte array of the image file.
     ///
     /// # Returns
-    /// * `Ok(Md5Hash)` - The computed pixel hash if the file was saved successfully.
+    /// * `Ok((PixelHash, Option<PHash>))` - The computed pixel hash and perceptual hash
+    ///   if the file was saved successfully. Raw files have no perceptual hash.
     /// * `Err(StorageError)` - If there was a collision or a saving error.
     ///
     /// # Errors
     /// - `StorageError::HashCollision` if a file with the same pixel hash already exists.
-    /// - `StorageError::UnsupportedFile` if the file type cannot be determined.
+    /// - `StorageError::EmptyInput` if `bytes` is empty.
+    /// - `StorageError::UnsupportedFile` if the file type cannot be determined, or is
+    ///   neither an image nor a video and raw files are not allowed, see
+    ///   `with_raw_files`.
     /// - `StorageError::Io` if directory creation or file writing fails.
     /// - `StorageError::Image` if operate the image fails.
+    /// - `StorageError::Busy` if admission control rejects the decode.
+    /// - `StorageError::QuotaExceeded` if the file does not fit within `with_max_bytes`.
     ///
     /// # Examples
     ///
@@ -67,22 +476,163 @@ impl Storage {
     /// # use tempfile::TempDir;
     /// let storage = Storage::new(TempDir::new().unwrap().path().to_path_buf());
     /// let bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
-    /// let hash = storage.create_file(bytes).unwrap();
-    /// println!("File stored with pixel hash: {:?}", hash);
+    /// let (hash, phash) = storage.create_file(bytes).unwrap();
+    /// println!("File stored with pixel hash: {:?}, perceptual hash: {:?}", hash, phash);
     /// ```
-    pub fn create_file(&self, bytes: &[u8]) -> Result<PixelHash, StorageError> {
-        let media = Media::new(bytes)?;
+    pub fn create_file(&self, bytes: &[u8]) -> Result<(PixelHash, Option<PHash>), StorageError> {
+        self.create_file_with_report(bytes, Priority::Interactive)
+            .map(|report| (report.hash, report.phash))
+    }
+
+    /// Creates and saves a new file into storage using the given admission lane.
+    ///
+    /// Behaves like `create_file`, but lets background callers use
+    /// `Priority::Maintenance` so they never starve interactive uploads.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw byte array of the image file.
+    /// * `priority` - The priority lane used when waiting for a decode slot.
+    pub fn create_file_with_priority(
+        &self,
+        bytes: &[u8],
+        priority: Priority,
+    ) -> Result<PixelHash, StorageError> {
+        self.create_file_with_report(bytes, priority)
+            .map(|report| report.hash)
+    }
+
+    /// Creates and saves a new file into storage, or finds the stored file with the
+    /// same visual content.
+    ///
+    /// Behaves like `create_file`, but a file with the same pixel hash is not an
+    /// error. Its hash is returned instead, and the stored file is left as it is.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw byte array of the image file.
+    ///
+    /// # Returns
+    /// * `Ok((PixelHash, bool))` - The pixel hash, and whether the file was newly written.
+    /// * `Err(StorageError)` - See `create_file`, apart from `HashCollision`.
+    pub fn create_or_get(&self, bytes: &[u8]) -> Result<(PixelHash, bool), StorageError> {
+        self.create_or_get_with_report(bytes, Priority::Interactive)
+            .map(|(report, created)| (report.hash, created))
+    }
+
+    /// Creates and saves a new file into storage, or reports the stored file with the
+    /// same visual content.
+    ///
+    /// Behaves like `create_or_get`, returning the report of `create_file_with_report`.
+    /// The report of a stored file lists no near-duplicates.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw byte array of the image file.
+    /// * `priority` - The priority lane used when waiting for a decode slot.
+    pub fn create_or_get_with_report(
+        &self,
+        bytes: &[u8],
+        priority: Priority,
+    ) -> Result<(CreateReport, bool), StorageError> {
+        self.or_existing(self.create_file_with_report(bytes, priority))
+    }
+
+    /// Creates and saves a new file into storage, reporting near-duplicate videos.
+    ///
+    /// Behaves like `create_file_with_priority`, and additionally returns the stored
+    /// videos that look like a new video if `with_video_near_duplicates` is enabled.
+    /// The report carries the path the file was stored at, so callers need not look
+    /// it up with `index_file` again.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw byte array of the image file.
+    /// * `priority` - The priority lane used when waiting for a decode slot.
+    pub fn create_file_with_report(
+        &self,
+        bytes: &[u8],
+        priority: Priority,
+    ) -> Result<CreateReport, StorageError> {
+        if bytes.is_empty() {
+            return Err(StorageError::EmptyInput);
+        }
+
+        // Wait for a likely identical upload before taking a decode slot, and skip
+        // the decode if it stored the same bytes.
+        let reservation = self.ingest_locks.as_deref().map(|l| l.reserve(bytes));
+        self.ingest(
+            reservation,
+            bytes,
+            bytes.len() as u64,
+            priority,
+            || match Media::new(bytes, self.auto_orient) {
+                Err(StorageError::UnsupportedFile { kind: Some(kind) }) if self.allow_raw => {
+                    Ok(Media::Raw {
+                        raw: bytes.to_vec(),
+                        extension: kind.extension().to_string(),
+                    })
+                }
+                media => media,
+            },
+        )
+    }
+
+    /// Decodes and saves an upload, once its ingest key is reserved.
+    ///
+    /// `head` holds at least the first bytes of the upload, which pick the admission lane,
+    /// and `size` is the length of the whole upload.
+    fn ingest(
+        &self,
+        reservation: Option<IngestReservation<'_>>,
+        head: &[u8],
+        size: u64,
+        priority: Priority,
+        decode: impl FnOnce() -> Result<Media, StorageError>,
+    ) -> Result<CreateReport, StorageError> {
+        if let Some(reservation) = &reservation
+            && let Some(hash) = reservation.previous_hash()
+            && let Some(entry) = self.find_entry(&hash)
+        {
+            reservation.skip_decode();
+            return Err(StorageError::HashCollision {
+                existing_path: entry.content_path().to_owned(),
+                hash,
+                digest: None,
+            });
+        }
+
+        let _permit = self.admit(head, priority)?;
+        let media = decode()?;
 
         // Compute an MD5 hash based on the image pixel data (RGBA).
         // This ensures that the file is uniquely identified by its visual content,
         // not its encoding or metadata differences.
-        let pixel_hash = match media {
-            Media::Video { ref thumbnail, .. } => compute_pixel_hash(thumbnail),
+        // Raw files have no pixels, so they are identified by their bytes instead.
+        let (pixel_hash, digest) = match media {
+            Media::Video { ref thumbnail, .. } => hash_pixels(thumbnail, self.hash_seed),
             Media::Image {
                 content: ref reader,
                 ..
-            } => compute_pixel_hash(reader),
+            } => hash_pixels(reader, self.hash_seed),
+            Media::Raw { ref raw, .. } => (
+                compute_content_hash(raw, self.hash_seed),
+                compute_digest(raw),
+            ),
+        };
+        let (phash, max_distance) = match media {
+            Media::Video { ref thumbnail, .. } => (
+                Some(PHash::from_image(thumbnail)),
+                self.video_near_duplicates,
+            ),
+            Media::Image { ref content, .. } => {
+                (Some(PHash::from_image(content)), self.image_near_duplicates)
+            }
+            Media::Raw { .. } => (None, None),
         };
+        if let Some(reservation) = &reservation {
+            reservation.record(pixel_hash.clone());
+        }
 
         // Based on the hash value, create a nested directory structure to improve file system indexing.
         // Example path: `/root_dir/12/34/1234567890abcdef1234567890abcdef.png`
@@ -95,39 +645,430 @@ impl Storage {
             return Err(StorageError::HashCollision {
                 existing_path: entry.content_path().to_owned(),
                 hash: pixel_hash,
+                digest: Some(digest),
             });
         }
 
+        self.check_quota(size)?;
+
         // Compose the filename as `{pixel_hash}.{extension}`,
         // and save the image using the guessed file format.
-        match media {
+        let rel_dir = self.derive_dir(&pixel_hash);
+        let path = match media {
             Media::Video {
                 raw,
                 thumbnail,
                 kind,
             } => {
-                let thumb_filename = self.derive_filename(&pixel_hash, "png");
-                let thumb_filepath = dir_path.join(thumb_filename);
-                thumbnail.save_with_format(thumb_filepath, ImageFormat::Png)?;
+                let thumb_dir_path = self.derive_abs_thumb_dir(&pixel_hash);
+                fs::create_dir_all(&thumb_dir_path)?;
+                let thumb_filename =
+                    self.derive_filename(&pixel_hash, self.thumbnail_format.extension());
+                let thumb = write_temp(&thumb_dir_path, |path| {
+                    self.thumbnail_format
+                        .save(&thumbnail, path.to_path_buf(), &self.format_options)
+                })?;
 
                 let video_filename = self.derive_filename(&pixel_hash, kind.extension());
-                let video_filepath = dir_path.join(video_filename);
-                fs::write(video_filepath, raw)?;
+                let video = match raw {
+                    VideoContent::Bytes(bytes) => {
+                        write_temp(&dir_path, |path| Ok(fs::write(path, bytes)?))?
+                    }
+                    VideoContent::Spooled(file) => file,
+                };
+
+                // Both files are complete before either is moved into place. The
+                // video goes last, as the entry is only complete with it.
+                thumb
+                    .persist(thumb_dir_path.join(&thumb_filename))
+                    .map_err(|e| e.error)?;
+                video
+                    .persist(dir_path.join(&video_filename))
+                    .map_err(|e| e.error)?;
+
+                MediaPath::Video {
+                    video: rel_dir.join(video_filename),
+                    thumb: rel_dir.join(thumb_filename),
+                }
+            }
+            Media::Image {
+                content,
+                kind,
+                exif,
+            } => {
+                let filename =
+                    self.save_image(&content, kind, exif.as_deref(), &dir_path, &pixel_hash)?;
+
+                MediaPath::Image(rel_dir.join(filename))
             }
-            Media::Image { content, kind } => {
-                let filename = self.derive_filename(&pixel_hash, kind.extension());
-                let filepath = dir_path.join(filename);
-                let format = ImageFormat::from_extension(kind.extension())
-                    .ok_or(StorageError::UnsupportedFile { kind: Some(kind) })?;
-                content.save_with_format(filepath, format)?;
+            Media::Raw { raw, extension } => {
+                let filename = self.derive_filename(&pixel_hash, &extension);
+                write_temp(&dir_path, |path| Ok(fs::write(path, raw)?))?
+                    .persist(dir_path.join(&filename))
+                    .map_err(|e| e.error)?;
+
+                MediaPath::Raw(rel_dir.join(filename))
             }
+        };
+        self.disk_usage.invalidate();
+
+        let near_duplicates = match (phash, max_distance) {
+            (Some(phash), Some(max_distance)) => self
+                .perceptual_index
+                .near_duplicates(&phash, max_distance, || self.scan_fingerprints())
+                .into_iter()
+                .filter(|n| n.hash != pixel_hash)
+                .collect(),
+            _ => vec![],
+        };
+        if let Some(phash) = phash {
+            self.perceptual_index.insert(pixel_hash.clone(), phash);
         }
 
-        Ok(pixel_hash)
+        Ok(CreateReport {
+            hash: pixel_hash,
+            path,
+            phash,
+            near_duplicates,
+            digest: Some(digest),
+        })
+    }
+
+    /// Reads a file from a stream and saves it into storage.
+    ///
+    /// Nothing is stored unless the stream is read to the end without errors and,
+    /// if `expected_len` is given, yields exactly that many bytes. This keeps an
+    /// interrupted upload from being stored as a truncated file.
+    ///
+    /// The stream is spooled to a temporary file under the storage root rather than
+    /// buffered in memory. Videos are renamed into place from there, so only images
+    /// are read back into memory to be decoded. The temporary file is removed if
+    /// anything fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream of the file's raw bytes.
+    /// * `expected_len` - The declared length of the file, if known.
+    ///
+    /// # Errors
+    /// - `StorageError::Truncated` if the stream ends before `expected_len` bytes,
+    ///   or yields more than that.
+    /// - `StorageError::Io` if reading the stream or spooling it fails.
+    /// - Any error of `create_file`.
+    pub fn create_file_from_reader<R: Read>(
+        &self,
+        reader: R,
+        expected_len: Option<u64>,
+    ) -> Result<PixelHash, StorageError> {
+        self.create_file_from_reader_with_report(reader, expected_len, Priority::Interactive)
+            .map(|report| report.hash)
+    }
+
+    /// Reads a file from a stream and saves it into storage, using the given
+    /// admission lane and reporting near-duplicate videos.
+    ///
+    /// Behaves like `create_file_from_reader`, see `create_file_with_report` for the
+    /// report.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream of the file's raw bytes.
+    /// * `expected_len` - The declared length of the file, if known.
+    /// * `priority` - The priority lane used when waiting for a decode slot.
+    pub fn create_file_from_reader_with_report<R: Read>(
+        &self,
+        mut reader: R,
+        expected_len: Option<u64>,
+        priority: Priority,
+    ) -> Result<CreateReport, StorageError> {
+        // Spool next to the stored files, so that a video is moved into place by a rename.
+        fs::create_dir_all(&self.root_path)?;
+        let mut spool = NamedTempFile::new_in(&self.root_path)?;
+        let received = io::copy(&mut reader, spool.as_file_mut())?;
+
+        self.create_file_from_spool(spool, received, expected_len, priority)
+    }
+
+    /// Reads a file from an asynchronous stream and saves it into storage.
+    ///
+    /// Behaves like `create_file_from_reader_with_report`, but spools the stream
+    /// without blocking. The file is decoded once the stream has ended. Dropping the
+    /// returned future removes the temporary file.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream of the file's raw bytes.
+    /// * `expected_len` - The declared length of the file, if known.
+    /// * `priority` - The priority lane used when waiting for a decode slot.
+    pub async fn create_file_from_async_reader<R: AsyncRead + Unpin>(
+        &self,
+        mut reader: R,
+        expected_len: Option<u64>,
+        priority: Priority,
+    ) -> Result<CreateReport, StorageError> {
+        tokio::fs::create_dir_all(&self.root_path).await?;
+        let spool = NamedTempFile::new_in(&self.root_path)?;
+        let mut file = tokio::fs::File::from_std(spool.as_file().try_clone()?);
+        let received = tokio::io::copy(&mut reader, &mut file).await?;
+        file.flush().await?;
+        drop(file);
+
+        self.run_blocking(move |storage| {
+            storage.create_file_from_spool(spool, received, expected_len, priority)
+        })
+        .await
+    }
+
+    /// Reads a file from an asynchronous stream and saves it into storage, or reports
+    /// the stored file with the same visual content.
+    ///
+    /// Behaves like `create_file_from_async_reader`, see `create_or_get_with_report`.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The stream of the file's raw bytes.
+    /// * `expected_len` - The declared length of the file, if known.
+    /// * `priority` - The priority lane used when waiting for a decode slot.
+    pub async fn create_or_get_from_async_reader<R: AsyncRead + Unpin>(
+        &self,
+        reader: R,
+        expected_len: Option<u64>,
+        priority: Priority,
+    ) -> Result<(CreateReport, bool), StorageError> {
+        self.or_existing(
+            self.create_file_from_async_reader(reader, expected_len, priority)
+                .await,
+        )
+    }
+
+    /// Saves a new file into storage, or reports the stored file with the same visual
+    /// content, without blocking the async runtime.
+    ///
+    /// Behaves like `create_or_get_with_report`, but decodes on the blocking thread
+    /// pool, as waiting for a decode slot or a likely identical upload blocks the
+    /// thread, see `AdmissionController::acquire`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw byte array of the image file.
+    /// * `priority` - The priority lane used when waiting for a decode slot.
+    pub async fn create_or_get_async(
+        &self,
+        bytes: Vec<u8>,
+        priority: Priority,
+    ) -> Result<(CreateReport, bool), StorageError> {
+        self.run_blocking(move |storage| storage.create_or_get_with_report(&bytes, priority))
+            .await
+    }
+
+    /// Runs blocking work with a clone of the storage on the blocking thread pool,
+    /// resuming its panic if it panics.
+    pub(crate) async fn run_blocking<T, F>(&self, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(Storage) -> T + Send + 'static,
+    {
+        let storage = self.clone();
+        tokio::task::spawn_blocking(move || f(storage))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+
+    /// Turns a `HashCollision` into the report of the stored file.
+    fn or_existing(
+        &self,
+        result: Result<CreateReport, StorageError>,
+    ) -> Result<(CreateReport, bool), StorageError> {
+        match result {
+            Ok(report) => Ok((report, true)),
+            Err(StorageError::HashCollision { hash, digest, .. }) => {
+                let path = self
+                    .index_file(&hash)
+                    .ok_or(StorageError::FileNotFound { hash: hash.clone() })?;
+                let phash = self.get_phash(&hash)?;
+
+                Ok((
+                    CreateReport {
+                        hash,
+                        path,
+                        phash,
+                        near_duplicates: vec![],
+                        digest,
+                    },
+                    false,
+                ))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Saves a file spooled by `create_file_from_reader_with_report` or
+    /// `create_file_from_async_reader`.
+    fn create_file_from_spool(
+        &self,
+        spool: NamedTempFile,
+        received: u64,
+        expected_len: Option<u64>,
+        priority: Priority,
+    ) -> Result<CreateReport, StorageError> {
+        if let Some(expected) = expected_len
+            && expected != received
+        {
+            return Err(StorageError::Truncated { expected, received });
+        }
+        if received == 0 {
+            return Err(StorageError::EmptyInput);
+        }
+
+        let mut head = vec![];
+        spool.reopen()?.take(SNIFF_LEN).read_to_end(&mut head)?;
+        let kind = match infer::get(&head) {
+            Some(kind) if kind.matcher_type() == infer::MatcherType::Video => kind,
+            // Images are decoded in memory anyway.
+            _ => return self.create_file_with_report(&fs::read(spool.path())?, priority),
+        };
+
+        let reservation = match self.ingest_locks.as_deref() {
+            Some(locks) => Some(locks.reserve_reader(spool.reopen()?, received)?),
+            None => None,
+        };
+        self.ingest(reservation, &head, received, priority, || {
+            Ok(Media::Video {
+                thumbnail: thumbnail_from_path(spool.path())?,
+                raw: VideoContent::Spooled(spool),
+                kind,
+            })
+        })
+    }
+
+    /// Saves an image under a precomputed pixel hash without decoding it.
+    ///
+    /// This is a trusted API for migrating between instances, where the hash of every
+    /// file is already known. The bytes are only sniffed to check that they match
+    /// `kind`, and stored as-is at the path derived from `hash`. Debug builds decode the
+    /// image and assert that the hash is correct.
+    ///
+    /// # Risks
+    ///
+    /// A wrong hash is not detected in release builds. The file then cannot be found
+    /// by its visual content, and a later upload of the same image is stored again
+    /// under its real hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw byte array of the image file.
+    /// * `hash` - The pixel hash of the image, as computed by `create_file`.
+    /// * `kind` - The file type of the image, which determines the extension.
+    ///
+    /// # Errors
+    /// - `StorageError::HashCollision` if a file with the same pixel hash already exists.
+    /// - `StorageError::EmptyInput` if `bytes` is empty.
+    /// - `StorageError::UnsupportedFile` if `kind` is not a supported image format,
+    ///   or if the bytes are not of type `kind`. Videos, animated GIFs and WebPs, and
+    ///   camera RAW files are rejected, since their thumbnail can only be generated
+    ///   by decoding.
+    /// - `StorageError::Io` if directory creation or file writing fails.
+    /// - `StorageError::QuotaExceeded` if the file does not fit within `with_max_bytes`.
+    pub fn create_file_with_hash(
+        &self,
+        bytes: &[u8],
+        hash: &PixelHash,
+        kind: infer::Type,
+    ) -> Result<(), StorageError> {
+        if bytes.is_empty() {
+            return Err(StorageError::EmptyInput);
+        }
+
+        if kind.matcher_type() != infer::MatcherType::Image
+            || ImageFormat::from_extension(kind.extension()).is_none()
+        {
+            return Err(StorageError::UnsupportedFile { kind: Some(kind) });
+        }
+
+        let detected = infer::get(bytes);
+        if detected != Some(kind) || is_stored_as_video(bytes, kind)? {
+            return Err(StorageError::UnsupportedFile { kind: detected });
+        }
+
+        // Lossily encoded files no longer decode to the pixels the hash was computed from.
+        #[cfg(debug_assertions)]
+        if ImageFormat::from_extension(kind.extension()).is_some_and(|f| !is_lossy(f, bytes))
+            && let Ok(img) = image::load_from_memory(bytes)
+        {
+            debug_assert_eq!(
+                *hash,
+                compute_pixel_hash(&img, self.hash_seed),
+                "precomputed hash is wrong"
+            );
+        }
+
+        let dir_path = self.derive_abs_dir(hash);
+        fs::create_dir_all(&dir_path)?;
+
+        if let Some(entry) = self.find_entry(hash) {
+            return Err(StorageError::HashCollision {
+                existing_path: entry.content_path().to_owned(),
+                hash: hash.clone(),
+                digest: None,
+            });
+        }
+
+        self.check_quota(bytes.len() as u64)?;
+        write_temp(&dir_path, |path| Ok(fs::write(path, bytes)?))?
+            .persist(dir_path.join(self.derive_filename(hash, kind.extension())))
+            .map_err(|e| e.error)?;
+        self.disk_usage.invalidate();
+
+        // Only a loaded index misses the new file, so only then is it decoded.
+        if self.perceptual_index.is_loaded()
+            && let Ok(img) = image::load_from_memory(bytes)
+        {
+            self.perceptual_index
+                .insert(hash.clone(), PHash::from_image(&img));
+        }
+
+        Ok(())
+    }
+
+    /// Saves a file as a raw file with the given extension, without detecting its type.
+    ///
+    /// This lets trusted callers such as `restore` keep files that `with_raw_files`
+    /// rejects, as their type cannot be detected. Like other raw files, they are never
+    /// decoded and their hash is computed from their bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The raw byte array of the file.
+    /// * `extension` - The extension the file is stored with, without the leading dot.
+    /// * `priority` - The priority lane used when waiting for a slot.
+    ///
+    /// # Errors
+    /// - `StorageError::HashCollision` if a file with the same hash already exists.
+    /// - `StorageError::EmptyInput` if `bytes` is empty.
+    pub fn create_raw_file(
+        &self,
+        bytes: &[u8],
+        extension: &str,
+        priority: Priority,
+    ) -> Result<PixelHash, StorageError> {
+        if bytes.is_empty() {
+            return Err(StorageError::EmptyInput);
+        }
+
+        let reservation = self.ingest_locks.as_deref().map(|l| l.reserve(bytes));
+        self.ingest(reservation, bytes, bytes.len() as u64, priority, || {
+            Ok(Media::Raw {
+                raw: bytes.to_vec(),
+                extension: extension.to_string(),
+            })
+        })
+        .map(|report| report.hash)
     }
 
     /// Returns the relative path of a stored file based on its hash, if it exists.
     ///
+    /// Thumbnails are relative to the thumbnail root if one is configured.
+    ///
     /// # Arguments
     /// * `hash` - The pixel hash to locate.
     ///
@@ -140,6 +1081,10 @@ impl Storage {
                 self.derive_dir(hash)
                     .join(path_buf.file_name().expect("Failed to get file name")),
             ),
+            MediaPath::Raw(path_buf) => MediaPath::Raw(
+                self.derive_dir(hash)
+                    .join(path_buf.file_name().expect("Failed to get file name")),
+            ),
             MediaPath::Video { video, thumb } => MediaPath::Video {
                 video: self
                     .derive_dir(hash)
@@ -153,7 +1098,7 @@ impl Storage {
 
     /// Ensures that the file associated with the given pixel hash does not exist.
     ///
-    /// If the file exists, it is deleted.
+    /// If the file exists, it is deleted along with its generated variants.
     /// If the file does not exist, this function still succeeds.
     ///
     /// # Arguments
@@ -163,18 +1108,45 @@ impl Storage {
     /// * `Ok(())` if the file does not exist after the call.
     /// * `Err(StorageError::FilesystemError)` if an unexpected I/O error occurs.
     pub fn ensure_deleted(&self, hash: &PixelHash) -> Result<(), StorageError> {
+        self.delete_variants(hash)?;
+
         if let Some(path) = self.find_entry(hash) {
             match path {
-                MediaPath::Image(path_buf) => fs::remove_file(path_buf)?,
+                MediaPath::Image(path_buf) | MediaPath::Raw(path_buf) => fs::remove_file(path_buf)?,
                 MediaPath::Video { video, thumb } => {
                     fs::remove_file(video)?;
                     fs::remove_file(thumb)?;
                 }
             }
+            self.perceptual_index.remove(hash);
+            self.disk_usage.invalidate();
         }
         Ok(())
     }
 
+    /// Removes the directories left empty under the storage roots, e.g. shard
+    /// directories after `ensure_deleted`.
+    ///
+    /// Empty leaf directories are removed first, then the parents they leave empty,
+    /// up to but not including the roots. Directories that hold files, or that are
+    /// removed by a concurrent call, are skipped. A shard directory may be removed
+    /// while an upload into it is just starting, which then fails with
+    /// `StorageError::Io`, so this is best run while no files are being stored.
+    ///
+    /// # Returns
+    /// * `Ok(count)` - The number of removed directories.
+    /// * `Err(StorageError::Io)` if a directory cannot be listed or removed.
+    pub fn remove_empty_dirs(&self) -> Result<usize, StorageError> {
+        let mut removed = remove_empty_subdirs(&self.root_path)?;
+        if let Some(thumbnail_root) = &self.thumbnail_root
+            && !thumbnail_root.starts_with(&self.root_path)
+        {
+            removed += remove_empty_subdirs(thumbnail_root)?;
+        }
+
+        Ok(removed)
+    }
+
     /// Retrieves metadata for an image file associated with a given pixel hash.
     ///
     /// This function attempts to locate the image file corresponding to the provided
@@ -200,48 +1172,286 @@ impl Storage {
         let entry = self
             .find_entry(hash)
             .ok_or(StorageError::FileNotFound { hash: hash.clone() })?;
-        let file_path = match &entry {
-            MediaPath::Image(path_buf) => path_buf,
-            MediaPath::Video { thumb, .. } => thumb,
-        };
 
-        let bytes = std::fs::read(file_path)?;
-        let extension = match &entry {
-            MediaPath::Image(path_buf) => path_buf.extension(),
-            MediaPath::Video { video, .. } => video.extension(),
-        }
-        .expect("filepath must have a extention");
+        metadata::extract(&entry, self.created_at_fallback)
+    }
+
+    /// Computes the perceptual hash of a stored file.
+    ///
+    /// Videos are hashed by their thumbnail, like in `create_file`. Raw files have
+    /// no pixels to hash, so `None` is returned for them.
+    ///
+    /// # Arguments
+    /// * `hash` - A reference to the `PixelHash` identifying the stored file.
+    ///
+    /// # Errors
+    /// - `StorageError::FileNotFound` if no file is located for the given hash.
+    /// - `StorageError::Image` if the image or thumbnail cannot be decoded.
+    pub fn get_phash(&self, hash: &PixelHash) -> Result<Option<PHash>, StorageError> {
+        let still = match self
+            .find_entry(hash)
+            .ok_or(StorageError::FileNotFound { hash: hash.clone() })?
+        {
+            MediaPath::Image(path) => image::open(path)?,
+            MediaPath::Video { thumb, .. } => image::open(thumb)?,
+            MediaPath::Raw(_) => return Ok(None),
+        };
+
+        Ok(Some(PHash::from_image(&still)))
+    }
+
+    /// Recomputes the pixel hash of a stored file from its content.
+    ///
+    /// Videos are hashed by a thumbnail generated from the video, like in `create_file`,
+    /// and animated GIFs by their middle frame.
+    /// Camera RAW files are decoded again, which needs the `raw-images` feature.
+    /// Raw files are hashed by their bytes.
+    /// Images in a lossy format (JPEG, AVIF, GIF or lossy WebP) were re-encoded when
+    /// stored, so their hash cannot be reproduced from the stored file, and `None` is
+    /// returned.
+    ///
+    /// # Arguments
+    /// * `hash` - A reference to the `PixelHash` the file is stored under.
+    ///
+    /// # Errors
+    /// - `StorageError::FileNotFound` if no file is located for the given hash.
+    /// - `StorageError::Image` if the image cannot be decoded.
+    /// - `StorageError::Video` or `StorageError::Thumbnail` if the video cannot be decoded.
+    pub fn recompute_hash(&self, hash: &PixelHash) -> Result<Option<PixelHash>, StorageError> {
+        let still = match self
+            .find_entry(hash)
+            .ok_or(StorageError::FileNotFound { hash: hash.clone() })?
+        {
+            MediaPath::Video { video, .. } if is_animation(&video) => {
+                match decode_animation(&fs::read(&video)?)? {
+                    Some(animation) => animation.middle_frame,
+                    None => return Ok(None),
+                }
+            }
+            #[cfg(feature = "raw-images")]
+            MediaPath::Video { video, .. } if camera_raw::is_raw_file(&video) => {
+                camera_raw::decode(&fs::read(&video)?)
+                    .ok_or_else(|| StorageError::Thumbnail {
+                        reason: "Failed to decode the RAW image".to_string(),
+                    })?
+                    .0
+            }
+            MediaPath::Video { video, .. } => thumbnail_from_path(&video)?,
+            MediaPath::Raw(path) => {
+                return Ok(Some(compute_content_hash(&fs::read(path)?, self.hash_seed)));
+            }
+            MediaPath::Image(video) if !is_still_image(&video) => thumbnail_from_path(&video)?,
+            MediaPath::Image(path) => {
+                let bytes = fs::read(&path)?;
+                match ImageFormat::from_path(&path) {
+                    Ok(format) if is_lossy(format, &bytes) => return Ok(None),
+                    _ => image::load_from_memory(&bytes)?,
+                }
+            }
+        };
+
+        Ok(Some(compute_pixel_hash(&still, self.hash_seed)))
+    }
+
+    /// Finds stored files whose hash starts with the given prefix.
+    ///
+    /// Only the directories the prefix can map to are listed, so longer prefixes are
+    /// cheaper. At most `MAX_PREFIX_MATCHES` hashes are returned, in ascending order.
+    ///
+    /// # Arguments
+    /// * `prefix` - The hex prefix to match.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<PixelHash>)` - The matching hashes, possibly empty.
+    /// * `Err(StorageError::Io)` - If the storage directory cannot be listed.
+    pub fn find_by_hash_prefix(&self, prefix: &HashPrefix) -> Result<Vec<PixelHash>, StorageError> {
+        // Unknown characters of the directory levels match any character.
+        let levels = self.sharding.levels as usize;
+        let padded = format!("{:?<width$}", prefix.as_str(), width = 2 * levels);
+        let pattern = (0..levels)
+            .fold(self.root_path.clone(), |dir, level| {
+                dir.join(&padded[2 * level..2 * level + 2])
+            })
+            .join(format!("{}*.*", prefix));
+
+        let hashes: BTreeSet<PixelHash> = glob(&pattern.to_string_lossy())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+            .filter_map(Result::ok)
+            .filter_map(|path| {
+                let stem = path.file_stem()?.to_str()?.to_string();
+                PixelHash::try_from(stem).ok()
+            })
+            .collect();
+
+        Ok(hashes.into_iter().take(MAX_PREFIX_MATCHES).collect())
+    }
+
+    /// Lists the hashes of all stored files, in ascending order.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<PixelHash>)` - The stored hashes, possibly empty.
+    /// * `Err(StorageError::Io)` - If the storage directory cannot be listed.
+    pub fn list_hashes(&self) -> Result<Vec<PixelHash>, StorageError> {
+        // Shard directories are two hex digits, unlike the directories of variants.
+        let pattern = (0..self.sharding.levels)
+            .fold(self.root_path.clone(), |dir, _| {
+                dir.join("[0-9a-f][0-9a-f]")
+            })
+            .join("*.*");
+
+        let hashes: BTreeSet<PixelHash> = glob(&pattern.to_string_lossy())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?
+            .filter_map(Result::ok)
+            .filter_map(|path| PixelHash::try_from(path.file_stem()?.to_str()?).ok())
+            .collect();
+
+        Ok(hashes.into_iter().collect())
+    }
+
+    /// Iterates over the hashes of all stored files, without duplicates and in
+    /// ascending order, which is the lexicographic order of their names.
+    ///
+    /// Unlike `list_hashes`, this walks the shard directories and never fails.
+    /// Directories that cannot be read, and files that are not named after a pixel
+    /// hash, are skipped with a warning. Hidden files, such as the temporary files of
+    /// writes in progress, are skipped silently.
+    pub fn list_all(&self) -> impl Iterator<Item = PixelHash> {
+        let mut dirs = vec![self.root_path.clone()];
+        for _ in 0..self.sharding.levels {
+            // Shard directories are two hex digits, unlike the directories of variants.
+            dirs = dirs
+                .iter()
+                .flat_map(|dir| read_dir_logged(dir))
+                .filter(|path| {
+                    path.is_dir()
+                        && path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+                            n.len() == 2
+                                && n.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+                        })
+                })
+                .collect();
+        }
+
+        let hashes: BTreeSet<PixelHash> = dirs
+            .iter()
+            .flat_map(|dir| read_dir_logged(dir))
+            .filter(|path| path.is_file())
+            .filter_map(|path| {
+                let name = path.file_name()?.to_string_lossy();
+                if name.starts_with('.') {
+                    return None;
+                }
+                let hash = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| PixelHash::try_from(stem).ok());
+                if hash.is_none() {
+                    tracing::warn!(
+                        path = %path.display(),
+                        "skipping a file not named after a pixel hash"
+                    );
+                }
+                hash
+            })
+            .collect();
+
+        hashes.into_iter()
+    }
+
+    /// Lists the hashes of stored videos whose thumbnail is missing, in ascending order.
+    ///
+    /// # Returns
+    /// * `Ok(Vec<PixelHash>)` - The hashes of videos without a thumbnail, possibly empty.
+    /// * `Err(StorageError::Io)` - If the storage directory cannot be listed.
+    pub fn missing_thumbnails(&self) -> Result<Vec<PixelHash>, StorageError> {
+        Ok(self
+            .list_hashes()?
+            .into_iter()
+            .filter(|hash| match self.find_entry(hash) {
+                Some(MediaPath::Image(path)) => !is_still_image(&path),
+                _ => false,
+            })
+            .collect())
+    }
+
+    /// Reads the stored file of the given hash, the video itself for videos.
+    ///
+    /// # Errors
+    /// - `StorageError::FileNotFound` if no file is located for the given hash.
+    /// - `StorageError::Io` if the file cannot be read.
+    pub fn read_file(&self, hash: &PixelHash) -> Result<Vec<u8>, StorageError> {
+        let entry = self
+            .find_entry(hash)
+            .ok_or(StorageError::FileNotFound { hash: hash.clone() })?;
+
+        Ok(fs::read(entry.content_path())?)
+    }
 
-        let img = image::load_from_memory(&bytes)?;
-        let (width, height) = img.dimensions();
-        let color_type = format!("{:?}", img.color());
+    /// Finds stored entries that look like a stored one, closest first.
+    ///
+    /// Entries are compared by the Hamming distance between their perceptual hashes,
+    /// see `PHash`. Videos are compared by their thumbnails. The entry itself is not
+    /// included, and an unknown hash has no similar entries.
+    ///
+    /// Every stored entry is decoded and hashed on the first call, or on the first
+    /// upload reporting near-duplicates, whichever comes first.
+    ///
+    /// # Arguments
+    /// * `hash` - The pixel hash of the stored entry.
+    /// * `distance` - The maximum Hamming distance (0-64), e.g. 8.
+    pub fn find_similar(&self, hash: &PixelHash, distance: u32) -> Vec<PixelHash> {
+        self.perceptual_index
+            .similar_to(hash, distance, || self.scan_fingerprints())
+            .into_iter()
+            .map(|n| n.hash)
+            .collect()
+    }
 
-        let metadata = std::fs::metadata(file_path)?;
-        let created_at = metadata.created().map(DateTime::from).ok();
-        let file_size = metadata.len();
+    /// Acquires a decode slot for the given bytes if admission control is configured.
+    fn admit(
+        &self,
+        bytes: &[u8],
+        priority: Priority,
+    ) -> Result<Option<AdmissionPermit<'_>>, StorageError> {
+        let Some(controller) = self.admission.as_deref() else {
+            return Ok(None);
+        };
 
-        let duration = match &entry {
-            MediaPath::Image(_) => None,
-            MediaPath::Video { video, .. } => {
-                Some(Decoder::new(video.as_path())?.duration()?.as_secs_f64())
-            }
+        let kind = match infer::get(bytes).map(|k| k.matcher_type()) {
+            Some(infer::MatcherType::Video) => WorkKind::VideoThumbnail,
+            _ => WorkKind::ImageDecode,
         };
 
-        Ok(ImageMetadata {
-            width,
-            height,
-            format: extension.to_string_lossy().to_string(),
-            color_type,
-            file_size,
-            created_at,
-            duration,
-        })
+        controller.acquire(kind, priority).map(Some)
+    }
+
+    /// Computes the perceptual hashes of all stored images and video thumbnails.
+    ///
+    /// Raw files and entries whose still image cannot be decoded are skipped.
+    fn scan_fingerprints(&self) -> HashMap<PixelHash, PHash> {
+        self.list_hashes()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|hash| {
+                let still = match self.find_entry(&hash)? {
+                    MediaPath::Video { thumb, .. } => image::open(thumb),
+                    MediaPath::Image(path) => image::open(path),
+                    MediaPath::Raw(_) => return None,
+                };
+                Some((hash, PHash::from_image(&still.ok()?)))
+            })
+            .collect()
     }
 
     /// Derives a relative directory path from the hash (for indexing).
-    /// Example: `01/23/`
+    /// Example: `01/23/` with the default sharding.
     fn derive_dir(&self, hash: &PixelHash) -> PathBuf {
-        PathBuf::from(format!("{:02x}/{:02x}/", hash.0[0], hash.0[1]))
+        PathBuf::from(
+            hash.0[..self.sharding.levels as usize]
+                .iter()
+                .map(|b| format!("{:02x}/", b))
+                .collect::<String>(),
+        )
     }
 
     /// Derives the absolute directory path on the filesystem.
@@ -249,6 +1459,14 @@ impl Storage {
         self.root_path.join(self.derive_dir(hash))
     }
 
+    /// Derives the absolute directory path of thumbnails on the filesystem.
+    fn derive_abs_thumb_dir(&self, hash: &PixelHash) -> PathBuf {
+        self.thumbnail_root
+            .as_ref()
+            .unwrap_or(&self.root_path)
+            .join(self.derive_dir(hash))
+    }
+
     /// Generates a filename based on the hash and extension.
     fn derive_filename(&self, hash: &PixelHash, ext: &str) -> PathBuf {
         let hash_str: String = hash.clone().into();
@@ -256,6 +1474,47 @@ impl Storage {
         PathBuf::from(format!("{}.{}", hash_str, ext))
     }
 
+    /// Encodes a decoded image into `dir`, returning the name of the written file.
+    ///
+    /// Images keep their original format unless `with_transcode` is set. The EXIF data
+    /// of the upload, if any, is embedded into stored JPEGs.
+    fn save_image(
+        &self,
+        content: &DynamicImage,
+        kind: infer::Type,
+        exif: Option<&[u8]>,
+        dir: &Path,
+        hash: &PixelHash,
+    ) -> Result<PathBuf, StorageError> {
+        #[cfg(feature = "webp")]
+        if let Some(transcode) = &self.transcode {
+            let filename = self.derive_filename(hash, transcode.format.extension());
+            let encoded = transcode.encode(content)?;
+            write_temp(dir, |path| Ok(fs::write(path, encoded)?))?
+                .persist(dir.join(&filename))
+                .map_err(|e| e.error)?;
+            return Ok(filename);
+        }
+
+        let filename = self.derive_filename(hash, kind.extension());
+        let format = ImageFormat::from_extension(kind.extension())
+            .ok_or(StorageError::UnsupportedFile { kind: Some(kind) })?;
+        write_temp(dir, |path| {
+            self.format_options.save(content, path, format)?;
+            if format == ImageFormat::Jpeg
+                && let Some(exif) = exif
+                && let Some(bytes) = encoding::embed_jpeg_exif(&fs::read(path)?, exif)
+            {
+                fs::write(path, bytes)?;
+            }
+            Ok(())
+        })?
+        .persist(dir.join(&filename))
+        .map_err(|e| e.error)?;
+
+        Ok(filename)
+    }
+
     /// Searches for a file matching the hash (with any extension).
     fn find_entry(&self, hash: &PixelHash) -> Option<MediaPath> {
         let dir = self.derive_abs_dir(hash);
@@ -264,17 +1523,40 @@ impl Storage {
 
         let mut entries: Vec<_> = glob(&glob_pattern).ok()?.filter_map(Result::ok).collect();
 
+        if self.thumbnail_root.is_some() {
+            let thumb_dir = self.derive_abs_thumb_dir(hash);
+            let filename: String = hash.clone().into();
+            let glob_pattern = format!("{}.*", thumb_dir.join(filename).to_string_lossy());
+            entries.extend(glob(&glob_pattern).ok()?.filter_map(Result::ok));
+            entries.sort();
+            entries.dedup();
+        }
+
         match entries.len() {
-            1 => entries.pop().map(MediaPath::Image),
+            1 => entries.pop().map(|path| {
+                // A single file is an image, a video whose thumbnail is missing, or a
+                // raw file, which is told apart from a video by its content.
+                let is_raw = !is_still_image(&path)
+                    && infer::get_from_path(&path)
+                        .ok()
+                        .flatten()
+                        .is_none_or(|kind| kind.matcher_type() != infer::MatcherType::Video);
+                if is_raw {
+                    MediaPath::Raw(path)
+                } else {
+                    MediaPath::Image(path)
+                }
+            }),
             2 => {
-                // .png とそうでない方を振り分ける
+                // The thumbnail is the still image, the other entry is the video. An
+                // animated GIF or WebP is the video next to a thumbnail of another
+                // format.
                 let (a, b) = (entries.pop()?, entries.pop()?);
-                let (video, thumb) = match (
-                    a.extension().and_then(|e| e.to_str()),
-                    b.extension().and_then(|e| e.to_str()),
-                ) {
-                    (Some("png"), _) => (b, a),
-                    (_, Some("png")) => (a, b),
+                let (video, thumb) = match (is_still_image(&a), is_still_image(&b)) {
+                    (true, false) => (b, a),
+                    (false, true) => (a, b),
+                    (true, true) if is_animation(&a) && !is_animation(&b) => (a, b),
+                    (true, true) if is_animation(&b) && !is_animation(&a) => (b, a),
                     _ => return None,
                 };
 
@@ -285,6 +1567,126 @@ impl Storage {
     }
 }
 
+/// Writes a file into a temporary file in `dir`, to be moved to its final name with
+/// `persist` once complete.
+///
+/// Stored files are found by name alone, so a partial file left under its final name
+/// by an interrupted write would pass for a stored one. A rename within a directory
+/// is atomic, so the final name only ever holds complete files.
+fn write_temp(
+    dir: &Path,
+    write: impl FnOnce(&Path) -> Result<(), StorageError>,
+) -> Result<NamedTempFile, StorageError> {
+    let file = NamedTempFile::new_in(dir)?;
+    write(file.path())?;
+
+    Ok(file)
+}
+
+/// Removes the empty directories below `dir`, deepest first, but not `dir` itself.
+fn remove_empty_subdirs(dir: &Path) -> Result<usize, io::Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = 0;
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        removed += remove_empty_subdirs(&path)?;
+        match fs::remove_dir(&path) {
+            Ok(()) => removed += 1,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::DirectoryNotEmpty | io::ErrorKind::NotFound
+                ) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Lists the paths of the entries of a directory, skipping it with a warning if it
+/// cannot be read.
+fn read_dir_logged(dir: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry.path()),
+                Err(e) => {
+                    tracing::warn!(
+                        dir = %dir.display(),
+                        error = %e,
+                        "skipping an unreadable entry"
+                    );
+                    None
+                }
+            })
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+        Err(e) => {
+            tracing::warn!(
+                dir = %dir.display(),
+                error = %e,
+                "skipping an unreadable directory"
+            );
+            vec![]
+        }
+    }
+}
+
+/// Returns whether the path has the extension of a still image format.
+fn is_still_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .and_then(ImageFormat::from_extension)
+        .is_some()
+}
+
+/// Returns whether an image file of the given format was encoded lossily, so its
+/// pixel hash cannot be reproduced from the file.
+fn is_lossy(format: ImageFormat, bytes: &[u8]) -> bool {
+    match format {
+        ImageFormat::Jpeg | ImageFormat::Avif | ImageFormat::Gif => true,
+        ImageFormat::WebP => is_lossy_webp(bytes),
+        _ => false,
+    }
+}
+
+/// Returns whether a WebP file holds lossy VP8 data, rather than only lossless VP8L.
+fn is_lossy_webp(bytes: &[u8]) -> bool {
+    // The RIFF header is followed by chunks of a FourCC, a little-endian size and
+    // the data, padded to an even length.
+    let mut offset = 12;
+    while let Some(header) = bytes.get(offset..offset + 8) {
+        if &header[..4] == b"VP8 " {
+            return true;
+        }
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        offset += 8 + size + size % 2;
+    }
+
+    false
+}
+
+/// Returns whether the path has the extension of a format that may be animated.
+fn is_animation(path: &Path) -> bool {
+    matches!(
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(ImageFormat::from_extension),
+        Some(ImageFormat::Gif | ImageFormat::WebP)
+    )
+}
+
 /// Contains metadata about an image stored within the storage system.
 ///
 /// The `ImageMetadata` struct provides detailed information about an image
@@ -301,9 +1703,15 @@ impl Storage {
 /// - `color_type`: A string describing the color type or model the image uses
 ///   (e.g., RGB, Grayscale).
 /// - `file_size`: The size of the image file in bytes.
-/// - `created_at`: An optional timestamp representing when the file was
-///   originally created on the filesystem. It may be `None` if the timestamp
-///   is unavailable or unsupported on the platform.
+/// - `created_at`: An optional timestamp representing when the image was
+///   taken according to its EXIF data, or else when the file was originally
+///   created on the filesystem. It may be `None` if neither is available.
+/// - `exif`: Camera details embedded in the image, if it carries EXIF data.
+/// - `orientation`: The EXIF orientation, from 1 to 8, which clients apply when
+///   displaying the image. Images are stored upright with an orientation of 1,
+///   unless `Storage::with_auto_orient` is disabled.
+/// - `digest`: The content digest of the upload, see `CreateReport::digest`. It is not
+///   read from stored files, and unknown for images archived before it was recorded.
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct ImageMetadata {
     pub width: u32,
@@ -312,21 +1720,63 @@ pub struct ImageMetadata {
     pub color_type: String,
     pub file_size: u64,
 
-    /// Filesystem-based creation timestamp
+    /// EXIF capture timestamp, or else filesystem-based creation timestamp
     pub created_at: Option<DateTime<Utc>>,
 
     pub duration: Option<f64>,
+
+    /// Boxed, as most files carry none and the metadata is passed around a lot
+    pub exif: Option<Box<ExifData>>,
+
+    /// EXIF orientation, where 1 is upright and 2 to 8 are flips and rotations
+    pub orientation: Option<u8>,
+
+    pub digest: Option<String>,
+}
+
+/// Camera details read from the EXIF data of JPEG, TIFF, HEIC, PNG and WebP files.
+///
+/// Every field is optional, as cameras and editors record different subsets.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ExifData {
+    /// The manufacturer of the camera, e.g. `Canon`.
+    pub camera_make: Option<String>,
+    /// The model of the camera.
+    pub camera_model: Option<String>,
+    /// The latitude in decimal degrees, negative in the southern hemisphere.
+    pub gps_lat: Option<f64>,
+    /// The longitude in decimal degrees, negative west of Greenwich.
+    pub gps_lon: Option<f64>,
+    /// When the picture was taken. Times without an offset are taken as UTC.
+    pub taken_at: Option<DateTime<Utc>>,
+    /// The exposure time in seconds.
+    pub exposure: Option<f64>,
 }
 
 /// Errors that can occur during storage operations.
 #[derive(Debug, Error)]
 pub enum StorageError {
+    /// `digest` is the content digest of the upload, see `CreateReport::digest`, if it
+    /// was decoded.
     #[error("Same pixel hash already exists: {hash:}")]
     HashCollision {
         existing_path: PathBuf,
         hash: PixelHash,
+        digest: Option<String>,
+    },
+
+    /// A different image than the stored one has the same pixel hash, as their content
+    /// digests differ. The upload is not stored.
+    #[error("Pixel hash {hash:} is taken by a different image")]
+    TrueCollision {
+        existing_path: PathBuf,
+        hash: PixelHash,
     },
 
+    #[error("Input is empty")]
+    EmptyInput,
+
+    /// The format is unsupported, or unrecognizable when `kind` is `None`.
     #[error("Unsupported or undetectable file format: {kind:?}")]
     UnsupportedFile { kind: Option<infer::Type> },
 
@@ -344,6 +1794,19 @@ pub enum StorageError {
 
     #[error("Thumbnail generation failure: {reason:}")]
     Thumbnail { reason: String },
+
+    #[error("Media processing is saturated, retry after {retry_after_hint:?}")]
+    Busy { retry_after_hint: Duration },
+
+    /// Storing the file would take more disk space than `Storage::with_max_bytes` allows.
+    #[error("Storage quota exceeded: {current_bytes} of {limit_bytes} bytes used")]
+    QuotaExceeded {
+        current_bytes: u64,
+        limit_bytes: u64,
+    },
+
+    #[error("Input ended after {received} of {expected} bytes")]
+    Truncated { expected: u64, received: u64 },
 }
 
 /// Represents a 8-byte hash.
@@ -439,6 +1902,44 @@ pub enum PixelHashParseError {
     InvalidHex,
 }
 
+/// The maximum number of hashes returned by a prefix lookup.
+///
+/// A short prefix can match a large part of the archive, so lookups stop here.
+pub const MAX_PREFIX_MATCHES: usize = 100;
+
+/// A lowercase hexadecimal prefix of a `PixelHash`, e.g. from a truncated log line.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HashPrefix(String);
+
+impl HashPrefix {
+    /// Returns the prefix as a lowercase hex string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for HashPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TryFrom<&str> for HashPrefix {
+    type Error = PixelHashParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if value.is_empty() || value.len() > 16 {
+            return Err(PixelHashParseError::InvalidLength);
+        }
+
+        if !value.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(PixelHashParseError::InvalidHex);
+        }
+
+        Ok(HashPrefix(value.to_ascii_lowercase()))
+    }
+}
+
 /// Converts an Md5Hash into a hex string.
 impl From<PixelHash> for String {
     fn from(value: PixelHash) -> Self {
@@ -464,40 +1965,128 @@ impl From<PixelHash> for [u8; 8] {
     }
 }
 
+/// Scales dimensions down to fit within a bounding box, preserving the aspect ratio.
+///
+/// Dimensions that already fit are returned unchanged, so images are never enlarged.
+/// Scaled sides are rounded to the nearest pixel but never drop below 1.
+///
+/// # Examples
+///
+/// ```
+/// # use buru::storage::fit_within;
+/// assert_eq!((180, 90), fit_within(1920, 960, 180, 180));
+/// ```
+pub fn fit_within(width: u32, height: u32, max_w: u32, max_h: u32) -> (u32, u32) {
+    if width <= max_w && height <= max_h {
+        return (width, height);
+    }
+
+    let scale = f64::min(max_w as f64 / width as f64, max_h as f64 / height as f64);
+    let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+
+    (scaled(width), scaled(height))
+}
+
 /// Computes a pixel hash from a DynamicImage.
-fn compute_pixel_hash(img: &DynamicImage) -> PixelHash {
+fn compute_pixel_hash(img: &DynamicImage, seed: u64) -> PixelHash {
+    let pixels = img.to_rgba8().into_raw();
+    compute_content_hash(&pixels, seed)
+}
+
+/// Computes the pixel hash and the content digest of a DynamicImage at once.
+fn hash_pixels(img: &DynamicImage, seed: u64) -> (PixelHash, String) {
     let pixels = img.to_rgba8().into_raw();
-    let mut hasher = XxHash64::with_seed(0);
-    hasher.write(&pixels);
+    (compute_content_hash(&pixels, seed), compute_digest(&pixels))
+}
+
+/// Computes the content digest of pixels or raw bytes, see `CreateReport::digest`.
+fn compute_digest(bytes: &[u8]) -> String {
+    format!("{:032x}", XxHash3_128::oneshot(bytes))
+}
+
+/// Computes the hash of raw bytes, which raw files are stored under.
+fn compute_content_hash(bytes: &[u8], seed: u64) -> PixelHash {
+    let mut hasher = XxHash64::with_seed(seed);
+    hasher.write(bytes);
 
     PixelHash::from(hasher.finish())
 }
 
 enum Media {
     Video {
-        raw: Vec<u8>,
+        raw: VideoContent,
         thumbnail: DynamicImage,
         kind: infer::Type,
     },
     Image {
         content: DynamicImage,
         kind: infer::Type,
+        /// The raw EXIF data of the upload, carried over into the stored file.
+        exif: Option<Vec<u8>>,
     },
+    /// A file of another type, stored as it is, see `Storage::with_raw_files`.
+    Raw { raw: Vec<u8>, extension: String },
+}
+
+/// The content of an uploaded video, which is either in memory or spooled to disk.
+enum VideoContent {
+    Bytes(Vec<u8>),
+    Spooled(NamedTempFile),
 }
 
 impl Media {
-    pub fn new(bytes: &[u8]) -> Result<Self, StorageError> {
+    /// Decodes an upload, orienting images upright if `auto_orient` is set.
+    pub fn new(bytes: &[u8], auto_orient: bool) -> Result<Self, StorageError> {
         let kind = infer::get(bytes).ok_or(StorageError::UnsupportedFile { kind: None })?;
 
         let media = match kind.matcher_type() {
-            infer::MatcherType::Image => Media::Image {
-                content: ImageReader::new(std::io::Cursor::new(bytes.to_vec()))
+            infer::MatcherType::Image => {
+                // Animated GIFs and WebPs are stored like videos, with a still frame as
+                // thumbnail.
+                if may_be_animated(kind)
+                    && let Some(animation) = decode_animation(bytes)?
+                {
+                    return Ok(Media::Video {
+                        raw: VideoContent::Bytes(bytes.to_vec()),
+                        thumbnail: animation.middle_frame,
+                        kind,
+                    });
+                }
+
+                // Camera RAW files are stored like videos, with the decoded image as
+                // thumbnail.
+                #[cfg(feature = "raw-images")]
+                if camera_raw::is_candidate(kind)
+                    && let Some((thumbnail, kind)) = camera_raw::decode(bytes)
+                {
+                    return Ok(Media::Video {
+                        raw: VideoContent::Bytes(bytes.to_vec()),
+                        thumbnail,
+                        kind,
+                    });
+                }
+
+                let mut decoder = ImageReader::new(std::io::Cursor::new(bytes))
                     .with_guessed_format()?
-                    .decode()?,
-                kind,
-            },
+                    .into_decoder()?;
+                let orientation = decoder.orientation()?;
+                let mut content = DynamicImage::from_decoder(decoder)?;
+                let mut exif = encoding::read_exif(bytes);
+                if auto_orient && orientation != Orientation::NoTransforms {
+                    content.apply_orientation(orientation);
+                    if let Some(exif) = &mut exif {
+                        encoding::reset_exif_orientation(exif);
+                    }
+                }
+
+                Media::Image {
+                    content,
+                    kind,
+                    exif,
+                }
+            }
             infer::MatcherType::Video => Media::Video {
-                raw: bytes.to_vec(),
+                raw: VideoContent::Bytes(bytes.to_vec()),
                 thumbnail: generate_thumbnail(bytes)?,
                 kind,
             },
@@ -508,9 +2097,75 @@ impl Media {
     }
 }
 
+/// The frames of a GIF or WebP with more than one frame.
+struct Animation {
+    /// The frame in the middle of the animation, used as its thumbnail.
+    middle_frame: DynamicImage,
+    /// The total of the frame delays.
+    duration: Duration,
+}
+
+/// Returns whether the file type may hold an animation, which is stored like a video.
+fn may_be_animated(kind: infer::Type) -> bool {
+    matches!(kind.extension(), "gif" | "webp")
+}
+
+/// Returns whether an image file is stored like a video rather than as an image, see
+/// `Media::new`. Such are animated GIFs and WebPs, and camera RAW files with the
+/// `raw-images` feature.
+pub(crate) fn is_stored_as_video(bytes: &[u8], kind: infer::Type) -> Result<bool, StorageError> {
+    #[cfg(feature = "raw-images")]
+    if camera_raw::is_candidate(kind) && camera_raw::decode(bytes).is_some() {
+        return Ok(true);
+    }
+
+    Ok(may_be_animated(kind) && decode_animation(bytes)?.is_some())
+}
+
+/// Decodes a GIF or WebP as an animation, or returns `None` if it has a single frame.
+///
+/// The frames are decoded twice, first to count them and then to keep only the
+/// middle one, so long animations are never held in memory at once. A still WebP
+/// has no frames to count, so it is never decoded here.
+fn decode_animation(bytes: &[u8]) -> Result<Option<Animation>, StorageError> {
+    let format = image::guess_format(bytes)?;
+    let frames = || -> Result<Frames<'_>, StorageError> {
+        let cursor = io::Cursor::new(bytes);
+        Ok(match format {
+            ImageFormat::WebP => WebPDecoder::new(cursor)?.into_frames(),
+            _ => GifDecoder::new(cursor)?.into_frames(),
+        })
+    };
+
+    let mut count = 0;
+    let mut duration = Duration::ZERO;
+    for frame in frames()? {
+        count += 1;
+        duration += Duration::from(frame?.delay());
+    }
+    if count < 2 {
+        return Ok(None);
+    }
+
+    let middle_frame = frames()?
+        .nth(count / 2)
+        .ok_or_else(|| StorageError::Thumbnail {
+            reason: "Failed to decode the middle frame of the animation".to_string(),
+        })??;
+
+    Ok(Some(Animation {
+        middle_frame: DynamicImage::ImageRgba8(middle_frame.into_buffer()),
+        duration,
+    }))
+}
+
 fn generate_thumbnail(bytes: &[u8]) -> Result<DynamicImage, StorageError> {
     let tmpfile = write_temp_video(bytes)?;
-    let decoder = Decoder::new(tmpfile.path())?;
+    thumbnail_from_path(tmpfile.path())
+}
+
+fn thumbnail_from_path(path: &Path) -> Result<DynamicImage, StorageError> {
+    let decoder = Decoder::new(path)?;
 
     let (width, height) = decoder.size();
     let total_frames = decoder.frames()? as i64;
@@ -552,111 +2207,511 @@ fn safe_seek_and_decode(mut decoder: Decoder, frame_index: i64) -> Result<Frame,
             Ok(decoder.decode()?.1)
         }
     }
-}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaPath {
+    Image(PathBuf),
+    Video {
+        video: PathBuf,
+        thumb: PathBuf,
+    },
+    /// A file that is neither an image nor a video, see `Storage::with_raw_files`.
+    Raw(PathBuf),
+}
+
+impl MediaPath {
+    pub fn content_path(&self) -> &PathBuf {
+        match self {
+            MediaPath::Image(path_buf) | MediaPath::Raw(path_buf) => path_buf,
+            MediaPath::Video { video, .. } => video,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::storage::{
+        AdmissionController, EncoderOptions, FormatOptions, HashPrefix, MediaPath, NearDuplicate,
+        PixelHash, PixelHashParseError, Priority, ShardingConfig, Storage, StorageError,
+        ThumbnailFormat, VariantSpec, WorkKind,
+    };
+    use std::{fs, i64, io::Read, path::PathBuf, time::Duration};
+    use tempfile::TempDir;
+
+    use super::{fit_within, generate_thumbnail, write_temp};
+    use image::{
+        Delay, Frame, ImageBuffer, Rgba,
+        codecs::{
+            gif::GifEncoder,
+            jpeg::JpegEncoder,
+            png::{CompressionType, FilterType},
+            webp::WebPEncoder,
+        },
+    };
+
+    #[test]
+    fn test_md5_parse() {
+        assert_eq!(
+            Ok(PixelHash([50, 148, 53, 229, 230, 107, 232, 9,])),
+            PixelHash::try_from("329435e5e66be809".to_string())
+        );
+        assert_eq!(
+            Err(PixelHashParseError::InvalidLength),
+            PixelHash::try_from("329435e5e66b".to_string())
+        );
+        assert_eq!(
+            Err(PixelHashParseError::InvalidHex),
+            PixelHash::try_from("Z29435e5e66be809".to_string())
+        );
+        assert_eq!(
+            3644597259979188233_u64,
+            u64::from(PixelHash::try_from("329435e5e66be809").unwrap())
+        );
+        assert_eq!(
+            i64::MIN,
+            PixelHash::try_from("0000000000000000").unwrap().to_signed()
+        );
+        assert_eq!(
+            i64::MAX,
+            PixelHash::try_from("ffffffffffffffff").unwrap().to_signed()
+        );
+        assert_eq!(
+            PixelHash::try_from("0000000000000000").unwrap(),
+            PixelHash::from_signed(i64::MIN)
+        );
+        assert_eq!(
+            PixelHash::try_from("ffffffffffffffff").unwrap(),
+            PixelHash::from_signed(i64::MAX),
+        );
+    }
+
+    #[test]
+    fn test_pathes() {
+        let storage = Storage::new("/root".into());
+
+        assert_eq!(
+            PathBuf::from("32/94"),
+            storage.derive_dir(&PixelHash::try_from("329435e5e66be809".to_string()).unwrap())
+        );
+
+        assert_eq!(
+            PathBuf::from("/root/32/94"),
+            storage.derive_abs_dir(&PixelHash::try_from("329435e5e66be809".to_string()).unwrap())
+        )
+    }
+
+    #[test]
+    fn test_sharding() {
+        let hash = PixelHash::try_from("329435e5e66be809").unwrap();
+        let storage = Storage::new("/root".into());
+        for (levels, expected) in [(1, "32"), (2, "32/94"), (3, "32/94/35")] {
+            let storage = storage.clone().with_sharding(ShardingConfig { levels });
+            assert_eq!(PathBuf::from(expected), storage.derive_dir(&hash));
+        }
+
+        let tmp_dir = TempDir::new().unwrap();
+        let storage =
+            Storage::new(tmp_dir.path().to_path_buf()).with_sharding(ShardingConfig { levels: 3 });
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let (hash, _) = storage.create_file(file_bytes).unwrap();
+
+        assert!(fs::exists(tmp_dir.path().join("44/a5/b6/44a5b6f94f4f6445.png")).unwrap());
+        assert_eq!(
+            Some(MediaPath::Image(PathBuf::from(
+                "44/a5/b6/44a5b6f94f4f6445.png"
+            ))),
+            storage.index_file(&hash)
+        );
+        assert_eq!(vec![hash.clone()], storage.list_hashes().unwrap());
+        for prefix in ["44a", "44a5b6f"] {
+            assert_eq!(
+                vec![hash.clone()],
+                storage
+                    .find_by_hash_prefix(&HashPrefix::try_from(prefix).unwrap())
+                    .unwrap()
+            );
+        }
+
+        // An archive is not found at another depth.
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        assert_eq!(None, storage.index_file(&hash));
+        assert!(storage.list_hashes().unwrap().is_empty());
+        assert_eq!(0, storage.list_all().count());
+    }
+
+    #[test]
+    fn test_list_all() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let (image, _) = storage
+            .create_file(include_bytes!("../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+
+        // A video next to its thumbnail, an unparseable name, a temporary file and a
+        // variant directory.
+        let video = PixelHash::try_from("06a5e19afdf4c2e3").unwrap();
+        let dir = tmp_dir.path().join("06/a5");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("06a5e19afdf4c2e3.webm"), b"video").unwrap();
+        fs::write(dir.join("06a5e19afdf4c2e3.png"), b"thumb").unwrap();
+        fs::write(dir.join("notes.txt"), b"notes").unwrap();
+        fs::write(dir.join(".tmpAbCdEf"), b"partial").unwrap();
+        let variant = tmp_dir.path().join("180x180/44/a5");
+        fs::create_dir_all(&variant).unwrap();
+        fs::write(variant.join("ffffffffffffffff.png"), b"variant").unwrap();
+
+        assert_eq!(vec![video, image], storage.list_all().collect::<Vec<_>>());
+        assert_eq!(
+            storage.list_hashes().unwrap(),
+            storage.list_all().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_create_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let expect_path = tmp_dir.path().join("44/a5/44a5b6f94f4f6445.png");
+
+        storage.create_file(file_bytes).unwrap();
+
+        assert!(fs::exists(expect_path).unwrap())
+    }
+
+    #[cfg(feature = "webp")]
+    #[test]
+    fn test_transcode_webp() {
+        use super::{TranscodeConfig, TranscodeFormat};
+
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf()).with_transcode(TranscodeConfig {
+            format: TranscodeFormat::WebP,
+            quality: 75,
+        });
+
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let (hash, _) = storage.create_file(file_bytes).unwrap();
+
+        // The hash is the one of the original pixels.
+        assert_eq!(PixelHash::try_from("44a5b6f94f4f6445").unwrap(), hash);
+        assert!(fs::exists(tmp_dir.path().join("44/a5/44a5b6f94f4f6445.webp")).unwrap());
+        assert_eq!("webp", storage.get_metadata(&hash).unwrap().format);
+        // Lossy WebP cannot reproduce the hash.
+        assert_eq!(None, storage.recompute_hash(&hash).unwrap());
+    }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum MediaPath {
-    Image(PathBuf),
-    Video { video: PathBuf, thumb: PathBuf },
-}
+    #[test]
+    fn test_create_or_get() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
 
-impl MediaPath {
-    pub fn content_path(&self) -> &PathBuf {
-        match self {
-            MediaPath::Image(path_buf) => path_buf,
-            MediaPath::Video { video, .. } => video,
-        }
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let (hash, created) = storage.create_or_get(file_bytes).unwrap();
+        assert!(created);
+
+        assert_eq!((hash, false), storage.create_or_get(file_bytes).unwrap());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::storage::{MediaPath, PixelHash, PixelHashParseError, Storage, StorageError};
-    use std::{fs, i64, path::PathBuf};
-    use tempfile::TempDir;
+    #[test]
+    fn test_create_file_reports_path() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
 
-    use super::generate_thumbnail;
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let report = storage
+            .create_file_with_report(file_bytes, Priority::Interactive)
+            .unwrap();
 
-    #[test]
-    fn test_md5_parse() {
-        assert_eq!(
-            Ok(PixelHash([50, 148, 53, 229, 230, 107, 232, 9,])),
-            PixelHash::try_from("329435e5e66be809".to_string())
-        );
-        assert_eq!(
-            Err(PixelHashParseError::InvalidLength),
-            PixelHash::try_from("329435e5e66b".to_string())
-        );
         assert_eq!(
-            Err(PixelHashParseError::InvalidHex),
-            PixelHash::try_from("Z29435e5e66be809".to_string())
+            MediaPath::Image(PathBuf::from("44/a5/44a5b6f94f4f6445.png")),
+            report.path
         );
+        assert_eq!(storage.index_file(&report.hash), Some(report.path));
+    }
+
+    #[test]
+    fn test_create_file_on_duplicated() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let expect_path = tmp_dir.path().join("44/a5/44a5b6f94f4f6445.png");
+
+        let report = storage
+            .create_file_with_report(file_bytes, Priority::Interactive)
+            .unwrap();
+        assert_eq!(Some(32), report.digest.as_ref().map(String::len));
+
+        let result = storage.create_file(file_bytes);
+        let Err(StorageError::HashCollision {
+            existing_path,
+            digest,
+            ..
+        }) = result
+        else {
+            panic!("Expected HashCollision error, but got {:?}", result);
+        };
+
+        assert_eq!(expect_path, existing_path);
+        assert_eq!(report.digest, digest);
+    }
+
+    #[test]
+    fn test_create_file_from_reader() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let hash = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+
+        let half = &file_bytes[..file_bytes.len() / 2];
+        let result = storage.create_file_from_reader(half, Some(file_bytes.len() as u64));
+        let Err(StorageError::Truncated { expected, received }) = result else {
+            panic!("Expected Truncated error, but got {:?}", result);
+        };
+        assert_eq!(file_bytes.len() as u64, expected);
+        assert_eq!(half.len() as u64, received);
+
+        let interrupted = half.chain(FailingReader);
+        let result = storage.create_file_from_reader(interrupted, None);
+        let Err(StorageError::Io(_)) = result else {
+            panic!("Expected Io error, but got {:?}", result);
+        };
+        assert_eq!(None, storage.index_file(&hash));
+        // Failed uploads leave no spooled file behind.
+        assert_eq!(0, fs::read_dir(tmp_dir.path()).unwrap().count());
+
         assert_eq!(
-            3644597259979188233_u64,
-            u64::from(PixelHash::try_from("329435e5e66be809").unwrap())
+            hash,
+            storage
+                .create_file_from_reader(&file_bytes[..], Some(file_bytes.len() as u64))
+                .unwrap()
         );
+    }
+
+    #[test]
+    fn test_create_video_from_reader() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf()).with_ingest_locks(64 * 1024);
+        let video_bytes = include_bytes!("../testdata/motion_video.mp4");
+        let hash = PixelHash::try_from("06a5e19afdf4c2e3").unwrap();
+
         assert_eq!(
-            i64::MIN,
-            PixelHash::try_from("0000000000000000").unwrap().to_signed()
+            hash,
+            storage
+                .create_file_from_reader(&video_bytes[..], None)
+                .unwrap()
         );
         assert_eq!(
-            i64::MAX,
-            PixelHash::try_from("ffffffffffffffff").unwrap().to_signed()
+            video_bytes.to_vec(),
+            fs::read(tmp_dir.path().join("06/a5/06a5e19afdf4c2e3.mp4")).unwrap()
         );
+
+        let result = storage.create_file_from_reader(&video_bytes[..], None);
+        let Err(StorageError::HashCollision { .. }) = result else {
+            panic!("Expected HashCollision error, but got {:?}", result);
+        };
+        // The spooled file was moved into place, or removed on the collision.
+        let entries: Vec<_> = fs::read_dir(tmp_dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(vec![std::ffi::OsString::from("06")], entries);
+    }
+
+    #[test]
+    fn test_find_by_hash_prefix() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let (hash, _) = storage
+            .create_file(include_bytes!("../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+
+        for prefix in ["4", "44a", "44a5b6", "44A5B6F94F4F6445"] {
+            let prefix = HashPrefix::try_from(prefix).unwrap();
+            assert_eq!(
+                vec![hash.clone()],
+                storage.find_by_hash_prefix(&prefix).unwrap()
+            );
+        }
+
+        let prefix = HashPrefix::try_from("44a5c").unwrap();
+        assert!(storage.find_by_hash_prefix(&prefix).unwrap().is_empty());
+
         assert_eq!(
-            PixelHash::try_from("0000000000000000").unwrap(),
-            PixelHash::from_signed(i64::MIN)
+            Err(PixelHashParseError::InvalidHex),
+            HashPrefix::try_from("44a5g")
         );
         assert_eq!(
-            PixelHash::try_from("ffffffffffffffff").unwrap(),
-            PixelHash::from_signed(i64::MAX),
+            Err(PixelHashParseError::InvalidLength),
+            HashPrefix::try_from("")
         );
     }
 
+    /// A reader whose connection drops on the first read.
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::ConnectionReset.into())
+        }
+    }
+
     #[test]
-    fn test_pathes() {
-        let storage = Storage::new("/root".into());
+    fn test_write_temp() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+
+        // Only the final file is left in the shard directory.
+        let (hash, _) = storage
+            .create_file(include_bytes!("../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+        let dir = tmp_dir.path().join("44/a5");
+        let entries: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(vec!["44a5b6f94f4f6445.png"], entries);
+
+        // An interrupted write leaves nothing behind.
+        let result = write_temp(&dir, |path| {
+            fs::write(path, b"partial")?;
+            Err(StorageError::EmptyInput)
+        });
+        assert!(matches!(result, Err(StorageError::EmptyInput)));
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+        assert!(storage.index_file(&hash).is_some());
+    }
+
+    #[test]
+    fn test_create_file_with_hash() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+
+        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let hash = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        let kind = infer::get(file_bytes).unwrap();
 
+        storage
+            .create_file_with_hash(file_bytes, &hash, kind)
+            .unwrap();
+
+        let expect_path = tmp_dir.path().join("44/a5/44a5b6f94f4f6445.png");
+        assert_eq!(file_bytes.to_vec(), fs::read(expect_path).unwrap());
         assert_eq!(
-            PathBuf::from("32/94"),
-            storage.derive_dir(&PixelHash::try_from("329435e5e66be809".to_string()).unwrap())
+            Some(MediaPath::Image(PathBuf::from(
+                "44/a5/44a5b6f94f4f6445.png"
+            ))),
+            storage.index_file(&hash)
         );
 
-        assert_eq!(
-            PathBuf::from("/root/32/94"),
-            storage.derive_abs_dir(&PixelHash::try_from("329435e5e66be809".to_string()).unwrap())
-        )
+        let result = storage.create_file_with_hash(file_bytes, &hash, kind);
+        let Err(StorageError::HashCollision { .. }) = result else {
+            panic!("Expected HashCollision error, but got {:?}", result);
+        };
     }
 
     #[test]
-    fn test_create_file() {
+    fn test_create_file_with_hash_validates_kind() {
         let tmp_dir = TempDir::new().unwrap();
         let storage = Storage::new(tmp_dir.path().to_path_buf());
 
         let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
-        let expect_path = tmp_dir.path().join("44/a5/44a5b6f94f4f6445.png");
+        let video_bytes = include_bytes!("../testdata/motion_video.mp4");
+        let hash = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+        let jpeg = infer::get(&[0xFF, 0xD8, 0xFF, 0xE0]).unwrap();
+        let mp4 = infer::get(video_bytes).unwrap();
 
-        storage.create_file(file_bytes).unwrap();
+        let result = storage.create_file_with_hash(file_bytes, &hash, jpeg);
+        let Err(StorageError::UnsupportedFile { .. }) = result else {
+            panic!("Expected UnsupportedFile error, but got {:?}", result);
+        };
 
-        assert!(fs::exists(expect_path).unwrap())
+        let result = storage.create_file_with_hash(video_bytes, &hash, mp4);
+        let Err(StorageError::UnsupportedFile { .. }) = result else {
+            panic!("Expected UnsupportedFile error, but got {:?}", result);
+        };
+
+        let webp = animated_webp(&[[255, 0, 0, 255], [0, 0, 255, 255]]);
+        let result = storage.create_file_with_hash(&webp, &hash, infer::get(&webp).unwrap());
+        let Err(StorageError::UnsupportedFile { .. }) = result else {
+            panic!("Expected UnsupportedFile error, but got {:?}", result);
+        };
+
+        assert_eq!(None, storage.index_file(&hash));
     }
 
     #[test]
-    fn test_create_file_on_duplicated() {
+    fn test_create_file_on_empty_or_unrecognized() {
         let tmp_dir = TempDir::new().unwrap();
         let storage = Storage::new(tmp_dir.path().to_path_buf());
 
-        let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
-        let expect_path = tmp_dir.path().join("44/a5/44a5b6f94f4f6445.png");
+        let result = storage.create_file(&[]);
+        let Err(StorageError::EmptyInput) = result else {
+            panic!("Expected EmptyInput error, but got {:?}", result);
+        };
 
-        storage.create_file(file_bytes).unwrap();
+        let result = storage.create_file(&[0x89]);
+        let Err(StorageError::UnsupportedFile { kind: None }) = result else {
+            panic!("Expected UnsupportedFile error, but got {:?}", result);
+        };
+    }
 
-        let result = storage.create_file(file_bytes);
-        let Err(StorageError::HashCollision { existing_path, .. }) = result else {
-            panic!("Expected HashCollision error, but got {:?}", result);
+    #[test]
+    fn test_raw_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let pdf =
+            b"%PDF-1.4\n1 0 obj << /Type /Catalog >> endobj\ntrailer << /Root 1 0 R >>\n%%EOF\n";
+
+        let result = Storage::new(tmp_dir.path().to_path_buf()).create_file(pdf);
+        let Err(StorageError::UnsupportedFile { kind: Some(kind) }) = result else {
+            panic!("Expected UnsupportedFile error, but got {:?}", result);
         };
+        assert_eq!("pdf", kind.extension());
+
+        let storage = Storage::new(tmp_dir.path().to_path_buf()).with_raw_files(true);
+        let (hash, phash) = storage.create_file(pdf).unwrap();
+        assert_eq!(None, phash);
+        assert_eq!(Some(hash.clone()), storage.recompute_hash(&hash).unwrap());
+
+        let path = storage.derive_dir(&hash).join(format!("{hash}.pdf"));
+        assert_eq!(Some(MediaPath::Raw(path)), storage.index_file(&hash));
+        assert_eq!(pdf.to_vec(), storage.read_file(&hash).unwrap());
+        assert!(matches!(
+            storage.create_file(pdf),
+            Err(StorageError::HashCollision { .. })
+        ));
+
+        let metadata = storage.get_metadata(&hash).unwrap();
+        assert_eq!("pdf", metadata.format);
+        assert_eq!(pdf.len() as u64, metadata.file_size);
+        assert_eq!((0, 0), (metadata.width, metadata.height));
+        assert_eq!(None, storage.get_phash(&hash).unwrap());
+        assert_eq!(None, storage.variant_path(&hash, VariantSpec::Scale(50)));
+        assert!(storage.missing_thumbnails().unwrap().is_empty());
+
+        storage.ensure_deleted(&hash).unwrap();
+        assert_eq!(None, storage.index_file(&hash));
+    }
 
-        assert_eq!(expect_path, existing_path)
+    #[test]
+    fn test_create_raw_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let notes = b"notes of no detectable type";
+
+        let hash = storage
+            .create_raw_file(notes, "txt", Priority::Maintenance)
+            .unwrap();
+        let path = storage.derive_dir(&hash).join(format!("{hash}.txt"));
+        assert_eq!(Some(MediaPath::Raw(path)), storage.index_file(&hash));
+        assert_eq!(Some(hash.clone()), storage.recompute_hash(&hash).unwrap());
+        assert!(matches!(
+            storage.create_raw_file(notes, "txt", Priority::Maintenance),
+            Err(StorageError::HashCollision { .. })
+        ));
     }
 
     #[test]
@@ -720,13 +2775,270 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_remove_empty_dirs() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+
+        let (hash, _) = storage
+            .create_file(include_bytes!("../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+        let mut other = vec![];
+        image::DynamicImage::ImageRgba8(ImageBuffer::from_pixel(4, 4, Rgba([1, 2, 3, 255])))
+            .write_to(
+                &mut std::io::Cursor::new(&mut other),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        let (kept, _) = storage.create_file(&other).unwrap();
+        assert_eq!(0, storage.remove_empty_dirs().unwrap());
+
+        // Both shard levels of the deleted file are removed, unless the first level
+        // is shared with the other file. The root is kept.
+        let shared =
+            storage.derive_abs_dir(&hash).parent() == storage.derive_abs_dir(&kept).parent();
+        storage.ensure_deleted(&hash).unwrap();
+        assert_eq!(
+            if shared { 1 } else { 2 },
+            storage.remove_empty_dirs().unwrap()
+        );
+        assert!(!storage.derive_abs_dir(&hash).exists());
+        assert!(storage.index_file(&kept).is_some());
+        assert!(tmp_dir.path().exists());
+
+        storage.ensure_deleted(&kept).unwrap();
+        assert_eq!(2, storage.remove_empty_dirs().unwrap());
+        assert_eq!(0, storage.remove_empty_dirs().unwrap());
+        assert_eq!(0, fs::read_dir(tmp_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn test_separate_thumbnail_root() {
+        let tmp_dir = TempDir::new().unwrap();
+        let thumb_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf())
+            .with_thumbnail_root(thumb_dir.path().to_path_buf());
+
+        let hash = PixelHash::try_from("06a5e19afdf4c2e3").unwrap();
+        let video = tmp_dir.path().join("06/a5/06a5e19afdf4c2e3.mp4");
+        let thumb = thumb_dir.path().join("06/a5/06a5e19afdf4c2e3.png");
+        fs::create_dir_all(video.parent().unwrap()).unwrap();
+        fs::create_dir_all(thumb.parent().unwrap()).unwrap();
+        fs::write(&video, include_bytes!("../testdata/motion_video.mp4")).unwrap();
+        fs::write(&thumb, include_bytes!("../testdata/44a5b6f94f4f6445.png")).unwrap();
+
+        assert_eq!(
+            Some(MediaPath::Video {
+                video: PathBuf::from("06/a5/06a5e19afdf4c2e3.mp4"),
+                thumb: PathBuf::from("06/a5/06a5e19afdf4c2e3.png"),
+            }),
+            storage.index_file(&hash)
+        );
+        assert_eq!(
+            Some(MediaPath::Image(PathBuf::from(
+                "06/a5/06a5e19afdf4c2e3.mp4"
+            ))),
+            Storage::new(tmp_dir.path().to_path_buf()).index_file(&hash)
+        );
+
+        storage.ensure_deleted(&hash).unwrap();
+
+        assert!(!video.exists());
+        assert!(!thumb.exists());
+        assert_eq!(None, storage.index_file(&hash));
+    }
+
+    #[test]
+    fn test_jpeg_thumbnail() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage =
+            Storage::new(tmp_dir.path().to_path_buf()).with_thumbnail_format(ThumbnailFormat::Jpeg);
+
+        let (hash, _) = storage
+            .create_file(include_bytes!("../testdata/motion_video.mp4"))
+            .unwrap();
+
+        let Some(MediaPath::Video { video, thumb }) = storage.index_file(&hash) else {
+            panic!("Expected a video entry");
+        };
+        assert_eq!(Some("mp4"), video.extension().and_then(|e| e.to_str()));
+        assert_eq!(Some("jpg"), thumb.extension().and_then(|e| e.to_str()));
+
+        let bytes = fs::read(tmp_dir.path().join(&thumb)).unwrap();
+        assert_eq!(
+            Some("image/jpeg"),
+            infer::get(&bytes).map(|k| k.mime_type())
+        );
+
+        storage.ensure_deleted(&hash).unwrap();
+
+        assert!(!tmp_dir.path().join(video).exists());
+        assert!(!tmp_dir.path().join(thumb).exists());
+        assert_eq!(None, storage.index_file(&hash));
+    }
+
+    #[test]
+    fn test_png_compression_option() {
+        let bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let default_dir = TempDir::new().unwrap();
+        let fast_dir = TempDir::new().unwrap();
+        let default = Storage::new(default_dir.path().to_path_buf());
+        let fast = Storage::new(fast_dir.path().to_path_buf()).with_format_options(
+            FormatOptions::new().with(EncoderOptions::Png {
+                compression: CompressionType::Fast,
+                filter: FilterType::NoFilter,
+            }),
+        );
+
+        let (hash, _) = default.create_file(bytes).unwrap();
+        assert_eq!(hash, fast.create_file(bytes).unwrap().0);
+
+        let size = |storage: &Storage, dir: &TempDir| {
+            let path = dir
+                .path()
+                .join(storage.index_file(&hash).unwrap().content_path());
+            fs::metadata(path).unwrap().len()
+        };
+        assert!(size(&fast, &fast_dir) > size(&default, &default_dir));
+        assert_eq!(
+            image::open(
+                default_dir
+                    .path()
+                    .join(default.index_file(&hash).unwrap().content_path())
+            )
+            .unwrap(),
+            image::open(
+                fast_dir
+                    .path()
+                    .join(fast.index_file(&hash).unwrap().content_path())
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_video_near_duplicate() {
+        let tmp_dir = TempDir::new().unwrap();
+        let bytes = include_bytes!("../testdata/motion_video.mp4");
+
+        // Store the clip once, then move it under another hash as a second encode would.
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let (hash, _) = storage.create_file(bytes).unwrap();
+        let Some(MediaPath::Video { video, thumb }) = storage.index_file(&hash) else {
+            panic!("Expected a video entry");
+        };
+        let other = PixelHash::from(1);
+        let dir = tmp_dir.path().join("00/00");
+        fs::create_dir_all(&dir).unwrap();
+        for path in [&video, &thumb] {
+            let ext = path.extension().unwrap().to_str().unwrap();
+            fs::copy(
+                tmp_dir.path().join(path),
+                dir.join(format!("{other}.{ext}")),
+            )
+            .unwrap();
+        }
+        storage.ensure_deleted(&hash).unwrap();
+
+        let storage = storage.with_video_near_duplicates(8);
+        let report = storage
+            .create_file_with_report(bytes, Priority::default())
+            .unwrap();
+
+        assert_eq!(hash, report.hash);
+        assert_eq!(
+            vec![NearDuplicate {
+                hash: other,
+                distance: 0
+            }],
+            report.near_duplicates
+        );
+    }
+
+    #[test]
+    fn test_find_similar() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf()).with_image_near_duplicates(8);
+        let bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let (original, _) = storage.create_file(bytes).unwrap();
+
+        // A lossy re-encode gets another pixel hash, but looks the same.
+        let mut reencoded = vec![];
+        image::load_from_memory(bytes)
+            .unwrap()
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut reencoded, 40))
+            .unwrap();
+        let report = storage
+            .create_file_with_report(&reencoded, Priority::default())
+            .unwrap();
+        assert_ne!(original, report.hash);
+        assert_eq!(
+            vec![original.clone()],
+            report
+                .near_duplicates
+                .iter()
+                .map(|n| n.hash.clone())
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            vec![report.hash.clone()],
+            storage.find_similar(&original, 8)
+        );
+        assert!(storage.find_similar(&PixelHash::from(1), 64).is_empty());
+
+        storage.ensure_deleted(&report.hash).unwrap();
+        assert!(storage.find_similar(&original, 8).is_empty());
+    }
+
+    #[test]
+    fn test_find_entry_with_any_thumbnail_format() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let hash = PixelHash::try_from("06a5e19afdf4c2e3").unwrap();
+        let dir = tmp_dir.path().join("06/a5");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("06a5e19afdf4c2e3.webm"), b"video").unwrap();
+
+        for ext in ["jpg", "webp", "png"] {
+            let thumb = dir.join(format!("06a5e19afdf4c2e3.{ext}"));
+            fs::write(&thumb, b"thumb").unwrap();
+
+            assert_eq!(
+                Some(MediaPath::Video {
+                    video: PathBuf::from("06/a5/06a5e19afdf4c2e3.webm"),
+                    thumb: PathBuf::from(format!("06/a5/06a5e19afdf4c2e3.{ext}")),
+                }),
+                storage.index_file(&hash)
+            );
+
+            fs::remove_file(thumb).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fit_within() {
+        // landscape
+        assert_eq!((180, 101), fit_within(1920, 1080, 180, 180));
+        assert_eq!((180, 1), fit_within(4000, 10, 180, 180));
+        // portrait
+        assert_eq!((120, 180), fit_within(800, 1200, 180, 180));
+        assert_eq!((90, 180), fit_within(300, 600, 360, 180));
+        // square
+        assert_eq!((180, 180), fit_within(512, 512, 180, 180));
+        // already fits
+        assert_eq!((100, 50), fit_within(100, 50, 180, 180));
+        assert_eq!((0, 0), fit_within(0, 0, 180, 180));
+    }
+
     #[test]
     fn test_get_metadata() {
         let tmp_dir = TempDir::new().unwrap();
         let storage = Storage::new(tmp_dir.path().to_path_buf());
 
         let file_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
-        let hash = storage.create_file(file_bytes).unwrap();
+        let (hash, _) = storage.create_file(file_bytes).unwrap();
 
         println!("{:?}", storage.get_metadata(&hash));
     }
@@ -738,11 +3050,201 @@ mod tests {
 
         let video_bytes = include_bytes!("../testdata/motion_video.mp4");
 
-        let hash = storage.create_file(video_bytes).unwrap();
+        let (hash, _) = storage.create_file(video_bytes).unwrap();
 
         assert_eq!(Some(3.0), storage.get_metadata(&hash).unwrap().duration);
     }
 
+    #[test]
+    fn test_animated_gif() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let mut gif = vec![];
+        GifEncoder::new(&mut gif)
+            .encode_frames(colors.map(|color| {
+                Frame::from_parts(
+                    ImageBuffer::from_pixel(4, 4, Rgba(color)),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(200, 1),
+                )
+            }))
+            .unwrap();
+
+        let (hash, _) = storage.create_file(&gif).unwrap();
+        let Some(MediaPath::Video { video, thumb }) = storage.index_file(&hash) else {
+            panic!("an animated GIF must be stored like a video");
+        };
+        assert_eq!(Some("gif"), video.extension().and_then(|e| e.to_str()));
+        assert_eq!(Some("png"), thumb.extension().and_then(|e| e.to_str()));
+        assert_eq!(gif, fs::read(tmp_dir.path().join(video)).unwrap());
+
+        // The thumbnail is the middle frame.
+        let still = image::open(tmp_dir.path().join(thumb)).unwrap().to_rgba8();
+        assert_eq!(Rgba(colors[1]), *still.get_pixel(0, 0));
+
+        let metadata = storage.get_metadata(&hash).unwrap();
+        assert_eq!("gif", metadata.format);
+        assert!((metadata.duration.unwrap() - 0.6).abs() < 1e-9);
+        assert_eq!(Some(hash.clone()), storage.recompute_hash(&hash).unwrap());
+
+        // A GIF with a single frame stays a still image.
+        let mut gif = vec![];
+        GifEncoder::new(&mut gif)
+            .encode_frames([Frame::new(ImageBuffer::from_pixel(4, 4, Rgba(colors[0])))])
+            .unwrap();
+        let (hash, _) = storage.create_file(&gif).unwrap();
+        assert!(matches!(
+            storage.index_file(&hash),
+            Some(MediaPath::Image(_))
+        ));
+        assert_eq!(None, storage.get_metadata(&hash).unwrap().duration);
+    }
+
+    /// Builds an animated WebP of lossless 4x4 frames of the given colors, each
+    /// shown for 200 milliseconds.
+    ///
+    /// `image` only encodes still WebPs, so the VP8L chunk of each still is wrapped
+    /// into an ANMF frame chunk by hand.
+    pub(crate) fn animated_webp(colors: &[[u8; 4]]) -> Vec<u8> {
+        fn chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+            let mut chunk = fourcc.to_vec();
+            chunk.extend((data.len() as u32).to_le_bytes());
+            chunk.extend(data);
+            if data.len() % 2 == 1 {
+                chunk.push(0);
+            }
+            chunk
+        }
+        let u24 = |value: u32| value.to_le_bytes()[..3].to_vec();
+
+        // Animation and alpha flags, then the canvas size minus one.
+        let mut body = b"WEBP".to_vec();
+        body.extend(chunk(
+            b"VP8X",
+            &[[0x12, 0, 0, 0].to_vec(), u24(3), u24(3)].concat(),
+        ));
+        body.extend(chunk(b"ANIM", &[0; 6]));
+        for color in colors {
+            let mut still = vec![];
+            WebPEncoder::new_lossless(&mut still)
+                .encode(&color.repeat(16), 4, 4, image::ExtendedColorType::Rgba8)
+                .unwrap();
+            // The still is a RIFF header followed by its VP8L chunk.
+            let frame = [u24(0), u24(0), u24(3), u24(3), u24(200), vec![0]].concat();
+            body.extend(chunk(b"ANMF", &[frame, still[12..].to_vec()].concat()));
+        }
+
+        chunk(b"RIFF", &body)
+    }
+
+    #[test]
+    fn test_animated_webp() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+        let webp = animated_webp(&colors);
+
+        let (hash, _) = storage.create_file(&webp).unwrap();
+        let Some(MediaPath::Video { video, thumb }) = storage.index_file(&hash) else {
+            panic!("an animated WebP must be stored like a video");
+        };
+        assert_eq!(Some("webp"), video.extension().and_then(|e| e.to_str()));
+        assert_eq!(Some("png"), thumb.extension().and_then(|e| e.to_str()));
+
+        // The thumbnail is the middle frame.
+        let still = image::open(tmp_dir.path().join(thumb)).unwrap().to_rgba8();
+        assert_eq!(Rgba(colors[1]), *still.get_pixel(0, 0));
+
+        let metadata = storage.get_metadata(&hash).unwrap();
+        assert_eq!("webp", metadata.format);
+        assert!((metadata.duration.unwrap() - 0.6).abs() < 1e-9);
+        assert_eq!(Some(hash.clone()), storage.recompute_hash(&hash).unwrap());
+
+        // A WebP with a single frame stays a still image.
+        let mut webp = vec![];
+        WebPEncoder::new_lossless(&mut webp)
+            .encode(&colors[0].repeat(16), 4, 4, image::ExtendedColorType::Rgba8)
+            .unwrap();
+        let (hash, _) = storage.create_file(&webp).unwrap();
+        assert!(matches!(
+            storage.index_file(&hash),
+            Some(MediaPath::Image(_))
+        ));
+        assert_eq!(None, storage.get_metadata(&hash).unwrap().duration);
+    }
+
+    #[test]
+    fn test_create_file_with_saturated_video_lane() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf())
+            .with_admission(AdmissionController::new(1, 1).with_queue_depth(0));
+
+        let _permit = storage
+            .admission()
+            .unwrap()
+            .acquire(WorkKind::VideoThumbnail, Priority::Maintenance)
+            .unwrap();
+
+        let video_bytes = include_bytes!("../testdata/motion_video.mp4");
+        let result = storage.create_file(video_bytes);
+        let Err(StorageError::Busy { .. }) = result else {
+            panic!("Expected Busy error, but got {:?}", result);
+        };
+
+        let image_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        assert!(storage.create_file(image_bytes).is_ok());
+        assert_eq!(0, storage.admission().unwrap().stats().image.in_flight);
+    }
+
+    #[tokio::test]
+    async fn test_async_create_with_queued_video() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf())
+            .with_admission(AdmissionController::new(1, 1));
+
+        let permit = storage
+            .admission()
+            .unwrap()
+            .acquire(WorkKind::VideoThumbnail, Priority::Maintenance)
+            .unwrap();
+
+        // The test runtime has a single thread, which a waiting video would block.
+        let video = tokio::spawn({
+            let storage = storage.clone();
+            async move {
+                storage
+                    .create_file_from_async_reader(
+                        &include_bytes!("../testdata/motion_video.mp4")[..],
+                        None,
+                        Priority::Interactive,
+                    )
+                    .await
+                    .map(|report| report.hash)
+            }
+        });
+        for _ in 0..500 {
+            if storage.admission().unwrap().stats().video.queued == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        assert_eq!(1, storage.admission().unwrap().stats().video.queued);
+
+        let image_bytes = include_bytes!("../testdata/44a5b6f94f4f6445.png");
+        let (_, created) = storage
+            .create_or_get_async(image_bytes.to_vec(), Priority::Interactive)
+            .await
+            .unwrap();
+        assert!(created);
+
+        drop(permit);
+        assert!(video.await.unwrap().is_ok());
+    }
+
     #[test]
     fn test_thumbnail() {
         let file_bytes = include_bytes!("../testdata/motion_video.mp4");
@@ -0,0 +1,333 @@
+//! Admission control for expensive media processing.
+//!
+//! Decoding images and generating video thumbnails are CPU heavy, and a burst of
+//! large uploads can otherwise saturate the whole service. The `AdmissionController`
+//! bounds how many decodes of each kind may run at once and how many callers may wait
+//! for a slot. Callers beyond the queue depth are rejected with `StorageError::Busy`
+//! instead of queuing unboundedly.
+//!
+//! Each kind of work has its own lane, so a saturated video lane never blocks image
+//! decodes. Within a lane, waiting `Priority::Interactive` callers are always admitted
+//! before waiting `Priority::Maintenance` callers.
+//!
+//! Waiting blocks the thread, so async callers wait on the blocking thread pool, see
+//! `Storage::create_or_get_async` and `Storage::create_file_from_async_reader`.
+
+use super::StorageError;
+use std::{
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+/// The kind of processing work that requires admission.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkKind {
+    /// Decoding a still image.
+    ImageDecode,
+    /// Generating a thumbnail from a video.
+    VideoThumbnail,
+}
+
+/// The priority lane selected by the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// User-facing work such as uploads.
+    #[default]
+    Interactive,
+    /// Background work such as regeneration. Never admitted while interactive work waits.
+    Maintenance,
+}
+
+/// A point-in-time snapshot of a single lane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LaneStats {
+    /// The number of admitted jobs currently running.
+    pub in_flight: usize,
+    /// The number of callers waiting for a slot.
+    pub queued: usize,
+}
+
+/// A point-in-time snapshot of every lane, suitable for exporting as metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AdmissionStats {
+    pub image: LaneStats,
+    pub video: LaneStats,
+}
+
+/// Bounds concurrent media processing and the number of callers waiting for it.
+#[derive(Debug)]
+pub struct AdmissionController {
+    image: Lane,
+    video: Lane,
+    max_queue_depth: usize,
+    retry_after_hint: Duration,
+}
+
+impl AdmissionController {
+    /// Creates a controller with separate concurrency limits for image decodes and
+    /// video thumbnail generations.
+    ///
+    /// A limit of zero is treated as one, since a lane that never admits anything
+    /// would block its callers forever.
+    ///
+    /// # Arguments
+    /// * `max_image_decodes` - Maximum number of concurrent image decodes.
+    /// * `max_video_thumbnails` - Maximum number of concurrent video thumbnail generations.
+    pub fn new(max_image_decodes: usize, max_video_thumbnails: usize) -> Self {
+        Self {
+            image: Lane::new(max_image_decodes),
+            video: Lane::new(max_video_thumbnails),
+            max_queue_depth: 16,
+            retry_after_hint: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the maximum number of callers that may wait per lane. Defaults to 16.
+    pub fn with_queue_depth(mut self, depth: usize) -> Self {
+        self.max_queue_depth = depth;
+        self
+    }
+
+    /// Sets the hint returned in `StorageError::Busy`. Defaults to one second.
+    pub fn with_retry_after(mut self, hint: Duration) -> Self {
+        self.retry_after_hint = hint;
+        self
+    }
+
+    /// Waits for a slot in the lane for `kind`, blocking the current thread.
+    ///
+    /// Must not be called on an async runtime thread, where waiting would stall other
+    /// tasks, including those holding the slots. The slot is held until the returned permit is dropped.
+    ///
+    /// # Errors
+    /// - `StorageError::Busy` if no slot is free and the lane's queue is full.
+    pub fn acquire(
+        &self,
+        kind: WorkKind,
+        priority: Priority,
+    ) -> Result<AdmissionPermit<'_>, StorageError> {
+        let lane = self.lane(kind);
+        let mut state = lane.lock();
+
+        if !state.can_admit(lane.max_in_flight, priority) {
+            if state.queued() >= self.max_queue_depth {
+                return Err(StorageError::Busy {
+                    retry_after_hint: self.retry_after_hint,
+                });
+            }
+
+            state.enqueue(priority);
+            state = lane
+                .available
+                .wait_while(state, |s| !s.can_admit(lane.max_in_flight, priority))
+                .unwrap_or_else(PoisonError::into_inner);
+            state.dequeue(priority);
+        }
+
+        state.in_flight += 1;
+
+        Ok(AdmissionPermit { lane })
+    }
+
+    /// Returns the current in-flight and queued counts of every lane.
+    pub fn stats(&self) -> AdmissionStats {
+        AdmissionStats {
+            image: self.image.stats(),
+            video: self.video.stats(),
+        }
+    }
+
+    fn lane(&self, kind: WorkKind) -> &Lane {
+        match kind {
+            WorkKind::ImageDecode => &self.image,
+            WorkKind::VideoThumbnail => &self.video,
+        }
+    }
+}
+
+/// A slot in an admission lane, released when dropped.
+#[derive(Debug)]
+pub struct AdmissionPermit<'a> {
+    lane: &'a Lane,
+}
+
+impl Drop for AdmissionPermit<'_> {
+    fn drop(&mut self) {
+        self.lane.lock().in_flight -= 1;
+        self.lane.available.notify_all();
+    }
+}
+
+#[derive(Debug)]
+struct Lane {
+    max_in_flight: usize,
+    state: Mutex<LaneState>,
+    available: Condvar,
+}
+
+impl Lane {
+    fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight: max_in_flight.max(1),
+            state: Mutex::new(LaneState::default()),
+            available: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LaneState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn stats(&self) -> LaneStats {
+        let state = self.lock();
+        LaneStats {
+            in_flight: state.in_flight,
+            queued: state.queued(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LaneState {
+    in_flight: usize,
+    interactive_waiting: usize,
+    maintenance_waiting: usize,
+}
+
+impl LaneState {
+    fn can_admit(&self, max_in_flight: usize, priority: Priority) -> bool {
+        self.in_flight < max_in_flight
+            && (priority == Priority::Interactive || self.interactive_waiting == 0)
+    }
+
+    fn queued(&self) -> usize {
+        self.interactive_waiting + self.maintenance_waiting
+    }
+
+    fn enqueue(&mut self, priority: Priority) {
+        match priority {
+            Priority::Interactive => self.interactive_waiting += 1,
+            Priority::Maintenance => self.maintenance_waiting += 1,
+        }
+    }
+
+    fn dequeue(&mut self, priority: Priority) {
+        match priority {
+            Priority::Interactive => self.interactive_waiting -= 1,
+            Priority::Maintenance => self.maintenance_waiting -= 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdmissionController, LaneStats, Priority, WorkKind};
+    use crate::storage::StorageError;
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    fn wait_for_queued(controller: &AdmissionController, kind: WorkKind, queued: usize) {
+        for _ in 0..500 {
+            let stats = controller.stats();
+            let lane = match kind {
+                WorkKind::ImageDecode => stats.image,
+                WorkKind::VideoThumbnail => stats.video,
+            };
+            if lane.queued == queued {
+                return;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        panic!("lane never reached {queued} queued callers");
+    }
+
+    #[test]
+    fn test_video_lane_rejects_beyond_queue_depth() {
+        let controller = Arc::new(AdmissionController::new(4, 1).with_queue_depth(1));
+
+        let first = controller
+            .acquire(WorkKind::VideoThumbnail, Priority::Interactive)
+            .unwrap();
+
+        let queued = {
+            let controller = controller.clone();
+            thread::spawn(move || {
+                controller
+                    .acquire(WorkKind::VideoThumbnail, Priority::Interactive)
+                    .map(|_| ())
+            })
+        };
+        wait_for_queued(&controller, WorkKind::VideoThumbnail, 1);
+
+        let third = controller.acquire(WorkKind::VideoThumbnail, Priority::Interactive);
+        assert!(matches!(third, Err(StorageError::Busy { .. })));
+
+        assert_eq!(
+            LaneStats {
+                in_flight: 1,
+                queued: 1
+            },
+            controller.stats().video
+        );
+
+        drop(first);
+        assert!(queued.join().unwrap().is_ok());
+        assert_eq!(LaneStats::default(), controller.stats().video);
+    }
+
+    #[test]
+    fn test_interactive_admitted_before_maintenance() {
+        let controller = Arc::new(AdmissionController::new(1, 1));
+        let order = Arc::new(Mutex::new(vec![]));
+
+        let held = controller
+            .acquire(WorkKind::ImageDecode, Priority::Interactive)
+            .unwrap();
+
+        let spawn_processor = |priority: Priority| {
+            let controller = controller.clone();
+            let order = order.clone();
+            thread::spawn(move || {
+                let _permit = controller.acquire(WorkKind::ImageDecode, priority).unwrap();
+                order.lock().unwrap().push(priority);
+            })
+        };
+
+        let maintenance = spawn_processor(Priority::Maintenance);
+        wait_for_queued(&controller, WorkKind::ImageDecode, 1);
+        let interactive = spawn_processor(Priority::Interactive);
+        wait_for_queued(&controller, WorkKind::ImageDecode, 2);
+
+        drop(held);
+        maintenance.join().unwrap();
+        interactive.join().unwrap();
+
+        assert_eq!(
+            vec![Priority::Interactive, Priority::Maintenance],
+            *order.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_image_lane_unaffected_by_saturated_video_lane() {
+        let controller = AdmissionController::new(1, 1).with_queue_depth(0);
+
+        let _video = controller
+            .acquire(WorkKind::VideoThumbnail, Priority::Interactive)
+            .unwrap();
+        assert!(
+            controller
+                .acquire(WorkKind::VideoThumbnail, Priority::Interactive)
+                .is_err()
+        );
+
+        assert!(
+            controller
+                .acquire(WorkKind::ImageDecode, Priority::Interactive)
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Pluggable object stores behind `Storage`.
+//!
+//! A `StorageBackend` stores opaque objects under keys, which are relative paths
+//! separated by `/` such as `44/a5/44a5b6f94f4f6445.png`, the same relative paths
+//! returned by `Storage::index_file`. `FilesystemBackend` keeps the objects below a
+//! root directory and is the default. `MemoryBackend` keeps them in memory, which
+//! lets tests avoid the disk. With the `s3` feature, `S3Backend` keeps them in an S3
+//! compatible bucket.
+//!
+//! Hashing, decoding and thumbnail generation still work on the local root of the
+//! `Storage`, since they need seekable files, so the root keeps a working copy of the
+//! stored files, which `Storage::index_file` and `Storage::get_metadata` read. A
+//! backend set with `Storage::with_backend` receives the files with
+//! `Storage::push_to_backend` once they are stored, loses them with `Storage::delete`,
+//! and is where they are read from when they are served, see `Storage::backend`.
+//! Generated variants stay in the root, since they are regenerated on demand.
+
+use super::{MediaPath, PixelHash, Storage, StorageError};
+use futures::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io,
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+#[cfg(feature = "s3")]
+mod s3;
+
+#[cfg(feature = "s3")]
+pub use s3::S3Backend;
+
+/// An asynchronous object store.
+///
+/// Implementations must be safe to share between tasks. Deleting a missing object
+/// succeeds, like `Storage::ensure_deleted`.
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Stores the bytes under the key, replacing an existing object.
+    fn write<'a>(&'a self, key: &'a str, bytes: Vec<u8>)
+    -> BoxFuture<'a, Result<(), StorageError>>;
+
+    /// Reads the object stored under the key.
+    ///
+    /// # Errors
+    /// - `StorageError::Io` with `io::ErrorKind::NotFound` if there is no such object.
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Vec<u8>, StorageError>>;
+
+    /// Deletes the object stored under the key, if any.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>>;
+
+    /// Returns whether an object is stored under the key.
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, StorageError>>;
+}
+
+impl Storage {
+    /// Copies the stored files of the hash to the backend set with `with_backend`,
+    /// under the relative paths returned by `index_file`. Without such a backend,
+    /// this does nothing.
+    ///
+    /// # Arguments
+    /// * `hash` - The pixel hash of the stored files to copy.
+    ///
+    /// # Errors
+    /// - `StorageError::FileNotFound` if no file is stored for the hash.
+    /// - `StorageError::Io` if a file cannot be read or written to the backend.
+    pub async fn push_to_backend(&self, hash: &PixelHash) -> Result<(), StorageError> {
+        if !self.remote_backend {
+            return Ok(());
+        }
+
+        let objects = self
+            .objects(hash)
+            .ok_or_else(|| StorageError::FileNotFound { hash: hash.clone() })?;
+        for (path, key) in objects {
+            let bytes = tokio::fs::read(path).await?;
+            self.backend.write(&key, bytes).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the stored files of the hash like `ensure_deleted`, and from the
+    /// backend set with `with_backend`, if any.
+    ///
+    /// The files are deleted from the root directory on the blocking thread pool.
+    ///
+    /// # Arguments
+    /// * `hash` - The pixel hash of the files to delete.
+    ///
+    /// # Errors
+    /// - `StorageError::Io` if a file cannot be deleted.
+    pub async fn delete(&self, hash: &PixelHash) -> Result<(), StorageError> {
+        let objects = self.objects(hash).unwrap_or_default();
+        let local = hash.clone();
+        self.run_blocking(move |storage| storage.ensure_deleted(&local))
+            .await?;
+
+        if self.remote_backend {
+            for (_, key) in objects {
+                self.backend.delete(&key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the absolute path and the object key of each stored file of the hash.
+    fn objects(&self, hash: &PixelHash) -> Option<Vec<(PathBuf, String)>> {
+        let paths = match self.find_entry(hash)? {
+            MediaPath::Image(path) | MediaPath::Raw(path) => vec![path],
+            MediaPath::Video { video, thumb } => vec![video, thumb],
+        };
+
+        paths
+            .into_iter()
+            .map(|path| {
+                let name = path.file_name()?.to_str()?;
+                let key = self
+                    .derive_dir(hash)
+                    .components()
+                    .filter_map(|c| c.as_os_str().to_str())
+                    .chain([name])
+                    .collect::<Vec<_>>()
+                    .join("/");
+                Some((path, key))
+            })
+            .collect()
+    }
+}
+
+/// Stores objects as files below a root directory.
+#[derive(Debug, Clone)]
+pub struct FilesystemBackend {
+    root: PathBuf,
+}
+
+impl FilesystemBackend {
+    /// Creates a backend storing objects below the given root directory.
+    pub fn new(root: PathBuf) -> FilesystemBackend {
+        FilesystemBackend { root }
+    }
+
+    /// Resolves a key to a path below the root, rejecting keys that would escape it.
+    fn resolve(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let relative = Path::new(key);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid object key: {key}"),
+            )
+            .into());
+        }
+
+        Ok(self.root.join(relative))
+    }
+}
+
+impl StorageBackend for FilesystemBackend {
+    fn write<'a>(
+        &'a self,
+        key: &'a str,
+        bytes: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            let path = self.resolve(key)?;
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            tokio::fs::write(path, bytes).await?;
+            Ok(())
+        })
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Vec<u8>, StorageError>> {
+        Box::pin(async move { Ok(tokio::fs::read(self.resolve(key)?).await?) })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            match tokio::fs::remove_file(self.resolve(key)?).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, StorageError>> {
+        Box::pin(async move { Ok(tokio::fs::try_exists(self.resolve(key)?).await?) })
+    }
+}
+
+/// Stores objects in memory, e.g. for tests.
+///
+/// Keys are not validated, since they never become paths. The objects are lost when
+/// the backend is dropped.
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryBackend {
+    /// Creates an empty backend.
+    pub fn new() -> MemoryBackend {
+        MemoryBackend::default()
+    }
+
+    /// Returns the number of stored objects.
+    pub fn len(&self) -> usize {
+        self.objects().len()
+    }
+
+    /// Returns whether no objects are stored.
+    pub fn is_empty(&self) -> bool {
+        self.objects().is_empty()
+    }
+
+    fn objects(&self) -> MutexGuard<'_, HashMap<String, Vec<u8>>> {
+        self.objects.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn write<'a>(
+        &'a self,
+        key: &'a str,
+        bytes: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        self.objects().insert(key.to_string(), bytes);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Vec<u8>, StorageError>> {
+        let bytes: Result<_, StorageError> = self.objects().get(key).cloned().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no such object: {key}")).into()
+        });
+        Box::pin(async { bytes })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        self.objects().remove(key);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, StorageError>> {
+        let exists = self.objects().contains_key(key);
+        Box::pin(async move { Ok(exists) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilesystemBackend, MemoryBackend, StorageBackend};
+    use crate::storage::{MediaPath, Storage, StorageError};
+    use std::{io, sync::Arc};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_filesystem_backend() {
+        let dir = TempDir::new().unwrap();
+        let backend = FilesystemBackend::new(dir.path().to_path_buf());
+        let key = "44/a5/44a5b6f94f4f6445.png";
+
+        assert!(!backend.exists(key).await.unwrap());
+        assert!(matches!(
+            backend.read(key).await,
+            Err(StorageError::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+
+        backend.write(key, b"first".to_vec()).await.unwrap();
+        backend.write(key, b"second".to_vec()).await.unwrap();
+        assert!(backend.exists(key).await.unwrap());
+        assert_eq!(b"second".to_vec(), backend.read(key).await.unwrap());
+        assert!(dir.path().join(key).exists());
+
+        backend.delete(key).await.unwrap();
+        backend.delete(key).await.unwrap();
+        assert!(!backend.exists(key).await.unwrap());
+
+        assert!(backend.read("../outside").await.is_err());
+        assert!(backend.write("/etc/passwd", vec![]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend() {
+        let backend = MemoryBackend::new();
+        let key = "44/a5/44a5b6f94f4f6445.png";
+
+        assert!(backend.is_empty());
+        assert!(!backend.exists(key).await.unwrap());
+        assert!(matches!(
+            backend.read(key).await,
+            Err(StorageError::Io(e)) if e.kind() == io::ErrorKind::NotFound
+        ));
+
+        backend.write(key, b"first".to_vec()).await.unwrap();
+        backend.write(key, b"second".to_vec()).await.unwrap();
+        assert_eq!(1, backend.len());
+        assert!(backend.exists(key).await.unwrap());
+        assert_eq!(b"second".to_vec(), backend.read(key).await.unwrap());
+
+        backend.delete(key).await.unwrap();
+        backend.delete(key).await.unwrap();
+        assert!(!backend.exists(key).await.unwrap());
+        assert!(backend.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_push_to_backend() {
+        let (root, bucket) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let backend = Arc::new(FilesystemBackend::new(bucket.path().to_path_buf()));
+        let storage = Storage::new(root.path().to_path_buf()).with_backend(backend.clone());
+
+        let (hash, _) = storage
+            .create_file(include_bytes!("../../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+        let Some(MediaPath::Image(path)) = storage.index_file(&hash) else {
+            panic!("expected an image");
+        };
+        let key = path.to_str().unwrap().replace('\\', "/");
+        assert!(!backend.exists(&key).await.unwrap());
+
+        storage.push_to_backend(&hash).await.unwrap();
+        assert_eq!(
+            std::fs::read(root.path().join(&path)).unwrap(),
+            backend.read(&key).await.unwrap()
+        );
+
+        storage.delete(&hash).await.unwrap();
+        assert!(!backend.exists(&key).await.unwrap());
+        assert!(storage.index_file(&hash).is_none());
+        storage.delete(&hash).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_push_without_backend() {
+        let root = TempDir::new().unwrap();
+        let storage = Storage::new(root.path().to_path_buf());
+        let (hash, _) = storage
+            .create_file(include_bytes!("../../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+
+        storage.push_to_backend(&hash).await.unwrap();
+        assert!(storage.index_file(&hash).is_some());
+
+        storage.delete(&hash).await.unwrap();
+        assert!(storage.index_file(&hash).is_none());
+    }
+}
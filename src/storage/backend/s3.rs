@@ -0,0 +1,87 @@
+use super::StorageBackend;
+use crate::storage::StorageError;
+use futures::future::BoxFuture;
+use object_store::{ObjectStore, PutPayload, aws::AmazonS3Builder, path::Path as ObjectPath};
+use std::{io, sync::Arc};
+
+/// Stores objects in an S3 compatible bucket.
+#[derive(Debug, Clone)]
+pub struct S3Backend {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl S3Backend {
+    /// Creates a backend over an already configured object store, e.g. one built
+    /// with a custom endpoint for MinIO or R2.
+    pub fn new(store: Arc<dyn ObjectStore>) -> S3Backend {
+        S3Backend { store }
+    }
+
+    /// Creates a backend for the bucket, reading credentials, region and endpoint
+    /// from the standard `AWS_*` environment variables.
+    pub fn from_env(bucket: &str) -> Result<S3Backend, StorageError> {
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()
+            .map_err(into_storage_error)?;
+
+        Ok(S3Backend::new(Arc::new(store)))
+    }
+}
+
+impl StorageBackend for S3Backend {
+    fn write<'a>(
+        &'a self,
+        key: &'a str,
+        bytes: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            self.store
+                .put(&ObjectPath::from(key), PutPayload::from(bytes))
+                .await
+                .map_err(into_storage_error)?;
+            Ok(())
+        })
+    }
+
+    fn read<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Vec<u8>, StorageError>> {
+        Box::pin(async move {
+            let object = self
+                .store
+                .get(&ObjectPath::from(key))
+                .await
+                .map_err(into_storage_error)?;
+            let bytes = object.bytes().await.map_err(into_storage_error)?;
+            Ok(bytes.to_vec())
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<(), StorageError>> {
+        Box::pin(async move {
+            match self.store.delete(&ObjectPath::from(key)).await {
+                Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+                Err(e) => Err(into_storage_error(e)),
+            }
+        })
+    }
+
+    fn exists<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<bool, StorageError>> {
+        Box::pin(async move {
+            match self.store.head(&ObjectPath::from(key)).await {
+                Ok(_) => Ok(true),
+                Err(object_store::Error::NotFound { .. }) => Ok(false),
+                Err(e) => Err(into_storage_error(e)),
+            }
+        })
+    }
+}
+
+/// Maps object store errors to I/O errors, keeping `NotFound` recognizable.
+fn into_storage_error(e: object_store::Error) -> StorageError {
+    let kind = match e {
+        object_store::Error::NotFound { .. } => io::ErrorKind::NotFound,
+        _ => io::ErrorKind::Other,
+    };
+
+    io::Error::new(kind, e).into()
+}
@@ -0,0 +1,177 @@
+//! Decoding of camera RAW files, with the `raw-images` feature.
+//!
+//! Neither `infer` nor `image` decode the sensor data of RAW files. `rawloader` reads
+//! it, and the color filter array is demosaiced into an RGB image at half the sensor
+//! resolution, one pixel per 2x2 block. The pixel hash and the perceptual hash are
+//! computed from that image, so the same shot hashes the same whatever container it
+//! came in. RAW files are stored like videos: the original file as it is, alongside
+//! the decoded image as thumbnail.
+//!
+//! Canon CR2 files are detected by `infer`. Nikon NEF and Sony ARW files sniff as
+//! TIFF, so TIFF uploads are also tried as RAW files, and decoded as plain TIFFs if
+//! `rawloader` does not recognize their camera.
+
+use image::{DynamicImage, RgbImage};
+use rawloader::{RawImage, RawImageData};
+use std::{io::Cursor, path::Path};
+
+/// Returns whether an upload of the detected type may be a camera RAW file.
+pub(super) fn is_candidate(kind: infer::Type) -> bool {
+    matches!(kind.extension(), "cr2" | "tif")
+}
+
+/// Returns whether a stored file is a camera RAW file, judging by its extension.
+pub(super) fn is_raw_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ["cr2", "nef", "arw"].contains(&e))
+}
+
+/// Decodes a camera RAW file into an RGB image.
+///
+/// # Returns
+/// The decoded image and the type the file is stored as, or `None` if the file is
+/// not a RAW file of a supported camera.
+pub(super) fn decode(bytes: &[u8]) -> Option<(DynamicImage, infer::Type)> {
+    let raw = rawloader::decode(&mut Cursor::new(bytes)).ok()?;
+    let kind = raw_type(&raw.clean_make)?;
+    let image = demosaic(&raw)?;
+
+    Some((DynamicImage::ImageRgb8(image), kind))
+}
+
+/// Returns the type of the RAW files written by cameras of the given make.
+fn raw_type(make: &str) -> Option<infer::Type> {
+    let (mime_type, extension) = match make {
+        "Canon" => ("image/x-canon-cr2", "cr2"),
+        "Nikon" => ("image/x-nikon-nef", "nef"),
+        "Sony" => ("image/x-sony-arw", "arw"),
+        _ => return None,
+    };
+
+    Some(infer::Type::new(
+        infer::MatcherType::Image,
+        mime_type,
+        extension,
+        |_| false,
+    ))
+}
+
+/// Scales the sensor data to RGB pixels, or returns `None` for unsupported layouts.
+fn demosaic(raw: &RawImage) -> Option<RgbImage> {
+    let data: Vec<f32> = match &raw.data {
+        RawImageData::Integer(data) => data.iter().copied().map(f32::from).collect(),
+        RawImageData::Float(data) => data.clone(),
+    };
+
+    // Levels are indexed by color, where 3 is the second green of some sensors.
+    let level = |value: f32, color: usize| {
+        let black = f32::from(raw.blacklevels[color]);
+        let white = f32::from(raw.whitelevels[color]);
+        ((value - black) / (white - black).max(1.0)).clamp(0.0, 1.0)
+    };
+    let balance = white_balance(raw.wb_coeffs);
+
+    match raw.cpp {
+        1 => Some(superpixels(raw.width, raw.height, balance, |row, col| {
+            let color = raw.cfa.color_at(row, col);
+            let channel = if color == 3 { 1 } else { color };
+            (channel, level(data[row * raw.width + col], color))
+        })),
+        3 => RgbImage::from_raw(
+            raw.width as u32,
+            raw.height as u32,
+            data.chunks_exact(3)
+                .flat_map(|rgb| [0, 1, 2].map(|c| encode(level(rgb[c], c) * balance[c])))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Normalizes the camera white balance to green, falling back to none if unknown.
+fn white_balance(coeffs: [f32; 4]) -> [f32; 3] {
+    [0, 1, 2].map(|c| {
+        let coeff = coeffs[c] / coeffs[1];
+        if coeff.is_finite() && coeff > 0.0 {
+            coeff
+        } else {
+            1.0
+        }
+    })
+}
+
+/// Averages every 2x2 block of a color filter array into one RGB pixel.
+///
+/// `sample` returns the channel (0 red, 1 green, 2 blue) and the linear value
+/// between 0 and 1 of the sensel at a row and column.
+fn superpixels(
+    width: usize,
+    height: usize,
+    balance: [f32; 3],
+    sample: impl Fn(usize, usize) -> (usize, f32),
+) -> RgbImage {
+    RgbImage::from_fn((width / 2) as u32, (height / 2) as u32, |x, y| {
+        let (x, y) = (x as usize * 2, y as usize * 2);
+        let mut sums = [0.0; 3];
+        let mut counts = [0.0; 3];
+        for (row, col) in [(y, x), (y, x + 1), (y + 1, x), (y + 1, x + 1)] {
+            let (channel, value) = sample(row, col);
+            sums[channel] += value;
+            counts[channel] += 1.0;
+        }
+
+        image::Rgb([0, 1, 2].map(|c| {
+            let mean = if counts[c] > 0.0 {
+                sums[c] / counts[c]
+            } else {
+                0.0
+            };
+            encode(mean * balance[c])
+        }))
+    })
+}
+
+/// Gamma encodes a linear value into 8 bits.
+fn encode(linear: f32) -> u8 {
+    (linear.clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_candidate, is_raw_file, raw_type, superpixels, white_balance};
+    use std::path::Path;
+
+    #[test]
+    fn test_superpixels() {
+        // An RGGB pattern of a pure red 4x2 sensor.
+        let image = superpixels(4, 2, [1.0; 3], |row, col| match (row % 2, col % 2) {
+            (0, 0) => (0, 1.0),
+            (1, 1) => (2, 0.0),
+            _ => (1, 0.0),
+        });
+
+        assert_eq!((2, 1), image.dimensions());
+        assert!(image.pixels().all(|p| p.0 == [255, 0, 0]));
+    }
+
+    #[test]
+    fn test_raw_type() {
+        assert_eq!("nef", raw_type("Nikon").unwrap().extension());
+        assert_eq!("image/x-sony-arw", raw_type("Sony").unwrap().mime_type());
+        assert!(raw_type("Leica").is_none());
+        assert!(is_raw_file(Path::new("44/a5/44a5b6f94f4f6445.nef")));
+        assert!(!is_raw_file(Path::new("44/a5/44a5b6f94f4f6445.tif")));
+
+        assert!(is_candidate(
+            infer::get(b"II*\x00\x10\x00\x00\x00CR\x02").unwrap()
+        ));
+        assert!(!is_candidate(infer::get(b"\x89PNG\r\n\x1a\n").unwrap()));
+    }
+
+    #[test]
+    fn test_white_balance() {
+        assert_eq!([2.0, 1.0, 1.5], white_balance([4.0, 2.0, 3.0, f32::NAN]));
+        assert_eq!([1.0, 1.0, 1.0], white_balance([f32::NAN; 4]));
+    }
+}
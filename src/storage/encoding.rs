@@ -0,0 +1,291 @@
+//! Encoder settings per stored image format.
+//!
+//! Stored images and generated thumbnails are encoded with the `image` crate's
+//! default settings unless `Storage::with_format_options` configures an encoder for
+//! their format. This trades file size against quality and encoding time per format,
+//! e.g. a slower PNG compression for an archive that is written once and read often.
+//!
+//! Encoders of the `image` crate drop the EXIF data of the original file. It is
+//! carried over into stored JPEGs, so camera details, capture times and orientation
+//! can still be read from the stored file.
+
+use super::StorageError;
+use image::{
+    DynamicImage, ImageFormat,
+    codecs::{
+        avif::AvifEncoder,
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+    },
+};
+use std::{collections::HashMap, fs, io::Cursor, path::Path};
+
+/// Encoder settings for one image format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderOptions {
+    /// PNG compression effort and row filter.
+    Png {
+        compression: CompressionType,
+        filter: FilterType,
+    },
+    /// JPEG quality, from 1 (smallest) to 100 (best).
+    Jpeg { quality: u8 },
+    /// AVIF encoding speed, from 1 (smallest) to 10 (fastest), and quality, from 1 to
+    /// 100 (best).
+    Avif { speed: u8, quality: u8 },
+}
+
+impl EncoderOptions {
+    /// Returns the image format these settings apply to.
+    pub fn format(&self) -> ImageFormat {
+        match self {
+            EncoderOptions::Png { .. } => ImageFormat::Png,
+            EncoderOptions::Jpeg { .. } => ImageFormat::Jpeg,
+            EncoderOptions::Avif { .. } => ImageFormat::Avif,
+        }
+    }
+
+    /// Encodes the image with these settings.
+    fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>, StorageError> {
+        let mut bytes = vec![];
+        match *self {
+            EncoderOptions::Png {
+                compression,
+                filter,
+            } => img.write_with_encoder(PngEncoder::new_with_quality(
+                &mut bytes,
+                compression,
+                filter,
+            ))?,
+            EncoderOptions::Jpeg { quality } => {
+                img.write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality))?
+            }
+            EncoderOptions::Avif { speed, quality } => img.write_with_encoder(
+                AvifEncoder::new_with_speed_quality(&mut bytes, speed, quality),
+            )?,
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Encoder settings keyed by image format.
+///
+/// Formats without settings are encoded with the `image` crate's defaults.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormatOptions {
+    options: HashMap<ImageFormat, EncoderOptions>,
+}
+
+impl FormatOptions {
+    /// Creates an empty set of settings, encoding every format with its defaults.
+    pub fn new() -> FormatOptions {
+        FormatOptions::default()
+    }
+
+    /// Sets the encoder settings of a format, replacing earlier settings of it.
+    pub fn with(mut self, options: EncoderOptions) -> FormatOptions {
+        self.options.insert(options.format(), options);
+        self
+    }
+
+    /// Returns the encoder settings of a format, if any.
+    pub fn get(&self, format: ImageFormat) -> Option<&EncoderOptions> {
+        self.options.get(&format)
+    }
+
+    /// Encodes the image in the given format into a file.
+    pub(super) fn save(
+        &self,
+        img: &DynamicImage,
+        path: &Path,
+        format: ImageFormat,
+    ) -> Result<(), StorageError> {
+        match self.get(format) {
+            Some(options) => fs::write(path, options.encode(img)?)?,
+            None => img.save_with_format(path, format)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the raw EXIF data, i.e. the TIFF structure, embedded in an image file.
+pub(super) fn read_exif(bytes: &[u8]) -> Option<Vec<u8>> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(bytes))
+        .ok()?;
+
+    Some(exif.buf().to_vec())
+}
+
+/// Sets the orientation in raw EXIF data to 1, i.e. upright, in place.
+///
+/// Once the pixels of an image are rotated to match its orientation, the orientation
+/// must no longer be applied by clients. Data without an orientation is left as is.
+pub(super) fn reset_exif_orientation(exif: &mut [u8]) {
+    const ORIENTATION: u16 = 0x0112;
+    const SHORT: u16 = 3;
+
+    let big_endian = match exif.get(..4) {
+        Some(b"MM\0*") => true,
+        Some(b"II*\0") => false,
+        _ => return,
+    };
+    let u16_at = |exif: &[u8], at: usize| {
+        let bytes = exif.get(at..at + 2)?.try_into().ok()?;
+        Some(match big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    };
+    let Some(ifd) = exif
+        .get(4..8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(|bytes| match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    else {
+        return;
+    };
+
+    // Entries of the primary IFD are 12 bytes: tag, type, count and value. A single
+    // SHORT is stored in the first two bytes of the value.
+    let ifd = ifd as usize;
+    let count = u16_at(exif, ifd).unwrap_or_default();
+    for entry in (0..usize::from(count)).map(|i| ifd + 2 + 12 * i) {
+        if u16_at(exif, entry) == Some(ORIENTATION) {
+            let upright = match big_endian {
+                true => 1u16.to_be_bytes(),
+                false => 1u16.to_le_bytes(),
+            };
+            if u16_at(exif, entry + 2) == Some(SHORT)
+                && let Some(value) = exif.get_mut(entry + 8..entry + 10)
+            {
+                value.copy_from_slice(&upright);
+            }
+            return;
+        }
+    }
+}
+
+/// Embeds raw EXIF data into a JPEG as an APP1 segment right after its start marker.
+///
+/// Returns `None` if the bytes are not a JPEG, or if the data does not fit into a
+/// single segment.
+pub(super) fn embed_jpeg_exif(jpeg: &[u8], exif: &[u8]) -> Option<Vec<u8>> {
+    const SOI: [u8; 2] = [0xff, 0xd8];
+    const APP1: [u8; 2] = [0xff, 0xe1];
+    const HEADER: &[u8] = b"Exif\0\0";
+
+    let body = jpeg.strip_prefix(&SOI)?;
+    // The segment length counts itself, the header and the data.
+    let len = u16::try_from(2 + HEADER.len() + exif.len()).ok()?;
+
+    let mut bytes = Vec::with_capacity(jpeg.len() + usize::from(len) + 2);
+    bytes.extend(SOI);
+    bytes.extend(APP1);
+    bytes.extend(len.to_be_bytes());
+    bytes.extend(HEADER);
+    bytes.extend(exif);
+    bytes.extend(body);
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        EncoderOptions, FormatOptions, embed_jpeg_exif, read_exif, reset_exif_orientation,
+    };
+    use exif::{Field, In, Tag, Value, experimental::Writer};
+    use image::{
+        GenericImageView, ImageBuffer, ImageFormat, Rgb,
+        codecs::png::{CompressionType, FilterType},
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_format_options() {
+        let png = EncoderOptions::Png {
+            compression: CompressionType::Best,
+            filter: FilterType::Paeth,
+        };
+        let options = FormatOptions::new()
+            .with(EncoderOptions::Jpeg { quality: 50 })
+            .with(EncoderOptions::Jpeg { quality: 90 })
+            .with(png);
+
+        assert_eq!(Some(&png), options.get(ImageFormat::Png));
+        assert_eq!(
+            Some(&EncoderOptions::Jpeg { quality: 90 }),
+            options.get(ImageFormat::Jpeg)
+        );
+        assert_eq!(None, options.get(ImageFormat::WebP));
+    }
+
+    #[test]
+    fn test_embed_jpeg_exif() {
+        let mut jpeg = vec![];
+        ImageBuffer::from_pixel(8, 8, Rgb([200u8, 100, 50]))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+        assert_eq!(None, read_exif(&jpeg));
+
+        let mut writer = Writer::new();
+        let orientation = Field {
+            tag: Tag::Orientation,
+            ifd_num: In::PRIMARY,
+            value: Value::Short(vec![6]),
+        };
+        writer.push_field(&orientation);
+        let mut tiff = Cursor::new(vec![]);
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        let embedded = embed_jpeg_exif(&jpeg, &tiff).unwrap();
+        assert_eq!(Some(tiff.clone()), read_exif(&embedded));
+        assert_eq!(
+            (8, 8),
+            image::load_from_memory(&embedded).unwrap().dimensions()
+        );
+
+        assert_eq!(None, embed_jpeg_exif(b"not a jpeg", &tiff));
+        assert_eq!(None, embed_jpeg_exif(&jpeg, &vec![0; u16::MAX as usize]));
+    }
+
+    #[test]
+    fn test_reset_exif_orientation() {
+        for little_endian in [false, true] {
+            let mut writer = Writer::new();
+            let make = Field {
+                tag: Tag::Make,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"Canon".to_vec()]),
+            };
+            let orientation = Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![6]),
+            };
+            writer.push_field(&make);
+            writer.push_field(&orientation);
+            let mut tiff = Cursor::new(vec![]);
+            writer.write(&mut tiff, little_endian).unwrap();
+            let mut tiff = tiff.into_inner();
+
+            reset_exif_orientation(&mut tiff);
+
+            let exif = exif::Reader::new().read_raw(tiff).unwrap();
+            let field = |tag| exif.get_field(tag, In::PRIMARY).unwrap();
+            assert_eq!(Some(1), field(Tag::Orientation).value.get_uint(0));
+            assert!(matches!(&field(Tag::Make).value, Value::Ascii(v) if v[0] == b"Canon"));
+        }
+
+        // Data without an orientation, or no EXIF data at all, is left as is.
+        let mut bytes = b"not exif".to_vec();
+        reset_exif_orientation(&mut bytes);
+        assert_eq!(b"not exif".to_vec(), bytes);
+    }
+}
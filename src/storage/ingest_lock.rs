@@ -0,0 +1,300 @@
+//! Advisory locks for concurrent ingests of likely identical content.
+//!
+//! Two concurrent uploads of the same large video both decode it fully before either
+//! reaches the collision check. With `Storage::with_ingest_locks`, uploads are keyed
+//! by their length and a quick hash of their first bytes, and uploads with the same
+//! key run one at a time. An upload that waited for an identical one then finds the
+//! stored pixel hash without decoding, and fails with `StorageError::HashCollision`
+//! right away.
+//!
+//! Uploads that merely share a key, e.g. videos with a common header, still decode;
+//! they only lose the concurrency between them. A key is forgotten once nobody holds
+//! or waits for it, so only overlapping uploads skip the decode.
+//!
+//! Waiting for a key blocks the thread. Async callers go through
+//! `Storage::create_or_get_async` or `Storage::create_file_from_async_reader`, which
+//! reserve keys on the blocking thread pool.
+
+use super::PixelHash;
+use std::{
+    collections::HashMap,
+    hash::Hasher,
+    io::{self, Read},
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+use twox_hash::XxHash64;
+
+/// The number of independently locked partitions of the key map.
+const SHARDS: usize = 16;
+
+/// A point-in-time snapshot of the ingest locks, suitable for exporting as metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IngestLockStats {
+    /// The number of uploads currently waiting for an upload with the same key.
+    pub waiting: usize,
+    /// The number of uploads rejected as duplicates without being decoded.
+    pub skipped_decodes: u64,
+}
+
+/// In-process locks keyed by a pre-hash of the uploaded bytes.
+#[derive(Debug)]
+pub(super) struct IngestLocks {
+    prefix_len: usize,
+    shards: Vec<Mutex<HashMap<u64, Arc<Slot>>>>,
+    waiting: AtomicUsize,
+    skipped_decodes: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    state: Mutex<SlotState>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct SlotState {
+    busy: bool,
+    /// The digest of the full bytes and the pixel hash of the last upload.
+    last: Option<(u64, PixelHash)>,
+}
+
+impl IngestLocks {
+    /// Creates locks keyed by the first `prefix_len` bytes of each upload.
+    pub(super) fn new(prefix_len: usize) -> IngestLocks {
+        IngestLocks {
+            prefix_len,
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            waiting: AtomicUsize::new(0),
+            skipped_decodes: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits until no other upload with the same key is in flight, and reserves the
+    /// key until the returned reservation is dropped.
+    ///
+    /// This blocks the calling thread, so it must not run on an async worker.
+    pub(super) fn reserve(&self, bytes: &[u8]) -> IngestReservation<'_> {
+        let len = bytes.len() as u64;
+        let key = digest(&bytes[..bytes.len().min(self.prefix_len)], len);
+        self.reserve_key(key, digest(bytes, len))
+    }
+
+    /// Reserves the key of an upload of `len` bytes that is read from `reader`,
+    /// without holding the upload in memory.
+    pub(super) fn reserve_reader<R: Read>(
+        &self,
+        mut reader: R,
+        len: u64,
+    ) -> io::Result<IngestReservation<'_>> {
+        let mut head = vec![];
+        (&mut reader)
+            .take(self.prefix_len as u64)
+            .read_to_end(&mut head)?;
+
+        // The hasher is streaming, so writing the upload in chunks digests it as a whole.
+        let mut hasher = XxHash64::with_seed(len);
+        hasher.write(&head);
+        let mut chunk = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut chunk)? {
+                0 => break,
+                n => hasher.write(&chunk[..n]),
+            }
+        }
+
+        Ok(self.reserve_key(digest(&head, len), hasher.finish()))
+    }
+
+    fn reserve_key(&self, key: u64, digest: u64) -> IngestReservation<'_> {
+        let shard = (key % SHARDS as u64) as usize;
+        let slot = lock(&self.shards[shard]).entry(key).or_default().clone();
+
+        let mut state = lock(&slot.state);
+        if state.busy {
+            self.waiting.fetch_add(1, Ordering::Relaxed);
+            state = slot
+                .released
+                .wait_while(state, |s| s.busy)
+                .unwrap_or_else(PoisonError::into_inner);
+            self.waiting.fetch_sub(1, Ordering::Relaxed);
+        }
+        state.busy = true;
+        drop(state);
+
+        IngestReservation {
+            locks: self,
+            shard,
+            key,
+            slot,
+            digest,
+        }
+    }
+
+    /// Returns a snapshot of the current waiters and skipped decodes.
+    pub(super) fn stats(&self) -> IngestLockStats {
+        IngestLockStats {
+            waiting: self.waiting.load(Ordering::Relaxed),
+            skipped_decodes: self.skipped_decodes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A reserved ingest key, released when dropped.
+#[derive(Debug)]
+pub(super) struct IngestReservation<'a> {
+    locks: &'a IngestLocks,
+    shard: usize,
+    key: u64,
+    slot: Arc<Slot>,
+    digest: u64,
+}
+
+impl IngestReservation<'_> {
+    /// Returns the pixel hash of an identical upload that held the key before.
+    pub(super) fn previous_hash(&self) -> Option<PixelHash> {
+        match &lock(&self.slot.state).last {
+            Some((digest, hash)) if *digest == self.digest => Some(hash.clone()),
+            _ => None,
+        }
+    }
+
+    /// Records the pixel hash of this upload for uploads waiting on the same key.
+    pub(super) fn record(&self, hash: PixelHash) {
+        lock(&self.slot.state).last = Some((self.digest, hash));
+    }
+
+    /// Counts this upload as rejected without being decoded.
+    pub(super) fn skip_decode(&self) {
+        self.locks.skipped_decodes.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for IngestReservation<'_> {
+    fn drop(&mut self) {
+        lock(&self.slot.state).busy = false;
+        self.slot.released.notify_one();
+
+        // The map and this reservation hold the only references when nobody waits,
+        // and new references are only taken under the shard lock.
+        let mut shard = lock(&self.locks.shards[self.shard]);
+        if Arc::strong_count(&self.slot) == 2 {
+            shard.remove(&self.key);
+        }
+    }
+}
+
+fn digest(bytes: &[u8], seed: u64) -> u64 {
+    let mut hasher = XxHash64::with_seed(seed);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IngestLocks;
+    use crate::storage::{IngestLockStats, PixelHash, Priority, Storage, StorageError};
+    use std::{thread, time::Duration};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_concurrent_identical_uploads_decode_once() {
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf()).with_ingest_locks(64 * 1024);
+        let bytes = include_bytes!("../../testdata/44a5b6f94f4f6445.png");
+
+        // Hold the key as an in-flight upload would, and let a second upload wait on it.
+        let locks = storage.ingest_locks.as_deref().unwrap();
+        let reservation = locks.reserve(bytes);
+        thread::scope(|s| {
+            let second = s.spawn(|| storage.create_file(bytes));
+            while locks.stats().waiting == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            let (hash, _) = Storage::new(dir.path().to_path_buf())
+                .create_file(bytes)
+                .unwrap();
+            reservation.record(hash.clone());
+            drop(reservation);
+
+            assert!(matches!(
+                second.join().unwrap(),
+                Err(StorageError::HashCollision { hash: h, .. }) if h == hash
+            ));
+        });
+
+        assert_eq!(
+            Some(IngestLockStats {
+                waiting: 0,
+                skipped_decodes: 1,
+            }),
+            storage.ingest_lock_stats()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_upload_waits_off_the_runtime() {
+        let dir = TempDir::new().unwrap();
+        let storage = Storage::new(dir.path().to_path_buf()).with_ingest_locks(64 * 1024);
+        let bytes = include_bytes!("../../testdata/44a5b6f94f4f6445.png");
+
+        let locks = storage.ingest_locks.as_deref().unwrap();
+        let reservation = locks.reserve(bytes);
+
+        // The test runtime has a single thread, which a waiting upload would block.
+        let second = tokio::spawn({
+            let storage = storage.clone();
+            async move {
+                storage
+                    .create_or_get_async(bytes.to_vec(), Priority::Interactive)
+                    .await
+            }
+        });
+        for _ in 0..500 {
+            if locks.stats().waiting == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        assert_eq!(1, locks.stats().waiting);
+
+        let (hash, _) = Storage::new(dir.path().to_path_buf())
+            .create_file(bytes)
+            .unwrap();
+        reservation.record(hash.clone());
+        drop(reservation);
+
+        let (report, created) = second.await.unwrap().unwrap();
+        assert_eq!((hash, false), (report.hash, created));
+    }
+
+    #[test]
+    fn test_previous_hash_requires_identical_bytes() {
+        let locks = IngestLocks::new(4);
+        let hash = PixelHash::from(1u64);
+
+        thread::scope(|s| {
+            let first = locks.reserve(b"head-aaaa");
+            let same = s.spawn(|| locks.reserve(b"head-aaaa").previous_hash());
+            let other = s.spawn(|| locks.reserve(b"head-bbbb").previous_hash());
+            while locks.stats().waiting < 2 {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            first.record(hash.clone());
+            drop(first);
+            assert_eq!(Some(hash.clone()), same.join().unwrap());
+            assert_eq!(None, other.join().unwrap());
+        });
+
+        // Keys are forgotten once nobody holds or waits for them.
+        assert!(locks.shards.iter().all(|s| s.lock().unwrap().is_empty()));
+    }
+}
@@ -0,0 +1,613 @@
+//! Metadata extraction dispatched by media kind.
+//!
+//! Each `MediaKind` runs a fixed list of extractors. An extractor fills in only the
+//! fields it knows about, and the partial results are merged into a single
+//! `ImageMetadata`. New fields are added by writing an extractor and registering it
+//! for the kinds it applies to, so e.g. still images never pay for a video probe.
+
+use super::{
+    DateTime, ExifData, ImageMetadata, MediaPath, StorageError, Utc, decode_animation, is_animation,
+};
+use chrono::{FixedOffset, NaiveDate, TimeZone};
+use exif::{Exif, In, Tag, Value};
+use image::GenericImageView;
+use std::{fs::File, io::BufReader, path::Path};
+use video_rs::Decoder;
+
+/// The kind of a stored media entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    /// A still image stored as a single file.
+    Image,
+    /// A video, animated GIF or WebP, or camera RAW file stored alongside a PNG thumbnail.
+    Video,
+    /// A file of another type, stored without decoding.
+    Raw,
+}
+
+impl MediaKind {
+    fn extractors(self) -> &'static [Extractor] {
+        match self {
+            MediaKind::Image => &[format, raster, file_stats, exif_data],
+            MediaKind::Video => &[format, raster, file_stats, video_stream],
+            MediaKind::Raw => &[format, file_stats],
+        }
+    }
+}
+
+impl MediaPath {
+    /// Returns the kind of media stored at this path.
+    pub fn kind(&self) -> MediaKind {
+        match self {
+            MediaPath::Image(_) => MediaKind::Image,
+            MediaPath::Video { .. } => MediaKind::Video,
+            MediaPath::Raw(_) => MediaKind::Raw,
+        }
+    }
+
+    /// Returns the path of the decodable still image, i.e. the thumbnail for videos.
+    ///
+    /// Raw files have no still image, and the file itself is returned.
+    fn raster_path(&self) -> &Path {
+        match self {
+            MediaPath::Image(path_buf) | MediaPath::Raw(path_buf) => path_buf,
+            MediaPath::Video { thumb, .. } => thumb,
+        }
+    }
+}
+
+/// The `created_at` of files whose creation time the filesystem does not report.
+///
+/// Some platforms and filesystems do not record creation times. Dating such files by
+/// their ingest time makes date filters reflect when a file was archived rather than
+/// when it was made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CreatedAtFallback {
+    /// Uses the time the metadata is extracted, i.e. about the ingest time.
+    #[default]
+    Now,
+    /// Uses the last modification time of the file, if the platform reports it.
+    Modified,
+    /// Leaves `created_at` unset, for callers that tell missing times apart. The
+    /// database records no time, so date filters leave the file out.
+    None,
+}
+
+impl CreatedAtFallback {
+    /// Returns the fallback creation time of the file at `path`.
+    fn resolve(self, path: &Path) -> Result<Option<DateTime<Utc>>, StorageError> {
+        Ok(match self {
+            CreatedAtFallback::Now => Some(Utc::now()),
+            CreatedAtFallback::Modified => {
+                std::fs::metadata(path)?.modified().ok().map(DateTime::from)
+            }
+            CreatedAtFallback::None => None,
+        })
+    }
+}
+
+type Extractor = fn(&MediaPath) -> Result<PartialMetadata, StorageError>;
+
+/// Metadata fields produced by a single extractor.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct PartialMetadata {
+    width: Option<u32>,
+    height: Option<u32>,
+    format: Option<String>,
+    color_type: Option<String>,
+    file_size: Option<u64>,
+    created_at: Option<DateTime<Utc>>,
+    duration: Option<f64>,
+    exif: Option<Box<ExifData>>,
+    orientation: Option<u8>,
+}
+
+impl PartialMetadata {
+    /// Combines two partial results, preferring fields set in `other`.
+    fn merge(self, other: PartialMetadata) -> PartialMetadata {
+        PartialMetadata {
+            width: other.width.or(self.width),
+            height: other.height.or(self.height),
+            format: other.format.or(self.format),
+            color_type: other.color_type.or(self.color_type),
+            file_size: other.file_size.or(self.file_size),
+            created_at: other.created_at.or(self.created_at),
+            duration: other.duration.or(self.duration),
+            exif: other.exif.or(self.exif),
+            orientation: other.orientation.or(self.orientation),
+        }
+    }
+}
+
+impl From<PartialMetadata> for ImageMetadata {
+    fn from(value: PartialMetadata) -> Self {
+        ImageMetadata {
+            width: value.width.unwrap_or_default(),
+            height: value.height.unwrap_or_default(),
+            format: value.format.unwrap_or_default(),
+            color_type: value.color_type.unwrap_or_default(),
+            file_size: value.file_size.unwrap_or_default(),
+            created_at: value.created_at,
+            duration: value.duration,
+            exif: value.exif,
+            orientation: value.orientation,
+            digest: None,
+        }
+    }
+}
+
+/// Runs every extractor registered for the entry's kind and merges the results.
+///
+/// If no extractor finds a creation time, it is taken from `fallback`.
+pub(super) fn extract(
+    entry: &MediaPath,
+    fallback: CreatedAtFallback,
+) -> Result<ImageMetadata, StorageError> {
+    let mut metadata = entry
+        .kind()
+        .extractors()
+        .iter()
+        .try_fold(PartialMetadata::default(), |acc, extractor| {
+            Ok::<_, StorageError>(acc.merge(extractor(entry)?))
+        })?;
+    if metadata.created_at.is_none() {
+        metadata.created_at = fallback.resolve(entry.raster_path())?;
+    }
+
+    Ok(metadata.into())
+}
+
+/// Extracts the format from the content file's extension.
+fn format(entry: &MediaPath) -> Result<PartialMetadata, StorageError> {
+    let extension = entry
+        .content_path()
+        .extension()
+        .expect("filepath must have a extention");
+
+    Ok(PartialMetadata {
+        format: Some(extension.to_string_lossy().to_string()),
+        ..Default::default()
+    })
+}
+
+/// Extracts dimensions and color type by decoding the still image.
+fn raster(entry: &MediaPath) -> Result<PartialMetadata, StorageError> {
+    let bytes = std::fs::read(entry.raster_path())?;
+    let img = image::load_from_memory(&bytes)?;
+    let (width, height) = img.dimensions();
+
+    Ok(PartialMetadata {
+        width: Some(width),
+        height: Some(height),
+        color_type: Some(format!("{:?}", img.color())),
+        ..Default::default()
+    })
+}
+
+/// Extracts the file size and filesystem creation timestamp of the still image.
+fn file_stats(entry: &MediaPath) -> Result<PartialMetadata, StorageError> {
+    let metadata = std::fs::metadata(entry.raster_path())?;
+
+    Ok(PartialMetadata {
+        file_size: Some(metadata.len()),
+        created_at: metadata.created().map(DateTime::from).ok(),
+        ..Default::default()
+    })
+}
+
+/// Extracts camera details and the orientation from embedded EXIF data.
+///
+/// The capture time replaces the filesystem timestamp as `created_at`, as it survives
+/// copies and downloads. Files without readable EXIF data yield no fields.
+fn exif_data(entry: &MediaPath) -> Result<PartialMetadata, StorageError> {
+    let mut reader = BufReader::new(File::open(entry.content_path())?);
+    let Ok(exif) = exif::Reader::new().read_from_container(&mut reader) else {
+        return Ok(PartialMetadata::default());
+    };
+
+    let data = ExifData {
+        camera_make: ascii(&exif, Tag::Make),
+        camera_model: ascii(&exif, Tag::Model),
+        gps_lat: coordinate(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+        gps_lon: coordinate(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+        taken_at: taken_at(&exif),
+        exposure: rational(&exif, Tag::ExposureTime),
+    };
+
+    Ok(PartialMetadata {
+        created_at: data.taken_at,
+        exif: (data != ExifData::default()).then(|| Box::new(data)),
+        orientation: orientation(&exif),
+        ..Default::default()
+    })
+}
+
+/// Reads the orientation, ignoring values outside the 1 to 8 defined by EXIF.
+fn orientation(exif: &Exif) -> Option<u8> {
+    let value = exif
+        .get_field(Tag::Orientation, In::PRIMARY)?
+        .value
+        .get_uint(0)?;
+
+    u8::try_from(value).ok().filter(|v| (1..=8).contains(v))
+}
+
+/// Reads the first string of an ASCII field, without padding.
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|v| {
+                String::from_utf8_lossy(v)
+                    .trim_matches(['\0', ' '])
+                    .to_string()
+            })
+            .filter(|v| !v.is_empty()),
+        _ => None,
+    }
+}
+
+/// Reads the first value of a rational field.
+fn rational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => values.first().map(|v| v.to_f64()),
+        _ => None,
+    }
+    .filter(|v| v.is_finite())
+}
+
+/// Reads a GPS coordinate in degrees, minutes and seconds as decimal degrees.
+fn coordinate(exif: &Exif, tag: Tag, reference: Tag, negative: &str) -> Option<f64> {
+    let Value::Rational(dms) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let [degrees, minutes, seconds] = dms.as_slice() else {
+        return None;
+    };
+
+    let value = degrees.to_f64() + minutes.to_f64() / 60.0 + seconds.to_f64() / 3600.0;
+    let value = match ascii(exif, reference) {
+        Some(r) if r.eq_ignore_ascii_case(negative) => -value,
+        _ => value,
+    };
+    value.is_finite().then_some(value)
+}
+
+/// Reads the original capture time, applying its offset when recorded.
+fn taken_at(exif: &Exif) -> Option<DateTime<Utc>> {
+    let Value::Ascii(values) = &exif.get_field(Tag::DateTimeOriginal, In::PRIMARY)?.value else {
+        return None;
+    };
+    let mut taken_at = exif::DateTime::from_ascii(values.first()?).ok()?;
+    if let Some(field) = exif.get_field(Tag::OffsetTimeOriginal, In::PRIMARY)
+        && let Value::Ascii(values) = &field.value
+        && let Some(offset) = values.first()
+    {
+        // A malformed offset leaves the time as UTC.
+        let _ = taken_at.parse_offset(offset);
+    }
+
+    let local = NaiveDate::from_ymd_opt(
+        taken_at.year.into(),
+        taken_at.month.into(),
+        taken_at.day.into(),
+    )?
+    .and_hms_opt(
+        taken_at.hour.into(),
+        taken_at.minute.into(),
+        taken_at.second.into(),
+    )?;
+    let offset = FixedOffset::east_opt(i32::from(taken_at.offset.unwrap_or(0)) * 60)?;
+
+    Some(
+        offset
+            .from_local_datetime(&local)
+            .single()?
+            .with_timezone(&Utc),
+    )
+}
+
+/// Extracts the duration by probing the video stream.
+///
+/// Animated GIFs and WebPs are not probed, their duration is the total of their frame
+/// delays.
+fn video_stream(entry: &MediaPath) -> Result<PartialMetadata, StorageError> {
+    let video = entry.content_path();
+    // Camera RAW files are stored like videos, but are stills.
+    let is_video = infer::get_from_path(video)?
+        .is_some_and(|kind| kind.matcher_type() == infer::MatcherType::Video);
+    let duration = if is_animation(video) {
+        decode_animation(&std::fs::read(video)?)?
+            .map(|animation| animation.duration.as_secs_f64())
+            .unwrap_or_default()
+    } else if is_video {
+        Decoder::new(video.as_path())?.duration()?.as_secs_f64()
+    } else {
+        return Ok(PartialMetadata::default());
+    };
+
+    Ok(PartialMetadata {
+        duration: Some(duration),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CreatedAtFallback, MediaKind, PartialMetadata, extract};
+    use crate::storage::{DateTime, ExifData, MediaPath, Storage, Utc};
+    use exif::{Field, In, Rational, Tag, Value, experimental::Writer};
+    use image::{ImageBuffer, ImageFormat, Rgb};
+    use std::{
+        fs::{self, File},
+        io::Cursor,
+        str::FromStr,
+    };
+    use tempfile::TempDir;
+
+    /// Encodes a small JPEG carrying the given EXIF fields in an APP1 segment.
+    fn jpeg_with_exif(fields: &[Field]) -> Vec<u8> {
+        sized_jpeg_with_exif(8, 8, fields)
+    }
+
+    /// Encodes a JPEG of the given size carrying the given EXIF fields.
+    fn sized_jpeg_with_exif(width: u32, height: u32, fields: &[Field]) -> Vec<u8> {
+        let mut jpeg = vec![];
+        ImageBuffer::from_pixel(width, height, Rgb([200u8, 100, 50]))
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        let mut writer = Writer::new();
+        fields.iter().for_each(|f| writer.push_field(f));
+        let mut tiff = Cursor::new(vec![]);
+        writer.write(&mut tiff, false).unwrap();
+        let tiff = tiff.into_inner();
+
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend([0xff, 0xe1]);
+        bytes.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        bytes.extend(b"Exif\0\0");
+        bytes.extend(tiff);
+        bytes.extend(&jpeg[2..]);
+        bytes
+    }
+
+    fn ascii(tag: Tag, value: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![value.as_bytes().to_vec()]),
+        }
+    }
+
+    fn rationals(tag: Tag, values: &[(u32, u32)]) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Rational(
+                values
+                    .iter()
+                    .map(|&(num, denom)| Rational { num, denom })
+                    .collect(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_merge_prefers_later_fields() {
+        let base = PartialMetadata {
+            width: Some(1),
+            format: Some("png".to_string()),
+            ..Default::default()
+        };
+        let other = PartialMetadata {
+            width: Some(2),
+            duration: Some(3.0),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            PartialMetadata {
+                width: Some(2),
+                format: Some("png".to_string()),
+                duration: Some(3.0),
+                ..Default::default()
+            },
+            base.merge(other)
+        );
+    }
+
+    #[test]
+    fn test_extract_image() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("image.png");
+        fs::write(&path, include_bytes!("../../testdata/44a5b6f94f4f6445.png")).unwrap();
+        let entry = MediaPath::Image(path.clone());
+
+        let metadata = extract(&entry, CreatedAtFallback::Now).unwrap();
+
+        assert_eq!(MediaKind::Image, entry.kind());
+        assert_eq!("png", metadata.format);
+        assert!(metadata.width > 0 && metadata.height > 0);
+        assert_eq!(fs::metadata(&path).unwrap().len(), metadata.file_size);
+        assert_eq!(None, metadata.duration);
+    }
+
+    #[test]
+    fn test_extract_video() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let (hash, _) = storage
+            .create_file(include_bytes!("../../testdata/motion_video.mp4"))
+            .unwrap();
+        let entry = storage.find_entry(&hash).unwrap();
+
+        let metadata = extract(&entry, CreatedAtFallback::Now).unwrap();
+
+        assert_eq!(MediaKind::Video, entry.kind());
+        assert_eq!("mp4", metadata.format);
+        assert!(metadata.width > 0 && metadata.height > 0);
+        assert_eq!(Some(3.0), metadata.duration);
+    }
+
+    #[test]
+    fn test_extract_exif() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("image.jpg");
+        fs::write(
+            &path,
+            jpeg_with_exif(&[
+                ascii(Tag::Make, "Canon"),
+                ascii(Tag::Model, "EOS 5D"),
+                ascii(Tag::DateTimeOriginal, "2024:05:06 07:08:09"),
+                ascii(Tag::OffsetTimeOriginal, "+09:00"),
+                rationals(Tag::ExposureTime, &[(1, 250)]),
+                ascii(Tag::GPSLatitudeRef, "N"),
+                rationals(Tag::GPSLatitude, &[(35, 1), (30, 1), (0, 1)]),
+                ascii(Tag::GPSLongitudeRef, "W"),
+                rationals(Tag::GPSLongitude, &[(139, 1), (45, 1), (0, 1)]),
+            ]),
+        )
+        .unwrap();
+
+        let metadata = extract(&MediaPath::Image(path), CreatedAtFallback::Now).unwrap();
+
+        let taken_at = DateTime::from_str("2024-05-05T22:08:09Z").unwrap();
+        assert_eq!(
+            Some(Box::new(ExifData {
+                camera_make: Some("Canon".to_string()),
+                camera_model: Some("EOS 5D".to_string()),
+                gps_lat: Some(35.5),
+                gps_lon: Some(-139.75),
+                taken_at: Some(taken_at),
+                exposure: Some(0.004),
+            })),
+            metadata.exif
+        );
+        // The capture time is preferred over the filesystem timestamp.
+        assert_eq!(Some(taken_at), metadata.created_at);
+    }
+
+    #[test]
+    fn test_extract_orientation() {
+        let tmp_dir = TempDir::new().unwrap();
+        let orientation = |value| Field {
+            tag: Tag::Orientation,
+            ifd_num: In::PRIMARY,
+            value: Value::Short(vec![value]),
+        };
+
+        // Rotated 90 degrees clockwise for display. The pixels are left as stored.
+        let path = tmp_dir.path().join("rotated.jpg");
+        fs::write(&path, jpeg_with_exif(&[orientation(6)])).unwrap();
+        let metadata = extract(&MediaPath::Image(path), CreatedAtFallback::Now).unwrap();
+        assert_eq!(Some(6), metadata.orientation);
+        assert_eq!((8, 8), (metadata.width, metadata.height));
+        assert_eq!(None, metadata.exif);
+
+        let path = tmp_dir.path().join("invalid.jpg");
+        fs::write(&path, jpeg_with_exif(&[orientation(9)])).unwrap();
+        let metadata = extract(&MediaPath::Image(path), CreatedAtFallback::Now).unwrap();
+        assert_eq!(None, metadata.orientation);
+    }
+
+    #[test]
+    fn test_stored_jpeg_keeps_exif() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf()).with_auto_orient(false);
+
+        let (hash, _) = storage
+            .create_file(&jpeg_with_exif(&[
+                ascii(Tag::Make, "Canon"),
+                ascii(Tag::DateTimeOriginal, "2024:05:06 07:08:09"),
+                Field {
+                    tag: Tag::Orientation,
+                    ifd_num: In::PRIMARY,
+                    value: Value::Short(vec![6]),
+                },
+            ]))
+            .unwrap();
+        let metadata = storage.get_metadata(&hash).unwrap();
+
+        let taken_at = DateTime::from_str("2024-05-06T07:08:09Z").unwrap();
+        assert_eq!(
+            Some("Canon"),
+            metadata.exif.as_ref().unwrap().camera_make.as_deref()
+        );
+        assert_eq!(Some(taken_at), metadata.created_at);
+        assert_eq!(Some(6), metadata.orientation);
+    }
+
+    #[test]
+    fn test_auto_orient() {
+        let rotated = sized_jpeg_with_exif(
+            16,
+            8,
+            &[
+                ascii(Tag::Make, "Canon"),
+                Field {
+                    tag: Tag::Orientation,
+                    ifd_num: In::PRIMARY,
+                    value: Value::Short(vec![6]),
+                },
+            ],
+        );
+
+        // Rotated 90 degrees clockwise on ingest, and recorded as upright.
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let (hash, _) = storage.create_file(&rotated).unwrap();
+        let metadata = storage.get_metadata(&hash).unwrap();
+        assert_eq!((8, 16), (metadata.width, metadata.height));
+        assert_eq!(Some(1), metadata.orientation);
+        assert_eq!(
+            Some("Canon"),
+            metadata.exif.as_ref().unwrap().camera_make.as_deref()
+        );
+
+        // Byte-faithful storage keeps the pixels and the orientation as uploaded.
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf()).with_auto_orient(false);
+        let (unrotated, _) = storage.create_file(&rotated).unwrap();
+        let metadata = storage.get_metadata(&unrotated).unwrap();
+        assert_eq!((16, 8), (metadata.width, metadata.height));
+        assert_eq!(Some(6), metadata.orientation);
+        assert_ne!(hash, unrotated);
+    }
+
+    #[test]
+    fn test_extract_without_exif() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("image.png");
+        fs::write(&path, include_bytes!("../../testdata/44a5b6f94f4f6445.png")).unwrap();
+
+        let metadata = extract(&MediaPath::Image(path), CreatedAtFallback::Now).unwrap();
+
+        assert_eq!(None, metadata.exif);
+        assert_eq!(None, metadata.orientation);
+        assert!(metadata.created_at.is_some());
+    }
+
+    #[test]
+    fn test_created_at_fallback() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("image.png");
+        fs::write(&path, include_bytes!("../../testdata/44a5b6f94f4f6445.png")).unwrap();
+        let modified = DateTime::from_str("2020-01-02T03:04:05Z").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified.into())
+            .unwrap();
+
+        let before = Utc::now();
+        let now = CreatedAtFallback::Now.resolve(&path).unwrap().unwrap();
+        assert!(before <= now && now <= Utc::now());
+        assert_eq!(
+            Some(modified),
+            CreatedAtFallback::Modified.resolve(&path).unwrap()
+        );
+        assert_eq!(None, CreatedAtFallback::None.resolve(&path).unwrap());
+    }
+}
@@ -0,0 +1,298 @@
+//! Perceptual hashing for near-duplicate detection.
+//!
+//! A `PixelHash` changes with any pixel, so two encodes of the same clip whose
+//! thumbnails differ slightly are stored as unrelated entries. A `PHash` (difference
+//! hash) changes only a few bits under re-encoding, scaling or small edits, so the
+//! Hamming distance between two hashes measures how alike two images look.
+//!
+//! `Storage` keeps an index of the perceptual hashes of all stored entries, taken from
+//! the image itself or from the thumbnail of a video. `Storage::find_similar` looks up
+//! near-duplicates of a stored entry, and `Storage::with_image_near_duplicates` and
+//! `Storage::with_video_near_duplicates` report them when a file is stored. The
+//! `PixelHash` stays the storage key.
+
+use super::PixelHash;
+use image::{DynamicImage, imageops::FilterType};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// A 64-bit difference hash of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PHash(u64);
+
+impl PHash {
+    /// Computes the difference hash of an image.
+    ///
+    /// The image is reduced to 9x8 grayscale pixels, and each bit records whether a
+    /// pixel is darker than its right neighbour. Color, size and encoding therefore
+    /// do not affect the hash, only the coarse brightness structure does.
+    ///
+    /// Grayscale and RGB(A) images are compared by luma, and alpha is ignored, so a
+    /// transparent image hashes like its copy with the alpha channel dropped. Images
+    /// smaller than the grid are upscaled; tiny or flat images have few brightness
+    /// changes and all hash close to zero.
+    pub fn from_image(img: &DynamicImage) -> PHash {
+        let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+        let mut bits = 0u64;
+        for y in 0..8 {
+            for x in 0..8 {
+                bits <<= 1;
+                if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                    bits |= 1;
+                }
+            }
+        }
+
+        PHash(bits)
+    }
+
+    /// Returns the number of differing bits, from 0 (alike) to 64.
+    pub fn distance(&self, other: &PHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    /// Reinterprets the bits as a signed 64-bit integer, for databases without
+    /// unsigned integers.
+    ///
+    /// Unlike `PixelHash::to_signed`, the bits are kept as they are, so the Hamming
+    /// distance can be computed on the stored values.
+    pub fn to_signed(self) -> i64 {
+        self.0 as i64
+    }
+
+    /// Converts a signed 64-bit integer from `to_signed` back into a `PHash`.
+    pub fn from_signed(v: i64) -> PHash {
+        PHash(v as u64)
+    }
+}
+
+impl From<u64> for PHash {
+    fn from(value: u64) -> Self {
+        PHash(value)
+    }
+}
+
+impl From<PHash> for u64 {
+    fn from(value: PHash) -> Self {
+        value.0
+    }
+}
+
+impl Display for PHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// A stored entry that looks like the one being stored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NearDuplicate {
+    /// The pixel hash of the stored entry.
+    pub hash: PixelHash,
+    /// The Hamming distance between the perceptual hashes of both entries.
+    pub distance: u32,
+}
+
+/// An in-memory index of the perceptual hashes of stored entries.
+///
+/// The index is loaded lazily by scanning the storage on first use, then kept up to
+/// date by the storage it belongs to. Changes made by other processes are only seen
+/// after a restart.
+#[derive(Debug, Default)]
+pub(super) struct PerceptualIndex {
+    fingerprints: Mutex<Option<HashMap<PixelHash, PHash>>>,
+}
+
+impl PerceptualIndex {
+    /// Returns whether the index has been loaded.
+    pub(super) fn is_loaded(&self) -> bool {
+        self.lock().is_some()
+    }
+
+    /// Returns indexed entries within the maximum distance of a hash, closest first.
+    ///
+    /// `load` scans the storage if the index has not been loaded yet.
+    pub(super) fn near_duplicates<F>(
+        &self,
+        phash: &PHash,
+        max_distance: u32,
+        load: F,
+    ) -> Vec<NearDuplicate>
+    where
+        F: FnOnce() -> HashMap<PixelHash, PHash>,
+    {
+        let mut fingerprints = self.lock();
+        nearest(fingerprints.get_or_insert_with(load), phash, max_distance)
+    }
+
+    /// Returns indexed entries within the maximum distance of an indexed entry, closest
+    /// first and without the entry itself. Unknown entries have no near-duplicates.
+    ///
+    /// `load` scans the storage if the index has not been loaded yet.
+    pub(super) fn similar_to<F>(
+        &self,
+        hash: &PixelHash,
+        max_distance: u32,
+        load: F,
+    ) -> Vec<NearDuplicate>
+    where
+        F: FnOnce() -> HashMap<PixelHash, PHash>,
+    {
+        let mut fingerprints = self.lock();
+        let fingerprints = fingerprints.get_or_insert_with(load);
+        let Some(phash) = fingerprints.get(hash) else {
+            return vec![];
+        };
+
+        nearest(fingerprints, phash, max_distance)
+            .into_iter()
+            .filter(|n| n.hash != *hash)
+            .collect()
+    }
+
+    /// Adds a stored entry, unless the index is still unloaded and will find it.
+    pub(super) fn insert(&self, hash: PixelHash, phash: PHash) {
+        if let Some(fingerprints) = self.lock().as_mut() {
+            fingerprints.insert(hash, phash);
+        }
+    }
+
+    /// Removes a deleted entry.
+    pub(super) fn remove(&self, hash: &PixelHash) {
+        if let Some(fingerprints) = self.lock().as_mut() {
+            fingerprints.remove(hash);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<HashMap<PixelHash, PHash>>> {
+        self.fingerprints
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn nearest(
+    fingerprints: &HashMap<PixelHash, PHash>,
+    phash: &PHash,
+    max_distance: u32,
+) -> Vec<NearDuplicate> {
+    let mut near: Vec<NearDuplicate> = fingerprints
+        .iter()
+        .map(|(hash, other)| NearDuplicate {
+            hash: hash.clone(),
+            distance: phash.distance(other),
+        })
+        .filter(|n| n.distance <= max_distance)
+        .collect();
+    near.sort_by(|a, b| a.distance.cmp(&b.distance).then(a.hash.cmp(&b.hash)));
+
+    near
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PHash, PerceptualIndex};
+    use crate::storage::PixelHash;
+    use image::{DynamicImage, GrayImage, ImageFormat, codecs::jpeg::JpegEncoder};
+    use std::collections::HashMap;
+
+    fn fixture() -> DynamicImage {
+        image::load_from_memory(include_bytes!("../../testdata/44a5b6f94f4f6445.png")).unwrap()
+    }
+
+    /// Re-encodes an image as a low quality JPEG, as a different encoder would.
+    fn reencode(img: &DynamicImage) -> DynamicImage {
+        let mut bytes = vec![];
+        img.to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, 40))
+            .unwrap();
+        image::load_from_memory_with_format(&bytes, ImageFormat::Jpeg).unwrap()
+    }
+
+    #[test]
+    fn test_phash_distance() {
+        let original = fixture();
+        let phash = PHash::from_image(&original);
+
+        assert!(phash.distance(&PHash::from_image(&reencode(&original))) <= 4);
+        assert!(
+            phash.distance(&PHash::from_image(&original.resize(
+                64,
+                64,
+                image::imageops::FilterType::Lanczos3
+            ))) <= 4
+        );
+        assert!(phash.distance(&PHash::from_image(&original.fliph())) > 16);
+        assert_eq!(0, phash.distance(&phash));
+    }
+
+    #[test]
+    fn test_phash_grayscale_and_alpha() {
+        let original = fixture();
+        let phash = PHash::from_image(&original);
+
+        // Grayscale and opaque copies keep the brightness structure, and thus the hash.
+        let gray = DynamicImage::ImageLuma8(original.to_luma8());
+        assert!(phash.distance(&PHash::from_image(&gray)) <= 2);
+        let opaque = DynamicImage::ImageRgb8(original.to_rgb8());
+        assert_eq!(phash, PHash::from_image(&opaque));
+    }
+
+    #[test]
+    fn test_phash_tiny_images() {
+        let flat = DynamicImage::ImageLuma8(GrayImage::from_pixel(1, 1, [128].into()));
+        assert_eq!(PHash::from(0), PHash::from_image(&flat));
+
+        // A two pixel gradient still spans the grid after upscaling.
+        let gradient = GrayImage::from_fn(2, 1, |x, _| [if x == 0 { 0 } else { 255 }].into());
+        assert_ne!(
+            PHash::from(0),
+            PHash::from_image(&DynamicImage::ImageLuma8(gradient))
+        );
+    }
+
+    #[test]
+    fn test_perceptual_index() {
+        let index = PerceptualIndex::default();
+        let a = PixelHash::from(1);
+        let b = PixelHash::from(2);
+        assert!(!index.is_loaded());
+
+        index.insert(a.clone(), PHash::from(0b1111));
+        let near = index.near_duplicates(&PHash::from(0), 4, || {
+            HashMap::from([
+                (a.clone(), PHash::from(0b1)),
+                (b.clone(), PHash::from(u64::MAX)),
+            ])
+        });
+        assert!(index.is_loaded());
+        assert_eq!(
+            vec![a.clone()],
+            near.iter().map(|n| n.hash.clone()).collect::<Vec<_>>()
+        );
+        assert_eq!(1, near[0].distance);
+
+        index.insert(b.clone(), PHash::from(0b11));
+        let similar = index.similar_to(&a, 4, HashMap::new);
+        assert_eq!(
+            vec![b.clone()],
+            similar.iter().map(|n| n.hash.clone()).collect::<Vec<_>>()
+        );
+        assert!(
+            index
+                .similar_to(&PixelHash::from(3), 64, HashMap::new)
+                .is_empty()
+        );
+
+        index.remove(&a);
+        let near = index.near_duplicates(&PHash::from(0), 4, HashMap::new);
+        assert_eq!(
+            vec![b],
+            near.iter().map(|n| n.hash.clone()).collect::<Vec<_>>()
+        );
+    }
+}
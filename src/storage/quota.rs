@@ -0,0 +1,156 @@
+//! A limit on the disk space taken by stored files.
+//!
+//! With `Storage::with_max_bytes`, new files are rejected with
+//! `StorageError::QuotaExceeded` once they would push the size of the storage root
+//! past the limit. Everything under the root counts, i.e. stored files, thumbnails
+//! and generated variants, plus the thumbnail root if one is configured.
+//!
+//! Walking the tree is expensive, so the usage is cached until the next write or
+//! delete through the same `Storage` or its clones. Files changed by other processes
+//! are only noticed after such a write or delete.
+
+use super::{Storage, StorageError};
+use std::{
+    fs, io,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+/// The cached number of bytes under the storage roots.
+#[derive(Debug, Default)]
+pub(super) struct DiskUsage {
+    cached: Mutex<Option<u64>>,
+}
+
+impl DiskUsage {
+    /// Forgets the cached usage, so the next check walks the tree again.
+    pub(super) fn invalidate(&self) {
+        *self.cached.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+impl Storage {
+    /// Limits the total size of the files under the storage root.
+    ///
+    /// A new file is rejected if the current usage plus the size of the upload
+    /// exceeds the limit. Re-encoded images may end up slightly larger or smaller
+    /// than their upload, so the limit can be overshot by that difference.
+    ///
+    /// # Arguments
+    /// * `limit` - The limit in bytes. `u64::MAX`, the default, disables the quota.
+    pub fn with_max_bytes(mut self, limit: u64) -> Storage {
+        self.max_bytes = limit;
+        self
+    }
+
+    /// Returns the configured limit in bytes, `u64::MAX` if there is none.
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Returns the total size in bytes of the files under the storage roots.
+    ///
+    /// The size is cached until the next write or delete.
+    ///
+    /// # Errors
+    /// - `StorageError::Io` if the tree cannot be listed.
+    pub fn disk_usage(&self) -> Result<u64, StorageError> {
+        let mut cached = self
+            .disk_usage
+            .cached
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(bytes) = *cached {
+            return Ok(bytes);
+        }
+
+        let mut bytes = dir_size(&self.root_path)?;
+        if let Some(thumbnail_root) = &self.thumbnail_root
+            && !thumbnail_root.starts_with(&self.root_path)
+        {
+            bytes += dir_size(thumbnail_root)?;
+        }
+        *cached = Some(bytes);
+
+        Ok(bytes)
+    }
+
+    /// Checks that `additional` more bytes fit within the quota.
+    ///
+    /// # Errors
+    /// - `StorageError::QuotaExceeded` if they do not.
+    /// - `StorageError::Io` if the usage cannot be computed.
+    pub(super) fn check_quota(&self, additional: u64) -> Result<(), StorageError> {
+        if self.max_bytes == u64::MAX {
+            return Ok(());
+        }
+
+        let current_bytes = self.disk_usage()?;
+        if current_bytes.saturating_add(additional) > self.max_bytes {
+            return Err(StorageError::QuotaExceeded {
+                current_bytes,
+                limit_bytes: self.max_bytes,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Sums the sizes of the files under `dir`, which need not exist.
+fn dir_size(dir: &Path) -> Result<u64, io::Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut bytes = 0;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            bytes += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            bytes += entry.metadata()?.len();
+        }
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{Storage, StorageError};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_quota() {
+        let tmp_dir = TempDir::new().unwrap();
+        let bytes = include_bytes!("../../testdata/44a5b6f94f4f6445.png");
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        assert_eq!(u64::MAX, storage.max_bytes());
+        assert_eq!(0, storage.disk_usage().unwrap());
+
+        let (hash, _) = storage.create_file(bytes).unwrap();
+        let used = storage.disk_usage().unwrap();
+        assert!(used > 0);
+
+        let storage = storage.with_max_bytes(used + bytes.len() as u64 - 1);
+        storage.ensure_deleted(&hash).unwrap();
+        assert_eq!(0, storage.disk_usage().unwrap());
+        storage.create_file(bytes).unwrap();
+        assert_eq!(used, storage.disk_usage().unwrap());
+
+        let result = storage.create_file(include_bytes!("../../testdata/motion_video.mp4"));
+        let Err(StorageError::QuotaExceeded {
+            current_bytes,
+            limit_bytes,
+        }) = result
+        else {
+            panic!("Expected QuotaExceeded error, but got {:?}", result);
+        };
+        assert_eq!(used, current_bytes);
+        assert_eq!(storage.max_bytes(), limit_bytes);
+    }
+}
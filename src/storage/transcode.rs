@@ -0,0 +1,95 @@
+//! Transcoding of stored images into another format.
+//!
+//! Images are decoded when they are stored, and encoded again in their original
+//! format. `Storage::with_transcode` encodes them in another format instead, e.g.
+//! lossy WebP to save space and bandwidth. The pixel hash is computed from the
+//! decoded pixels before transcoding, so the same picture is recognized whatever
+//! format it is stored in.
+
+use super::StorageError;
+use image::{
+    DynamicImage, ImageError, ImageFormat,
+    error::{EncodingError, ImageFormatHint},
+};
+
+/// A format images can be transcoded into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscodeFormat {
+    /// Lossy WebP, encoded by libwebp.
+    WebP,
+}
+
+impl TranscodeFormat {
+    /// Returns the file extension of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            TranscodeFormat::WebP => "webp",
+        }
+    }
+}
+
+/// How images are transcoded before they are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranscodeConfig {
+    /// The format images are stored in.
+    pub format: TranscodeFormat,
+    /// The encoding quality, from 0 (smallest) to 100 (best).
+    pub quality: u8,
+}
+
+impl TranscodeConfig {
+    /// Encodes the image in the configured format.
+    pub(super) fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>, StorageError> {
+        match self.format {
+            TranscodeFormat::WebP => {
+                // libwebp only takes 8-bit RGB and RGBA pixels.
+                let converted;
+                let img = match img {
+                    DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => img,
+                    _ => {
+                        converted = DynamicImage::ImageRgba8(img.to_rgba8());
+                        &converted
+                    }
+                };
+                let encoder = webp::Encoder::from_image(img)
+                    .map_err(|reason| webp_error(reason.to_string()))?;
+                let encoded = encoder
+                    .encode_simple(false, f32::from(self.quality.min(100)))
+                    .map_err(|e| webp_error(format!("{e:?}")))?;
+
+                Ok(encoded.to_vec())
+            }
+        }
+    }
+}
+
+/// Wraps a failure of libwebp as an encoding error of the `image` crate.
+fn webp_error(reason: String) -> StorageError {
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::WebP),
+        reason,
+    ))
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TranscodeConfig, TranscodeFormat};
+    use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+
+    #[test]
+    fn test_encode_webp() {
+        let img = DynamicImage::ImageRgb16(
+            DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([200, 20, 20]))).to_rgb16(),
+        );
+        let config = TranscodeConfig {
+            format: TranscodeFormat::WebP,
+            quality: 80,
+        };
+
+        let bytes = config.encode(&img).unwrap();
+        assert_eq!(ImageFormat::WebP, image::guess_format(&bytes).unwrap());
+        let decoded = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((8, 8), (decoded.width(), decoded.height()));
+    }
+}
@@ -0,0 +1,534 @@
+//! Downscaled variants of stored media.
+//!
+//! Deployments without a resizing CDN can serve variants straight from the storage
+//! root. A variant is the still image of an entry, i.e. the image itself or the
+//! thumbnail of a video, downscaled according to a `VariantSpec`. It is stored at
+//! `{root}/{spec}/` followed by the relative path of the still image, e.g.
+//! `180x180/44/a5/44a5b6f94f4f6445.png`, which matches the URL layout of the web
+//! server's variants.
+//!
+//! Variants can be generated ahead of time with `create_variant`, or with
+//! `create_variants` when archiving, which skips the variants that exist already.
+//! `get_variant` generates any variant on first use, and reads it from disk
+//! afterwards, and `list_variants` reports which variants of an entry exist.
+//!
+//! Width variants, e.g. `640w`, suit responsive `srcset`s. `nearest_variant` resolves
+//! a requested width to the narrowest generated one that is at least as wide, so
+//! arbitrary widths do not each end up as a file.
+
+use super::{MediaPath, PixelHash, Priority, Storage, StorageError, fit_within, write_temp};
+use image::{DynamicImage, ImageFormat, imageops::FilterType};
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The bounding box of a variant. Variants keep the aspect ratio of the original and
+/// are never upscaled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VariantSize {
+    /// The maximum width in pixels.
+    pub width: u32,
+    /// The maximum height in pixels.
+    pub height: u32,
+}
+
+impl VariantSize {
+    /// Creates a new bounding box.
+    pub const fn new(width: u32, height: u32) -> VariantSize {
+        VariantSize { width, height }
+    }
+}
+
+impl Display for VariantSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// How a variant is downscaled from the still image of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VariantSpec {
+    /// Fits within a bounding box, e.g. `180x180` previews.
+    Box(VariantSize),
+    /// Scales both sides to a percentage, e.g. `scale50` for half-size samples.
+    /// Percentages above 100 are treated as 100.
+    Scale(u32),
+    /// Scales to a width, whatever the height, e.g. `640w`.
+    Width(u32),
+}
+
+impl VariantSpec {
+    /// Returns the dimensions of the variant of a still image of the given size.
+    ///
+    /// Variants keep the aspect ratio of the original, are never upscaled, and are
+    /// at least one pixel wide and high.
+    pub fn dimensions(self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            VariantSpec::Box(size) => fit_within(width, height, size.width, size.height),
+            VariantSpec::Scale(percent) => {
+                let percent = u64::from(percent.min(100));
+                let scaled = |side: u32| ((u64::from(side) * percent / 100) as u32).max(1);
+                (scaled(width), scaled(height))
+            }
+            VariantSpec::Width(max_width) => fit_within(width, height, max_width, u32::MAX),
+        }
+    }
+}
+
+impl From<VariantSize> for VariantSpec {
+    fn from(size: VariantSize) -> Self {
+        VariantSpec::Box(size)
+    }
+}
+
+impl Display for VariantSpec {
+    /// Formats the spec as the name of its directory, e.g. `180x180`, `scale50` or `640w`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VariantSpec::Box(size) => size.fmt(f),
+            VariantSpec::Scale(percent) => write!(f, "scale{percent}"),
+            VariantSpec::Width(width) => write!(f, "{width}w"),
+        }
+    }
+}
+
+impl FromStr for VariantSpec {
+    type Err = String;
+
+    /// Parses the name of a variant directory, e.g. `180x180`, `scale50` or `640w`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid variant: {s}");
+        let number = |s: &str| match s.parse::<u32>() {
+            Ok(n) if n > 0 && !s.starts_with('0') => Ok(n),
+            _ => Err(invalid()),
+        };
+
+        match (s.strip_prefix("scale"), s.split_once('x')) {
+            (Some(percent), _) => Ok(VariantSpec::Scale(number(percent)?)),
+            (None, Some((width, height))) => Ok(VariantSpec::Box(VariantSize::new(
+                number(width)?,
+                number(height)?,
+            ))),
+            (None, None) => match s.strip_suffix('w') {
+                Some(width) => Ok(VariantSpec::Width(number(width)?)),
+                None => Err(invalid()),
+            },
+        }
+    }
+}
+
+impl Storage {
+    /// Configures the variant sizes generated by `create_variant` callers such as
+    /// `app::pregenerate_variants`.
+    ///
+    /// # Arguments
+    /// * `sizes` - The bounding boxes of the variants.
+    pub fn with_variant_sizes(mut self, sizes: Vec<VariantSize>) -> Storage {
+        self.variant_sizes = sizes;
+        self
+    }
+
+    /// Returns the configured variant sizes.
+    pub fn variant_sizes(&self) -> &[VariantSize] {
+        &self.variant_sizes
+    }
+
+    /// Returns the relative path of a variant, whether it exists or not.
+    ///
+    /// # Returns
+    /// * `Some(relative_path)` if the entry exists.
+    /// * `None` if no matching entry is found, or it is a raw file without a still image.
+    pub fn variant_path(&self, hash: &PixelHash, spec: impl Into<VariantSpec>) -> Option<PathBuf> {
+        let still = match self.index_file(hash)? {
+            MediaPath::Image(path) => path,
+            MediaPath::Video { thumb, .. } => thumb,
+            MediaPath::Raw(_) => return None,
+        };
+
+        Some(PathBuf::from(spec.into().to_string()).join(still))
+    }
+
+    /// Returns whether the variant of an entry has been generated.
+    pub fn has_variant(&self, hash: &PixelHash, spec: impl Into<VariantSpec>) -> bool {
+        self.variant_path(hash, spec)
+            .is_some_and(|path| self.root_path.join(path).exists())
+    }
+
+    /// Returns the bytes of a variant, generating it on first use.
+    ///
+    /// A generated variant is kept on disk, so later calls read it instead of
+    /// resizing again. Variants are encoded in the format of the still image.
+    ///
+    /// # Errors
+    /// - `StorageError::FileNotFound` if no entry is located for the given hash.
+    /// - `StorageError::Busy` if admission control rejects the decode.
+    /// - `StorageError::Io` or `StorageError::Image` if reading, decoding or writing fails.
+    pub fn get_variant(
+        &self,
+        hash: &PixelHash,
+        spec: VariantSpec,
+    ) -> Result<Vec<u8>, StorageError> {
+        let relative = self
+            .variant_path(hash, spec)
+            .ok_or(StorageError::FileNotFound { hash: hash.clone() })?;
+        match fs::read(self.root_path.join(&relative)) {
+            Ok(bytes) => return Ok(bytes),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let relative = self.create_variant(hash, spec)?;
+        Ok(fs::read(self.root_path.join(relative))?)
+    }
+
+    /// Resolves a requested variant to the generated one that serves it best.
+    ///
+    /// A width variant that does not exist resolves to the narrowest generated width
+    /// variant that is at least as wide. Any other spec, or a width without such a
+    /// variant, resolves to itself, to be generated on first use.
+    ///
+    /// # Errors
+    /// - `StorageError::Io` if the variant directories cannot be listed.
+    pub fn nearest_variant(
+        &self,
+        hash: &PixelHash,
+        spec: VariantSpec,
+    ) -> Result<VariantSpec, StorageError> {
+        let VariantSpec::Width(width) = spec else {
+            return Ok(spec);
+        };
+        if self.has_variant(hash, spec) {
+            return Ok(spec);
+        }
+
+        Ok(self
+            .list_variants(hash)?
+            .into_iter()
+            .filter_map(|spec| match spec {
+                VariantSpec::Width(w) if w >= width => Some(w),
+                _ => None,
+            })
+            .min()
+            .map_or(spec, VariantSpec::Width))
+    }
+
+    /// Generates the variant of an entry, replacing an existing one.
+    ///
+    /// Decoding waits for a slot with `Priority::Maintenance` if admission control is
+    /// configured. The variant is written to a temporary file first and then moved
+    /// into place, so concurrent readers never see a partial file.
+    ///
+    /// # Returns
+    /// * `Ok(relative_path)` - The relative path of the generated variant.
+    ///
+    /// # Errors
+    /// - `StorageError::FileNotFound` if no entry is located for the given hash.
+    /// - `StorageError::Busy` if admission control rejects the decode.
+    /// - `StorageError::Io` or `StorageError::Image` if reading, decoding or writing fails.
+    pub fn create_variant(
+        &self,
+        hash: &PixelHash,
+        spec: impl Into<VariantSpec>,
+    ) -> Result<PathBuf, StorageError> {
+        let (still, img) = self.decode_still(hash)?;
+        self.write_variant(hash, &still, &img, spec.into())
+    }
+
+    /// Generates the variants of an entry that do not exist yet.
+    ///
+    /// The still image is decoded at most once for all variants, and not at all if
+    /// every variant exists already, so calling this again for the same entry is
+    /// cheap and leaves existing variants untouched.
+    ///
+    /// # Returns
+    /// * `Ok(relative_paths)` - The relative paths of all given variants, in order.
+    ///
+    /// # Errors
+    /// - `StorageError::FileNotFound` if no entry is located for the given hash.
+    /// - `StorageError::Busy` if admission control rejects the decode.
+    /// - `StorageError::Io` or `StorageError::Image` if reading, decoding or writing fails.
+    pub fn create_variants(
+        &self,
+        hash: &PixelHash,
+        specs: &[VariantSpec],
+    ) -> Result<Vec<PathBuf>, StorageError> {
+        let mut decoded = None;
+        let mut paths = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let relative = self
+                .variant_path(hash, *spec)
+                .ok_or(StorageError::FileNotFound { hash: hash.clone() })?;
+            if !self.root_path.join(&relative).exists() {
+                let (still, img) = match &decoded {
+                    Some(decoded) => decoded,
+                    None => decoded.insert(self.decode_still(hash)?),
+                };
+                self.write_variant(hash, still, img, *spec)?;
+            }
+            paths.push(relative);
+        }
+
+        Ok(paths)
+    }
+
+    /// Returns the specs of every generated variant of an entry, whether configured
+    /// or not, ordered by the names of their directories.
+    pub fn list_variants(&self, hash: &PixelHash) -> Result<Vec<VariantSpec>, StorageError> {
+        Ok(self
+            .variant_files(hash)?
+            .into_iter()
+            .filter(|(_, path)| path.exists())
+            .map(|(spec, _)| spec)
+            .collect())
+    }
+
+    /// Deletes every generated variant of an entry, whether its spec is configured
+    /// or not.
+    pub(super) fn delete_variants(&self, hash: &PixelHash) -> Result<(), StorageError> {
+        for (_, path) in self.variant_files(hash)? {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the absolute path of the variant of an entry in every variant directory
+    /// of the root, whether the variant exists or not.
+    fn variant_files(&self, hash: &PixelHash) -> Result<Vec<(VariantSpec, PathBuf)>, StorageError> {
+        let Some(still) = self.variant_path(hash, VariantSpec::Scale(100)) else {
+            return Ok(vec![]);
+        };
+        let still = still.iter().skip(1).collect::<PathBuf>();
+
+        let mut files = vec![];
+        for entry in fs::read_dir(&self.root_path)? {
+            let entry = entry?;
+            if let Some(spec) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse::<VariantSpec>().ok())
+            {
+                files.push((spec, entry.path().join(&still)));
+            }
+        }
+        files.sort_by(|a, b| a.1.cmp(&b.1));
+
+        Ok(files)
+    }
+
+    /// Reads and decodes the still image of an entry, returning its absolute path.
+    ///
+    /// Decoding waits for a slot with `Priority::Maintenance` if admission control is
+    /// configured.
+    fn decode_still(&self, hash: &PixelHash) -> Result<(PathBuf, DynamicImage), StorageError> {
+        let still = match self
+            .find_entry(hash)
+            .ok_or(StorageError::FileNotFound { hash: hash.clone() })?
+        {
+            MediaPath::Image(path) => path,
+            MediaPath::Video { thumb, .. } => thumb,
+            MediaPath::Raw(path) => {
+                return Err(StorageError::UnsupportedFile {
+                    kind: infer::get_from_path(path)?,
+                });
+            }
+        };
+
+        let bytes = fs::read(&still)?;
+        let _permit = self.admit(&bytes, Priority::Maintenance)?;
+        let img = image::load_from_memory(&bytes)?;
+
+        Ok((still, img))
+    }
+
+    /// Resizes a decoded still image and writes it as the variant of the given spec,
+    /// in the format of the still image.
+    fn write_variant(
+        &self,
+        hash: &PixelHash,
+        still: &Path,
+        img: &DynamicImage,
+        spec: VariantSpec,
+    ) -> Result<PathBuf, StorageError> {
+        let relative = self
+            .variant_path(hash, spec)
+            .ok_or(StorageError::FileNotFound { hash: hash.clone() })?;
+
+        let (width, height) = spec.dimensions(img.width(), img.height());
+        let variant = img.resize_exact(width, height, FilterType::Lanczos3);
+
+        let path = self.root_path.join(&relative);
+        let dir = path.parent().unwrap_or(&self.root_path);
+        fs::create_dir_all(dir)?;
+        let format = ImageFormat::from_path(still)?;
+        write_temp(dir, |temp| self.format_options.save(&variant, temp, format))?
+            .persist(&path)
+            .map_err(|e| e.error)?;
+        self.disk_usage.invalidate();
+
+        Ok(relative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VariantSize, VariantSpec};
+    use crate::storage::Storage;
+    use image::GenericImageView;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_create_variant() {
+        let tmp_dir = TempDir::new().unwrap();
+        let small = VariantSize::new(180, 180);
+        let large = VariantSize::new(4096, 4096);
+        let storage =
+            Storage::new(tmp_dir.path().to_path_buf()).with_variant_sizes(vec![small, large]);
+
+        let (hash, _) = storage
+            .create_file(include_bytes!("../../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+        assert!(!storage.has_variant(&hash, small));
+
+        let path = storage.create_variant(&hash, small).unwrap();
+        assert_eq!(PathBuf::from("180x180/44/a5/44a5b6f94f4f6445.png"), path);
+        assert!(storage.has_variant(&hash, small));
+
+        let original = image::open(
+            tmp_dir
+                .path()
+                .join(storage.index_file(&hash).unwrap().content_path()),
+        )
+        .unwrap();
+        let variant = image::open(tmp_dir.path().join(&path)).unwrap();
+        assert!(variant.width() <= 180 && variant.height() <= 180);
+        assert_eq!(
+            crate::storage::fit_within(original.width(), original.height(), 180, 180),
+            variant.dimensions()
+        );
+
+        // Variants are never upscaled.
+        let path = storage.create_variant(&hash, large).unwrap();
+        assert_eq!(
+            original.dimensions(),
+            image::open(tmp_dir.path().join(path)).unwrap().dimensions()
+        );
+
+        storage.ensure_deleted(&hash).unwrap();
+        assert!(
+            !tmp_dir
+                .path()
+                .join("180x180/44/a5/44a5b6f94f4f6445.png")
+                .exists()
+        );
+        assert!(
+            !tmp_dir
+                .path()
+                .join("4096x4096/44/a5/44a5b6f94f4f6445.png")
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_variant_spec() {
+        for (name, spec) in [
+            ("180x180", VariantSpec::Box(VariantSize::new(180, 180))),
+            ("scale50", VariantSpec::Scale(50)),
+            ("640w", VariantSpec::Width(640)),
+        ] {
+            assert_eq!(Ok(spec), name.parse());
+            assert_eq!(name, spec.to_string());
+        }
+        for name in [
+            "original", "ab", "0x180", "scale", "scale0", "scale050", "180x", "w", "0w", "640wx",
+        ] {
+            assert!(name.parse::<VariantSpec>().is_err(), "{name}");
+        }
+
+        assert_eq!((50, 25), VariantSpec::Scale(50).dimensions(100, 50));
+        assert_eq!((1, 1), VariantSpec::Scale(1).dimensions(10, 10));
+        assert_eq!((100, 50), VariantSpec::Scale(200).dimensions(100, 50));
+        assert_eq!((40, 20), VariantSpec::Width(40).dimensions(100, 50));
+        assert_eq!((100, 50), VariantSpec::Width(640).dimensions(100, 50));
+    }
+
+    #[test]
+    fn test_nearest_variant() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let (hash, _) = storage
+            .create_file(include_bytes!("../../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+        storage
+            .create_variants(
+                &hash,
+                &[
+                    VariantSpec::Width(320),
+                    VariantSpec::Width(640),
+                    VariantSpec::Scale(50),
+                ],
+            )
+            .unwrap();
+
+        for (requested, nearest) in [(100, 320), (320, 320), (321, 640), (641, 641)] {
+            assert_eq!(
+                VariantSpec::Width(nearest),
+                storage
+                    .nearest_variant(&hash, VariantSpec::Width(requested))
+                    .unwrap()
+            );
+        }
+        assert_eq!(
+            VariantSpec::Scale(25),
+            storage
+                .nearest_variant(&hash, VariantSpec::Scale(25))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_variant() {
+        let tmp_dir = TempDir::new().unwrap();
+        let storage = Storage::new(tmp_dir.path().to_path_buf());
+        let (hash, _) = storage
+            .create_file(include_bytes!("../../testdata/44a5b6f94f4f6445.png"))
+            .unwrap();
+        let original = image::open(
+            tmp_dir
+                .path()
+                .join(storage.index_file(&hash).unwrap().content_path()),
+        )
+        .unwrap();
+
+        let spec = VariantSpec::Scale(50);
+        let bytes = storage.get_variant(&hash, spec).unwrap();
+        let variant = image::load_from_memory(&bytes).unwrap();
+        assert_eq!(
+            spec.dimensions(original.width(), original.height()),
+            variant.dimensions()
+        );
+        let path = tmp_dir.path().join("scale50/44/a5/44a5b6f94f4f6445.png");
+        assert_eq!(bytes, std::fs::read(&path).unwrap());
+
+        // A cached variant is read from disk instead of being generated again.
+        std::fs::write(&path, b"cached").unwrap();
+        assert_eq!(
+            b"cached".to_vec(),
+            storage.get_variant(&hash, spec).unwrap()
+        );
+
+        // Variants of unconfigured specs are deleted along with the entry.
+        storage.ensure_deleted(&hash).unwrap();
+        assert!(!path.exists());
+        assert!(matches!(
+            storage.get_variant(&hash, spec),
+            Err(crate::storage::StorageError::FileNotFound { .. })
+        ));
+    }
+}
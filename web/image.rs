@@ -1,21 +1,34 @@
 use crate::{AppConfig, AppState};
 use axum::{
     Json,
-    extract::{Multipart, Path, Query, State},
-    http::StatusCode,
+    extract::{Multipart, Path, Query, State, multipart::MultipartError},
+    http::{StatusCode, header},
     response::IntoResponse,
 };
-use buru::{prelude::*, query};
-use bytes::BytesMut;
+use buru::{
+    parser::{METADATA_FIELDS, parse_age, parse_query},
+    prelude::*,
+    query,
+};
 use futures::TryStreamExt;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use tokio::{
+    fs::File,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct ImageQueryParam {
     tags: Option<String>, // e.g. "cute cat"
+    ids: Option<String>,  // e.g. "-123,456" or "44a5b6f94f4f6445,..."
     page: Option<u32>,
     limit: Option<u32>,
+    min_filesize: Option<u64>, // in bytes, inclusive
+    max_filesize: Option<u64>, // in bytes, inclusive
+    min_width: Option<u64>,    // in pixels, inclusive
+    max_width: Option<u64>,    // in pixels, inclusive
+    min_height: Option<u64>,   // in pixels, inclusive
+    max_height: Option<u64>,   // in pixels, inclusive
 }
 
 #[derive(Serialize, Debug)]
@@ -31,7 +44,7 @@ pub struct ImageResponse {
     pub tag_string_copyright: String,
     pub tag_string_character: String,
     pub tag_string_meta: String,
-    pub rating: String,
+    pub rating: Option<String>,
     pub parent_id: Option<u32>,
     pub pixiv_id: Option<u32>,
     pub source: String,
@@ -77,6 +90,7 @@ pub struct MediaAsset {
     pub image_width: u32,
     pub image_height: u32,
     pub duration: Option<f64>,
+    pub orientation: Option<u8>,
     pub status: String,
     pub file_key: String,
     pub is_public: bool,
@@ -103,9 +117,10 @@ impl MediaAsset {
             image_width: image.metadata.width,
             image_height: image.metadata.height,
             duration: image.metadata.duration,
+            orientation: image.metadata.orientation,
             status: "active".to_string(),
             file_key: "bbD6k0WiU".to_string(),
-            is_public: true,
+            is_public: image.is_public,
             pixel_hash: hash.clone().to_string(),
             variants: variants.clone().into(),
         }
@@ -122,26 +137,34 @@ pub struct Variant {
     pub file_ext: String,
 }
 
+/// The variant shown in listings.
+const PREVIEW: VariantSpec = VariantSpec::Box(VariantSize::new(180, 180));
+/// The variant shown on the page of an image.
+const SAMPLE: VariantSpec = VariantSpec::Scale(50);
+
 fn generate_variants(config: &AppConfig, org: &Media) -> Variants {
     let (original_path, preview_path) = match org.path {
-        MediaPath::Image(ref path_buf) => (path_buf, path_buf),
+        MediaPath::Image(ref path_buf) | MediaPath::Raw(ref path_buf) => (path_buf, path_buf),
         MediaPath::Video {
             ref video,
             ref thumb,
         } => (video, thumb),
     };
+    let (preview_width, preview_height) =
+        PREVIEW.dimensions(org.metadata.width, org.metadata.height);
+    let (sample_width, sample_height) = SAMPLE.dimensions(org.metadata.width, org.metadata.height);
 
     Variants {
         preview: Variant {
             variant_type: "180x180".to_string(),
             url: config
                 .cdn_base_url
-                .join(PathBuf::from("180x180"))
+                .join(PREVIEW.to_string())
                 .join(preview_path)
                 .to_string_lossy()
                 .to_string(),
-            width: 180,
-            height: 180,
+            width: preview_width,
+            height: preview_height,
             file_ext: preview_path
                 .extension()
                 .unwrap()
@@ -152,16 +175,12 @@ fn generate_variants(config: &AppConfig, org: &Media) -> Variants {
             variant_type: "sample".to_string(),
             url: config
                 .cdn_base_url
-                .join(format!(
-                    "{}x{}",
-                    org.metadata.width / 2,
-                    org.metadata.height / 2
-                ))
+                .join(SAMPLE.to_string())
                 .join(preview_path)
                 .to_string_lossy()
                 .to_string(),
-            width: org.metadata.width / 2,
-            height: org.metadata.height / 2,
+            width: sample_width,
+            height: sample_height,
             file_ext: preview_path
                 .extension()
                 .unwrap()
@@ -209,21 +228,25 @@ impl ImageResponse {
             .unwrap_or_default();
         let variants = generate_variants(&config, &value);
         let asset = MediaAsset::from_image(&value, &variants);
+        let [general, artist, character, copyright, meta] = TagCategory::ALL.map(|category| {
+            let tags = value.tags_of_category(category);
+            (tags.join(" "), tags.len() as u32)
+        });
 
         ImageResponse {
             id: value.hash.clone().to_signed(),
-            tag_string: value.tags.join(" "),
+            tag_string: value.canonical_tag_string(),
             file_url: Some(variants.orig.url),
             created_at: created_at.clone(),
             updated_at: created_at.clone(),
             uploader_id: 0,
             approver_id: None,
-            tag_string_general: value.tags.join(" "),
-            tag_string_artist: "".to_string(),
-            tag_string_copyright: "".to_string(),
-            tag_string_character: "".to_string(),
-            tag_string_meta: "".to_string(),
-            rating: "e".to_string(),
+            tag_string_general: general.0,
+            tag_string_artist: artist.0,
+            tag_string_copyright: copyright.0,
+            tag_string_character: character.0,
+            tag_string_meta: meta.0,
+            rating: value.rating.map(|r| r.as_str().to_string()),
             parent_id: None,
             pixiv_id: None,
             source: value.source.unwrap_or_default(),
@@ -238,11 +261,11 @@ impl ImageResponse {
             up_score: 0,
             down_score: 0,
             fav_count: 0,
-            tag_count_general: value.tags.len() as u32,
-            tag_count_artist: 0,
-            tag_count_copyright: 0,
-            tag_count_character: 0,
-            tag_count_meta: 0,
+            tag_count_general: general.1,
+            tag_count_artist: artist.1,
+            tag_count_copyright: copyright.1,
+            tag_count_character: character.1,
+            tag_count_meta: meta.1,
             last_comment_bumped_at: None,
             last_noted_at: None,
             has_large: true,
@@ -269,24 +292,77 @@ impl From<ImageQueryParam> for query::ImageQuery {
             .collect::<Vec<_>>();
 
         let mut exprs: Vec<query::ImageQueryExpr> = vec![];
+        // Tags prefixed with `~` form a single OR group, AND-ed with the rest.
+        let mut any: Vec<query::ImageQueryExpr> = vec![];
         let mut order_by: Option<query::OrderBy> = None;
 
         for tag in tags {
             match tag.as_str() {
+                or if tag.starts_with("~") => {
+                    any.push(query::image::tag(or.strip_prefix("~").unwrap()))
+                }
                 negate if tag.starts_with("-") => exprs.push(query::image::not(query::image::tag(
                     negate.strip_prefix("-").unwrap(),
                 ))),
                 order if tag.starts_with("order:") => match order.strip_prefix("order:").unwrap() {
                     "random" => order_by = Some(OrderBy::Random),
+                    "featured" => order_by = Some(OrderBy::FeaturedFirst),
                     "created_at" => order_by = Some(OrderBy::CreatedAtAsc),
                     "created_at_desc" => order_by = Some(OrderBy::CreatedAtDesc),
                     "filesize" => order_by = Some(OrderBy::FileSizeAsc),
                     "filesize_desc" => order_by = Some(OrderBy::FileSizeDesc),
                     _ => (),
                 },
+                "is:featured" => exprs.push(query::image::featured()),
+                group if tag.starts_with("is:") => {
+                    if let Ok(group) = group.strip_prefix("is:").unwrap().parse::<MediaGroup>() {
+                        exprs.push(query::image::media_group(group))
+                    }
+                }
+                rating if tag.starts_with("rating:") => exprs.push(query::image::rating(
+                    rating.strip_prefix("rating:").unwrap(),
+                )),
+                age if tag.starts_with("age:") => {
+                    let age = age.strip_prefix("age:").unwrap();
+                    if let Some(Ok(age)) = age.strip_prefix("<").map(parse_age) {
+                        exprs.push(query::image::age_less_than(age))
+                    } else if let Some(Ok(age)) = age.strip_prefix(">").map(parse_age) {
+                        exprs.push(query::image::age_greater_than(age))
+                    }
+                }
+                ratio if tag.starts_with("ratio:") => {
+                    if let Ok(expr) = parse_query(ratio) {
+                        exprs.push(expr)
+                    }
+                }
+                sources if tag.starts_with("sources:") => {
+                    if let Ok(expr) = parse_query(sources) {
+                        exprs.push(expr)
+                    }
+                }
+                meta if tag.split_once(':').is_some_and(|(field, _)| {
+                    METADATA_FIELDS.iter().any(|(name, _)| *name == field)
+                }) =>
+                {
+                    if let Ok(expr) = parse_query(meta) {
+                        exprs.push(expr)
+                    }
+                }
                 other => exprs.push(query::image::tag(other)),
             }
         }
+        exprs.extend(any.into_iter().reduce(ImageQueryExpr::or));
+        let bounds = [
+            (MetadataField::FileSize, Comparison::Gte, value.min_filesize),
+            (MetadataField::FileSize, Comparison::Lte, value.max_filesize),
+            (MetadataField::Width, Comparison::Gte, value.min_width),
+            (MetadataField::Width, Comparison::Lte, value.max_width),
+            (MetadataField::Height, Comparison::Gte, value.min_height),
+            (MetadataField::Height, Comparison::Lte, value.max_height),
+        ];
+        exprs.extend(bounds.into_iter().filter_map(|(field, comparison, bound)| {
+            bound.map(|bound| query::image::metadata(field, comparison, bound))
+        }));
 
         query::ImageQuery {
             expr: exprs
@@ -303,15 +379,48 @@ impl From<ImageQueryParam> for query::ImageQuery {
                     * value.limit.unwrap_or(20),
             ),
             order: order_by.or(Some(OrderBy::CreatedAtDesc)),
+            include_private: false,
+            include_deleted: false,
+            cursor: None,
         }
     }
 }
 
+/// Parses a comma separated list of signed ids or hex pixel hashes.
+///
+/// Entries that parse as integers are treated as signed ids.
+fn parse_ids(ids: &str) -> Result<Vec<PixelHash>, String> {
+    ids.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| match id.parse::<i64>() {
+            Ok(signed) => Ok(PixelHash::from_signed(signed)),
+            Err(_) => PixelHash::try_from(id).map_err(|e| format!("invalid id {id}: {e}")),
+        })
+        .collect()
+}
+
 pub async fn get_images(
     State(app): State<AppState>,
-    Query(params): Query<ImageQueryParam>,
+    Query(mut params): Query<ImageQueryParam>,
 ) -> Result<Json<Vec<ImageResponse>>, ImageError> {
-    let results = query_image(&app.db, &app.storage, params.into()).await?;
+    params.limit = params.limit.map(|l| l.min(app.config.max_page_size));
+
+    let results = match params.ids.take() {
+        Some(ids) => {
+            let hashes = parse_ids(&ids).map_err(ImageError::BadRequest)?;
+            get_images_by_hashes(&app.db, &app.storage, &hashes, MissingPolicy::Skip)
+                .await?
+                .into_iter()
+                .filter_map(|entry| match entry {
+                    // The web API has no authorization, so private images are left out.
+                    MediaOrMissing::Media(media) if media.is_public => Some(*media),
+                    _ => None,
+                })
+                .collect()
+        }
+        None => query_image(&app.db, &app.storage, params.into()).await?,
+    };
 
     Ok(Json(
         results
@@ -328,55 +437,113 @@ pub async fn get_image(
     let hash = PixelHash::from_signed(id);
 
     let image = find_image_by_hash(&app.db, &app.storage, &hash).await?;
+    // The web API has no authorization, so private images are not found.
+    if !image.is_public {
+        return Err(AppError::StorageNotFound { hash }.into());
+    }
 
     Ok(Json(ImageResponse::from_image(app.config, image)))
 }
 
 pub async fn post_image(
     State(state): State<AppState>,
-    mut multipart: Multipart,
+    multipart: Multipart,
 ) -> Result<Json<ImageResponse>, ImageError> {
-    let mut bytes = None;
-    let mut tags = vec![];
-    let mut source = None;
+    let upload = read_upload(multipart, state.storage.root()).await?;
+
+    let file = match upload.file {
+        Some(f) => f,
+        None => return Err(ImageError::BadRequest("missing file".to_string())),
+    };
+
+    let img = ArchiveImageCommand {
+        tags: upload.tags,
+        source: upload.source,
+        source_policy: state.config.source_policy.clone(),
+        rating: upload.rating,
+        variants: vec![PREVIEW, SAMPLE],
+        ..ArchiveImageCommand::from_reader(file)
+    }
+    .execute(&state.storage, &state.db)
+    .await?;
+
+    Ok(Json(ImageResponse::from_image(state.config, img)))
+}
+
+#[derive(Default)]
+struct Upload {
+    file: Option<File>,
+    tags: Vec<String>,
+    source: Option<String>,
+    rating: Option<Rating>,
+}
 
-    while let Some(field) = multipart.next_field().await.unwrap_or(None) {
+/// Reads the fields of an upload form.
+///
+/// A multipart stream that breaks off is rejected instead of being read as if it
+/// had ended, so a partial file never reaches the storage.
+///
+/// The file is written chunk by chunk to an unnamed temporary file in `spool_dir`,
+/// which the system removes once it is closed, so uploads are never held in memory
+/// and never left behind.
+async fn read_upload(
+    mut multipart: Multipart,
+    spool_dir: &std::path::Path,
+) -> Result<Upload, ImageError> {
+    let mut upload = Upload::default();
+
+    while let Some(field) = multipart.next_field().await.map_err(interrupted)? {
         let name = field.name().unwrap_or_default().to_string();
 
         match name.as_str() {
             "file" => {
-                let mut data = BytesMut::new();
+                let expected = field
+                    .headers()
+                    .get(header::CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<u64>().ok());
+
+                let mut file =
+                    File::from_std(tempfile::tempfile_in(spool_dir).map_err(AppError::Io)?);
+                let mut received = 0;
                 let mut stream = field.into_stream();
-                while let Some(chunk) = stream.try_next().await.unwrap_or(None) {
-                    data.extend_from_slice(&chunk);
+                while let Some(chunk) = stream.try_next().await.map_err(interrupted)? {
+                    file.write_all(&chunk).await.map_err(AppError::Io)?;
+                    received += chunk.len() as u64;
+                }
+
+                if let Some(expected) = expected
+                    && expected != received
+                {
+                    return Err(
+                        AppError::Storage(StorageError::Truncated { expected, received }).into(),
+                    );
                 }
-                bytes = Some(data.freeze().to_vec());
+
+                file.flush().await.map_err(AppError::Io)?;
+                file.rewind().await.map_err(AppError::Io)?;
+                upload.file = Some(file);
             }
             "tags" => {
-                let text = field.text().await.unwrap_or_default();
-                tags = text.split_whitespace().map(str::to_string).collect();
+                let text = field.text().await.map_err(interrupted)?;
+                upload.tags = text.split_whitespace().map(str::to_string).collect();
             }
             "source" => {
-                source = Some(field.text().await.unwrap_or_default());
+                upload.source = Some(field.text().await.map_err(interrupted)?);
+            }
+            "rating" => {
+                let text = field.text().await.map_err(interrupted)?;
+                upload.rating = Some(text.trim().parse().map_err(ImageError::BadRequest)?);
             }
             _ => {} // ignore
         }
     }
 
-    let bytes = match bytes {
-        Some(b) => b,
-        None => return Err(ImageError::BadRequest("missing file".to_string())),
-    };
-
-    let img = ArchiveImageCommand {
-        bytes,
-        tags,
-        source,
-    }
-    .execute(&state.storage, &state.db)
-    .await?;
+    Ok(upload)
+}
 
-    Ok(Json(ImageResponse::from_image(state.config, img)))
+fn interrupted(e: MultipartError) -> ImageError {
+    ImageError::BadRequest(format!("interrupted upload: {}", e.body_text()))
 }
 
 pub async fn put_tags(
@@ -432,6 +599,8 @@ impl IntoResponse for ImageError {
                     StorageError::HashCollision { hash, .. } => {
                         (StatusCode::BAD_REQUEST, hash.to_string())
                     }
+                    e @ StorageError::TrueCollision { .. } => (StatusCode::CONFLICT, e.to_string()),
+                    StorageError::EmptyInput => (StatusCode::BAD_REQUEST, "empty file".to_string()),
                     StorageError::UnsupportedFile { kind } => (
                         StatusCode::BAD_REQUEST,
                         kind.map(|k| k.mime_type().to_string())
@@ -450,11 +619,40 @@ impl IntoResponse for ImageError {
                     StorageError::Thumbnail { reason } => {
                         (StatusCode::UNPROCESSABLE_ENTITY, reason)
                     }
+                    e @ StorageError::Truncated { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
+                    e @ StorageError::QuotaExceeded { .. } => {
+                        (StatusCode::INSUFFICIENT_STORAGE, e.to_string())
+                    }
+                    StorageError::Busy { retry_after_hint } => {
+                        return (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            [(
+                                header::RETRY_AFTER,
+                                retry_after_hint.as_secs().max(1).to_string(),
+                            )],
+                            Json(ErrorResponse {
+                                message: "media processing is busy".to_string(),
+                            }),
+                        )
+                            .into_response();
+                    }
                 },
+                AppError::Database(DatabaseError::ReadOnly) => {
+                    (StatusCode::FORBIDDEN, DatabaseError::ReadOnly.to_string())
+                }
                 AppError::Database(database_error) => {
                     (StatusCode::SERVICE_UNAVAILABLE, database_error.to_string())
                 }
                 AppError::StorageNotFound { hash } => (StatusCode::NOT_FOUND, hash.to_string()),
+                e @ AppError::ImageDeleted { .. } => (StatusCode::GONE, e.to_string()),
+                e @ AppError::TaskFailed { .. } => {
+                    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                }
+                e @ AppError::InvalidSource { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
+                AppError::TagNotFound { tag } => (StatusCode::NOT_FOUND, tag),
+                e @ AppError::InvalidTag { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
+                e @ AppError::InvalidCursor { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
+                AppError::Io(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
             },
             ImageError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
         };
@@ -465,15 +663,60 @@ impl IntoResponse for ImageError {
 
 #[cfg(test)]
 mod tests {
-    use super::ImageQueryParam;
-    use buru::query::{ImageQuery, ImageQueryKind, OrderBy, image};
+    use super::{ImageError, ImageQueryParam, get_image, get_images, parse_ids, read_upload};
+    use crate::{AppConfig, AppState};
+    use axum::{
+        Json,
+        body::Body,
+        extract::{FromRequest, Multipart, Path, Query, State},
+        http::{Request, header},
+    };
+    use buru::app::{AppError, ArchiveImageCommand, SourcePolicy};
+    use buru::database::{Database, MIGRATOR, Pool, Rating};
+    use buru::query::{
+        Comparison, ImageQuery, ImageQueryExpr, ImageQueryKind, MediaGroup, MetadataField, OrderBy,
+        image,
+    };
+    use buru::storage::{CreatedAtFallback, PixelHash, Storage, ThumbnailFormat};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+    use tokio::io::AsyncReadExt;
+
+    fn state(pool: Pool, dir: &TempDir) -> AppState {
+        AppState {
+            db: Arc::new(Database::new(pool)),
+            storage: Arc::new(Storage::new(dir.path().to_path_buf())),
+            config: AppConfig {
+                database_url: String::new(),
+                cdn_base_url: "http://localhost:3000/files".into(),
+                image_dir: dir.path().to_path_buf(),
+                thumbnail_dir: None,
+                thumbnail_format: ThumbnailFormat::Png,
+                created_at_fallback: CreatedAtFallback::Now,
+                port: 3000,
+                body_limit: 20 * 1024 * 1024,
+                max_page_size: 200,
+                max_image_decodes: 1,
+                max_video_thumbnails: 1,
+                decode_queue_depth: 8,
+                read_only: false,
+                source_policy: SourcePolicy::default(),
+            },
+        }
+    }
+
+    fn ids_query(ids: String) -> ImageQueryParam {
+        ImageQueryParam {
+            ids: Some(ids),
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn test_build_query() {
         let image_query = ImageQueryParam {
-            tags: Some("cat cute -black order:random".to_string()),
-            page: None,
-            limit: None,
+            tags: Some("cat cute -black is:animated is:private rating:e order:random".to_string()),
+            ..Default::default()
         };
 
         assert_eq!(
@@ -482,12 +725,232 @@ mod tests {
                     image::tag("cat")
                         .and(image::tag("cute"))
                         .and(image::not(image::tag("black")))
+                        .and(image::media_group(MediaGroup::Animated))
+                        .and(image::rating("e"))
                 ),
                 limit: Some(20),
                 offset: Some(0),
-                order: Some(OrderBy::Random)
+                order: Some(OrderBy::Random),
+                include_private: false,
+                include_deleted: false,
+                cursor: None,
             },
             image_query.into()
         )
     }
+
+    #[test]
+    fn test_build_or_query() {
+        let image_query = ImageQueryParam {
+            tags: Some("cat ~dog ~fox -bird".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ImageQueryKind::Where(
+                image::tag("cat")
+                    .and(image::not(image::tag("bird")))
+                    .and(image::tag("dog").or(image::tag("fox")))
+            ),
+            ImageQuery::from(image_query).expr
+        );
+
+        // A single `~` tag is required, like a plain one.
+        let image_query = ImageQueryParam {
+            tags: Some("~dog".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ImageQueryKind::Where(image::tag("dog")),
+            ImageQuery::from(image_query).expr
+        );
+    }
+
+    #[test]
+    fn test_build_metadata_query() {
+        let image_query = ImageQueryParam {
+            tags: Some("cat width:>=1920 filesize:<2M height:!1 sources:0".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ImageQueryKind::Where(
+                image::tag("cat")
+                    .and(ImageQueryExpr::width_gte(1920))
+                    .and(ImageQueryExpr::filesize_lt(2 << 20))
+                    .and(image::source_count(Comparison::Eq, 0))
+            ),
+            ImageQuery::from(image_query).expr
+        );
+
+        let image_query = ImageQueryParam {
+            tags: Some("cat filesize:1K..1M".to_string()),
+            min_filesize: Some(100),
+            max_filesize: Some(5 << 20),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ImageQueryKind::Where(
+                image::tag("cat")
+                    .and(ImageQueryExpr::filesize_gte(1 << 10).and(image::metadata(
+                        MetadataField::FileSize,
+                        Comparison::Lte,
+                        1 << 20
+                    )))
+                    .and(ImageQueryExpr::filesize_gte(100))
+                    .and(image::metadata(
+                        MetadataField::FileSize,
+                        Comparison::Lte,
+                        5 << 20
+                    ))
+            ),
+            ImageQuery::from(image_query).expr
+        );
+    }
+
+    #[test]
+    fn test_build_dimensions_query() {
+        let image_query = ImageQueryParam {
+            tags: Some("cat ratio:16:9".to_string()),
+            min_width: Some(1920),
+            max_height: Some(1080),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            ImageQueryKind::Where(
+                image::tag("cat")
+                    .and(image::aspect_ratio(16, 9))
+                    .and(ImageQueryExpr::width_gte(1920))
+                    .and(image::metadata(
+                        MetadataField::Height,
+                        Comparison::Lte,
+                        1080
+                    ))
+            ),
+            ImageQuery::from(image_query).expr
+        );
+    }
+
+    #[test]
+    fn test_parse_ids() {
+        let hash = PixelHash::try_from("44a5b6f94f4f6445").unwrap();
+
+        assert_eq!(
+            vec![hash.clone(), hash.clone()],
+            parse_ids(&format!("{},44a5b6f94f4f6445", hash.clone().to_signed())).unwrap()
+        );
+        assert!(parse_ids("not-a-hash").is_err());
+    }
+
+    async fn multipart(body: &'static str) -> Multipart {
+        let request = Request::builder()
+            .header(header::CONTENT_TYPE, "multipart/form-data; boundary=X")
+            .body(Body::from(body.replace('\n', "\r\n")))
+            .unwrap();
+
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_read_upload() {
+        let dir = TempDir::new().unwrap();
+        let upload = read_upload(
+            multipart(
+                "--X\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\n\nPNG\n\
+                 --X\nContent-Disposition: form-data; name=\"tags\"\n\ncat cute\n--X--\n",
+            )
+            .await,
+            dir.path(),
+        )
+        .await
+        .ok()
+        .unwrap();
+
+        let mut bytes = vec![];
+        upload.file.unwrap().read_to_end(&mut bytes).await.unwrap();
+        assert_eq!(b"PNG".to_vec(), bytes);
+        assert_eq!(vec!["cat", "cute"], upload.tags);
+    }
+
+    #[tokio::test]
+    async fn test_read_upload_interrupted() {
+        let dir = TempDir::new().unwrap();
+        let result = read_upload(
+            multipart(
+                "--X\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\n\nPNG",
+            )
+            .await,
+            dir.path(),
+        )
+        .await;
+        assert!(matches!(result, Err(ImageError::BadRequest(_))));
+
+        let result = read_upload(
+            multipart(
+                "--X\nContent-Disposition: form-data; name=\"file\"; filename=\"a.png\"\n\
+                 Content-Length: 10\n\nPNG\n--X--\n",
+            )
+            .await,
+            dir.path(),
+        )
+        .await;
+        assert!(matches!(result, Err(ImageError::App(_))));
+        // Partial uploads leave nothing behind.
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_private_images_are_hidden(pool: Pool) {
+        let dir = TempDir::new().unwrap();
+        let app = state(pool, &dir);
+        let hash = ArchiveImageCommand::new(include_bytes!("../testdata/44a5b6f94f4f6445.png"))
+            .execute(&app.storage, &app.db)
+            .await
+            .unwrap()
+            .hash;
+        let id = hash.clone().to_signed();
+
+        assert!(get_image(State(app.clone()), Path(id)).await.is_ok());
+        let found = get_images(State(app.clone()), Query(ids_query(id.to_string())))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(1, found.len());
+
+        app.db.set_visibility(&hash, false).await.unwrap();
+
+        assert!(matches!(
+            get_image(State(app.clone()), Path(id)).await,
+            Err(ImageError::App(AppError::StorageNotFound { .. }))
+        ));
+        let found = get_images(State(app.clone()), Query(ids_query(id.to_string())))
+            .await
+            .ok()
+            .unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn test_unrated_images_have_no_rating(pool: Pool) {
+        let dir = TempDir::new().unwrap();
+        let app = state(pool, &dir);
+        let hash = ArchiveImageCommand::new(include_bytes!("../testdata/44a5b6f94f4f6445.png"))
+            .execute(&app.storage, &app.db)
+            .await
+            .unwrap()
+            .hash;
+        let id = hash.clone().to_signed();
+
+        let Json(image) = get_image(State(app.clone()), Path(id)).await.ok().unwrap();
+        assert_eq!(None, image.rating);
+
+        app.db
+            .ensure_image_has_rating(&hash, Rating::General)
+            .await
+            .unwrap();
+        let Json(image) = get_image(State(app.clone()), Path(id)).await.ok().unwrap();
+        assert_eq!(Some(Rating::General.as_str().to_string()), image.rating);
+    }
 }
@@ -0,0 +1,7 @@
+use crate::AppState;
+use axum::{Json, extract::State};
+use buru::prelude::*;
+
+pub async fn get_info(State(app): State<AppState>) -> Json<Capabilities> {
+    Json(capabilities(&app.db, &app.config.limits()).await)
+}
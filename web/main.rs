@@ -1,14 +1,23 @@
 mod image;
+mod info;
 mod tag;
 
 use axum::Router;
 use axum::extract::{DefaultBodyLimit, Path, State};
-use axum::http::{Response, StatusCode};
+use axum::http::{Response, StatusCode, header};
 use axum::response::IntoResponse;
 use axum::routing::{get, put};
-use buru::{database::Database, storage::Storage};
+use buru::{
+    app::SourcePolicy,
+    capabilities::Limits,
+    database::Database,
+    storage::{
+        AdmissionController, CreatedAtFallback, PixelHash, Storage, StorageError, ThumbnailFormat,
+        VariantSpec,
+    },
+};
 use sqlx::Pool;
-use std::{env, fs};
+use std::env;
 use std::{path::PathBuf, sync::Arc};
 
 #[derive(Clone)]
@@ -16,8 +25,17 @@ pub struct AppConfig {
     pub database_url: String,
     pub cdn_base_url: PathBuf,
     pub image_dir: PathBuf,
+    pub thumbnail_dir: Option<PathBuf>,
+    pub thumbnail_format: ThumbnailFormat,
+    pub created_at_fallback: CreatedAtFallback,
     pub port: u16,
     pub body_limit: usize,
+    pub max_page_size: u32,
+    pub max_image_decodes: usize,
+    pub max_video_thumbnails: usize,
+    pub decode_queue_depth: usize,
+    pub read_only: bool,
+    pub source_policy: SourcePolicy,
 }
 
 impl AppConfig {
@@ -32,6 +50,17 @@ impl AppConfig {
             image_dir: env::var("IMAGE_DIR")
                 .unwrap_or_else(|_| "static/images".to_string())
                 .into(),
+            thumbnail_dir: env::var("THUMBNAIL_DIR").ok().map(PathBuf::from),
+            thumbnail_format: match env::var("THUMBNAIL_FORMAT").as_deref() {
+                Ok("jpeg" | "jpg") => ThumbnailFormat::Jpeg,
+                Ok("webp") => ThumbnailFormat::WebP,
+                _ => ThumbnailFormat::Png,
+            },
+            created_at_fallback: match env::var("CREATED_AT_FALLBACK").as_deref() {
+                Ok("modified") => CreatedAtFallback::Modified,
+                Ok("none") => CreatedAtFallback::None,
+                _ => CreatedAtFallback::Now,
+            },
             port: env::var("PORT")
                 .ok()
                 .and_then(|s| s.parse().ok())
@@ -40,12 +69,41 @@ impl AppConfig {
                 .ok()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(20 * 1024 * 1024), // 20 MB
+            max_page_size: env::var("MAX_PAGE_SIZE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(200),
+            max_image_decodes: env::var("MAX_IMAGE_DECODES")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+                .unwrap_or(4),
+            max_video_thumbnails: env::var("MAX_VIDEO_THUMBNAILS")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(1),
+            decode_queue_depth: env::var("DECODE_QUEUE_DEPTH")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(8),
+            read_only: env::var("READ_ONLY").is_ok_and(|s| s == "1" || s == "true"),
+            source_policy: source_policy_from_env(),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_upload_bytes: Some(self.body_limit as u64),
+            max_page_size: Some(self.max_page_size),
+            max_image_decodes: Some(self.max_image_decodes),
+            max_video_thumbnails: Some(self.max_video_thumbnails),
+            decode_queue_depth: Some(self.decode_queue_depth),
         }
     }
 
     pub async fn create_database(&self) {
-        #[cfg(all(feature = "sqlite", not(feature = "postgres")))]
-        {
+        #[cfg(all(feature = "sqlite", not(any(feature = "postgres", feature = "mysql"))))]
+        if !self.read_only {
             use sqlx::migrate::MigrateDatabase;
             sqlx::Sqlite::create_database(&self.database_url)
                 .await
@@ -54,12 +112,22 @@ impl AppConfig {
     }
 
     pub async fn into_state(self) -> AppState {
-        let db = Database {
-            pool: Pool::connect(&self.database_url).await.unwrap(),
-        };
-        db.migrate().await.unwrap();
+        let db = Database::new(Pool::connect(&self.database_url).await.unwrap())
+            .with_read_only(self.read_only);
+        if !db.is_read_only() {
+            db.migrate().await.unwrap();
+        }
 
-        let storage = Storage::new(self.image_dir.clone());
+        let mut storage = Storage::new(self.image_dir.clone())
+            .with_thumbnail_format(self.thumbnail_format)
+            .with_created_at_fallback(self.created_at_fallback)
+            .with_admission(
+                AdmissionController::new(self.max_image_decodes, self.max_video_thumbnails)
+                    .with_queue_depth(self.decode_queue_depth),
+            );
+        if let Some(thumbnail_dir) = &self.thumbnail_dir {
+            storage = storage.with_thumbnail_root(thumbnail_dir.clone());
+        }
 
         AppState {
             db: Arc::new(db),
@@ -69,6 +137,32 @@ impl AppConfig {
     }
 }
 
+/// Builds the source policy from comma-separated `SOURCE_SCHEMES`,
+/// `SOURCE_ALLOWED_HOSTS` and `SOURCE_DENIED_HOSTS`. Unset variables add no restriction.
+fn source_policy_from_env() -> SourcePolicy {
+    let list = |key: &str| {
+        env::var(key).ok().map(|s| {
+            s.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let mut policy = SourcePolicy::permissive();
+    if let Some(schemes) = list("SOURCE_SCHEMES") {
+        policy = policy.with_schemes(schemes);
+    }
+    if let Some(hosts) = list("SOURCE_ALLOWED_HOSTS") {
+        policy = policy.with_allowed_hosts(hosts);
+    }
+    if let Some(hosts) = list("SOURCE_DENIED_HOSTS") {
+        policy = policy.with_denied_hosts(hosts);
+    }
+    policy
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: Arc<Database>,
@@ -95,6 +189,8 @@ async fn main() {
         .route("/tags", get(tag::get_tags))
         .route("/tags/suggest", get(tag::suggest_tags))
         .route("/refresh/tag_counts", put(tag::refresh_count))
+        .route("/info", get(info::get_info))
+        .route("/status.json", get(info::get_info))
         .route("/files/{vari}/{*hash}", get(serve_file))
         .layer(DefaultBodyLimit::max(config.body_limit))
         .with_state(config.into_state().await);
@@ -103,14 +199,89 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Returns whether the key is the relative path of a stored file, i.e. hex shard
+/// directories followed by a hash named file, so that it stays below the directory it
+/// is joined to.
+fn is_object_key(key: &str) -> bool {
+    let Some((dirs, name)) = key.rsplit_once('/') else {
+        return false;
+    };
+    let Some((stem, ext)) = name.split_once('.') else {
+        return false;
+    };
+
+    dirs.split('/')
+        .all(|dir| dir.len() == 2 && dir.bytes().all(|b| b.is_ascii_hexdigit()))
+        && PixelHash::try_from(stem).is_ok()
+        && !ext.is_empty()
+        && ext.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
 async fn serve_file(
     State(state): State<AppState>,
-    Path((_vari, hash)): Path<(String, String)>,
+    Path((vari, hash)): Path<(String, String)>,
 ) -> impl IntoResponse {
-    let path = state.config.image_dir.join(PathBuf::from(hash));
+    // Resized variants are generated on first request and cached by the storage.
+    // Widths are served by the nearest wider variant that exists, if any.
+    if let Ok(spec) = vari.parse::<VariantSpec>() {
+        let Some(pixel_hash) = PathBuf::from(&hash)
+            .file_stem()
+            .and_then(|stem| PixelHash::try_from(stem.to_string_lossy().as_ref()).ok())
+        else {
+            return StatusCode::NOT_FOUND.into_response();
+        };
+        let storage = state.storage.clone();
+        return match tokio::task::spawn_blocking(move || {
+            let spec = storage.nearest_variant(&pixel_hash, spec)?;
+            storage.get_variant(&pixel_hash, spec)
+        })
+        .await
+        {
+            Ok(Ok(bytes)) => Response::builder().body(bytes.into()).unwrap(),
+            Ok(Err(StorageError::FileNotFound { .. })) => StatusCode::NOT_FOUND.into_response(),
+            Ok(Err(StorageError::Busy { retry_after_hint })) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(
+                    header::RETRY_AFTER,
+                    retry_after_hint.as_secs().max(1).to_string(),
+                )],
+            )
+                .into_response(),
+            _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+    }
 
-    match fs::read(path) {
+    let bytes = match state.storage.backend().read(&hash).await {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => match &state.config.thumbnail_dir {
+            Some(dir) if is_object_key(&hash) => {
+                tokio::fs::read(dir.join(&hash)).await.map_err(|_| e)
+            }
+            _ => Err(e),
+        },
+    };
+
+    match bytes {
         Ok(bytes) => Response::builder().body(bytes.into()).unwrap(),
         Err(_) => StatusCode::NOT_FOUND.into_response(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::is_object_key;
+
+    #[test]
+    fn test_is_object_key() {
+        assert!(is_object_key("44/a5/44a5b6f94f4f6445.png"));
+        assert!(is_object_key("44/44a5b6f94f4f6445.webp"));
+
+        assert!(!is_object_key("44a5b6f94f4f6445.png"));
+        assert!(!is_object_key("../a5/44a5b6f94f4f6445.png"));
+        assert!(!is_object_key("44/../44a5b6f94f4f6445.png"));
+        assert!(!is_object_key("/44/44a5b6f94f4f6445.png"));
+        assert!(!is_object_key("44/a5/passwd"));
+        assert!(!is_object_key("44/a5/44a5b6f94f4f6445."));
+        assert!(!is_object_key("44/a5/44a5b6f94f4f6445.png/.."));
+    }
+}
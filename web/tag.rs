@@ -4,10 +4,10 @@ use crate::AppState;
 use axum::{
     Json,
     extract::{Query, State},
-    http::StatusCode,
+    http::{StatusCode, header},
     response::IntoResponse,
 };
-use buru::prelude::*;
+use buru::{parser::parse_tag_query, prelude::*};
 use serde::{Deserialize, Serialize};
 use std::hash::Hasher;
 use twox_hash::XxHash64;
@@ -24,6 +24,8 @@ fn compute_hash(tag: &str) -> i64 {
 pub struct TagQuery {
     #[serde(rename = "search[name_comma]")]
     tags: Option<String>,
+    #[serde(rename = "search[name_matches]")]
+    name_matches: Option<String>,
     page: Option<u32>,
     limit: Option<u32>,
 }
@@ -55,8 +57,10 @@ impl TagResponse {
 
 pub async fn get_tags(
     State(app): State<AppState>,
-    Query(params): Query<TagQuery>,
+    Query(mut params): Query<TagQuery>,
 ) -> Result<Json<Vec<TagResponse>>, TagError> {
+    params.limit = params.limit.map(|l| l.min(app.config.max_page_size));
+
     let tags = params
         .tags
         .unwrap_or_default()
@@ -65,10 +69,20 @@ pub async fn get_tags(
         .map(String::from)
         .collect::<Vec<_>>();
 
+    let matches = params
+        .name_matches
+        .filter(|e| !e.trim().is_empty())
+        .map(|e| parse_tag_query(&e))
+        .transpose()
+        .map_err(|e| TagError::BadRequest(format!("{:?}", e)))?;
+
     let query = buru::query::TagQuery::new(
         tags.into_iter()
             .map(TagQueryExpr::Exact)
             .reduce(TagQueryExpr::or)
+            .into_iter()
+            .chain(matches)
+            .reduce(TagQueryExpr::and)
             .map(TagQueryKind::Where)
             .unwrap_or(TagQueryKind::All),
     )
@@ -111,24 +125,34 @@ pub struct SuggestTagResponse {
 
 async fn tag_counts(db: &Database, tags: &[&str]) -> Result<HashMap<String, u64>, TagError> {
     let mut set = tokio::task::JoinSet::new();
+    let mut keys = HashMap::new();
 
     for tag in tags.iter() {
         let db = db.clone();
         let tag = tag.to_string();
-        set.spawn(async move {
+        let key = tag.clone();
+        let task = set.spawn(async move {
             let count = count_image_by_tag(&db, &tag).await?;
-            Ok::<(String, u64), TagError>((tag, count))
+            Ok::<u64, TagError>(count)
         });
+        keys.insert(task.id(), key);
     }
 
     let mut map = HashMap::new();
-    while let Some(result) = set.join_next().await {
+    while let Some(result) = set.join_next_with_id().await {
         match result {
-            Ok(Ok((tag, count))) => {
-                map.insert(tag, count);
+            Ok((id, Ok(count))) => {
+                if let Some(tag) = keys.remove(&id) {
+                    map.insert(tag, count);
+                }
+            }
+            Ok((_, Err(e))) => return Err(e),
+            Err(join_err) => {
+                return Err(TagError::App(AppError::TaskFailed {
+                    key: keys.remove(&join_err.id()).unwrap_or_default(),
+                    reason: join_err.to_string(),
+                }));
             }
-            Ok(Err(e)) => return Err(e),
-            Err(join_err) => panic!("task panicked in post count retrieval: {join_err}"),
         }
     }
 
@@ -149,8 +173,10 @@ impl SuggestTagResponse {
 
 pub async fn suggest_tags(
     State(app): State<AppState>,
-    Query(params): Query<SuggestTagQuery>,
+    Query(mut params): Query<SuggestTagQuery>,
 ) -> Result<Json<Vec<SuggestTagResponse>>, TagError> {
+    params.limit = params.limit.map(|l| l.min(app.config.max_page_size));
+
     let query = buru::query::TagQuery::new(
         params
             .looking_for
@@ -179,6 +205,8 @@ pub async fn refresh_count(State(app): State<AppState>) -> Result<StatusCode, Ta
 
 pub enum TagError {
     App(AppError),
+
+    BadRequest(String),
 }
 
 impl From<AppError> for TagError {
@@ -200,6 +228,8 @@ impl IntoResponse for TagError {
                     StorageError::HashCollision { hash, .. } => {
                         (StatusCode::BAD_REQUEST, hash.to_string())
                     }
+                    e @ StorageError::TrueCollision { .. } => (StatusCode::CONFLICT, e.to_string()),
+                    StorageError::EmptyInput => (StatusCode::BAD_REQUEST, "empty file".to_string()),
                     StorageError::UnsupportedFile { kind } => (
                         StatusCode::BAD_REQUEST,
                         kind.map(|k| k.mime_type().to_string())
@@ -218,12 +248,42 @@ impl IntoResponse for TagError {
                     StorageError::Thumbnail { reason } => {
                         (StatusCode::UNPROCESSABLE_ENTITY, reason)
                     }
+                    e @ StorageError::Truncated { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
+                    e @ StorageError::QuotaExceeded { .. } => {
+                        (StatusCode::INSUFFICIENT_STORAGE, e.to_string())
+                    }
+                    StorageError::Busy { retry_after_hint } => {
+                        return (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            [(
+                                header::RETRY_AFTER,
+                                retry_after_hint.as_secs().max(1).to_string(),
+                            )],
+                            Json(ErrorResponse {
+                                message: "media processing is busy".to_string(),
+                            }),
+                        )
+                            .into_response();
+                    }
                 },
+                AppError::Database(DatabaseError::ReadOnly) => {
+                    (StatusCode::FORBIDDEN, DatabaseError::ReadOnly.to_string())
+                }
                 AppError::Database(database_error) => {
                     (StatusCode::SERVICE_UNAVAILABLE, database_error.to_string())
                 }
                 AppError::StorageNotFound { hash } => (StatusCode::NOT_FOUND, hash.to_string()),
+                e @ AppError::ImageDeleted { .. } => (StatusCode::GONE, e.to_string()),
+                e @ AppError::TaskFailed { .. } => {
+                    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                }
+                e @ AppError::InvalidSource { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
+                AppError::TagNotFound { tag } => (StatusCode::NOT_FOUND, tag),
+                e @ AppError::InvalidTag { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
+                e @ AppError::InvalidCursor { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
+                AppError::Io(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
             },
+            TagError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
         };
 
         (status, Json(ErrorResponse { message })).into_response()